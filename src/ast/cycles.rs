@@ -0,0 +1,346 @@
+// Rough LH5801 cycle cost of running each statement with the ROM interpreter
+//
+// The ROM reads numbers from the program text on every execution and works
+// on 8 byte BCD values, so arithmetic dominates. Jumps search the program
+// from its first line, so they get slower the further down the target is.
+// The figures are estimates to rank statements against each other, not
+// exact timings.
+
+use super::{
+    node::{Coordinate, DataItem, Function, LValue, PrintItem, UnaryOperator, Unsigned},
+    BinaryOperator, Expression, ExpressionVisitor, Statement, StatementVisitor,
+};
+
+// Fetching and dispatching a statement
+const STATEMENT: u64 = 600;
+// Converting a number from the program text to BCD
+const NUMBER: u64 = 400;
+const STRING: u64 = 200;
+const VARIABLE: u64 = 300;
+const ARRAY_ELEMENT: u64 = 700;
+const UNARY: u64 = 200;
+const ADD: u64 = 1000;
+const MULTIPLY: u64 = 2500;
+const DIVIDE: u64 = 4500;
+const COMPARE: u64 = 800;
+const LOGIC: u64 = 600;
+const FUNCTION: u64 = 500;
+// Assigning a value to a variable
+const STORE: u64 = 300;
+// Skipping one line while searching for a jump target
+const LINE_SEARCH: u64 = 60;
+// Drawing a character on the LCD
+const CHARACTER: u64 = 1200;
+const LOOP: u64 = 1500;
+// Moving the CE-150 pen, for each argument
+const PLOTTER: u64 = 3000;
+
+pub struct CycleEstimator<'a> {
+    // Line numbers in program order, to price the search for a jump target
+    lines: &'a [u32],
+}
+
+impl<'a> CycleEstimator<'a> {
+    pub fn new(lines: &'a [u32]) -> Self {
+        CycleEstimator { lines }
+    }
+
+    pub fn estimate(&mut self, statement: &'a Statement) -> u64 {
+        statement.accept(self)
+    }
+
+    fn search(&self, line_number: u32) -> u64 {
+        let skipped = self.lines.partition_point(|line| *line < line_number);
+        STATEMENT + LINE_SEARCH * skipped as u64
+    }
+
+    fn expressions(&mut self, expressions: &'a [Expression]) -> u64 {
+        expressions.iter().map(|e| e.accept(self)).sum()
+    }
+
+    fn optional(&mut self, expression: Option<&'a Expression>) -> u64 {
+        expression.map_or(0, |e| e.accept(self))
+    }
+
+    fn lvalue(&mut self, lvalue: &'a LValue) -> u64 {
+        match lvalue {
+            LValue::Variable(_) => VARIABLE,
+            LValue::ArrayElement { index, .. } => ARRAY_ELEMENT + index.accept(self),
+        }
+    }
+
+    fn print_items(&mut self, content: &'a [PrintItem]) -> u64 {
+        content
+            .iter()
+            .map(|item| item.expression.accept(self) + CHARACTER)
+            .sum()
+    }
+
+    fn coordinate(&mut self, coordinate: &'a Coordinate) -> u64 {
+        coordinate.x.accept(self) + coordinate.y.accept(self) + PLOTTER
+    }
+}
+
+impl<'a> ExpressionVisitor<'a, u64> for CycleEstimator<'a> {
+    fn visit_number_literal(&mut self, _: i32) -> u64 {
+        NUMBER
+    }
+
+    fn visit_string_literal(&mut self, _: &'a str) -> u64 {
+        STRING
+    }
+
+    fn visit_variable(&mut self, lvalue: &'a LValue) -> u64 {
+        self.lvalue(lvalue)
+    }
+
+    fn visit_unary_op(&mut self, _: UnaryOperator, operand: &'a Expression) -> u64 {
+        UNARY + operand.accept(self)
+    }
+
+    fn visit_binary_op(
+        &mut self,
+        left: &'a Expression,
+        op: BinaryOperator,
+        right: &'a Expression,
+    ) -> u64 {
+        let cost = match op {
+            BinaryOperator::Add | BinaryOperator::Sub => ADD,
+            BinaryOperator::Mul => MULTIPLY,
+            BinaryOperator::Div => DIVIDE,
+            BinaryOperator::And | BinaryOperator::Or => LOGIC,
+            BinaryOperator::Eq
+            | BinaryOperator::Ne
+            | BinaryOperator::Lt
+            | BinaryOperator::Le
+            | BinaryOperator::Gt
+            | BinaryOperator::Ge => COMPARE,
+        };
+        cost + left.accept(self) + right.accept(self)
+    }
+
+    fn visit_function(&mut self, _: Function, args: &'a [Expression]) -> u64 {
+        FUNCTION + self.expressions(args)
+    }
+}
+
+impl<'a> StatementVisitor<'a, u64> for CycleEstimator<'a> {
+    fn visit_let(&mut self, variable: &'a LValue, expression: &'a Expression) -> u64 {
+        STATEMENT + STORE + self.lvalue(variable) + expression.accept(self)
+    }
+
+    fn visit_print(&mut self, _: Option<&'a str>, content: &'a [PrintItem]) -> u64 {
+        STATEMENT + self.print_items(content)
+    }
+
+    fn visit_pause(&mut self, _: Option<&'a str>, content: &'a [PrintItem]) -> u64 {
+        STATEMENT + self.print_items(content)
+    }
+
+    fn visit_input(&mut self, prompt: Option<&'a Expression>, variable: &'a LValue) -> u64 {
+        STATEMENT + STORE + self.optional(prompt) + self.lvalue(variable)
+    }
+
+    fn visit_wait(&mut self, time: Option<&'a Expression>) -> u64 {
+        STATEMENT + self.optional(time)
+    }
+
+    fn visit_read(&mut self, variables: &'a [LValue]) -> u64 {
+        let stores: u64 = variables
+            .iter()
+            .map(|v| self.lvalue(v) + STORE + NUMBER)
+            .sum();
+        STATEMENT + stores
+    }
+
+    fn visit_data(&mut self, _: &'a [DataItem]) -> u64 {
+        STATEMENT
+    }
+
+    fn visit_restore(&mut self, line_number: Option<Unsigned>) -> u64 {
+        line_number.map_or(STATEMENT, |line| self.search(line.value))
+    }
+
+    fn visit_poke(&mut self, address: &'a Expression, values: &'a [Expression]) -> u64 {
+        STATEMENT + address.accept(self) + self.expressions(values)
+    }
+
+    fn visit_call(&mut self, address: &'a Expression, argument: Option<&'a Expression>) -> u64 {
+        STATEMENT + address.accept(self) + self.optional(argument)
+    }
+
+    fn visit_goto(&mut self, line_number: Unsigned) -> u64 {
+        self.search(line_number.value)
+    }
+
+    fn visit_for(
+        &mut self,
+        _: &'a str,
+        from: &'a Expression,
+        to: &'a Expression,
+        step: Option<&'a Expression>,
+    ) -> u64 {
+        STATEMENT + LOOP + STORE + from.accept(self) + to.accept(self) + self.optional(step)
+    }
+
+    fn visit_next(&mut self, _: &'a str) -> u64 {
+        STATEMENT + LOOP + ADD + COMPARE + STORE
+    }
+
+    fn visit_end(&mut self) -> u64 {
+        STATEMENT
+    }
+
+    fn visit_gosub(&mut self, line_number: Unsigned) -> u64 {
+        self.search(line_number.value) + LOOP
+    }
+
+    fn visit_return(&mut self) -> u64 {
+        STATEMENT + LOOP
+    }
+
+    fn visit_on_error(&mut self, _: Unsigned) -> u64 {
+        STATEMENT
+    }
+
+    // The branches are statements of their own
+    fn visit_if(
+        &mut self,
+        condition: &'a Expression,
+        _: &'a Statement,
+        _: Option<&'a Statement>,
+    ) -> u64 {
+        STATEMENT + condition.accept(self)
+    }
+
+    fn visit_seq(&mut self, statements: &'a [Statement]) -> u64 {
+        statements.iter().map(|s| s.accept(self)).sum()
+    }
+
+    fn visit_rem(&mut self, _: &'a str) -> u64 {
+        STATEMENT
+    }
+
+    fn visit_dim(&mut self, _: &'a str, _: Unsigned, _: Option<Unsigned>) -> u64 {
+        STATEMENT + STORE
+    }
+
+    fn visit_cls(&mut self) -> u64 {
+        STATEMENT + CHARACTER
+    }
+
+    fn visit_cursor(&mut self, column: &'a Expression) -> u64 {
+        STATEMENT + column.accept(self)
+    }
+
+    fn visit_gcursor(&mut self, column: &'a Expression) -> u64 {
+        STATEMENT + column.accept(self)
+    }
+
+    fn visit_gprint(&mut self, content: &'a [Expression]) -> u64 {
+        STATEMENT + self.expressions(content) + CHARACTER * content.len() as u64
+    }
+
+    fn visit_beep(
+        &mut self,
+        count: &'a Expression,
+        frequency: Option<&'a Expression>,
+        duration: Option<&'a Expression>,
+    ) -> u64 {
+        STATEMENT + count.accept(self) + self.optional(frequency) + self.optional(duration)
+    }
+
+    fn visit_lprint(&mut self, _: Option<&'a str>, content: &'a [PrintItem]) -> u64 {
+        STATEMENT + self.print_items(content) + PLOTTER
+    }
+
+    fn visit_line(
+        &mut self,
+        _: bool,
+        start: Option<&'a Coordinate>,
+        points: &'a [Coordinate],
+        line_type: Option<&'a Expression>,
+        color: Option<&'a Expression>,
+        _: bool,
+    ) -> u64 {
+        let start = start.map_or(0, |start| self.coordinate(start));
+        let points: u64 = points.iter().map(|point| self.coordinate(point)).sum();
+        STATEMENT + start + points + self.optional(line_type) + self.optional(color)
+    }
+
+    fn visit_glcursor(&mut self, position: &'a Coordinate) -> u64 {
+        STATEMENT + self.coordinate(position)
+    }
+
+    fn visit_color(&mut self, color: &'a Expression) -> u64 {
+        STATEMENT + color.accept(self)
+    }
+
+    fn visit_csize(&mut self, size: &'a Expression) -> u64 {
+        STATEMENT + size.accept(self)
+    }
+
+    fn visit_rotate(&mut self, direction: &'a Expression) -> u64 {
+        STATEMENT + direction.accept(self)
+    }
+
+    fn visit_graph(&mut self) -> u64 {
+        STATEMENT
+    }
+
+    fn visit_text(&mut self) -> u64 {
+        STATEMENT
+    }
+
+    fn visit_sorgn(&mut self) -> u64 {
+        STATEMENT
+    }
+
+    fn visit_lf(&mut self, lines: &'a Expression) -> u64 {
+        STATEMENT + lines.accept(self) + PLOTTER
+    }
+
+    fn visit_tron(&mut self) -> u64 {
+        STATEMENT
+    }
+
+    fn visit_troff(&mut self) -> u64 {
+        STATEMENT
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn jumps_cost_more_further_down() {
+        let lines = [10, 20, 30, 40];
+        let mut estimator = CycleEstimator::new(&lines);
+
+        let near = estimator.estimate(&Statement::Goto {
+            line_number: Unsigned {
+                value: 10,
+                hex: false,
+            },
+        });
+        let far = estimator.estimate(&Statement::Goto {
+            line_number: Unsigned {
+                value: 40,
+                hex: false,
+            },
+        });
+        let division = estimator.estimate(&Statement::Let {
+            variable: LValue::Variable("A".to_owned()),
+            expression: Expression::Binary {
+                left: Box::new(Expression::Number(1)),
+                op: BinaryOperator::Div,
+                right: Box::new(Expression::Number(3)),
+            },
+        });
+
+        assert_eq!(near, STATEMENT);
+        assert_eq!(far, STATEMENT + 3 * LINE_SEARCH);
+        assert_eq!(division, STATEMENT + STORE + VARIABLE + DIVIDE + 2 * NUMBER);
+    }
+}
@@ -0,0 +1,555 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use super::flow::ControlFlow;
+use super::node::{DataItem, LValue};
+use super::semantics::Ty;
+use super::stack::DEFAULT_STACK_LIMIT;
+use super::{Expression, Statement};
+
+// Upper bound on explored states before giving up on the program
+const STATE_BUDGET: usize = 50_000;
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+struct Loop<'a> {
+    variable: &'a str,
+    // Iterations left including the current one, None when the bounds are not
+    // literal
+    remaining: Option<i64>,
+    // First statement of the loop body
+    body: usize,
+    // DATA pointer when the loop was entered
+    start: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+struct State<'a> {
+    node: usize,
+    // Index of the next DATA item to read, None when unknown
+    pointer: Option<usize>,
+    loops: Vec<Loop<'a>>,
+    calls: Vec<usize>,
+    // Whether the state was reached by a GOTO to an earlier statement
+    back: bool,
+}
+
+// Statement with the loops and subroutine calls it runs in
+type Context<'a> = (usize, Vec<Loop<'a>>, Vec<usize>);
+
+// Explored states and their successors, to tell the paths that fail at a
+// READ from the ones that get past it
+#[derive(Default)]
+struct Paths<'a> {
+    // By context and pointer
+    ids: HashMap<Context<'a>, HashMap<Option<usize>, usize>>,
+    // Node of every state
+    nodes: Vec<usize>,
+    successors: Vec<Vec<usize>>,
+    // Error a state stops with at its READ
+    failures: Vec<Option<String>>,
+}
+
+impl<'a> Paths<'a> {
+    fn id(&mut self, state: &State<'a>) -> usize {
+        let context = (state.node, state.loops.clone(), state.calls.clone());
+        let pointers = self.ids.entry(context).or_default();
+        if let Some(id) = pointers.get(&state.pointer) {
+            return *id;
+        }
+
+        let id = self.nodes.len();
+        pointers.insert(state.pointer, id);
+        self.nodes.push(state.node);
+        self.successors.push(Vec::new());
+        self.failures.push(None);
+        id
+    }
+
+    // Whether every path reaching the READ stops there with the error. A state
+    // always fails when it fails itself or all of its successors do, so paths
+    // that end elsewhere or never end get past the READ
+    fn always_fail(&self, read: usize, error: &str) -> bool {
+        let mut predecessors = vec![Vec::new(); self.nodes.len()];
+        for (id, successors) in self.successors.iter().enumerate() {
+            for successor in successors {
+                predecessors[*successor].push(id);
+            }
+        }
+
+        let mut pending: Vec<usize> = self.successors.iter().map(Vec::len).collect();
+        let mut failing = vec![false; self.nodes.len()];
+        let mut worklist: Vec<usize> = (0..self.nodes.len())
+            .filter(|id| self.failures[*id].as_deref() == Some(error))
+            .collect();
+        while let Some(id) = worklist.pop() {
+            if failing[id] {
+                continue;
+            }
+            failing[id] = true;
+            for predecessor in &predecessors[id] {
+                pending[*predecessor] -= 1;
+                if pending[*predecessor] == 0 {
+                    worklist.push(*predecessor);
+                }
+            }
+        }
+
+        (0..self.nodes.len())
+            .filter(|id| self.nodes[*id] == read)
+            .all(|id| failing[id])
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct DataReport {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+// Simulates the DATA pointer along every control-flow path of the program, so
+// that READ statements can be checked against the DATA items they consume.
+// Loops with literal bounds are unrolled; GOTO loops and loops with unknown
+// bounds that move the pointer make it unknown from then on. A READ that
+// fails on every path reaching it is an error, and a warning when it only
+// fails on some
+pub struct DataAnalysis<'a, 'b> {
+    flow: &'b ControlFlow<'a>,
+    // DATA items in program order with the line they come from
+    items: Vec<(u32, &'a DataItem)>,
+    // Error the READ of the last step stopped its path with
+    failure: Option<String>,
+    // GOSUB frames followed before giving up on a path
+    stack_limit: usize,
+    report: DataReport,
+}
+
+impl<'a, 'b> DataAnalysis<'a, 'b> {
+    pub fn new(flow: &'b ControlFlow<'a>) -> Self {
+        // Nodes are laid out in program order, which is also the order the
+        // ROM reads DATA statements in
+        let mut items = Vec::new();
+        for node in flow.nodes() {
+            if let Statement::Data { values } = node.statement {
+                items.extend(values.iter().map(|value| (node.line, value)));
+            }
+        }
+
+        DataAnalysis {
+            flow,
+            items,
+            failure: None,
+            stack_limit: DEFAULT_STACK_LIMIT,
+            report: DataReport::default(),
+        }
+    }
+
+    // GOSUB and FOR frames the ROM's stack holds
+    pub fn set_stack_limit(&mut self, limit: usize) {
+        self.stack_limit = limit;
+    }
+
+    pub fn analyze(mut self) -> DataReport {
+        let Some(entry) = self.flow.entry() else {
+            return self.report;
+        };
+
+        // Loop and call contexts statements were explored in
+        let mut contexts: HashSet<Context<'a>> = HashSet::new();
+        let mut paths = Paths::default();
+        let mut done = HashSet::new();
+        let mut worklist = vec![State {
+            node: entry,
+            pointer: Some(0),
+            loops: Vec::new(),
+            calls: Vec::new(),
+            back: false,
+        }];
+        let mut explored = 0;
+        // Paths left unexplored may read what the others could not
+        let mut complete = true;
+
+        while let Some(mut state) = worklist.pop() {
+            let id = paths.id(&state);
+            if !done.insert(id) {
+                continue;
+            }
+
+            // A statement jumped back to in the same context with another
+            // pointer is part of a loop driven by the data itself, which goes
+            // on like the state with an unknown pointer. Paths merging from
+            // branches keep their own pointers
+            let context = (state.node, state.loops.clone(), state.calls.clone());
+            if state.back && state.pointer.is_some() && contexts.contains(&context) {
+                state.pointer = None;
+                let unknown = paths.id(&state);
+                paths.successors[id].push(unknown);
+                worklist.push(state);
+                continue;
+            }
+            contexts.insert(context);
+
+            explored += 1;
+            if explored > STATE_BUDGET {
+                self.report.warnings.push(
+                    "DATA pointer cannot be tracked: too many paths, READ statements not fully checked"
+                        .to_owned(),
+                );
+                complete = false;
+                break;
+            }
+
+            let next = self.step(state);
+            paths.failures[id] = self.failure.take();
+            for successor in &next {
+                let successor = paths.id(successor);
+                paths.successors[id].push(successor);
+            }
+            worklist.extend(next);
+        }
+
+        // Errors by READ statement, in program order
+        let mut failures: BTreeMap<usize, Vec<&str>> = BTreeMap::new();
+        for (id, failure) in paths.failures.iter().enumerate() {
+            if let Some(error) = failure {
+                let errors = failures.entry(paths.nodes[id]).or_default();
+                if !errors.contains(&error.as_str()) {
+                    errors.push(error);
+                }
+            }
+        }
+        for (read, errors) in failures {
+            for error in errors {
+                if complete && paths.always_fail(read, error) {
+                    self.error(error.to_owned());
+                } else {
+                    self.warn(format!("{} on some paths", error));
+                }
+            }
+        }
+        self.report
+    }
+
+    fn step(&mut self, mut state: State<'a>) -> Vec<State<'a>> {
+        let node = self.flow.node(state.node);
+        state.back = false;
+
+        match node.statement {
+            Statement::Read { variables } => {
+                let completed = self.read(node.line, variables, &mut state.pointer);
+                if !completed {
+                    return Vec::new();
+                }
+            }
+            Statement::Restore { line_number } => {
+                let line = line_number.map_or(0, |line| line.value);
+                state.pointer = Some(
+                    self.items
+                        .iter()
+                        .position(|(item_line, _)| *item_line >= line)
+                        .unwrap_or(self.items.len()),
+                );
+            }
+            Statement::For {
+                variable,
+                from,
+                to,
+                step,
+            } => {
+                let Some(body) = node.next else {
+                    return Vec::new();
+                };
+
+                // Re-entering a FOR with the same variable discards its frame
+                // and every frame nested inside it
+                if let Some(index) = state.loops.iter().position(|l| l.variable == variable) {
+                    state.loops.truncate(index);
+                }
+
+                let step = step.as_ref().map_or(Some(1), Expression::as_constant);
+                let remaining = match (from.as_constant(), to.as_constant(), step) {
+                    (Some(from), Some(to), Some(step)) => trip_count(from, to, step),
+                    _ => None,
+                };
+
+                state.loops.push(Loop {
+                    variable,
+                    remaining,
+                    body,
+                    start: state.pointer,
+                });
+                state.node = body;
+                return vec![state];
+            }
+            Statement::Next { variable } => {
+                let Some(index) = state.loops.iter().rposition(|l| l.variable == variable) else {
+                    // Reported by the semantic checker
+                    return Vec::new();
+                };
+                state.loops.truncate(index + 1);
+                let frame = state.loops.pop().expect("frame was just found");
+
+                let mut exit = node.next.map(|next| State {
+                    node: next,
+                    ..state.clone()
+                });
+
+                return match frame.remaining {
+                    Some(remaining) if remaining > 1 => {
+                        state.node = frame.body;
+                        state.loops.push(Loop {
+                            remaining: Some(remaining - 1),
+                            ..frame
+                        });
+                        vec![state]
+                    }
+                    Some(_) => exit.into_iter().collect(),
+                    None => {
+                        if frame.start != state.pointer {
+                            state.pointer = None;
+                            if let Some(exit) = &mut exit {
+                                exit.pointer = None;
+                            }
+                        }
+                        state.node = frame.body;
+                        state.loops.push(frame);
+                        std::iter::once(state).chain(exit).collect()
+                    }
+                };
+            }
+            Statement::GoSub { line_number } => {
+                let (Some(target), Some(next)) =
+                    (self.flow.line_entry(line_number.value), node.next)
+                else {
+                    return Vec::new();
+                };
+                // Deeper recursion is left to StackAnalysis to report
+                if state.calls.len() > self.stack_limit {
+                    return Vec::new();
+                }
+
+                state.calls.push(next);
+                state.node = target;
+                return vec![state];
+            }
+            Statement::Return => {
+                return match state.calls.pop() {
+                    Some(next) => {
+                        state.node = next;
+                        vec![state]
+                    }
+                    None => Vec::new(),
+                };
+            }
+            _ => {}
+        }
+
+        self.flow
+            .successors(state.node)
+            .into_iter()
+            .map(|successor| State {
+                node: successor,
+                back: successor <= state.node,
+                ..state.clone()
+            })
+            .collect()
+    }
+
+    // Consumes one DATA item per variable. Returns false when execution stops
+    // with an error
+    fn read(&mut self, line: u32, variables: &[LValue], pointer: &mut Option<usize>) -> bool {
+        let Some(mut index) = *pointer else {
+            self.warn(format!(
+                "DATA pointer cannot be tracked at READ in line {}",
+                line
+            ));
+            return true;
+        };
+
+        for variable in variables {
+            let Some((item_line, item)) = self.items.get(index) else {
+                self.failure = Some(format!("Out of DATA at READ in line {}", line));
+                return false;
+            };
+
+            let name = match variable {
+                LValue::Variable(name) => name,
+                LValue::ArrayElement { variable, .. } => variable,
+            };
+            let variable_ty = if name.ends_with('$') {
+                Ty::String
+            } else {
+                Ty::Int
+            };
+            let item_ty = match item {
+                DataItem::Number(_) | DataItem::HexNumber(_) => Ty::Int,
+                DataItem::String(_) => Ty::String,
+            };
+
+            if variable_ty != item_ty {
+                self.failure = Some(format!(
+                    "READ type mismatch at line {}: variable {} is {}, DATA item at line {} is {}",
+                    line, variable, variable_ty, item_line, item_ty
+                ));
+                return false;
+            }
+
+            index += 1;
+        }
+
+        *pointer = Some(index);
+        true
+    }
+
+    fn error(&mut self, message: String) {
+        if !self.report.errors.contains(&message) {
+            self.report.errors.push(message);
+        }
+    }
+
+    fn warn(&mut self, message: String) {
+        if !self.report.warnings.contains(&message) {
+            self.report.warnings.push(message);
+        }
+    }
+}
+
+// Number of times a FOR body runs. The ROM only tests the bound at NEXT, so
+// the body always runs at least once
+fn trip_count(from: i32, to: i32, step: i32) -> Option<i64> {
+    let (from, to, step) = (i64::from(from), i64::from(to), i64::from(step));
+    match step {
+        0 => None,
+        step if step > 0 && to >= from => Some((to - from) / step + 1),
+        step if step < 0 && to <= from => Some((from - to) / -step + 1),
+        _ => Some(1),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Program;
+    use crate::test_support::parse;
+
+    fn analyze(program: &Program) -> DataReport {
+        let flow = ControlFlow::new(program);
+        DataAnalysis::new(&flow).analyze()
+    }
+
+    #[test]
+    fn read_loop_within_data() {
+        let program = parse("10 FOR I = 1 TO 3: READ A: NEXT I\n20 END\n30 DATA 1, 2, 3\n");
+
+        let report = analyze(&program);
+
+        assert!(report.errors.is_empty());
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn read_loop_out_of_data() {
+        let program = parse("10 FOR I = 1 TO 4: READ A: NEXT I\n20 END\n30 DATA 1, 2, 3\n");
+
+        let report = analyze(&program);
+
+        assert_eq!(report.errors, vec!["Out of DATA at READ in line 10"]);
+    }
+
+    #[test]
+    fn out_of_data_on_one_branch() {
+        // Only the paths through line 20 run out at line 30
+        let program = parse(concat!(
+            "10 INPUT X\n",
+            "20 IF X > 0 THEN READ A\n",
+            "30 READ B, C\n",
+            "40 END\n",
+            "50 DATA 1, 2\n",
+        ));
+
+        let report = analyze(&program);
+
+        assert!(report.errors.is_empty());
+        assert_eq!(
+            report.warnings,
+            vec!["Out of DATA at READ in line 30 on some paths"]
+        );
+    }
+
+    #[test]
+    fn out_of_data_on_the_only_branch_reading() {
+        // The other branch finishes without reaching the READ
+        let program = parse("10 INPUT X\n20 IF X > 0 THEN READ A, B\n30 END\n40 DATA 1\n");
+
+        let report = analyze(&program);
+
+        assert_eq!(report.errors, vec!["Out of DATA at READ in line 20"]);
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn out_of_data_on_both_branches() {
+        let program = parse(concat!(
+            "10 INPUT X\n",
+            "20 IF X > 0 THEN READ A, B: END\n",
+            "30 READ A, B, C\n",
+            "40 DATA 1\n",
+        ));
+
+        let report = analyze(&program);
+
+        assert_eq!(
+            report.errors,
+            vec![
+                "Out of DATA at READ in line 20",
+                "Out of DATA at READ in line 30",
+            ]
+        );
+    }
+
+    #[test]
+    fn type_mismatch() {
+        let program = parse("10 READ A\n20 READ B\n30 END\n40 DATA 1, \"X\"\n");
+
+        let report = analyze(&program);
+
+        assert_eq!(
+            report.errors,
+            vec!["READ type mismatch at line 20: variable B is INT, DATA item at line 40 is STR"]
+        );
+    }
+
+    #[test]
+    fn restore_rewinds_pointer() {
+        let program = parse(concat!(
+            "10 READ A$\n",
+            "20 RESTORE 60\n",
+            "30 READ B\n",
+            "40 END\n",
+            "50 DATA \"X\"\n",
+            "60 DATA 1\n",
+        ));
+
+        let report = analyze(&program);
+
+        assert!(report.errors.is_empty());
+    }
+
+    #[test]
+    fn unknown_loop_bound_falls_back_to_warning() {
+        let program = parse(concat!(
+            "10 FOR I = 1 TO N: READ A: NEXT I\n",
+            "20 READ B\n",
+            "30 END\n",
+            "40 DATA 1, 2, 3\n",
+        ));
+
+        let report = analyze(&program);
+
+        assert!(report.errors.is_empty());
+        assert!(report
+            .warnings
+            .contains(&"DATA pointer cannot be tracked at READ in line 10".to_owned()));
+        assert!(report
+            .warnings
+            .contains(&"DATA pointer cannot be tracked at READ in line 20".to_owned()));
+    }
+}
@@ -0,0 +1,643 @@
+// Tokenized program format of the SHARP PC-1500
+//
+// Every line is stored as the line number (2 bytes, big endian), the length of
+// the rest of the line (1 byte) and the line body terminated by 0x0D. Keywords
+// take 2 bytes; numbers, variables and operators are kept as ASCII text.
+
+use std::marker::PhantomData;
+
+use super::{
+    node::{Coordinate, DataItem, Function, LValue, PrintItem, Separator, UnaryOperator, Unsigned},
+    BinaryOperator, Expression, ExpressionVisitor, Program, ProgramVisitor, Statement,
+    StatementVisitor,
+};
+use crate::tokens::Hex;
+
+pub const END_OF_LINE: u8 = 0x0D;
+pub const END_OF_PROGRAM: u8 = 0xFF;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Keyword {
+    And,
+    Beep,
+    Call,
+    Cls,
+    Color,
+    CSize,
+    Cursor,
+    Data,
+    Dim,
+    End,
+    Erl,
+    Ern,
+    Error,
+    For,
+    GCursor,
+    GlCursor,
+    Gosub,
+    Goto,
+    GPrint,
+    Graph,
+    If,
+    Inkey,
+    Input,
+    Lf,
+    Line,
+    LPrint,
+    Next,
+    Not,
+    On,
+    Or,
+    Pause,
+    Point,
+    Poke,
+    Print,
+    Read,
+    Rem,
+    Restore,
+    Return,
+    RLine,
+    Rotate,
+    Sorgn,
+    Step,
+    Text,
+    Then,
+    To,
+    Troff,
+    Tron,
+    Using,
+    Wait,
+}
+
+impl Keyword {
+    pub fn code(self) -> u16 {
+        match self {
+            Keyword::And => 0xF150,
+            Keyword::Beep => 0xF182,
+            Keyword::Call => 0xF18A,
+            Keyword::Cls => 0xF088,
+            Keyword::Color => 0xF0B5,
+            Keyword::CSize => 0xE680,
+            Keyword::Cursor => 0xF084,
+            Keyword::Data => 0xF18D,
+            Keyword::Dim => 0xF18B,
+            Keyword::End => 0xF19E,
+            Keyword::Erl => 0xF053,
+            Keyword::Ern => 0xF052,
+            Keyword::Error => 0xF1B4,
+            Keyword::For => 0xF1A5,
+            Keyword::GCursor => 0xF093,
+            Keyword::GlCursor => 0xE682,
+            Keyword::Gosub => 0xF194,
+            Keyword::Goto => 0xF192,
+            Keyword::GPrint => 0xF09F,
+            Keyword::Graph => 0xE681,
+            Keyword::If => 0xF196,
+            Keyword::Inkey => 0xF15C,
+            Keyword::Input => 0xF091,
+            Keyword::Lf => 0xF0B6,
+            Keyword::Line => 0xF0B7,
+            Keyword::LPrint => 0xF0B9,
+            Keyword::Next => 0xF19A,
+            Keyword::Not => 0xF16D,
+            Keyword::On => 0xF19C,
+            Keyword::Or => 0xF151,
+            Keyword::Pause => 0xF1A2,
+            Keyword::Point => 0xF13D,
+            Keyword::Poke => 0xF1A1,
+            Keyword::Print => 0xF097,
+            Keyword::Read => 0xF1A6,
+            Keyword::Rem => 0xF1AB,
+            Keyword::Restore => 0xF1A7,
+            Keyword::Return => 0xF199,
+            Keyword::RLine => 0xF0BA,
+            Keyword::Rotate => 0xE685,
+            Keyword::Sorgn => 0xE684,
+            Keyword::Step => 0xF1AD,
+            Keyword::Text => 0xE686,
+            Keyword::Then => 0xF1AE,
+            Keyword::To => 0xF1B1,
+            Keyword::Troff => 0xF0B4,
+            Keyword::Tron => 0xF0B3,
+            Keyword::Using => 0xF085,
+            Keyword::Wait => 0xF1B3,
+        }
+    }
+}
+
+pub struct Encoder<'a> {
+    output: Vec<u8>,
+    _phantom: PhantomData<&'a ()>,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new() -> Self {
+        Encoder {
+            output: Vec::new(),
+            _phantom: PhantomData,
+        }
+    }
+
+    // Whole program area, including the end of program marker
+    pub fn build(mut self, program: &'a Program) -> Vec<u8> {
+        program.accept(&mut self);
+        self.output.push(END_OF_PROGRAM);
+        self.output
+    }
+
+    pub fn encode_line(
+        line_number: u32,
+        label: Option<&'a str>,
+        statement: &'a Statement,
+    ) -> Vec<u8> {
+        let mut body = Encoder::new();
+        if let Some(label) = label {
+            body.visit_string_literal(label);
+        }
+        statement.accept(&mut body);
+        body.output.push(END_OF_LINE);
+
+        // Line numbers go up to 65279 on the device, longer lines are rejected
+        // by the ROM editor
+        let mut line = Vec::with_capacity(body.output.len() + 3);
+        line.extend_from_slice(&(line_number as u16).to_be_bytes());
+        line.push(u8::try_from(body.output.len()).unwrap_or(u8::MAX));
+        line.extend(body.output);
+        line
+    }
+
+    fn keyword(&mut self, keyword: Keyword) {
+        self.output.extend_from_slice(&keyword.code().to_be_bytes());
+    }
+
+    fn text(&mut self, text: &str) {
+        self.output.extend(text.bytes());
+    }
+
+    fn list<T>(&mut self, items: &'a [T], mut encode: impl FnMut(&mut Self, &'a T)) {
+        for (i, item) in items.iter().enumerate() {
+            if i > 0 {
+                self.output.push(b',');
+            }
+            encode(self, item);
+        }
+    }
+
+    fn print_items(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
+        if let Some(format) = format {
+            self.keyword(Keyword::Using);
+            self.visit_string_literal(format);
+            if !content.is_empty() {
+                self.output.push(b';');
+            }
+        }
+        for item in content {
+            item.expression.accept(self);
+            match item.separator {
+                Some(Separator::Semicolon) => self.output.push(b';'),
+                Some(Separator::Comma) => self.output.push(b','),
+                None => {}
+            }
+        }
+    }
+
+    fn coordinate(&mut self, coordinate: &'a Coordinate) {
+        self.output.push(b'(');
+        coordinate.x.accept(self);
+        self.output.push(b',');
+        coordinate.y.accept(self);
+        self.output.push(b')');
+    }
+
+    fn parenthesized(&mut self, expression: &'a Expression, parens: bool) {
+        if parens {
+            self.output.push(b'(');
+        }
+        expression.accept(self);
+        if parens {
+            self.output.push(b')');
+        }
+    }
+
+    fn lvalue(&mut self, lvalue: &'a LValue) {
+        match lvalue {
+            LValue::Variable(name) => self.text(name),
+            LValue::ArrayElement { variable, index } => {
+                self.text(variable);
+                self.output.push(b'(');
+                index.accept(self);
+                self.output.push(b')');
+            }
+        }
+    }
+}
+
+impl<'a> ExpressionVisitor<'a> for Encoder<'a> {
+    fn visit_number_literal(&mut self, num: i32) {
+        self.text(&num.to_string());
+    }
+
+    fn visit_hex_literal(&mut self, num: i32) {
+        self.text(&Hex(num).to_string());
+    }
+
+    fn visit_string_literal(&mut self, content: &'a str) {
+        self.output.push(b'"');
+        self.text(content);
+        self.output.push(b'"');
+    }
+
+    fn visit_variable(&mut self, lvalue: &'a LValue) {
+        self.lvalue(lvalue);
+    }
+
+    fn visit_unary_op(&mut self, op: UnaryOperator, operand: &'a Expression) {
+        match op {
+            UnaryOperator::Plus => self.output.push(b'+'),
+            UnaryOperator::Minus => self.output.push(b'-'),
+            UnaryOperator::Not => self.keyword(Keyword::Not),
+        }
+        operand.accept(self);
+    }
+
+    fn visit_binary_op(&mut self, left: &'a Expression, op: BinaryOperator, right: &'a Expression) {
+        // The printer always parenthesizes, but the stored form only needs it
+        // when an operand would otherwise bind differently
+        let left_parens = precedence_of(left).is_some_and(|p| p < precedence(op));
+        let right_parens = precedence_of(right).is_some_and(|p| p <= precedence(op));
+
+        self.parenthesized(left, left_parens);
+        match op {
+            BinaryOperator::And => self.keyword(Keyword::And),
+            BinaryOperator::Or => self.keyword(Keyword::Or),
+            _ => self.text(&op.to_string()),
+        }
+        self.parenthesized(right, right_parens);
+    }
+
+    fn visit_function(&mut self, function: Function, args: &'a [Expression]) {
+        match function {
+            Function::Point => self.keyword(Keyword::Point),
+            Function::Inkey => self.keyword(Keyword::Inkey),
+            Function::Ern => self.keyword(Keyword::Ern),
+            Function::Erl => self.keyword(Keyword::Erl),
+        }
+        if !function.parenthesized() {
+            return;
+        }
+        self.output.push(b'(');
+        self.list(args, |encoder, arg| arg.accept(encoder));
+        self.output.push(b')');
+    }
+}
+
+fn precedence(op: BinaryOperator) -> u8 {
+    match op {
+        BinaryOperator::And | BinaryOperator::Or => 0,
+        BinaryOperator::Eq
+        | BinaryOperator::Ne
+        | BinaryOperator::Lt
+        | BinaryOperator::Le
+        | BinaryOperator::Gt
+        | BinaryOperator::Ge => 1,
+        BinaryOperator::Add | BinaryOperator::Sub => 2,
+        BinaryOperator::Mul | BinaryOperator::Div => 3,
+    }
+}
+
+fn precedence_of(expression: &Expression) -> Option<u8> {
+    match expression {
+        Expression::Binary { op, .. } => Some(precedence(*op)),
+        _ => None,
+    }
+}
+
+impl<'a> StatementVisitor<'a> for Encoder<'a> {
+    fn visit_let(&mut self, variable: &'a LValue, expression: &'a Expression) {
+        // LET is optional and the ROM does not store it unless typed
+        self.lvalue(variable);
+        self.output.push(b'=');
+        expression.accept(self);
+    }
+
+    fn visit_print(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
+        self.keyword(Keyword::Print);
+        self.print_items(format, content);
+    }
+
+    fn visit_pause(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
+        self.keyword(Keyword::Pause);
+        self.print_items(format, content);
+    }
+
+    fn visit_input(&mut self, prompt: Option<&'a Expression>, variable: &'a LValue) {
+        self.keyword(Keyword::Input);
+        if let Some(prompt) = prompt {
+            prompt.accept(self);
+            self.output.push(b';');
+        }
+        self.lvalue(variable);
+    }
+
+    fn visit_wait(&mut self, time: Option<&'a Expression>) {
+        self.keyword(Keyword::Wait);
+        if let Some(time) = time {
+            time.accept(self);
+        }
+    }
+
+    fn visit_read(&mut self, variables: &'a [LValue]) {
+        self.keyword(Keyword::Read);
+        self.list(variables, Self::lvalue);
+    }
+
+    fn visit_data(&mut self, values: &'a [DataItem]) {
+        self.keyword(Keyword::Data);
+        self.list(values, |encoder, value| match value {
+            DataItem::Number(num) => encoder.visit_number_literal(*num),
+            DataItem::HexNumber(num) => encoder.visit_hex_literal(*num),
+            DataItem::String(string) => encoder.visit_string_literal(string),
+        });
+    }
+
+    fn visit_restore(&mut self, line_number: Option<Unsigned>) {
+        self.keyword(Keyword::Restore);
+        if let Some(line_number) = line_number {
+            self.text(&line_number.to_string());
+        }
+    }
+
+    fn visit_poke(&mut self, address: &'a Expression, values: &'a [Expression]) {
+        self.keyword(Keyword::Poke);
+        address.accept(self);
+        self.output.push(b',');
+        self.list(values, |encoder, value| value.accept(encoder));
+    }
+
+    fn visit_call(&mut self, address: &'a Expression, argument: Option<&'a Expression>) {
+        self.keyword(Keyword::Call);
+        address.accept(self);
+        if let Some(argument) = argument {
+            self.output.push(b',');
+            argument.accept(self);
+        }
+    }
+
+    fn visit_goto(&mut self, line_number: Unsigned) {
+        self.keyword(Keyword::Goto);
+        self.text(&line_number.to_string());
+    }
+
+    fn visit_on_error(&mut self, line_number: Unsigned) {
+        self.keyword(Keyword::On);
+        self.keyword(Keyword::Error);
+        self.visit_goto(line_number);
+    }
+
+    fn visit_for(
+        &mut self,
+        variable: &'a str,
+        from: &'a Expression,
+        to: &'a Expression,
+        step: Option<&'a Expression>,
+    ) {
+        self.keyword(Keyword::For);
+        self.text(variable);
+        self.output.push(b'=');
+        from.accept(self);
+        self.keyword(Keyword::To);
+        to.accept(self);
+        if let Some(step) = step {
+            self.keyword(Keyword::Step);
+            step.accept(self);
+        }
+    }
+
+    fn visit_next(&mut self, variable: &'a str) {
+        self.keyword(Keyword::Next);
+        self.text(variable);
+    }
+
+    fn visit_end(&mut self) {
+        self.keyword(Keyword::End);
+    }
+
+    fn visit_gosub(&mut self, line_number: Unsigned) {
+        self.keyword(Keyword::Gosub);
+        self.text(&line_number.to_string());
+    }
+
+    fn visit_return(&mut self) {
+        self.keyword(Keyword::Return);
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &'a Expression,
+        then: &'a Statement,
+        else_: Option<&'a Statement>,
+    ) {
+        self.keyword(Keyword::If);
+        condition.accept(self);
+        self.keyword(Keyword::Then);
+        then.accept(self);
+        if let Some(else_) = else_ {
+            // ELSE has no token on the PC-1500, so it is kept as text
+            self.text("ELSE");
+            else_.accept(self);
+        }
+    }
+
+    fn visit_seq(&mut self, statements: &'a [Statement]) {
+        for (i, statement) in statements.iter().enumerate() {
+            if i > 0 {
+                self.output.push(b':');
+            }
+            statement.accept(self);
+        }
+    }
+
+    fn visit_rem(&mut self, content: &'a str) {
+        self.keyword(Keyword::Rem);
+        self.text(content);
+    }
+
+    fn visit_dim(&mut self, variable: &'a str, size: Unsigned, length: Option<Unsigned>) {
+        self.keyword(Keyword::Dim);
+        self.text(variable);
+        self.output.push(b'(');
+        self.text(&size.to_string());
+        self.output.push(b')');
+        if let Some(length) = length {
+            self.output.push(b'*');
+            self.text(&length.to_string());
+        }
+    }
+
+    fn visit_cls(&mut self) {
+        self.keyword(Keyword::Cls);
+    }
+
+    fn visit_cursor(&mut self, column: &'a Expression) {
+        self.keyword(Keyword::Cursor);
+        column.accept(self);
+    }
+
+    fn visit_gcursor(&mut self, column: &'a Expression) {
+        self.keyword(Keyword::GCursor);
+        column.accept(self);
+    }
+
+    fn visit_gprint(&mut self, content: &'a [Expression]) {
+        self.keyword(Keyword::GPrint);
+        for (i, item) in content.iter().enumerate() {
+            if i > 0 {
+                self.output.push(b';');
+            }
+            item.accept(self);
+        }
+    }
+
+    fn visit_beep(
+        &mut self,
+        count: &'a Expression,
+        frequency: Option<&'a Expression>,
+        duration: Option<&'a Expression>,
+    ) {
+        self.keyword(Keyword::Beep);
+        count.accept(self);
+        for arg in frequency.into_iter().chain(duration) {
+            self.output.push(b',');
+            arg.accept(self);
+        }
+    }
+
+    fn visit_lprint(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
+        self.keyword(Keyword::LPrint);
+        self.print_items(format, content);
+    }
+
+    fn visit_line(
+        &mut self,
+        relative: bool,
+        start: Option<&'a Coordinate>,
+        points: &'a [Coordinate],
+        line_type: Option<&'a Expression>,
+        color: Option<&'a Expression>,
+        boxed: bool,
+    ) {
+        self.keyword(if relative {
+            Keyword::RLine
+        } else {
+            Keyword::Line
+        });
+        if let Some(start) = start {
+            self.coordinate(start);
+        }
+        for point in points {
+            self.output.push(b'-');
+            self.coordinate(point);
+        }
+        for arg in line_type.into_iter().chain(color) {
+            self.output.push(b',');
+            arg.accept(self);
+        }
+        if boxed {
+            self.text(",B");
+        }
+    }
+
+    fn visit_glcursor(&mut self, position: &'a Coordinate) {
+        self.keyword(Keyword::GlCursor);
+        self.coordinate(position);
+    }
+
+    fn visit_color(&mut self, color: &'a Expression) {
+        self.keyword(Keyword::Color);
+        color.accept(self);
+    }
+
+    fn visit_csize(&mut self, size: &'a Expression) {
+        self.keyword(Keyword::CSize);
+        size.accept(self);
+    }
+
+    fn visit_rotate(&mut self, direction: &'a Expression) {
+        self.keyword(Keyword::Rotate);
+        direction.accept(self);
+    }
+
+    fn visit_graph(&mut self) {
+        self.keyword(Keyword::Graph);
+    }
+
+    fn visit_text(&mut self) {
+        self.keyword(Keyword::Text);
+    }
+
+    fn visit_sorgn(&mut self) {
+        self.keyword(Keyword::Sorgn);
+    }
+
+    fn visit_lf(&mut self, lines: &'a Expression) {
+        self.keyword(Keyword::Lf);
+        lines.accept(self);
+    }
+
+    fn visit_tron(&mut self) {
+        self.keyword(Keyword::Tron);
+    }
+
+    fn visit_troff(&mut self) {
+        self.keyword(Keyword::Troff);
+    }
+}
+
+impl<'a> ProgramVisitor<'a> for Encoder<'a> {
+    fn visit_program(&mut self, program: &'a Program) {
+        for (line_number, statement) in program.iter() {
+            self.output.extend(Encoder::encode_line(
+                *line_number,
+                program.label(*line_number),
+                statement,
+            ));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encode_goto_line() {
+        let line = Encoder::encode_line(
+            10,
+            None,
+            &Statement::Goto {
+                line_number: 100.into(),
+            },
+        );
+
+        assert_eq!(
+            line,
+            vec![0x00, 0x0A, 0x06, 0xF1, 0x92, b'1', b'0', b'0', 0x0D]
+        );
+    }
+
+    #[test]
+    fn encode_let_without_keyword() {
+        let statement = Statement::Let {
+            variable: LValue::Variable("A".to_owned()),
+            expression: Expression::Binary {
+                left: Box::new(Expression::Number(1)),
+                op: BinaryOperator::Add,
+                right: Box::new(Expression::Number(2)),
+            },
+        };
+
+        let line = Encoder::encode_line(5, None, &statement);
+
+        assert_eq!(&line[3..], b"A=1+2\r");
+    }
+}
@@ -1,9 +1,14 @@
+use crate::tokens::LexError;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum ErrorKind {
+    // Text that is not a token
+    Lexical(LexError),
     // Parse errors
     ExpectedDataItem,
     ExpectedEndOfLine,
     ExpectedExpression,
+    ExpectedFormat,
     ExpectedIdentifier,
     ExpectedLeftParen,
     ExpectedLineNumber,
@@ -24,9 +29,11 @@ impl std::fmt::Display for Error {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "Error at line {}: ", self.line)?;
         match self.kind {
+            ErrorKind::Lexical(error) => write!(f, "{}", error),
             ErrorKind::ExpectedDataItem => write!(f, "Expected data item"),
             ErrorKind::ExpectedEndOfLine => write!(f, "Expected end of line"),
             ErrorKind::ExpectedExpression => write!(f, "Expected expression"),
+            ErrorKind::ExpectedFormat => write!(f, "Expected USING format string"),
             ErrorKind::ExpectedIdentifier => write!(f, "Expected identifier"),
             ErrorKind::ExpectedLineNumber => write!(f, "Expected line number"),
             ErrorKind::ExpectedStatement => write!(f, "Expected statement"),
@@ -0,0 +1,134 @@
+use std::collections::BTreeMap;
+
+use super::{Program, Statement};
+
+// A single atomic statement (never a SEQ) of the program, laid out in execution order
+#[derive(Debug)]
+pub struct Node<'a> {
+    pub line: u32,
+    pub statement: &'a Statement,
+    // Where execution continues once the statement completes. For an IF this is
+    // the ELSE branch, or the next line when there is none
+    pub next: Option<usize>,
+    // First statement of the THEN branch, only set for IF
+    pub then: Option<usize>,
+}
+
+// Control-flow graph of a program. Nodes are stored in program order, so the
+// first node of a line is always followed by the rest of that line.
+//
+// NEXT has no edge back to its FOR: whether the loop repeats is only known at
+// runtime, and the loop body is reachable through the FOR anyway.
+#[derive(Debug)]
+pub struct ControlFlow<'a> {
+    nodes: Vec<Node<'a>>,
+    lines: BTreeMap<u32, usize>,
+}
+
+impl<'a> ControlFlow<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        let mut flow = ControlFlow {
+            nodes: Vec::new(),
+            lines: BTreeMap::new(),
+        };
+
+        let mut pending: Vec<usize> = Vec::new();
+        for (line, statement) in program.iter() {
+            let (entry, exits) = flow.lower(*line, statement);
+            for id in pending {
+                flow.nodes[id].next = Some(entry);
+            }
+            flow.lines.insert(*line, entry);
+            pending = exits;
+        }
+
+        flow
+    }
+
+    // Returns the entry node of the statement and the nodes that fall through to
+    // whatever follows it
+    fn lower(&mut self, line: u32, statement: &'a Statement) -> (usize, Vec<usize>) {
+        match statement {
+            Statement::Seq { statements } => {
+                let mut entry = None;
+                let mut pending: Vec<usize> = Vec::new();
+                for child in statements {
+                    let (first, exits) = self.lower(line, child);
+                    for id in pending {
+                        self.nodes[id].next = Some(first);
+                    }
+                    entry.get_or_insert(first);
+                    pending = exits;
+                }
+
+                match entry {
+                    Some(entry) => (entry, pending),
+                    // An empty line falls through to the next one
+                    None => {
+                        let id = self.push(line, statement);
+                        (id, vec![id])
+                    }
+                }
+            }
+            Statement::If { then, else_, .. } => {
+                let id = self.push(line, statement);
+                let (then_entry, mut exits) = self.lower(line, then);
+                self.nodes[id].then = Some(then_entry);
+
+                if let Some(else_) = else_ {
+                    let (else_entry, else_exits) = self.lower(line, else_);
+                    self.nodes[id].next = Some(else_entry);
+                    exits.extend(else_exits);
+                } else {
+                    exits.push(id);
+                }
+
+                (id, exits)
+            }
+            _ => {
+                let id = self.push(line, statement);
+                (id, vec![id])
+            }
+        }
+    }
+
+    fn push(&mut self, line: u32, statement: &'a Statement) -> usize {
+        self.nodes.push(Node {
+            line,
+            statement,
+            next: None,
+            then: None,
+        });
+        self.nodes.len() - 1
+    }
+
+    pub fn entry(&self) -> Option<usize> {
+        self.lines.values().next().copied()
+    }
+
+    pub fn line_entry(&self, line: u32) -> Option<usize> {
+        self.lines.get(&line).copied()
+    }
+
+    pub fn node(&self, id: usize) -> &Node<'a> {
+        &self.nodes[id]
+    }
+
+    pub fn nodes(&self) -> &[Node<'a>] {
+        &self.nodes
+    }
+
+    // Successors within the current subroutine: a GOSUB continues with the
+    // statement after it, and RETURN or END leave the graph
+    pub fn successors(&self, id: usize) -> Vec<usize> {
+        let node = &self.nodes[id];
+        match node.statement {
+            Statement::Goto { line_number } => {
+                self.line_entry(line_number.value).into_iter().collect()
+            }
+            Statement::End | Statement::Return => Vec::new(),
+            Statement::If { .. } => node.then.into_iter().chain(node.next).collect(),
+            _ => node.next.into_iter().collect(),
+        }
+    }
+}
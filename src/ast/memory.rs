@@ -0,0 +1,504 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use super::{
+    encoder::Encoder,
+    flow::ControlFlow,
+    node::{Coordinate, DataItem, Function, LValue, PrintItem, UnaryOperator, Unsigned},
+    stack::{Depth, StackAnalysis, DEFAULT_STACK_LIMIT},
+    BinaryOperator, Expression, ExpressionVisitor, Program, ProgramVisitor, Statement,
+    StatementVisitor,
+};
+use crate::machine::MemoryConfig;
+
+// Numbers are 8 byte BCD values, both in variables and in array cells
+pub const NUMBER_BYTES: usize = 8;
+// Strings without a declared length hold up to 16 characters
+pub const DEFAULT_STRING_LENGTH: usize = 16;
+// Name and type of a variable created in the variable area
+pub const VARIABLE_HEADER_BYTES: usize = 2;
+// Name, type, dimensions and cell size of an array
+pub const ARRAY_HEADER_BYTES: usize = 7;
+// Return address and frame marker
+pub const GOSUB_FRAME_BYTES: usize = 3;
+// Loop variable address, limit, step and loop start address
+pub const FOR_FRAME_BYTES: usize = 2 + NUMBER_BYTES + NUMBER_BYTES + 2;
+
+// How many of the largest lines and arrays are listed
+const LARGEST_ITEMS: usize = 5;
+
+#[derive(Debug)]
+pub struct Array {
+    pub name: String,
+    pub size: u32,
+    pub length: Option<u32>,
+    pub bytes: usize,
+}
+
+#[derive(Debug)]
+pub struct MemoryReport {
+    pub config: MemoryConfig,
+    // Tokenized size of every line
+    pub lines: Vec<(u32, usize)>,
+    // Program area, including the end of program marker
+    pub program_bytes: usize,
+    // Variables outside of the fixed A-Z area
+    pub variables: Vec<String>,
+    pub variable_bytes: usize,
+    pub arrays: Vec<Array>,
+    pub array_bytes: usize,
+    // None when recursion makes the stack usage unbounded
+    pub stack_depth: Option<Depth>,
+    pub stack_bytes: usize,
+}
+
+impl MemoryReport {
+    pub fn total(&self) -> usize {
+        self.program_bytes + self.variable_bytes + self.array_bytes + self.stack_bytes
+    }
+
+    pub fn fits(&self) -> bool {
+        self.stack_depth.is_some() && self.total() <= self.config.user_bytes()
+    }
+
+    // The program is stored from the start of user RAM
+    pub fn program_area(&self) -> Range<u32> {
+        let start = self.config.user_area().start;
+        start..start.saturating_add(self.program_bytes as u32)
+    }
+
+    // Variables are allocated downwards from the end of user RAM
+    pub fn variable_area(&self) -> Range<u32> {
+        let end = self.config.user_area().end;
+        end.saturating_sub((self.variable_bytes + self.array_bytes) as u32)..end
+    }
+}
+
+impl std::fmt::Display for MemoryReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Program area: {} bytes ({} lines)",
+            self.program_bytes,
+            self.lines.len()
+        )?;
+        writeln!(
+            f,
+            "Variables: {} bytes ({} variables)",
+            self.variable_bytes,
+            self.variables.len()
+        )?;
+        writeln!(
+            f,
+            "Arrays: {} bytes ({} arrays)",
+            self.array_bytes,
+            self.arrays.len()
+        )?;
+        match self.stack_depth {
+            Some(depth) => writeln!(f, "Stack: {} bytes, depth {}", self.stack_bytes, depth)?,
+            None => writeln!(f, "Stack: unbounded (recursive GOSUB)")?,
+        }
+
+        let available = self.config.user_bytes();
+        writeln!(
+            f,
+            "Total: {} of {} bytes available on {}",
+            self.total(),
+            available,
+            self.config
+        )?;
+        if self.fits() {
+            writeln!(f, "Free: {} bytes", available - self.total())?;
+        } else if self.stack_depth.is_some() {
+            writeln!(
+                f,
+                "Warning: program needs {} bytes more than available",
+                self.total() - available
+            )?;
+        } else {
+            writeln!(f, "Warning: stack usage cannot be bounded")?;
+        }
+
+        let mut lines = self.lines.clone();
+        lines.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        writeln!(f)?;
+        writeln!(f, "Largest lines:")?;
+        for (line, bytes) in lines.iter().take(LARGEST_ITEMS) {
+            writeln!(f, "  {:>5}: {} bytes", line, bytes)?;
+        }
+
+        if !self.arrays.is_empty() {
+            let mut arrays: Vec<&Array> = self.arrays.iter().collect();
+            arrays.sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.name.cmp(&b.name)));
+            writeln!(f)?;
+            writeln!(f, "Largest arrays:")?;
+            for array in arrays.iter().take(LARGEST_ITEMS) {
+                write!(f, "  {}({})", array.name, array.size)?;
+                if let Some(length) = array.length {
+                    write!(f, "*{}", length)?;
+                }
+                writeln!(f, ": {} bytes", array.bytes)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+// Estimates how much of the user area a program needs on the device
+pub struct MemoryEstimator<'a> {
+    program: &'a Program,
+    variables: BTreeSet<&'a str>,
+    arrays: BTreeMap<&'a str, (u32, Option<u32>)>,
+    stack_limit: usize,
+}
+
+impl<'a> MemoryEstimator<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        MemoryEstimator {
+            program,
+            variables: BTreeSet::new(),
+            arrays: BTreeMap::new(),
+            stack_limit: DEFAULT_STACK_LIMIT,
+        }
+    }
+
+    // GOSUB and FOR frames the ROM's stack holds
+    pub fn set_stack_limit(&mut self, limit: usize) {
+        self.stack_limit = limit;
+    }
+
+    pub fn estimate(mut self, config: MemoryConfig) -> MemoryReport {
+        self.program.accept(&mut self);
+
+        let lines: Vec<(u32, usize)> = self
+            .program
+            .iter()
+            .map(|(line, statement)| {
+                let label = self.program.label(*line);
+                (*line, Encoder::encode_line(*line, label, statement).len())
+            })
+            .collect();
+        let program_bytes = Encoder::new().build(self.program).len();
+
+        // Arrays live in their own storage, even when named like a variable
+        let variables: Vec<String> = self
+            .variables
+            .iter()
+            .filter(|name| !is_fixed(name) && !self.arrays.contains_key(*name))
+            .map(|name| (*name).to_owned())
+            .collect();
+        let variable_bytes = variables
+            .iter()
+            .map(|name| {
+                VARIABLE_HEADER_BYTES
+                    + if name.ends_with('$') {
+                        DEFAULT_STRING_LENGTH
+                    } else {
+                        NUMBER_BYTES
+                    }
+            })
+            .sum();
+
+        let arrays: Vec<Array> = self
+            .arrays
+            .iter()
+            .map(|(name, (size, length))| {
+                let cell = if name.ends_with('$') {
+                    length.map_or(DEFAULT_STRING_LENGTH, |length| length as usize)
+                } else {
+                    NUMBER_BYTES
+                };
+                Array {
+                    name: (*name).to_owned(),
+                    size: *size,
+                    length: *length,
+                    bytes: ARRAY_HEADER_BYTES + (*size as usize + 1) * cell,
+                }
+            })
+            .collect();
+        let array_bytes = arrays.iter().map(|array| array.bytes).sum();
+
+        let flow = ControlFlow::new(self.program);
+        let mut stack = StackAnalysis::new(&flow);
+        stack.set_limit(self.stack_limit);
+        let stack_depth = stack.analyze().depth;
+        let stack_bytes = stack_depth.map_or(0, |depth| {
+            depth.gosub * GOSUB_FRAME_BYTES + depth.for_ * FOR_FRAME_BYTES
+        });
+
+        MemoryReport {
+            config,
+            lines,
+            program_bytes,
+            variables,
+            variable_bytes,
+            arrays,
+            array_bytes,
+            stack_depth,
+            stack_bytes,
+        }
+    }
+
+    fn lvalue(&mut self, lvalue: &'a LValue) {
+        match lvalue {
+            LValue::Variable(name) => {
+                self.variables.insert(name);
+            }
+            LValue::ArrayElement { variable, index } => {
+                // Arrays used without DIM get the implicit 10 elements
+                self.arrays.entry(variable).or_insert((10, None));
+                index.accept(self);
+            }
+        }
+    }
+}
+
+// A-Z and A$-Z$ have dedicated storage outside of the user area
+fn is_fixed(name: &str) -> bool {
+    let name = name.strip_suffix('$').unwrap_or(name);
+    name.len() == 1
+}
+
+impl<'a> ExpressionVisitor<'a> for MemoryEstimator<'a> {
+    fn visit_number_literal(&mut self, _: i32) {}
+
+    fn visit_string_literal(&mut self, _: &'a str) {}
+
+    fn visit_variable(&mut self, lvalue: &'a LValue) {
+        self.lvalue(lvalue);
+    }
+
+    fn visit_unary_op(&mut self, _: UnaryOperator, operand: &'a Expression) {
+        operand.accept(self);
+    }
+
+    fn visit_binary_op(&mut self, left: &'a Expression, _: BinaryOperator, right: &'a Expression) {
+        left.accept(self);
+        right.accept(self);
+    }
+
+    fn visit_function(&mut self, _: Function, args: &'a [Expression]) {
+        for arg in args {
+            arg.accept(self);
+        }
+    }
+}
+
+impl<'a> StatementVisitor<'a> for MemoryEstimator<'a> {
+    fn visit_let(&mut self, variable: &'a LValue, expression: &'a Expression) {
+        self.lvalue(variable);
+        expression.accept(self);
+    }
+
+    fn visit_print(&mut self, _: Option<&'a str>, content: &'a [PrintItem]) {
+        for item in content {
+            item.expression.accept(self);
+        }
+    }
+
+    fn visit_pause(&mut self, _: Option<&'a str>, content: &'a [PrintItem]) {
+        for item in content {
+            item.expression.accept(self);
+        }
+    }
+
+    fn visit_input(&mut self, prompt: Option<&'a Expression>, variable: &'a LValue) {
+        if let Some(prompt) = prompt {
+            prompt.accept(self);
+        }
+        self.lvalue(variable);
+    }
+
+    fn visit_wait(&mut self, time: Option<&'a Expression>) {
+        if let Some(time) = time {
+            time.accept(self);
+        }
+    }
+
+    fn visit_read(&mut self, variables: &'a [LValue]) {
+        for variable in variables {
+            self.lvalue(variable);
+        }
+    }
+
+    fn visit_data(&mut self, _: &'a [DataItem]) {}
+
+    fn visit_restore(&mut self, _: Option<Unsigned>) {}
+
+    fn visit_poke(&mut self, address: &'a Expression, values: &'a [Expression]) {
+        address.accept(self);
+        for value in values {
+            value.accept(self);
+        }
+    }
+
+    fn visit_call(&mut self, address: &'a Expression, argument: Option<&'a Expression>) {
+        address.accept(self);
+        if let Some(argument) = argument {
+            argument.accept(self);
+        }
+    }
+
+    fn visit_goto(&mut self, _: Unsigned) {}
+
+    fn visit_on_error(&mut self, _: Unsigned) {}
+
+    fn visit_for(
+        &mut self,
+        variable: &'a str,
+        from: &'a Expression,
+        to: &'a Expression,
+        step: Option<&'a Expression>,
+    ) {
+        self.variables.insert(variable);
+        from.accept(self);
+        to.accept(self);
+        if let Some(step) = step {
+            step.accept(self);
+        }
+    }
+
+    fn visit_next(&mut self, _: &'a str) {}
+
+    fn visit_end(&mut self) {}
+
+    fn visit_gosub(&mut self, _: Unsigned) {}
+
+    fn visit_return(&mut self) {}
+
+    fn visit_if(
+        &mut self,
+        condition: &'a Expression,
+        then: &'a Statement,
+        else_: Option<&'a Statement>,
+    ) {
+        condition.accept(self);
+        then.accept(self);
+        if let Some(else_) = else_ {
+            else_.accept(self);
+        }
+    }
+
+    fn visit_seq(&mut self, statements: &'a [Statement]) {
+        for statement in statements {
+            statement.accept(self);
+        }
+    }
+
+    fn visit_rem(&mut self, _: &'a str) {}
+
+    fn visit_dim(&mut self, variable: &'a str, size: Unsigned, length: Option<Unsigned>) {
+        self.arrays
+            .insert(variable, (size.value, length.map(|length| length.value)));
+    }
+
+    fn visit_cls(&mut self) {}
+
+    fn visit_cursor(&mut self, column: &'a Expression) {
+        column.accept(self);
+    }
+
+    fn visit_gcursor(&mut self, column: &'a Expression) {
+        column.accept(self);
+    }
+
+    fn visit_gprint(&mut self, content: &'a [Expression]) {
+        for item in content {
+            item.accept(self);
+        }
+    }
+
+    fn visit_beep(
+        &mut self,
+        count: &'a Expression,
+        frequency: Option<&'a Expression>,
+        duration: Option<&'a Expression>,
+    ) {
+        count.accept(self);
+        for arg in frequency.into_iter().chain(duration) {
+            arg.accept(self);
+        }
+    }
+
+    fn visit_lprint(&mut self, _: Option<&'a str>, content: &'a [PrintItem]) {
+        for item in content {
+            item.expression.accept(self);
+        }
+    }
+
+    fn visit_line(
+        &mut self,
+        _: bool,
+        start: Option<&'a Coordinate>,
+        points: &'a [Coordinate],
+        line_type: Option<&'a Expression>,
+        color: Option<&'a Expression>,
+        _: bool,
+    ) {
+        for point in start.into_iter().chain(points) {
+            self.visit_glcursor(point);
+        }
+        for arg in line_type.into_iter().chain(color) {
+            arg.accept(self);
+        }
+    }
+
+    fn visit_glcursor(&mut self, position: &'a Coordinate) {
+        position.x.accept(self);
+        position.y.accept(self);
+    }
+
+    fn visit_color(&mut self, color: &'a Expression) {
+        color.accept(self);
+    }
+
+    fn visit_csize(&mut self, size: &'a Expression) {
+        size.accept(self);
+    }
+
+    fn visit_rotate(&mut self, direction: &'a Expression) {
+        direction.accept(self);
+    }
+
+    fn visit_graph(&mut self) {}
+
+    fn visit_text(&mut self) {}
+
+    fn visit_sorgn(&mut self) {}
+
+    fn visit_lf(&mut self, lines: &'a Expression) {
+        lines.accept(self);
+    }
+
+    fn visit_tron(&mut self) {}
+
+    fn visit_troff(&mut self) {}
+}
+
+impl<'a> ProgramVisitor<'a> for MemoryEstimator<'a> {
+    fn visit_program(&mut self, program: &'a Program) {
+        for statement in program.values() {
+            statement.accept(self);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::parse;
+
+    #[test]
+    fn variables_and_arrays() {
+        let program = parse("10 DIM N$(9)*20\n20 AB = C\n");
+
+        let report = MemoryEstimator::new(&program).estimate(MemoryConfig::Pc1500);
+
+        // Only AB lives in the variable area, C is a fixed variable
+        assert_eq!(report.variables, vec!["AB"]);
+        assert_eq!(report.variable_bytes, VARIABLE_HEADER_BYTES + NUMBER_BYTES);
+        assert_eq!(report.array_bytes, ARRAY_HEADER_BYTES + 10 * 20);
+        assert!(report.fits());
+    }
+}
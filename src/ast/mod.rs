@@ -1,13 +1,26 @@
+mod cycles;
+mod data;
+mod encoder;
 mod error;
+mod flow;
+mod memory;
 mod node;
 mod parser;
 mod printer;
 mod semantics;
+mod stack;
 mod visitor;
 
+pub use cycles::CycleEstimator;
 pub use error::Error;
-pub use node::{BinaryOperator, Expression, Program, Statement, UnaryOperator};
-pub use parser::Parser;
+pub use flow::ControlFlow;
+pub use memory::MemoryEstimator;
+pub use node::{
+    BinaryOperator, Coordinate, DataItem, Expression, Function, LValue, PrintItem, Program,
+    Separator, Statement, UnaryOperator, Unsigned,
+};
+pub use parser::{ExpressionParser, Parser};
 pub use printer::Printer;
 pub use semantics::SemanticChecker;
+pub use stack::DEFAULT_STACK_LIMIT;
 pub use visitor::{ExpressionVisitor, ProgramVisitor, StatementVisitor};
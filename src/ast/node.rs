@@ -1,5 +1,7 @@
 use std::collections::BTreeMap;
 
+use crate::tokens::Hex;
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum BinaryOperator {
     // Arithmetic
@@ -58,6 +60,36 @@ impl std::fmt::Display for UnaryOperator {
     }
 }
 
+// Built-in functions with parenthesized arguments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Function {
+    // Dot pattern of an LCD column
+    Point,
+    // Key pressed since the last call
+    Inkey,
+    // Code and line of the last error trapped by ON ERROR GOTO
+    Ern,
+    Erl,
+}
+
+impl Function {
+    // INKEY$, ERN and ERL are written without parentheses
+    pub fn parenthesized(self) -> bool {
+        self == Function::Point
+    }
+}
+
+impl std::fmt::Display for Function {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Function::Point => write!(f, "POINT"),
+            Function::Inkey => write!(f, "INKEY$"),
+            Function::Ern => write!(f, "ERN"),
+            Function::Erl => write!(f, "ERL"),
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum LValue {
     Variable(String),
@@ -79,6 +111,7 @@ impl std::fmt::Display for LValue {
 #[derive(PartialEq, Eq, Hash, Debug, Clone)]
 pub enum Expression {
     Number(i32),
+    HexNumber(i32),
     String(String),
     LValue(LValue),
     Unary {
@@ -90,6 +123,28 @@ pub enum Expression {
         op: BinaryOperator,
         right: Box<Expression>,
     },
+    Function {
+        function: Function,
+        args: Vec<Expression>,
+    },
+}
+
+impl Expression {
+    // Value of a literal number, optionally signed
+    pub fn as_constant(&self) -> Option<i32> {
+        match self {
+            Expression::Number(value) | Expression::HexNumber(value) => Some(*value),
+            Expression::Unary {
+                op: UnaryOperator::Plus,
+                operand,
+            } => operand.as_constant(),
+            Expression::Unary {
+                op: UnaryOperator::Minus,
+                operand,
+            } => operand.as_constant().and_then(i32::checked_neg),
+            _ => None,
+        }
+    }
 }
 
 impl std::fmt::Display for Expression {
@@ -97,9 +152,47 @@ impl std::fmt::Display for Expression {
         match self {
             Expression::String(content) => write!(f, "\"{}\"", content),
             Expression::Number(value) => write!(f, "{}", value),
+            Expression::HexNumber(value) => write!(f, "{}", Hex(*value)),
             Expression::LValue(variable) => write!(f, "{}", variable),
             Expression::Unary { op, operand } => write!(f, "{}{}", op, operand),
             Expression::Binary { left, op, right } => write!(f, "{} {} {}", left, op, right),
+            Expression::Function { function, .. } if !function.parenthesized() => {
+                write!(f, "{}", function)
+            }
+            Expression::Function { function, args } => {
+                write!(f, "{}(", function)?;
+                for (i, arg) in args.iter().enumerate() {
+                    if i > 0 {
+                        write!(f, ", ")?;
+                    }
+                    write!(f, "{}", arg)?;
+                }
+                write!(f, ")")
+            }
+        }
+    }
+}
+
+// Unsigned literal of a statement, like a line number or an array size.
+// Only the printers care about the radix
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Unsigned {
+    pub value: u32,
+    pub hex: bool,
+}
+
+impl From<u32> for Unsigned {
+    fn from(value: u32) -> Self {
+        Unsigned { value, hex: false }
+    }
+}
+
+impl std::fmt::Display for Unsigned {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.hex {
+            write!(f, "{}", Hex(self.value))
+        } else {
+            write!(f, "{}", self.value)
         }
     }
 }
@@ -107,9 +200,47 @@ impl std::fmt::Display for Expression {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum DataItem {
     Number(i32),
+    HexNumber(i32),
     String(String),
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Separator {
+    // Next item goes right after this one
+    Semicolon,
+    // Next item goes to the other half of the display
+    Comma,
+}
+
+impl std::fmt::Display for Separator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Separator::Semicolon => write!(f, ";"),
+            Separator::Comma => write!(f, ","),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct PrintItem {
+    pub expression: Expression,
+    // A separator after the last item keeps the display line open
+    pub separator: Option<Separator>,
+}
+
+// Plotter position, in steps of 0.2 mm from the origin
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Coordinate {
+    pub x: Expression,
+    pub y: Expression,
+}
+
+impl std::fmt::Display for Coordinate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "({}, {})", self.x, self.y)
+    }
+}
+
 #[derive(Debug)]
 pub enum Statement {
     Let {
@@ -118,14 +249,17 @@ pub enum Statement {
     },
     Dim {
         variable: String,
-        size: u32,
-        length: Option<u32>, // Only for strings
+        size: Unsigned,
+        length: Option<Unsigned>, // Only for strings
     },
     Print {
-        content: Vec<Expression>,
+        // USING format string
+        format: Option<String>,
+        content: Vec<PrintItem>,
     },
     Pause {
-        content: Vec<Expression>,
+        format: Option<String>,
+        content: Vec<PrintItem>,
     },
     Input {
         prompt: Option<Expression>,
@@ -141,14 +275,16 @@ pub enum Statement {
         variables: Vec<LValue>,
     },
     Restore {
-        line_number: Option<u32>,
+        line_number: Option<Unsigned>,
     },
     Poke {
-        address: u32,
-        values: Vec<u8>,
+        address: Expression,
+        values: Vec<Expression>,
     },
     Call {
-        address: u32,
+        address: Expression,
+        // Passed to the machine code routine in a register
+        argument: Option<Expression>,
     },
     For {
         variable: String,
@@ -160,13 +296,17 @@ pub enum Statement {
         variable: String,
     },
     Goto {
-        line_number: u32,
+        line_number: Unsigned,
     },
     End,
     GoSub {
-        line_number: u32,
+        line_number: Unsigned,
     },
     Return,
+    // Line 0 turns error trapping off
+    OnErrorGoto {
+        line_number: Unsigned,
+    },
     If {
         condition: Expression,
         then: Box<Statement>,
@@ -178,17 +318,77 @@ pub enum Statement {
     Rem {
         content: String,
     },
+    // LCD control
+    Cls,
+    Cursor {
+        column: Expression,
+    },
+    GCursor {
+        column: Expression,
+    },
+    GPrint {
+        // Hex strings with two digits per column, or numeric column patterns
+        content: Vec<Expression>,
+    },
+    Beep {
+        count: Expression,
+        frequency: Option<Expression>,
+        duration: Option<Expression>,
+    },
+    // CE-150 printer and plotter
+    LPrint {
+        format: Option<String>,
+        content: Vec<PrintItem>,
+    },
+    // LINE (x1, y1)-(x2, y2), type, color, B. RLINE takes offsets from the
+    // previous point instead of absolute positions
+    Line {
+        relative: bool,
+        // Without it the line starts at the pen position
+        start: Option<Coordinate>,
+        points: Vec<Coordinate>,
+        line_type: Option<Expression>,
+        color: Option<Expression>,
+        // Draws the rectangle with the two points as corners
+        boxed: bool,
+    },
+    GlCursor {
+        position: Coordinate,
+    },
+    Color {
+        color: Expression,
+    },
+    CSize {
+        size: Expression,
+    },
+    Rotate {
+        direction: Expression,
+    },
+    // Switch between plotting and printing text
+    Graph,
+    Text,
+    // Makes the pen position the origin
+    Sorgn,
+    Lf {
+        lines: Expression,
+    },
+    // Trace execution line by line
+    Tron,
+    Troff,
 }
 
 #[derive(Debug)]
 pub struct Program {
     pub lines: BTreeMap<u32, Statement>,
+    // Lines starting with a string, like 10 "A" PRINT, keyed by line number
+    pub labels: BTreeMap<u32, String>,
 }
 
 impl Program {
     pub fn new() -> Self {
         Program {
             lines: BTreeMap::new(),
+            labels: BTreeMap::new(),
         }
     }
 
@@ -196,6 +396,22 @@ impl Program {
         self.lines.insert(line_number, statement);
     }
 
+    pub fn add_label(&mut self, line_number: u32, label: String) {
+        self.labels.insert(line_number, label);
+    }
+
+    pub fn label(&self, line_number: u32) -> Option<&str> {
+        self.labels.get(&line_number).map(String::as_str)
+    }
+
+    // First line with the label
+    pub fn label_line(&self, label: &str) -> Option<u32> {
+        self.labels
+            .iter()
+            .find(|(_, name)| *name == label)
+            .map(|(line, _)| *line)
+    }
+
     pub fn lookup_line(&self, line_number: u32) -> Option<&Statement> {
         self.lines.get(&line_number)
     }
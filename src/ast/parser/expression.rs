@@ -1,39 +1,42 @@
 use crate::ast::{
-    error::ErrorKind, node::LValue, BinaryOperator, Error, Expression, UnaryOperator,
+    error::ErrorKind,
+    node::{Function, LValue},
+    BinaryOperator, Error, Expression, UnaryOperator,
 };
 use crate::tokens::{Lexer, Token};
-use std::{iter::Peekable, mem};
+use std::mem;
 
 pub struct ExpressionParser<'a> {
-   pub lexer: Peekable<Lexer<'a>>, // hecho público
+    pub lexer: Lexer<'a>, // hecho público
 }
 
 impl<'a> ExpressionParser<'a> {
-    pub fn new(lexer: Peekable<Lexer<'a>>) -> Self {
+    pub fn new(lexer: Lexer<'a>) -> Self {
         Self { lexer }
     }
 
     pub fn parse(&mut self) -> Result<Option<Expression>, Error> {
-        // println!("expression");
-        self.comparison()
+        self.or()
     }
 
-   pub fn lvalue(&mut self) -> Result<LValue, Error> {
-        // println!("lvalue");
+    pub fn lvalue(&mut self) -> Result<LValue, Error> {
         match self.lexer.peek_mut() {
             Some(Token::Identifier(v)) => {
                 let variable = mem::take(v);
-                let next = self.lexer.next();
-
-                // println!("identifier {}", v);
+                self.lexer.next();
 
-                if next == Some(Token::LeftParen) {
+                if self.lexer.peek() == Some(&Token::LeftParen) {
                     self.lexer.next();
-                    let index = self.parse()?;
+                    let Some(index) = self.parse()? else {
+                        return Err(Error {
+                            kind: ErrorKind::ExpectedExpression,
+                            line: self.lexer.current_line(),
+                        });
+                    };
                     if self.lexer.peek() == Some(&Token::RightParen) {
                         let res = Ok(LValue::ArrayElement {
                             variable,
-                            index: Box::new(index.unwrap()),
+                            index: Box::new(index),
                         });
 
                         self.lexer.next();
@@ -42,20 +45,17 @@ impl<'a> ExpressionParser<'a> {
                     } else {
                         Err(Error {
                             kind: ErrorKind::MismatchedParentheses,
-                            line: 0, // TODO FIX : line: self.lexer.current_line(), 
+                            line: self.lexer.current_line(),
                         })
                     }
                 } else {
                     Ok(LValue::Variable(variable))
                 }
             }
-            _ => {
-                // println!("expected identifier");
-                Err(Error {
-                    kind: ErrorKind::ExpectedIdentifier,
-                    line: 0,// TODO
-                })
-            }
+            _ => Err(Error {
+                kind: ErrorKind::ExpectedIdentifier,
+                line: self.lexer.current_line(),
+            }),
         }
     }
 
@@ -66,12 +66,36 @@ impl<'a> ExpressionParser<'a> {
                 self.lexer.next();
                 res
             }
+            Some(Token::HexNumber(n)) => {
+                let res = Ok(Some(Expression::HexNumber(*n)));
+                self.lexer.next();
+                res
+            }
             Some(Token::Identifier(_)) => self.lvalue().map(|v| Some(Expression::LValue(v))),
             Some(Token::String(s)) => {
                 let res = Ok(Some(Expression::String(mem::take(s))));
                 self.lexer.next();
                 res
             }
+            Some(Token::Point) => {
+                self.lexer.next();
+                self.function(Function::Point).map(Some)
+            }
+            Some(Token::Inkey | Token::Ern | Token::Erl) => {
+                let function = match self.lexer.next() {
+                    Some(Token::Ern) => Function::Ern,
+                    Some(Token::Erl) => Function::Erl,
+                    _ => Function::Inkey,
+                };
+                Ok(Some(Expression::Function {
+                    function,
+                    args: Vec::new(),
+                }))
+            }
+            Some(Token::Invalid(error)) => Err(Error {
+                kind: ErrorKind::Lexical(*error),
+                line: self.lexer.current_line(),
+            }),
             Some(Token::LeftParen) => {
                 self.lexer.next(); // Consumir '('
                 let res = self.parse()?;
@@ -80,7 +104,7 @@ impl<'a> ExpressionParser<'a> {
                 } else {
                     Err(Error {
                         kind: ErrorKind::MismatchedParentheses,
-                        line: 0, // TODO
+                        line: self.lexer.current_line(),
                     })
                 }
             }
@@ -88,9 +112,43 @@ impl<'a> ExpressionParser<'a> {
         }
     }
 
+    // Parenthesized argument list after the function name
+    fn function(&mut self, function: Function) -> Result<Expression, Error> {
+        if self.lexer.next() != Some(Token::LeftParen) {
+            return Err(Error {
+                kind: ErrorKind::ExpectedLeftParen,
+                line: self.lexer.current_line(),
+            });
+        }
+
+        let mut args = Vec::new();
+        loop {
+            match self.parse()? {
+                Some(arg) => args.push(arg),
+                None => {
+                    return Err(Error {
+                        kind: ErrorKind::ExpectedExpression,
+                        line: self.lexer.current_line(),
+                    });
+                }
+            }
+            match self.lexer.next() {
+                Some(Token::Comma) => {}
+                Some(Token::RightParen) => break,
+                _ => {
+                    return Err(Error {
+                        kind: ErrorKind::ExpectedRightParen,
+                        line: self.lexer.current_line(),
+                    });
+                }
+            }
+        }
+
+        Ok(Expression::Function { function, args })
+    }
+
     // unary + and -
     fn factor(&mut self) -> Result<Option<Expression>, Error> {
-        // println!("factor");
         if self.lexer.peek() == Some(&Token::Plus) || self.lexer.peek() == Some(&Token::Minus) {
             let op = match self.lexer.next() {
                 Some(Token::Plus) => UnaryOperator::Plus,
@@ -104,7 +162,7 @@ impl<'a> ExpressionParser<'a> {
             } else {
                 return Err(Error {
                     kind: ErrorKind::ExpectedExpression,
-                    line: 0, // TODO
+                    line: self.lexer.current_line(),
                 });
             };
 
@@ -137,7 +195,7 @@ impl<'a> ExpressionParser<'a> {
             } else {
                 return Err(Error {
                     kind: ErrorKind::ExpectedExpression,
-                    line: 0, // TODO
+                    line: self.lexer.current_line(),
                 });
             };
 
@@ -159,8 +217,6 @@ impl<'a> ExpressionParser<'a> {
         };
 
         while let Some(&Token::Plus) | Some(&Token::Minus) = self.lexer.peek() {
-            println!("add_sub");
-
             let op = match self.lexer.next() {
                 Some(Token::Plus) => BinaryOperator::Add,
                 Some(Token::Minus) => BinaryOperator::Sub,
@@ -173,7 +229,7 @@ impl<'a> ExpressionParser<'a> {
             } else {
                 return Err(Error {
                     kind: ErrorKind::ExpectedExpression,
-                    line: 0, // TODO
+                    line: self.lexer.current_line(),
                 });
             };
 
@@ -217,7 +273,7 @@ impl<'a> ExpressionParser<'a> {
             } else {
                 return Err(Error {
                     kind: ErrorKind::ExpectedExpression,
-                    line: 0, // TODO
+                    line: self.lexer.current_line(),
                 });
             };
 
@@ -230,6 +286,86 @@ impl<'a> ExpressionParser<'a> {
 
         Ok(Some(left))
     }
+
+    // NOT binds looser than comparisons, so NOT A=B negates the comparison
+    fn not(&mut self) -> Result<Option<Expression>, Error> {
+        if self.lexer.peek() != Some(&Token::Not) {
+            return self.comparison();
+        }
+        self.lexer.next();
+
+        let operand = if let Some(operand) = self.not()? {
+            operand
+        } else {
+            return Err(Error {
+                kind: ErrorKind::ExpectedExpression,
+                line: self.lexer.current_line(),
+            });
+        };
+
+        Ok(Some(Expression::Unary {
+            op: UnaryOperator::Not,
+            operand: Box::new(operand),
+        }))
+    }
+
+    fn and(&mut self) -> Result<Option<Expression>, Error> {
+        let mut left = if let Some(left) = self.not()? {
+            left
+        } else {
+            return Ok(None);
+        };
+
+        while self.lexer.peek() == Some(&Token::And) {
+            self.lexer.next();
+
+            let right = if let Some(right) = self.not()? {
+                right
+            } else {
+                return Err(Error {
+                    kind: ErrorKind::ExpectedExpression,
+                    line: self.lexer.current_line(),
+                });
+            };
+
+            left = Expression::Binary {
+                left: Box::new(left),
+                op: BinaryOperator::And,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(Some(left))
+    }
+
+    fn or(&mut self) -> Result<Option<Expression>, Error> {
+        let mut left = if let Some(left) = self.and()? {
+            left
+        } else {
+            return Ok(None);
+        };
+
+        while self.lexer.peek() == Some(&Token::Or) {
+            self.lexer.next();
+
+            let right = if let Some(right) = self.and()? {
+                right
+            } else {
+                return Err(Error {
+                    kind: ErrorKind::ExpectedExpression,
+                    line: self.lexer.current_line(),
+                });
+            };
+
+            left = Expression::Binary {
+                left: Box::new(left),
+                op: BinaryOperator::Or,
+                right: Box::new(right),
+            };
+        }
+
+        Ok(Some(left))
+    }
 }
 
 #[cfg(test)]
@@ -249,7 +385,7 @@ mod tests {
         };
 
         let lexer = Lexer::new("1 + 2 - 3");
-        let mut parser = ExpressionParser::new(lexer.peekable());
+        let mut parser = ExpressionParser::new(lexer);
 
         let res = parser
             .add_sub()
@@ -272,17 +408,15 @@ mod tests {
                 right: Box::new(Expression::Number(3)),
             }),
         };
-    
+
         let lexer = Lexer::new("1 + 2 * 3");
-        let mut parser = ExpressionParser::new(lexer.peekable());
-    
+        let mut parser = ExpressionParser::new(lexer);
+
         let res = parser
             .add_sub()
             .expect("Failed to parse expression")
             .expect("Expected an expression");
-    
-        dbg!(&res);  // Agrega esta línea para ver el resultado generado
-    
+
         assert_eq!(res, expected);
     }
 
@@ -299,7 +433,7 @@ mod tests {
         };
 
         let lexer = Lexer::new("1 * 2 / 3");
-        let mut parser = ExpressionParser::new(lexer.peekable());
+        let mut parser = ExpressionParser::new(lexer);
 
         let res = parser
             .mul_div()
@@ -314,19 +448,52 @@ mod tests {
         let expected = LValue::Variable("A".to_owned());
 
         let lexer = Lexer::new("A");
-        let mut parser = ExpressionParser::new(lexer.peekable());
+        let mut parser = ExpressionParser::new(lexer);
 
         let res = parser.lvalue().expect("Failed to parse lvalue");
 
         assert_eq!(res, expected);
     }
 
+    #[test]
+    fn lvalue_array_element() {
+        let expected = LValue::ArrayElement {
+            variable: "A".to_owned(),
+            index: Box::new(Expression::Binary {
+                left: Box::new(Expression::LValue(LValue::Variable("I".to_owned()))),
+                op: BinaryOperator::Add,
+                right: Box::new(Expression::Number(1)),
+            }),
+        };
+
+        let lexer = Lexer::new("A(I + 1)");
+        let mut parser = ExpressionParser::new(lexer);
+
+        let res = parser.lvalue().expect("Failed to parse lvalue");
+
+        assert_eq!(res, expected);
+        assert_eq!(parser.lexer.next(), None);
+    }
+
+    #[test]
+    fn lvalue_empty_index() {
+        let lexer = Lexer::new("A()");
+        let mut parser = ExpressionParser::new(lexer);
+
+        let res = parser.lvalue();
+
+        assert_eq!(
+            res.map_err(|error| error.kind),
+            Err(ErrorKind::ExpectedExpression)
+        );
+    }
+
     #[test]
     fn factor_1() {
         let expected = Expression::Number(42);
 
         let lexer = Lexer::new("42");
-        let mut parser = ExpressionParser::new(lexer.peekable());
+        let mut parser = ExpressionParser::new(lexer);
 
         let res = parser
             .factor()
@@ -345,7 +512,7 @@ mod tests {
         };
 
         let lexer = Lexer::new("+42");
-        let mut parser = ExpressionParser::new(lexer.peekable());
+        let mut parser = ExpressionParser::new(lexer);
 
         let res = parser
             .factor()
@@ -364,7 +531,7 @@ mod tests {
         };
 
         let lexer = Lexer::new("-42");
-        let mut parser = ExpressionParser::new(lexer.peekable());
+        let mut parser = ExpressionParser::new(lexer);
 
         let res = parser
             .factor()
@@ -385,7 +552,7 @@ mod tests {
 
         let lexer = Lexer::new("(42 * 43)");
 
-        let mut parser = ExpressionParser::new(lexer.peekable());
+        let mut parser = ExpressionParser::new(lexer);
 
         let res = parser
             .term()
@@ -404,7 +571,7 @@ mod tests {
         };
 
         let lexer = Lexer::new("42 = 43");
-        let mut parser = ExpressionParser::new(lexer.peekable());
+        let mut parser = ExpressionParser::new(lexer);
 
         let res = parser
             .comparison()
@@ -413,4 +580,79 @@ mod tests {
 
         assert_eq!(res, expected);
     }
+
+    #[test]
+    fn point_function() {
+        let expected = Expression::Binary {
+            left: Box::new(Expression::Function {
+                function: Function::Point,
+                args: vec![Expression::Number(10)],
+            }),
+            op: BinaryOperator::Add,
+            right: Box::new(Expression::Number(1)),
+        };
+
+        let lexer = Lexer::new("POINT(10) + 1");
+        let mut parser = ExpressionParser::new(lexer);
+
+        let res = parser
+            .parse()
+            .expect("Failed to parse expression")
+            .expect("Expected an expression");
+
+        assert_eq!(res, expected);
+    }
+
+    #[test]
+    fn inkey_function() {
+        let expected = Expression::Binary {
+            left: Box::new(Expression::Function {
+                function: Function::Inkey,
+                args: Vec::new(),
+            }),
+            op: BinaryOperator::Eq,
+            right: Box::new(Expression::String("Y".to_owned())),
+        };
+
+        let lexer = Lexer::new("INKEY$ = \"Y\"");
+        let mut parser = ExpressionParser::new(lexer);
+
+        let res = parser
+            .parse()
+            .expect("Failed to parse expression")
+            .expect("Expected an expression");
+
+        assert_eq!(res, expected);
+        assert_eq!(res.to_string(), "INKEY$ = \"Y\"");
+    }
+
+    #[test]
+    fn logical_operators() {
+        let lexer = Lexer::new("NOT A = 1 OR B AND C");
+        let mut parser = ExpressionParser::new(lexer);
+
+        let res = parser
+            .parse()
+            .expect("Failed to parse expression")
+            .expect("Expected an expression");
+
+        let Expression::Binary { left, op, right } = res else {
+            panic!("Expected OR at the top");
+        };
+        assert_eq!(op, BinaryOperator::Or);
+        assert!(matches!(
+            *left,
+            Expression::Unary {
+                op: UnaryOperator::Not,
+                ..
+            }
+        ));
+        assert!(matches!(
+            *right,
+            Expression::Binary {
+                op: BinaryOperator::And,
+                ..
+            }
+        ));
+    }
 }
@@ -1,96 +1,47 @@
 mod expression;
 
-use std::iter::Peekable;
 use std::mem;
 
-use expression::ExpressionParser;
+pub use expression::ExpressionParser;
 
 use super::error::ErrorKind;
-use super::node::{DataItem, LValue, UnaryOperator};
-use super::{BinaryOperator, Error, Expression, Program, Statement};
+use super::node::{Coordinate, DataItem, PrintItem, Separator, Unsigned};
+use super::{Error, Expression, Program, Statement};
 use crate::tokens::{Lexer, Token};
 
-
 pub struct Parser<'a> {
-    expr_parser: ExpressionParser<'a>, // Se incluye ExpressionParser en el struct
-    current_token: Option<Token>, // self.current_token possible fix
+    // The current token is the one the expression parser's lexer peeks, so
+    // both parsers always agree on where they are
+    expr_parser: ExpressionParser<'a>,
 }
 
 impl<'a> Parser<'a> {
-    pub fn new(mut lexer: Lexer<'a>) -> Self {
-        let first_token = lexer.next();
+    pub fn new(lexer: Lexer<'a>) -> Self {
         Self {
             expr_parser: ExpressionParser::new(lexer),
-            current_token: first_token,
         }
     }
 
-    /*pub fn advance(&mut self) {
-        self.current_token = self.expr_parser.lexer.next();
-    }
-    */
-
-    pub fn current_token(&self) -> Option<&Token> {
-        self.current_token.as_ref()
-    }
-
     pub fn parse(&mut self) -> (Program, Vec<Error>) {
-        let mut errors = Vec::new();
-        let mut program = Vec::new(); // Aquí almacenamos las líneas que estamos parseando
-    
-        while let Some(token) = self.expr_parser.lexer.peek() {
-            match token {
-                Token::Number(_) => {
-                    // Es posible que el número sea el inicio de una línea, así que parseamos la sentencia
-                    match self.line() {
-                        Ok((_, stmt)) => program.push(stmt),
-                        Err(e) => errors.push(e),
-                    }
-                }
-                Token::Let | Token::Identifier(_) | Token::Print | Token::Pause | Token::Input | Token::Wait |
-                Token::Goto | Token::For | Token::Next | Token::End | Token::Gosub | Token::If |
-                Token::Return | Token::Data | Token::Read | Token::Restore | Token::Poke | Token::Call |
-                Token::Dim | Token::Rem(_) => {
-                    // Si encontramos una sentencia de tipo conocido, la procesamos
-                    match self.statement() {
-                        Ok(stmt) => program.push(stmt),
-                        Err(e) => errors.push(e),
-                    }
-                }
-                _ => {
-                    // Si encontramos un token inesperado, lo registramos como error
-                    errors.push(Error {
-                        kind: ErrorKind::UnexpectedToken,
-                        line: self.lexer.current_line(),
-                    });
-                    self.expr_parser.lexer.next();  // Avanzamos al siguiente token
-                }
-            }
-        }
-    
-        // Cambiar `statements` por `lines`, ya que el error indica que `Program` tiene el campo `lines`
-        (Program { lines: program }, errors)
+        self.program()
     }
-    
-    
 
-    fn let_(&mut self) -> Result<Statement, Error> { // TODO revisar la siguiente función
-        println!("let");
-    
+    fn let_(&mut self) -> Result<Statement, Error> {
+        // TODO revisar la siguiente función
+
         let variable; // Declaramos la variable fuera del match
-    
+
         // Revisamos si el token actual es "let"
-        match &mut self.current_token {
+        match self.expr_parser.lexer.peek_mut() {
             Some(Token::Let) => {
                 // Si el token es "let", avanzamos al siguiente token
-                self.current_token = self.expr_parser.lexer.next();
-    
+                self.expr_parser.lexer.next();
+
                 // Ahora esperamos que el siguiente token sea un identificador
-                match &self.current_token {
+                match self.expr_parser.lexer.peek() {
                     Some(Token::Identifier(_)) => {
                         // Si es un identificador, analizamos la variable
                         variable = self.expr_parser.lvalue()?;
-                        println!("identifier: {:?}", variable);
                     }
                     _ => {
                         // Si no es un identificador, retornamos un error
@@ -101,676 +52,1018 @@ impl<'a> Parser<'a> {
                     }
                 }
             }
-            Some(Token::Identifier(v)) => {
-                // Si el token es un identificador sin "let", tomamos el valor
-                self.current_token = Some(Token::Identifier(mem::take(v)));
-                println!("identifier");
+            Some(Token::Identifier(_)) => {
+                // Si el token es un identificador sin "let", lo deja al lvalue
                 // Llamamos a `lvalue()` para obtener la variable, envuelta en Ok
                 variable = self.expr_parser.lvalue()?;
             }
             _ => {
                 // Si el token no es "let" ni un identificador, es un error
-                unreachable!("Expected 'let' or an identifier, found {:?}", self.current_token);
+                unreachable!(
+                    "Expected 'let' or an identifier, found {:?}",
+                    self.expr_parser.lexer.peek()
+                );
             }
         };
-    
+
         // Después de la variable, esperamos el token "="
-        if self.current_token != Some(Token::Equal) {
-            println!("not equal {:?}", self.current_token);
+        if self.expr_parser.lexer.peek() != Some(&Token::Equal) {
             return Err(Error {
                 kind: ErrorKind::UnexpectedToken,
                 line: self.expr_parser.lexer.current_line(),
             });
         }
-    
-        println!("equal");
-    
+
         // Avanzamos al siguiente token, que debe ser la expresión
-        self.current_token = self.expr_parser.lexer.next();
-    
+        self.expr_parser.lexer.next();
+
         // Parseamos la expresión que debe estar después del "="
         let expression = self.expr_parser.parse()?;
-    
+
         // Verificamos si la expresión fue válida
         let expression = if let Some(expression) = expression {
-            println!("expression: {:?}", expression);
             expression
         } else {
-            println!("no expression");
             return Err(Error {
                 kind: ErrorKind::ExpectedExpression,
                 line: self.expr_parser.lexer.current_line(),
             });
         };
-    
+
         // Finalmente, retornamos el Statement de tipo Let
         Ok(Statement::Let {
             variable, // Ahora la variable está disponible en este contexto
             expression,
         })
     }
-    
-    
-    
-
-     fn pause(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let mut content = Vec::new();
-
-         while let Some(expr) = self.expr_parser.parse()? {
-             content.push(expr);
-
-             if self.current_token == Some(Token::Semicolon) {
-                 self.current_token = self.expr_parser.lexer.next();
-             } else {
-                 break;
-             }
-         }
-
-         Ok(Statement::Pause { content })
-     }
-
-     fn print(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let mut content = Vec::new();
-
-         while let Some(expr) = self.expr_parser.parse()? {
-             content.push(expr);
-
-             if self.current_token == Some(Token::Semicolon) {
-                 self.current_token = self.expr_parser.lexer.next();
-             } else {
-                 break;
-             }
-         }
-
-         Ok(Statement::Print { content })
-     }
-
-     fn input(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let prompt = self.expr_parser.parse()?;
-
-         if self.current_token == Some(Token::Semicolon) {
-             self.current_token = self.expr_parser.lexer.next();
-         }
-
-         let variable = match self.current_token {
-             Some(Token::Identifier(_)) => self.expr_parser.lvalue()?,
-             _ => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedIdentifier,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         };
-
-         self.current_token = self.expr_parser.lexer.next();
-
-         Ok(Statement::Input { prompt, variable })
-     }
-
-     fn wait(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let time = self.expr_parser.parse()?; // could be self.parse()?
-
-         Ok(Statement::Wait { time })
-     }
-
-     fn data(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let mut values = Vec::new();
-
-         loop {
-             match &mut self.current_token {
-                 Some(Token::Number(n)) => {
-                     values.push(DataItem::Number(*n));
-                     self.current_token = self.expr_parser.lexer.next();
-                 }
-                 Some(Token::String(s)) => {
-                     values.push(DataItem::String(std::mem::take(s)));
-                     self.current_token = self.expr_parser.lexer.next();
-                 }
-                 _ => {
-                     return Err(Error {
-                         kind: ErrorKind::ExpectedDataItem,
-                         line: self.lexer.current_line(),
-                     });
-                 }
-             }
-
-             if self.current_token == Some(Token::Comma) {
-                 self.current_token = self.expr_parser.lexer.next();
-             } else {
-                 break;
-             }
-         }
-
-         Ok(Statement::Data { values })
-     }
-
-     fn read(&mut self) -> Result<Statement, Error> { // TODO revisar esta función y el tipo Read en node.rs
-        self.current_token = self.expr_parser.lexer.next();
+
+    fn pause(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let (format, content) = self.print_items()?;
+
+        Ok(Statement::Pause { format, content })
+    }
+
+    fn print(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let (format, content) = self.print_items()?;
+
+        Ok(Statement::Print { format, content })
+    }
+
+    // [USING "format";] item {; | , item} [; | ,]
+    fn print_items(&mut self) -> Result<(Option<String>, Vec<PrintItem>), Error> {
+        let mut format = None;
+        if self.expr_parser.lexer.peek() == Some(&Token::Using) {
+            self.expr_parser.lexer.next();
+            match self.expr_parser.lexer.peek_mut() {
+                Some(Token::String(s)) => format = Some(mem::take(s)),
+                _ => {
+                    return Err(Error {
+                        kind: ErrorKind::ExpectedFormat,
+                        line: self.expr_parser.lexer.current_line(),
+                    });
+                }
+            }
+            self.expr_parser.lexer.next();
+            if self.expr_parser.lexer.peek() == Some(&Token::Semicolon) {
+                self.expr_parser.lexer.next();
+            }
+        }
+
+        let mut content = Vec::new();
+        while let Some(expression) = self.expr_parser.parse()? {
+            // The expression parser has moved on, the separator is its next
+            // token
+            let separator = match self.expr_parser.lexer.peek() {
+                Some(Token::Semicolon) => Some(Separator::Semicolon),
+                Some(Token::Comma) => Some(Separator::Comma),
+                _ => None,
+            };
+            content.push(PrintItem {
+                expression,
+                separator,
+            });
+
+            if separator.is_some() {
+                self.expr_parser.lexer.next();
+            } else {
+                break;
+            }
+        }
+
+        Ok((format, content))
+    }
+
+    fn input(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        // Only a string prompts, INPUT A reads straight into A
+        let prompt = match self.expr_parser.lexer.peek() {
+            Some(Token::String(_)) => self.expr_parser.parse()?,
+            _ => None,
+        };
+
+        if self.expr_parser.lexer.peek() == Some(&Token::Semicolon) {
+            self.expr_parser.lexer.next();
+        }
+
+        let variable = match self.expr_parser.lexer.peek() {
+            Some(Token::Identifier(_)) => self.expr_parser.lvalue()?,
+            _ => {
+                return Err(Error {
+                    kind: ErrorKind::ExpectedIdentifier,
+                    line: self.expr_parser.lexer.current_line(),
+                });
+            }
+        };
+
+        Ok(Statement::Input { prompt, variable })
+    }
+
+    fn wait(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let time = self.expr_parser.parse()?; // could be self.parse()?
+
+        Ok(Statement::Wait { time })
+    }
+
+    fn data(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let mut values = Vec::new();
+
+        loop {
+            match self.expr_parser.lexer.peek_mut() {
+                Some(Token::Number(n)) => {
+                    values.push(DataItem::Number(*n));
+                    self.expr_parser.lexer.next();
+                }
+                Some(Token::HexNumber(n)) => {
+                    values.push(DataItem::HexNumber(*n));
+                    self.expr_parser.lexer.next();
+                }
+                Some(Token::String(s)) => {
+                    values.push(DataItem::String(std::mem::take(s)));
+                    self.expr_parser.lexer.next();
+                }
+                Some(Token::Invalid(error)) => {
+                    return Err(Error {
+                        kind: ErrorKind::Lexical(*error),
+                        line: self.expr_parser.lexer.current_line(),
+                    });
+                }
+                _ => {
+                    return Err(Error {
+                        kind: ErrorKind::ExpectedDataItem,
+                        line: self.expr_parser.lexer.current_line(),
+                    });
+                }
+            }
+
+            if self.expr_parser.lexer.peek() == Some(&Token::Comma) {
+                self.expr_parser.lexer.next();
+            } else {
+                break;
+            }
+        }
+
+        Ok(Statement::Data { values })
+    }
+
+    fn read(&mut self) -> Result<Statement, Error> {
+        // TODO revisar esta función y el tipo Read en node.rs
+        self.expr_parser.lexer.next();
         let mut variables = Vec::new();
-    
+
         loop {
-            match self.current_token {
+            match self.expr_parser.lexer.peek() {
                 Some(Token::Identifier(_)) => {
                     // Usamos ? para propagar el error de `lvalue()`
-                    let variable = self.expr_parser.lvalue()?;  // Esto devuelve un `LValue`, no un `Result`
+                    let variable = self.expr_parser.lvalue()?; // Esto devuelve un `LValue`, no un `Result`
                     variables.push(variable);
-                    self.current_token = self.expr_parser.lexer.next();
                 }
                 _ => {
                     return Err(Error {
                         kind: ErrorKind::ExpectedIdentifier,
-                        line: self.lexer.current_line(),
+                        line: self.expr_parser.lexer.current_line(),
                     });
                 }
             }
-    
+
             // Avanzamos si encontramos una coma
-            if self.current_token == Some(Token::Comma) {
-                self.current_token = self.expr_parser.lexer.next();
+            if self.expr_parser.lexer.peek() == Some(&Token::Comma) {
+                self.expr_parser.lexer.next();
             } else {
                 break;
             }
         }
-    
+
         Ok(Statement::Read { variables }) // TODO revisar! Esta función se ha modificado para devolver Vec<LValue> en vez de  Result<LValue, Error>
     }
-    
-
-     fn restore(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let line_number = match &self.current_token {
-             Some(Token::Number(n)) => match u32::try_from(*n) {
-                 Ok(n) => Some(n),
-                 Err(_) => {
-                     return Err(Error {
-                         kind: ErrorKind::ExpectedUnsigned,
-                         line: self.lexer.current_line(),
-                     });
-                 }
-             },
-             _ => None,
-         };
-
-         if line_number.is_some() {
-             self.current_token = self.expr_parser.lexer.next();
-         }
-
-         Ok(Statement::Restore { line_number })
-     }
-
-     fn poke(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let address = match &self.current_token {
-             Some(Token::Number(n)) => u32::try_from(*n).map_err(|_e| Error {
-                 kind: ErrorKind::ExpectedUnsigned,
-                 line: self.lexer.current_line(),
-             })?,
-             _ => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedUnsigned,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         };
-
-         self.current_token = self.expr_parser.lexer.next();
-         if self.current_token != Some(Token::Comma) {
-             return Err(Error {
-                 kind: ErrorKind::UnexpectedToken,
-                 line: self.lexer.current_line(),
-             });
-         }
-
-         self.current_token = self.expr_parser.lexer.next();
-         let mut values: Vec<u8> = Vec::new();
-
-         loop {
-             match &mut self.current_token {
-                 Some(Token::Number(n)) => {
-                     values.push(u8::try_from(*n).map_err(|_e| Error {
-                         kind: ErrorKind::ExpectedUnsigned,
-                         line: self.lexer.current_line(),
-                     })?);
-                     self.current_token = self.expr_parser.lexer.next();
-                 }
-                 _ => {
-                     return Err(Error {
-                         kind: ErrorKind::ExpectedUnsigned,
-                         line: self.lexer.current_line(),
-                     });
-                 }
-             }
-
-             if self.current_token == Some(Token::Comma) {
-                 self.current_token = self.expr_parser.lexer.next();
-             } else {
-                 break;
-             }
-         }
-
-         Ok(Statement::Poke { address, values })
-     }
-
-     fn call(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let address = match &self.current_token {
-             Some(Token::Number(n)) => u32::try_from(*n).map_err(|_e| Error {
-                 kind: ErrorKind::ExpectedUnsigned,
-                 line: self.lexer.current_line(),
-             })?,
-             _ => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedUnsigned,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         };
-
-         self.current_token = self.expr_parser.lexer.next();
-
-         Ok(Statement::Call { address })
-     }
-
-     fn goto(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let line_number = match &self.current_token {
-             Some(Token::Number(n)) => match u32::try_from(*n) {
-                 Ok(n) => n,
-                 Err(_) => {
-                     return Err(Error {
-                         kind: ErrorKind::ExpectedUnsigned,
-                         line: self.lexer.current_line(),
-                     });
-                 }
-             },
-             _ => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedUnsigned,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         };
-
-         self.current_token = self.expr_parser.lexer.next();
-
-         Ok(Statement::Goto { line_number })
-     }
-
-     fn gosub(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let line_number = match &self.current_token {
-             Some(Token::Number(n)) => match u32::try_from(*n) {
-                 Ok(n) => n,
-                 Err(_) => {
-                     return Err(Error {
-                         kind: ErrorKind::ExpectedUnsigned,
-                         line: self.lexer.current_line(),
-                     });
-                 }
-             },
-             _ => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedUnsigned,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         };
-
-         self.current_token = self.expr_parser.lexer.next();
-
-         Ok(Statement::GoSub { line_number })
-     }
-
-     fn return_(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-
-         Ok(Statement::Return)
-     }
-
-     fn if_(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let condition = match self.expr_parser.parse()? { //original self.expression()
-             Some(expr) => expr,
-             None => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedExpression,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         };
-
-         if self.current_token == Some(Token::Then) {
-             self.current_token = self.expr_parser.lexer.next();
-         }
-
-         let then = Box::new(self.statement()?);
-
-         let else_ = if self.current_token == Some(Token::Else) {
-             self.current_token = self.expr_parser.lexer.next();
-             let statement = self.statement()?;
-             Some(Box::new(statement))
-         } else {
-             None
-         };
-
-         Ok(Statement::If {
-             condition,
-             then,
-             else_,
-         })
-     }
-
-     fn for_(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let variable = match &mut self.current_token {
-             Some(Token::Identifier(v)) => mem::take(v),
-             _ => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedIdentifier,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         };
-
-         self.current_token = self.expr_parser.lexer.next();
-         if self.current_token != Some(Token::Equal) {
-             return Err(Error {
-                 kind: ErrorKind::UnexpectedToken,
-                 line: self.lexer.current_line(),
-             });
-         }
-
-         self.current_token = self.expr_parser.lexer.next();
-         let from = match self.expr_parser.parse()? {
-             Some(expr) => expr,
-             None => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedExpression,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         };
-
-         if self.current_token != Some(Token::To) {
-             return Err(Error {
-                 kind: ErrorKind::UnexpectedToken,
-                 line: self.lexer.current_line(),
-             });
-         }
-
-         self.current_token = self.expr_parser.lexer.next();
-         let to = match self.expr_parser.parse()? {
-             Some(expr) => expr,
-             None => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedExpression,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         };
-
-         let step = if self.current_token == Some(Token::Step) {
-             self.current_token = self.expr_parser.lexer.next();
-             match self.expr_parser.parse()? {
-                 Some(expr) => Some(expr),
-                 None => {
-                     return Err(Error {
-                         kind: ErrorKind::ExpectedExpression,
-                         line: self.lexer.current_line(),
-                     });
-                 }
-             }
-         } else {
-             None
-         };
-
-         Ok(Statement::For {
-             variable,
-             from,
-             to,
-             step,
-         })
-     }
-
-     fn next(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let variable = match &mut self.current_token {
-             Some(Token::Identifier(v)) => mem::take(v),
-             _ => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedIdentifier,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         };
-
-         self.current_token = self.expr_parser.lexer.next();
-
-         Ok(Statement::Next { variable })
-     }
-
-     fn end(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-
-         Ok(Statement::End)
-     }
-
-     fn comment(&mut self) -> Result<Statement, Error> {
-         match &mut self.current_token {
-             Some(Token::Rem(s)) => {
-                 let res = Ok(Statement::Rem {
-                     content: mem::take(s),
-                 });
-
-                 self.current_token = self.expr_parser.lexer.next();
-
-                 res
-             }
-             _ => {
-                 unreachable!("We already checked for REM");
-             }
-         }
-     }
-
-     fn dim(&mut self) -> Result<Statement, Error> {
-         self.current_token = self.expr_parser.lexer.next();
-         let variable = match &mut self.current_token {
-             Some(Token::Identifier(v)) => mem::take(v),
-             _ => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedIdentifier,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         };
-
-         self.current_token = self.expr_parser.lexer.next();
-         if self.current_token != Some(Token::LeftParen) {
-             return Err(Error {
-                 kind: ErrorKind::ExpectedLeftParen,
-                 line: self.lexer.current_line(),
-             });
-         }
-
-         self.current_token = self.expr_parser.lexer.next();
-         let size = match &self.current_token {
-             Some(Token::Number(n)) => match u32::try_from(*n) {
-                 Ok(n) => n,
-                 Err(_) => {
-                     return Err(Error {
-                         kind: ErrorKind::ExpectedUnsigned,
-                         line: self.lexer.current_line(),
-                     });
-                 }
-             },
-             _ => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedUnsigned,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         };
-
-         if self.current_token != Some(Token::RightParen) {
-             return Err(Error {
-                 kind: ErrorKind::ExpectedRightParen,
-                 line: self.lexer.current_line(),
-             });
-         }
-
-         self.current_token = self.expr_parser.lexer.next();
-
-         let length = if self.current_token == Some(Token::Star) {
-             self.current_token = self.expr_parser.lexer.next();
-             match &self.current_token {
-                 Some(Token::Number(n)) => match u32::try_from(*n) {
-                     Ok(n) => {
-                         self.current_token = self.expr_parser.lexer.next();
-                         Some(n)
-                     }
-                     Err(_) => {
-                         return Err(Error {
-                             kind: ErrorKind::ExpectedUnsigned,
-                             line: self.lexer.current_line(),
-                         });
-                     }
-                 },
-                 _ => {
-                     return Err(Error {
-                         kind: ErrorKind::ExpectedUnsigned,
-                         line: self.lexer.current_line(),
-                     });
-                 }
-             }
-         } else {
-             None
-         };
-
-         Ok(Statement::Dim {
-             variable,
-             size,
-             length,
-         })
-     }
-
-     fn atomic_statement(&mut self) -> Result<Statement, Error> {
-          println!("Atomic statement: {:?}", self.current_token);
-         match self.current_token {
-             Some(Token::Let | Token::Identifier(_)) => self.let_(),
-             Some(Token::Print) => self.print(),
-             Some(Token::Pause) => self.pause(),
-             Some(Token::Input) => self.input(),
-             Some(Token::Wait) => self.wait(),
-             Some(Token::Goto) => self.goto(),
-             Some(Token::For) => self.for_(),
-             Some(Token::Next) => self.next(),
-             Some(Token::End) => self.end(),
-             Some(Token::Gosub) => self.gosub(),
-             Some(Token::If) => self.if_(),
-             Some(Token::Return) => self.return_(),
-             Some(Token::Data) => self.data(),
-             Some(Token::Read) => self.read(),
-             Some(Token::Restore) => self.restore(),
-             Some(Token::Poke) => self.poke(),
-             Some(Token::Call) => self.call(),
-             Some(Token::Dim) => self.dim(),
-             Some(Token::Rem(_)) => self.comment(),
-             _ => Err(Error {
-                 kind: ErrorKind::ExpectedStatement,
-                 line: self.lexer.current_line(),
-             }),
-         }
-     }
-
-     fn statement(&mut self) -> Result<Statement, Error> {
-         //TODO: small vec optimization
-         let mut statements = Vec::new();
-
-         loop {
-             let stmt = self.atomic_statement()?;
-
-             statements.push(stmt);
-
-             if self.current_token == Some(Token::Colon) {
-                 self.current_token = self.expr_parser.lexer.next();
-             } else {
-                 break;
-             }
-         }
-
-         Ok(if statements.len() == 1 {
-             statements.remove(0)
-         } else {
-             Statement::Seq { statements }
-         })
-     }
-
-     fn line(&mut self) -> Result<(u32, Statement), Error> {
-         let line_number = match &self.current_token {
-             Some(Token::Number(n)) => {
-                 if let Ok(n) = u32::try_from(*n) {
-                     n
-                 } else {
-                     return Err(Error {
-                         kind: ErrorKind::ExpectedLineNumber,
-                         line: self.lexer.current_line(),
-                     });
-                 }
-             }
-             _ => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedLineNumber,
-                     line: self.lexer.current_line(),
-                 })
-             }
-         };
-
-         self.current_token = self.expr_parser.lexer.next();
-         let statement = self.statement()?;
-
-         match self.current_token {
-             Some(Token::Newline) => {
-                 self.current_token = self.expr_parser.lexer.next();
-             }
-             None => {}
-             _ => {
-                 return Err(Error {
-                     kind: ErrorKind::ExpectedEndOfLine,
-                     line: self.lexer.current_line(),
-                 });
-             }
-         }
-
-         Ok((line_number, statement))
-     }
-
-     fn program(&mut self) -> (Program, Vec<Error>) {
-         let mut errors = Vec::new();
-         let mut program = Program::new();
-
-         self.current_token = self.expr_parser.lexer.next();
-
-         while self.current_token.is_some() {
-             match self.line() {
-                 Ok((line_number, statement)) => {
-                     program.add_line(line_number, statement);
-                 }
-                 Err(e) => {
-                     errors.push(e);
-                     self.current_token = self.expr_parser.lexer.next();
-
-                     while self.current_token != Some(Token::Newline) {
-                         self.current_token = self.expr_parser.lexer.next();
-                     }
-                 }
-             }
-         }
-
-         (program, errors)
-     }
+
+    fn restore(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let line_number = match self.expr_parser.lexer.peek() {
+            Some(Token::Number(_) | Token::HexNumber(_)) => Some(self.unsigned()?),
+            _ => None,
+        };
+
+        Ok(Statement::Restore { line_number })
+    }
+
+    fn poke(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let address = self.expression()?;
+
+        if self.expr_parser.lexer.peek() != Some(&Token::Comma) {
+            return Err(Error {
+                kind: ErrorKind::UnexpectedToken,
+                line: self.expr_parser.lexer.current_line(),
+            });
+        }
+
+        self.expr_parser.lexer.next();
+        let mut values = Vec::new();
+
+        loop {
+            values.push(self.expression()?);
+
+            if self.expr_parser.lexer.peek() == Some(&Token::Comma) {
+                self.expr_parser.lexer.next();
+            } else {
+                break;
+            }
+        }
+
+        Ok(Statement::Poke { address, values })
+    }
+
+    fn call(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let address = self.expression()?;
+
+        let argument = if self.expr_parser.lexer.peek() == Some(&Token::Comma) {
+            self.expr_parser.lexer.next();
+            Some(self.expression()?)
+        } else {
+            None
+        };
+
+        Ok(Statement::Call { address, argument })
+    }
+
+    fn cls(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+
+        Ok(Statement::Cls)
+    }
+
+    fn cursor(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let column = self.expression()?;
+
+        Ok(Statement::Cursor { column })
+    }
+
+    fn gcursor(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let column = self.expression()?;
+
+        Ok(Statement::GCursor { column })
+    }
+
+    fn gprint(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let mut content = Vec::new();
+
+        loop {
+            content.push(self.expression()?);
+
+            if self.expr_parser.lexer.peek() == Some(&Token::Semicolon) {
+                self.expr_parser.lexer.next();
+            } else {
+                break;
+            }
+        }
+
+        Ok(Statement::GPrint { content })
+    }
+
+    // BEEP count [, frequency [, duration]]
+    fn beep(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let count = self.expression()?;
+
+        let mut optional = || -> Result<Option<Expression>, Error> {
+            if self.expr_parser.lexer.peek() != Some(&Token::Comma) {
+                return Ok(None);
+            }
+            self.expr_parser.lexer.next();
+            self.expression().map(Some)
+        };
+        let frequency = optional()?;
+        let duration = optional()?;
+
+        Ok(Statement::Beep {
+            count,
+            frequency,
+            duration,
+        })
+    }
+
+    fn lprint(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let (format, content) = self.print_items()?;
+
+        Ok(Statement::LPrint { format, content })
+    }
+
+    // [(x, y)] -(x, y) {-(x, y)} [, type [, color]] [, B]
+    fn line_(&mut self, relative: bool) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+
+        let start = if self.expr_parser.lexer.peek() == Some(&Token::LeftParen) {
+            Some(self.coordinate()?)
+        } else {
+            None
+        };
+        let mut points = Vec::new();
+        while self.expr_parser.lexer.peek() == Some(&Token::Minus) {
+            self.expr_parser.lexer.next();
+            points.push(self.coordinate()?);
+        }
+        if points.is_empty() {
+            return Err(Error {
+                kind: ErrorKind::UnexpectedToken,
+                line: self.expr_parser.lexer.current_line(),
+            });
+        }
+
+        // A lone B after a comma is the box flag, not a variable
+        let mut arguments = Vec::new();
+        let mut boxed = false;
+        while self.expr_parser.lexer.peek() == Some(&Token::Comma) {
+            self.expr_parser.lexer.next();
+            if self.expr_parser.lexer.peek() == Some(&Token::Identifier("B".to_owned())) {
+                self.expr_parser.lexer.next();
+                boxed = true;
+                break;
+            }
+            arguments.push(self.expression()?);
+        }
+        if arguments.len() > 2 {
+            return Err(Error {
+                kind: ErrorKind::UnexpectedToken,
+                line: self.expr_parser.lexer.current_line(),
+            });
+        }
+        let mut arguments = arguments.into_iter();
+
+        Ok(Statement::Line {
+            relative,
+            start,
+            points,
+            line_type: arguments.next(),
+            color: arguments.next(),
+            boxed,
+        })
+    }
+
+    // (x, y)
+    fn coordinate(&mut self) -> Result<Coordinate, Error> {
+        if self.expr_parser.lexer.peek() != Some(&Token::LeftParen) {
+            return Err(Error {
+                kind: ErrorKind::ExpectedLeftParen,
+                line: self.expr_parser.lexer.current_line(),
+            });
+        }
+        self.expr_parser.lexer.next();
+        let x = self.expression()?;
+
+        if self.expr_parser.lexer.peek() != Some(&Token::Comma) {
+            return Err(Error {
+                kind: ErrorKind::UnexpectedToken,
+                line: self.expr_parser.lexer.current_line(),
+            });
+        }
+        self.expr_parser.lexer.next();
+        let y = self.expression()?;
+
+        if self.expr_parser.lexer.peek() != Some(&Token::RightParen) {
+            return Err(Error {
+                kind: ErrorKind::ExpectedRightParen,
+                line: self.expr_parser.lexer.current_line(),
+            });
+        }
+        self.expr_parser.lexer.next();
+
+        Ok(Coordinate { x, y })
+    }
+
+    fn glcursor(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let position = self.coordinate()?;
+
+        Ok(Statement::GlCursor { position })
+    }
+
+    fn color(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let color = self.expression()?;
+
+        Ok(Statement::Color { color })
+    }
+
+    fn csize(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let size = self.expression()?;
+
+        Ok(Statement::CSize { size })
+    }
+
+    fn rotate(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let direction = self.expression()?;
+
+        Ok(Statement::Rotate { direction })
+    }
+
+    fn graph(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+
+        Ok(Statement::Graph)
+    }
+
+    fn text(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+
+        Ok(Statement::Text)
+    }
+
+    fn sorgn(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+
+        Ok(Statement::Sorgn)
+    }
+
+    fn tron(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+
+        Ok(Statement::Tron)
+    }
+
+    fn troff(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+
+        Ok(Statement::Troff)
+    }
+
+    fn lf(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let lines = self.expression()?;
+
+        Ok(Statement::Lf { lines })
+    }
+
+    // Expression that must be present
+    fn expression(&mut self) -> Result<Expression, Error> {
+        match self.expr_parser.parse()? {
+            Some(expression) => Ok(expression),
+            None => Err(Error {
+                kind: ErrorKind::ExpectedExpression,
+                line: self.expr_parser.lexer.current_line(),
+            }),
+        }
+    }
+
+    fn goto(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        self.goto_line()
+    }
+
+    // The line number of a GOTO, or of a THEN on its own
+    fn goto_line(&mut self) -> Result<Statement, Error> {
+        let line_number = self.unsigned()?;
+
+        Ok(Statement::Goto { line_number })
+    }
+
+    // Literal like a line number or an array size, in decimal or hex
+    fn unsigned(&mut self) -> Result<Unsigned, Error> {
+        let literal = match self.expr_parser.lexer.peek() {
+            Some(Token::Number(n)) => u32::try_from(*n).ok().map(Unsigned::from),
+            Some(Token::HexNumber(n)) => u32::try_from(*n)
+                .ok()
+                .map(|value| Unsigned { value, hex: true }),
+            Some(Token::Invalid(error)) => {
+                return Err(Error {
+                    kind: ErrorKind::Lexical(*error),
+                    line: self.expr_parser.lexer.current_line(),
+                });
+            }
+            _ => None,
+        };
+
+        let Some(literal) = literal else {
+            return Err(Error {
+                kind: ErrorKind::ExpectedUnsigned,
+                line: self.expr_parser.lexer.current_line(),
+            });
+        };
+        self.expr_parser.lexer.next();
+
+        Ok(literal)
+    }
+
+    // ON ERROR GOTO is the only form of ON
+    fn on_error(&mut self) -> Result<Statement, Error> {
+        for expected in [Token::Error, Token::Goto] {
+            self.expr_parser.lexer.next();
+            if self.expr_parser.lexer.peek() != Some(&expected) {
+                return Err(Error {
+                    kind: ErrorKind::UnexpectedToken,
+                    line: self.expr_parser.lexer.current_line(),
+                });
+            }
+        }
+
+        self.expr_parser.lexer.next();
+        let line_number = self.unsigned()?;
+
+        Ok(Statement::OnErrorGoto { line_number })
+    }
+
+    fn gosub(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let line_number = self.unsigned()?;
+
+        Ok(Statement::GoSub { line_number })
+    }
+
+    fn return_(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+
+        Ok(Statement::Return)
+    }
+
+    fn if_(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let condition = match self.expr_parser.parse()? {
+            //original self.expression()
+            Some(expr) => expr,
+            None => {
+                return Err(Error {
+                    kind: ErrorKind::ExpectedExpression,
+                    line: self.expr_parser.lexer.current_line(),
+                });
+            }
+        };
+
+        if self.expr_parser.lexer.peek() == Some(&Token::Then) {
+            self.expr_parser.lexer.next();
+        }
+
+        let then =
+            if let Some(Token::Number(_) | Token::HexNumber(_)) = self.expr_parser.lexer.peek() {
+                Box::new(self.goto_line()?)
+            } else {
+                Box::new(self.statement()?)
+            };
+
+        let else_ = if self.expr_parser.lexer.peek() == Some(&Token::Else) {
+            self.expr_parser.lexer.next();
+            let statement = self.statement()?;
+            Some(Box::new(statement))
+        } else {
+            None
+        };
+
+        Ok(Statement::If {
+            condition,
+            then,
+            else_,
+        })
+    }
+
+    fn for_(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let variable = match self.expr_parser.lexer.peek_mut() {
+            Some(Token::Identifier(v)) => mem::take(v),
+            _ => {
+                return Err(Error {
+                    kind: ErrorKind::ExpectedIdentifier,
+                    line: self.expr_parser.lexer.current_line(),
+                });
+            }
+        };
+
+        self.expr_parser.lexer.next();
+        if self.expr_parser.lexer.peek() != Some(&Token::Equal) {
+            return Err(Error {
+                kind: ErrorKind::UnexpectedToken,
+                line: self.expr_parser.lexer.current_line(),
+            });
+        }
+
+        self.expr_parser.lexer.next();
+        let from = match self.expr_parser.parse()? {
+            Some(expr) => expr,
+            None => {
+                return Err(Error {
+                    kind: ErrorKind::ExpectedExpression,
+                    line: self.expr_parser.lexer.current_line(),
+                });
+            }
+        };
+
+        if self.expr_parser.lexer.peek() != Some(&Token::To) {
+            return Err(Error {
+                kind: ErrorKind::UnexpectedToken,
+                line: self.expr_parser.lexer.current_line(),
+            });
+        }
+
+        self.expr_parser.lexer.next();
+        let to = match self.expr_parser.parse()? {
+            Some(expr) => expr,
+            None => {
+                return Err(Error {
+                    kind: ErrorKind::ExpectedExpression,
+                    line: self.expr_parser.lexer.current_line(),
+                });
+            }
+        };
+
+        let step = if self.expr_parser.lexer.peek() == Some(&Token::Step) {
+            self.expr_parser.lexer.next();
+            match self.expr_parser.parse()? {
+                Some(expr) => Some(expr),
+                None => {
+                    return Err(Error {
+                        kind: ErrorKind::ExpectedExpression,
+                        line: self.expr_parser.lexer.current_line(),
+                    });
+                }
+            }
+        } else {
+            None
+        };
+
+        Ok(Statement::For {
+            variable,
+            from,
+            to,
+            step,
+        })
+    }
+
+    fn next(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let variable = match self.expr_parser.lexer.peek_mut() {
+            Some(Token::Identifier(v)) => mem::take(v),
+            _ => {
+                return Err(Error {
+                    kind: ErrorKind::ExpectedIdentifier,
+                    line: self.expr_parser.lexer.current_line(),
+                });
+            }
+        };
+
+        self.expr_parser.lexer.next();
+
+        Ok(Statement::Next { variable })
+    }
+
+    fn end(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+
+        Ok(Statement::End)
+    }
+
+    fn comment(&mut self) -> Result<Statement, Error> {
+        match self.expr_parser.lexer.peek_mut() {
+            Some(Token::Rem(s)) => {
+                let res = Ok(Statement::Rem {
+                    content: mem::take(s),
+                });
+
+                self.expr_parser.lexer.next();
+
+                res
+            }
+            _ => {
+                unreachable!("We already checked for REM");
+            }
+        }
+    }
+
+    fn dim(&mut self) -> Result<Statement, Error> {
+        self.expr_parser.lexer.next();
+        let variable = match self.expr_parser.lexer.peek_mut() {
+            Some(Token::Identifier(v)) => mem::take(v),
+            _ => {
+                return Err(Error {
+                    kind: ErrorKind::ExpectedIdentifier,
+                    line: self.expr_parser.lexer.current_line(),
+                });
+            }
+        };
+
+        self.expr_parser.lexer.next();
+        if self.expr_parser.lexer.peek() != Some(&Token::LeftParen) {
+            return Err(Error {
+                kind: ErrorKind::ExpectedLeftParen,
+                line: self.expr_parser.lexer.current_line(),
+            });
+        }
+
+        self.expr_parser.lexer.next();
+        let size = self.unsigned()?;
+
+        if self.expr_parser.lexer.peek() != Some(&Token::RightParen) {
+            return Err(Error {
+                kind: ErrorKind::ExpectedRightParen,
+                line: self.expr_parser.lexer.current_line(),
+            });
+        }
+
+        self.expr_parser.lexer.next();
+
+        let length = if self.expr_parser.lexer.peek() == Some(&Token::Star) {
+            self.expr_parser.lexer.next();
+            Some(self.unsigned()?)
+        } else {
+            None
+        };
+
+        Ok(Statement::Dim {
+            variable,
+            size,
+            length,
+        })
+    }
+
+    fn atomic_statement(&mut self) -> Result<Statement, Error> {
+        match self.expr_parser.lexer.peek() {
+            Some(Token::Let | Token::Identifier(_)) => self.let_(),
+            Some(Token::Print) => self.print(),
+            Some(Token::Pause) => self.pause(),
+            Some(Token::Input) => self.input(),
+            Some(Token::Wait) => self.wait(),
+            Some(Token::Goto) => self.goto(),
+            Some(Token::For) => self.for_(),
+            Some(Token::Next) => self.next(),
+            Some(Token::End) => self.end(),
+            Some(Token::Gosub) => self.gosub(),
+            Some(Token::On) => self.on_error(),
+            Some(Token::If) => self.if_(),
+            Some(Token::Return) => self.return_(),
+            Some(Token::Data) => self.data(),
+            Some(Token::Read) => self.read(),
+            Some(Token::Restore) => self.restore(),
+            Some(Token::Poke) => self.poke(),
+            Some(Token::Call) => self.call(),
+            Some(Token::Cls) => self.cls(),
+            Some(Token::Cursor) => self.cursor(),
+            Some(Token::GCursor) => self.gcursor(),
+            Some(Token::GPrint) => self.gprint(),
+            Some(Token::Beep) => self.beep(),
+            Some(Token::LPrint) => self.lprint(),
+            Some(Token::Line) => self.line_(false),
+            Some(Token::RLine) => self.line_(true),
+            Some(Token::GlCursor) => self.glcursor(),
+            Some(Token::Color) => self.color(),
+            Some(Token::CSize) => self.csize(),
+            Some(Token::Rotate) => self.rotate(),
+            Some(Token::Graph) => self.graph(),
+            Some(Token::Text) => self.text(),
+            Some(Token::Sorgn) => self.sorgn(),
+            Some(Token::Tron) => self.tron(),
+            Some(Token::Troff) => self.troff(),
+            Some(Token::Lf) => self.lf(),
+            Some(Token::Dim) => self.dim(),
+            Some(Token::Rem(_)) => self.comment(),
+            Some(Token::Invalid(error)) => Err(Error {
+                kind: ErrorKind::Lexical(*error),
+                line: self.expr_parser.lexer.current_line(),
+            }),
+            _ => Err(Error {
+                kind: ErrorKind::ExpectedStatement,
+                line: self.expr_parser.lexer.current_line(),
+            }),
+        }
+    }
+
+    fn statement(&mut self) -> Result<Statement, Error> {
+        //TODO: small vec optimization
+        let mut statements = Vec::new();
+
+        loop {
+            let stmt = self.atomic_statement()?;
+
+            statements.push(stmt);
+
+            if self.expr_parser.lexer.peek() == Some(&Token::Colon) {
+                self.expr_parser.lexer.next();
+            } else {
+                break;
+            }
+        }
+
+        Ok(if statements.len() == 1 {
+            statements.remove(0)
+        } else {
+            Statement::Seq { statements }
+        })
+    }
+
+    fn line(&mut self) -> Result<(u32, Option<String>, Statement), Error> {
+        // The device keeps line numbers in binary and lists them in decimal,
+        // so the radix of the line's own number is not kept
+        let line_number = match self.expr_parser.lexer.peek() {
+            Some(Token::Number(_) | Token::HexNumber(_)) => {
+                self.unsigned()
+                    .map_err(|error| Error {
+                        kind: ErrorKind::ExpectedLineNumber,
+                        ..error
+                    })?
+                    .value
+            }
+            _ => {
+                return Err(Error {
+                    kind: ErrorKind::ExpectedLineNumber,
+                    line: self.expr_parser.lexer.current_line(),
+                })
+            }
+        };
+
+        // A string right after the line number labels the line
+        let label = match self.expr_parser.lexer.peek_mut() {
+            Some(Token::String(label)) => {
+                let label = mem::take(label);
+                self.expr_parser.lexer.next();
+                if self.expr_parser.lexer.peek() == Some(&Token::Colon) {
+                    self.expr_parser.lexer.next();
+                }
+                Some(label)
+            }
+            _ => None,
+        };
+        let statement = self.statement()?;
+
+        match self.expr_parser.lexer.peek() {
+            Some(Token::Newline) => {
+                self.expr_parser.lexer.next();
+            }
+            None => {}
+            _ => {
+                return Err(Error {
+                    kind: ErrorKind::ExpectedEndOfLine,
+                    line: self.expr_parser.lexer.current_line(),
+                });
+            }
+        }
+
+        Ok((line_number, label, statement))
+    }
+
+    fn program(&mut self) -> (Program, Vec<Error>) {
+        let mut errors = Vec::new();
+        let mut program = Program::new();
+
+        loop {
+            // Blank lines before a line, or at the end
+            while self.expr_parser.lexer.peek() == Some(&Token::Newline) {
+                self.expr_parser.lexer.next();
+            }
+            if self.expr_parser.lexer.peek().is_none() {
+                break;
+            }
+
+            match self.line() {
+                Ok((line_number, label, statement)) => {
+                    program.add_line(line_number, statement);
+                    if let Some(label) = label {
+                        program.add_label(line_number, label);
+                    }
+                }
+                Err(e) => {
+                    errors.push(e);
+
+                    // Carry on with the next line
+                    while !matches!(self.expr_parser.lexer.next(), Some(Token::Newline) | None) {}
+                }
+            }
+        }
+
+        (program, errors)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Printer;
+    use crate::test_support::parse;
+    use crate::tokens::LexError;
+
+    const GAME: &str = "\
+10 \"S\": CLS: WAIT 0
+20 DIM N(9)
+30 RESTORE: FOR I=1 TO 9: READ N(I): NEXT I
+40 INPUT \"GUESS\";G
+50 IF G=N(1) THEN 90
+60 PRINT USING \"###\";G;\" NO\"
+70 GOSUB 100: GOTO 40
+
+90 PRINT \"YES\": END
+100 BEEP 1: RETURN
+110 DATA 7,3,&1F,\"X\"
+";
+
+    #[test]
+    fn programs() {
+        let program = parse(GAME);
+        assert_eq!(
+            program.iter().map(|(line, _)| *line).collect::<Vec<_>>(),
+            [10, 20, 30, 40, 50, 60, 70, 90, 100, 110]
+        );
+        assert_eq!(program.label_line("S"), Some(10));
+        assert!(matches!(
+            program.lookup_line(50),
+            Some(Statement::If { then, .. }) if matches!(**then, Statement::Goto { line_number: Unsigned { value: 90, hex: false } })
+        ));
+        assert!(matches!(
+            program.lookup_line(60),
+            Some(Statement::Print { format: Some(format), content }) if format == "###" && content.len() == 2
+        ));
+
+        // The listing parses back to the same listing
+        let listing = Printer::new().build(&program);
+        assert_eq!(Printer::new().build(&parse(&listing)), listing);
+    }
+
+    #[test]
+    fn bad_lines_are_skipped() {
+        let (program, errors) = Parser::new(Lexer::new("10 PRINT 1\n20 FOR = 5\n30 END")).parse();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].line, 2);
+        assert_eq!(program.iter().count(), 2);
+        assert!(matches!(program.lookup_line(30), Some(Statement::End)));
+    }
+
+    #[test]
+    fn empty_index_is_an_error() {
+        let (program, errors) = Parser::new(Lexer::new("10 A() = 1\n20 END")).parse();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::ExpectedExpression);
+        assert_eq!(errors[0].line, 1);
+        assert!(matches!(program.lookup_line(20), Some(Statement::End)));
+    }
+
+    #[test]
+    fn lexical_errors() {
+        let (program, errors) = Parser::new(Lexer::new("10 POKE &, 1\n20 END")).parse();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].kind, ErrorKind::Lexical(LexError::HexNumber));
+        assert_eq!(errors[0].line, 1);
+        assert!(matches!(program.lookup_line(20), Some(Statement::End)));
+    }
+
+    #[test]
+    fn on_error_goto() {
+        let program = parse("10 ON ERROR GOTO 100\n100 END\n");
+        assert!(matches!(
+            program.lookup_line(10),
+            Some(Statement::OnErrorGoto {
+                line_number: Unsigned {
+                    value: 100,
+                    hex: false
+                }
+            })
+        ));
+    }
+    #[test]
+    fn hex_line_numbers_and_sizes() {
+        let program = parse(concat!(
+            "10 DIM A$(&A) * &14: GOSUB &1E: RESTORE &1E\n",
+            "20 ON ERROR GOTO &28: IF A THEN &14\n",
+            "&1E DATA 1: RETURN\n",
+        ));
+
+        assert!(matches!(
+            program.lookup_line(10),
+            Some(Statement::Seq { statements }) if matches!(
+                statements[1],
+                Statement::GoSub { line_number: Unsigned { value: 30, hex: true } }
+            )
+        ));
+        assert_eq!(
+            Printer::new().build(&program),
+            concat!(
+                "10 DIM A$(&A) * &14: GOSUB &1E: RESTORE &1E\n",
+                "20 ON ERROR GOTO &28: IF A THEN GOTO &14\n",
+                "30 DATA 1: RETURN\n",
+            )
+        );
+    }
 }
@@ -1,9 +1,10 @@
 use std::marker::PhantomData;
 
 use super::{
-    node::{DataItem, LValue, UnaryOperator},
+    node::{Coordinate, DataItem, Function, LValue, PrintItem, UnaryOperator, Unsigned},
     Expression, ExpressionVisitor, Program, ProgramVisitor, Statement, StatementVisitor,
 };
+use crate::tokens::Hex;
 
 pub struct Printer<'a> {
     output: String,
@@ -23,6 +24,41 @@ impl<'a> Printer<'a> {
         ast.accept(&mut visitor);
         visitor.output
     }
+
+    pub fn statement(self, statement: &'a Statement) -> String {
+        let mut visitor = Printer::new();
+        statement.accept(&mut visitor);
+        visitor.output
+    }
+
+    fn print_items(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
+        if let Some(format) = format {
+            self.output.push_str("USING ");
+            self.visit_string_literal(format);
+            if !content.is_empty() {
+                self.output.push_str("; ");
+            }
+        }
+        for item in content {
+            item.expression.accept(self);
+            if let Some(separator) = item.separator {
+                self.output.push_str(&separator.to_string());
+                self.output.push(' ');
+            }
+        }
+        // No space after a trailing separator
+        if content.last().is_some_and(|item| item.separator.is_some()) {
+            self.output.pop();
+        }
+    }
+
+    fn coordinate(&mut self, coordinate: &'a Coordinate) {
+        self.output.push('(');
+        coordinate.x.accept(self);
+        self.output.push_str(", ");
+        coordinate.y.accept(self);
+        self.output.push(')');
+    }
 }
 
 impl<'a> ExpressionVisitor<'a> for Printer<'a> {
@@ -30,6 +66,25 @@ impl<'a> ExpressionVisitor<'a> for Printer<'a> {
         self.output.push_str(&num.to_string());
     }
 
+    fn visit_hex_literal(&mut self, num: i32) {
+        self.output.push_str(&Hex(num).to_string());
+    }
+
+    fn visit_function(&mut self, function: Function, args: &'a [Expression]) {
+        self.output.push_str(&function.to_string());
+        if !function.parenthesized() {
+            return;
+        }
+        self.output.push('(');
+        for (i, arg) in args.iter().enumerate() {
+            if i > 0 {
+                self.output.push_str(", ");
+            }
+            arg.accept(self);
+        }
+        self.output.push(')');
+    }
+
     fn visit_variable(&mut self, variable: &'a LValue) {
         self.output.push_str(variable.to_string().as_str());
     }
@@ -69,24 +124,14 @@ impl<'a> StatementVisitor<'a> for Printer<'a> {
         expression.accept(self);
     }
 
-    fn visit_print(&mut self, content: &'a [Expression]) {
+    fn visit_print(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
         self.output.push_str("PRINT ");
-        for (i, item) in content.iter().enumerate() {
-            if i > 0 {
-                self.output.push_str("; ");
-            }
-            item.accept(self);
-        }
+        self.print_items(format, content);
     }
 
-    fn visit_pause(&mut self, content: &'a [Expression]) {
+    fn visit_pause(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
         self.output.push_str("PAUSE ");
-        for (i, item) in content.iter().enumerate() {
-            if i > 0 {
-                self.output.push_str("; ");
-            }
-            item.accept(self);
-        }
+        self.print_items(format, content);
     }
 
     fn visit_input(&mut self, prompt: Option<&'a Expression>, variable: &'a LValue) {
@@ -105,11 +150,16 @@ impl<'a> StatementVisitor<'a> for Printer<'a> {
         }
     }
 
-    fn visit_goto(&mut self, line_number: u32) {
+    fn visit_goto(&mut self, line_number: Unsigned) {
         self.output.push_str("GOTO ");
         self.output.push_str(&line_number.to_string());
     }
 
+    fn visit_on_error(&mut self, line_number: Unsigned) {
+        self.output.push_str("ON ERROR GOTO ");
+        self.output.push_str(&line_number.to_string());
+    }
+
     fn visit_for(
         &mut self,
         variable: &'a str,
@@ -138,7 +188,7 @@ impl<'a> StatementVisitor<'a> for Printer<'a> {
         self.output.push_str("END");
     }
 
-    fn visit_gosub(&mut self, line_number: u32) {
+    fn visit_gosub(&mut self, line_number: Unsigned) {
         self.output.push_str("GOSUB ");
         self.output.push_str(&line_number.to_string());
     }
@@ -194,36 +244,41 @@ impl<'a> StatementVisitor<'a> for Printer<'a> {
             }
             match value {
                 DataItem::Number(num) => self.output.push_str(&num.to_string()),
-                DataItem::String(string) => self.output.push_str(string),
+                DataItem::HexNumber(num) => self.visit_hex_literal(*num),
+                DataItem::String(string) => self.visit_string_literal(string),
             }
         }
     }
 
-    fn visit_restore(&mut self, line_number: Option<u32>) {
+    fn visit_restore(&mut self, line_number: Option<Unsigned>) {
         self.output.push_str("RESTORE ");
         if let Some(line_number) = line_number {
             self.output.push_str(&line_number.to_string());
         }
     }
 
-    fn visit_poke(&mut self, address: u32, values: &'a [u8]) {
+    fn visit_poke(&mut self, address: &'a Expression, values: &'a [Expression]) {
         self.output.push_str("POKE ");
-        self.output.push_str(&address.to_string());
+        address.accept(self);
         self.output.push_str(", ");
         for (i, value) in values.iter().enumerate() {
             if i > 0 {
                 self.output.push_str(", ");
             }
-            self.output.push_str(&value.to_string());
+            value.accept(self);
         }
     }
 
-    fn visit_call(&mut self, address: u32) {
+    fn visit_call(&mut self, address: &'a Expression, argument: Option<&'a Expression>) {
         self.output.push_str("CALL ");
-        self.output.push_str(&address.to_string());
+        address.accept(self);
+        if let Some(argument) = argument {
+            self.output.push_str(", ");
+            argument.accept(self);
+        }
     }
 
-    fn visit_dim(&mut self, variable: &'a str, size: u32, length: Option<u32>) {
+    fn visit_dim(&mut self, variable: &'a str, size: Unsigned, length: Option<Unsigned>) {
         self.output.push_str("DIM ");
         self.output.push_str(variable);
         self.output.push('(');
@@ -235,15 +290,150 @@ impl<'a> StatementVisitor<'a> for Printer<'a> {
             self.output.push_str(&length.to_string());
         }
     }
+
+    fn visit_cls(&mut self) {
+        self.output.push_str("CLS");
+    }
+
+    fn visit_cursor(&mut self, column: &'a Expression) {
+        self.output.push_str("CURSOR ");
+        column.accept(self);
+    }
+
+    fn visit_gcursor(&mut self, column: &'a Expression) {
+        self.output.push_str("GCURSOR ");
+        column.accept(self);
+    }
+
+    fn visit_gprint(&mut self, content: &'a [Expression]) {
+        self.output.push_str("GPRINT ");
+        for (i, item) in content.iter().enumerate() {
+            if i > 0 {
+                self.output.push_str("; ");
+            }
+            item.accept(self);
+        }
+    }
+
+    fn visit_beep(
+        &mut self,
+        count: &'a Expression,
+        frequency: Option<&'a Expression>,
+        duration: Option<&'a Expression>,
+    ) {
+        self.output.push_str("BEEP ");
+        count.accept(self);
+        for arg in frequency.into_iter().chain(duration) {
+            self.output.push_str(", ");
+            arg.accept(self);
+        }
+    }
+
+    fn visit_lprint(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
+        self.output.push_str("LPRINT ");
+        self.print_items(format, content);
+    }
+
+    fn visit_line(
+        &mut self,
+        relative: bool,
+        start: Option<&'a Coordinate>,
+        points: &'a [Coordinate],
+        line_type: Option<&'a Expression>,
+        color: Option<&'a Expression>,
+        boxed: bool,
+    ) {
+        self.output
+            .push_str(if relative { "RLINE " } else { "LINE " });
+        if let Some(start) = start {
+            self.coordinate(start);
+        }
+        for point in points {
+            self.output.push('-');
+            self.coordinate(point);
+        }
+        for arg in line_type.into_iter().chain(color) {
+            self.output.push_str(", ");
+            arg.accept(self);
+        }
+        if boxed {
+            self.output.push_str(", B");
+        }
+    }
+
+    fn visit_glcursor(&mut self, position: &'a Coordinate) {
+        self.output.push_str("GLCURSOR ");
+        self.coordinate(position);
+    }
+
+    fn visit_color(&mut self, color: &'a Expression) {
+        self.output.push_str("COLOR ");
+        color.accept(self);
+    }
+
+    fn visit_csize(&mut self, size: &'a Expression) {
+        self.output.push_str("CSIZE ");
+        size.accept(self);
+    }
+
+    fn visit_rotate(&mut self, direction: &'a Expression) {
+        self.output.push_str("ROTATE ");
+        direction.accept(self);
+    }
+
+    fn visit_graph(&mut self) {
+        self.output.push_str("GRAPH");
+    }
+
+    fn visit_text(&mut self) {
+        self.output.push_str("TEXT");
+    }
+
+    fn visit_sorgn(&mut self) {
+        self.output.push_str("SORGN");
+    }
+
+    fn visit_lf(&mut self, lines: &'a Expression) {
+        self.output.push_str("LF ");
+        lines.accept(self);
+    }
+
+    fn visit_tron(&mut self) {
+        self.output.push_str("TRON");
+    }
+
+    fn visit_troff(&mut self) {
+        self.output.push_str("TROFF");
+    }
 }
 
 impl<'a> ProgramVisitor<'a> for Printer<'a> {
     fn visit_program(&mut self, program: &'a Program) {
         for (line_number, ast) in program.iter() {
             self.output.push_str(&line_number.to_string());
+            self.output.push(' ');
+            if let Some(label) = program.label(*line_number) {
+                self.visit_string_literal(label);
+                self.output.push(' ');
+            }
 
             ast.accept(self);
             self.output.push('\n');
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Parser;
+    use crate::tokens::Lexer;
+
+    #[test]
+    fn hex_literals_keep_their_radix() {
+        let source = "10 POKE &7600, &FF, 12\n20 DATA &1F, 31\n";
+        let (program, errors) = Parser::new(Lexer::new(source)).parse();
+        assert!(errors.is_empty());
+        assert_eq!(Printer::new().build(&program), source);
+    }
+}
@@ -1,8 +1,21 @@
+use std::ops::{Range, RangeInclusive};
+
 use super::{
-    node::{LValue, UnaryOperator},
+    data::DataAnalysis,
+    flow::ControlFlow,
+    memory::MemoryEstimator,
+    node::{Coordinate, Function, LValue, PrintItem, UnaryOperator, Unsigned},
+    stack::{StackAnalysis, StackReport, DEFAULT_STACK_LIMIT},
     BinaryOperator, Expression, ExpressionVisitor, Program, ProgramVisitor, Statement,
     StatementVisitor,
 };
+use crate::machine::{
+    format::{Format, DISPLAY_COLUMNS, GRAPHICS_COLUMNS},
+    MemoryConfig, MemoryMap, Peripheral, Region, MAX_ADDRESS,
+};
+
+// Pen positions the CE-150 accepts, in 0.2 mm steps
+const PLOTTER_RANGE: RangeInclusive<i32> = -2048..=2047;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Ty {
@@ -19,32 +32,173 @@ impl std::fmt::Display for Ty {
     }
 }
 
+#[derive(Debug, Default)]
+pub struct Diagnostics {
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+    // Subroutines and stack depth, its errors and warnings are in the above
+    pub stack: StackReport,
+}
+
 pub struct SemanticChecker<'a> {
     program: &'a Program,
     errors: Vec<String>,
+    warnings: Vec<String>,
     // symbol_table: &'a SymbolTable<'a>,
     for_stack: Vec<&'a str>,
+    // Line being checked
+    line: u32,
+    memory: MemoryConfig,
+    memory_map: MemoryMap,
+    // Where the program and its variables end up in user RAM
+    program_area: Range<u32>,
+    variable_area: Range<u32>,
+    peripherals: &'a [Peripheral],
+    stack_limit: usize,
 }
 
 impl<'a> SemanticChecker<'a> {
-    pub fn new(program: &'a Program) -> Self {
+    pub fn new(program: &'a Program, memory: MemoryConfig, peripherals: &'a [Peripheral]) -> Self {
         SemanticChecker {
             errors: Vec::new(),
+            warnings: Vec::new(),
             for_stack: Vec::new(),
             program,
             // symbol_table,
+            line: 0,
+            memory,
+            memory_map: memory.memory_map(),
+            program_area: 0..0,
+            variable_area: 0..0,
+            peripherals,
+            stack_limit: DEFAULT_STACK_LIMIT,
         }
     }
 
-    pub fn check(mut self) -> Result<(), Vec<String>> {
+    // GOSUB and FOR frames the ROM's stack holds
+    pub fn set_stack_limit(&mut self, limit: usize) {
+        self.stack_limit = limit;
+    }
+
+    pub fn check(mut self) -> Diagnostics {
+        let mut estimator = MemoryEstimator::new(self.program);
+        estimator.set_stack_limit(self.stack_limit);
+        let usage = estimator.estimate(self.memory);
+        self.program_area = usage.program_area();
+        self.variable_area = usage.variable_area();
+
         self.program.accept(&mut self);
-        if self.errors.is_empty() {
-            Ok(())
-        } else {
-            Err(self.errors)
+
+        let flow = ControlFlow::new(self.program);
+        let mut stack = StackAnalysis::new(&flow);
+        stack.set_limit(self.stack_limit);
+        let mut stack = stack.analyze();
+        self.errors.append(&mut stack.errors);
+        self.warnings.append(&mut stack.warnings);
+
+        let mut data = DataAnalysis::new(&flow);
+        data.set_stack_limit(self.stack_limit);
+        let data = data.analyze();
+        self.errors.extend(data.errors);
+        self.warnings.extend(data.warnings);
+
+        Diagnostics {
+            errors: self.errors,
+            warnings: self.warnings,
+            stack,
         }
     }
 
+    // Address given as a literal, checked against the 17 bit address space.
+    // Other addresses are only known at runtime
+    fn constant_address(&mut self, address: &Expression, statement: &str) -> Option<u32> {
+        let value = address.as_constant()?;
+        match u32::try_from(value) {
+            Ok(valid) if valid <= MAX_ADDRESS => Some(valid),
+            _ => {
+                self.errors.push(format!(
+                    "{} address {} out of range in line {}",
+                    statement, value, self.line
+                ));
+                None
+            }
+        }
+    }
+
+    // USING formats are checked here, since a bad one stops the program on
+    // the device only when the statement runs
+    fn print_items(&mut self, statement: &str, format: Option<&'a str>, content: &'a [PrintItem]) {
+        let format = match format.map(Format::parse) {
+            Some(Ok(format)) => Some(format),
+            Some(Err(reason)) => {
+                self.errors.push(format!(
+                    "Invalid USING format in {} at line {}: {}",
+                    statement, self.line, reason
+                ));
+                None
+            }
+            None => None,
+        };
+
+        for item in content {
+            let ty = item.expression.accept(self);
+            let expected = match format {
+                Some(Format::Number { .. }) => Ty::Int,
+                Some(Format::String { .. }) => Ty::String,
+                None => continue,
+            };
+            if ty != expected {
+                self.errors.push(format!(
+                    "USING format in {} at line {} is for {}, item {} is {}",
+                    statement, self.line, expected, item.expression, ty
+                ));
+            }
+        }
+    }
+
+    // Integer argument of a display or sound statement. Constants are checked
+    // against the range the ROM accepts, anything else at runtime
+    fn integer_argument(
+        &mut self,
+        statement: &str,
+        argument: &'a Expression,
+        range: RangeInclusive<i32>,
+    ) {
+        if argument.accept(self) != Ty::Int {
+            self.errors.push(format!(
+                "{} argument must be an integer in line {}",
+                statement, self.line
+            ));
+        }
+        if let Some(value) = argument.as_constant() {
+            if !range.contains(&value) {
+                self.errors.push(format!(
+                    "{} argument {} out of range {}-{} in line {}",
+                    statement,
+                    value,
+                    range.start(),
+                    range.end(),
+                    self.line
+                ));
+            }
+        }
+    }
+
+    // Statements of a peripheral are unknown to the ROM without it
+    fn require(&mut self, peripheral: Peripheral, statement: &str) {
+        if !self.peripherals.contains(&peripheral) {
+            self.errors.push(format!(
+                "{} needs the {} in line {}, enable it with --peripheral",
+                statement, peripheral, self.line
+            ));
+        }
+    }
+
+    fn coordinate(&mut self, statement: &str, coordinate: &'a Coordinate) {
+        self.integer_argument(statement, &coordinate.x, PLOTTER_RANGE);
+        self.integer_argument(statement, &coordinate.y, PLOTTER_RANGE);
+    }
+
     fn get_ty(&self, name: &'a LValue) -> Ty {
         let name = match name {
             LValue::Variable(name) => name,
@@ -105,6 +259,8 @@ impl<'a> ExpressionVisitor<'a, Ty> for SemanticChecker<'a> {
         }
 
         match op {
+            // String concatenation
+            BinaryOperator::Add if left_ty == Ty::String => return Ty::String,
             BinaryOperator::Add
             | BinaryOperator::Sub
             | BinaryOperator::Mul
@@ -133,6 +289,25 @@ impl<'a> ExpressionVisitor<'a, Ty> for SemanticChecker<'a> {
     fn visit_string_literal(&mut self, _: &'a str) -> Ty {
         Ty::String
     }
+
+    fn visit_function(&mut self, function: Function, args: &'a [Expression]) -> Ty {
+        match function {
+            Function::Point => {
+                if let [column] = args {
+                    self.integer_argument("POINT", column, 0..=GRAPHICS_COLUMNS as i32 - 1);
+                } else {
+                    self.errors.push(format!(
+                        "POINT takes 1 argument, {} given in line {}",
+                        args.len(),
+                        self.line
+                    ));
+                }
+                Ty::Int
+            }
+            Function::Inkey => Ty::String,
+            Function::Ern | Function::Erl => Ty::Int,
+        }
+    }
 }
 
 impl<'a> StatementVisitor<'a> for SemanticChecker<'a> {
@@ -147,27 +322,27 @@ impl<'a> StatementVisitor<'a> for SemanticChecker<'a> {
         }
     }
 
-    fn visit_print(&mut self, content: &'a [Expression]) {
-        for item in content {
-            item.accept(self);
-        }
+    fn visit_print(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
+        self.print_items("PRINT", format, content);
     }
 
-    fn visit_pause(&mut self, content: &'a [Expression]) {
-        for item in content {
-            item.accept(self);
-        }
+    fn visit_pause(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
+        self.print_items("PAUSE", format, content);
     }
 
     fn visit_input(&mut self, _: Option<&'a Expression>, _: &'a LValue) {
         // TODO: check prompt is string? Are integer prompts allowed?
     }
 
-    fn visit_wait(&mut self, _: Option<&'a Expression>) {
-        // TODO: check time is in range? If possible
+    fn visit_wait(&mut self, time: Option<&'a Expression>) {
+        // In 1/64 s ticks
+        if let Some(time) = time {
+            self.integer_argument("WAIT", time, 0..=0xFFFF);
+        }
     }
 
-    fn visit_goto(&mut self, line_number: u32) {
+    fn visit_goto(&mut self, line_number: Unsigned) {
+        let line_number = line_number.value;
         let to_node = self.program.lookup_line(line_number);
         if to_node.is_none() {
             self.errors
@@ -224,12 +399,10 @@ impl<'a> StatementVisitor<'a> for SemanticChecker<'a> {
 
         if let Some(last) = self.for_stack.pop() {
             if last != variable {
-                self.errors.push(
-                    "NEXT variable: ".to_owned()
-                        + variable
-                        + " does not match FOR variable: "
-                        + last,
-                );
+                self.errors.push(format!(
+                    "NEXT variable: {} does not match FOR variable: {}",
+                    variable, last
+                ));
             }
         } else {
             self.errors.push("NEXT without matching FOR".to_owned());
@@ -238,7 +411,8 @@ impl<'a> StatementVisitor<'a> for SemanticChecker<'a> {
 
     fn visit_end(&mut self) {}
 
-    fn visit_gosub(&mut self, line_number: u32) {
+    fn visit_gosub(&mut self, line_number: Unsigned) {
+        let line_number = line_number.value;
         let to_node = self.program.lookup_line(line_number);
         if to_node.is_none() {
             self.errors
@@ -248,6 +422,14 @@ impl<'a> StatementVisitor<'a> for SemanticChecker<'a> {
 
     fn visit_return(&mut self) {}
 
+    fn visit_on_error(&mut self, line_number: Unsigned) {
+        let line_number = line_number.value;
+        if line_number != 0 && self.program.lookup_line(line_number).is_none() {
+            self.errors
+                .push(format!("ON ERROR GOTO to undefined line {}", line_number));
+        }
+    }
+
     fn visit_if(
         &mut self,
         condition: &'a Expression,
@@ -274,13 +456,14 @@ impl<'a> StatementVisitor<'a> for SemanticChecker<'a> {
     fn visit_rem(&mut self, _: &'a str) {}
 
     fn visit_read(&mut self, _variables: &'a [LValue]) {
-        // TODO: is it possible to check types of read variables? Probably not
+        // Types of read variables are checked against the DATA items by
+        // DataAnalysis, which follows the DATA pointer through the program
     }
 
     fn visit_data(&mut self, _values: &'a [super::node::DataItem]) {}
 
-    fn visit_restore(&mut self, line_number: Option<u32>) {
-        if let Some(line_number) = line_number {
+    fn visit_restore(&mut self, line_number: Option<Unsigned>) {
+        if let Some(line_number) = line_number.map(|line| line.value) {
             let to_node = self.program.lookup_line(line_number);
             if to_node.is_none() {
                 self.errors
@@ -301,23 +484,229 @@ impl<'a> StatementVisitor<'a> for SemanticChecker<'a> {
         }
     }
 
-    fn visit_poke(&mut self, _address: u32, _values: &'a [u8]) {
-        // TODO: maybe check adress is in wirtable memory?
-        // Check that the literals fit in a byte is done in parsing
+    fn visit_poke(&mut self, address: &'a Expression, values: &'a [Expression]) {
+        if address.accept(self) != Ty::Int {
+            self.errors.push(format!(
+                "POKE address must be an integer in line {}",
+                self.line
+            ));
+        }
+        for value in values {
+            if value.accept(self) != Ty::Int {
+                self.errors.push(format!(
+                    "POKE value must be an integer in line {}",
+                    self.line
+                ));
+            }
+            // Non constant values are checked by the interpreter
+            if let Some(value) = value.as_constant() {
+                if u8::try_from(value).is_err() {
+                    self.errors.push(format!(
+                        "POKE value {} out of range 0-255 in line {}",
+                        value, self.line
+                    ));
+                }
+            }
+        }
+
+        let Some(address) = self.constant_address(address, "POKE") else {
+            return;
+        };
+        let end = address.saturating_add(values.len() as u32);
+        for region in self.memory_map.regions(address..end) {
+            match region {
+                Region::Rom => self.errors.push(format!(
+                    "POKE into ROM at &{:04X} in line {}",
+                    address, self.line
+                )),
+                Region::Unmapped => self.warnings.push(format!(
+                    "POKE into unmapped memory at &{:04X} in line {}",
+                    address, self.line
+                )),
+                Region::UserRam => {
+                    if overlaps(&self.program_area, address..end) {
+                        self.warnings.push(format!(
+                            "POKE over the BASIC program at &{:04X} in line {}",
+                            address, self.line
+                        ));
+                    }
+                    if overlaps(&self.variable_area, address..end) {
+                        self.warnings.push(format!(
+                            "POKE over the variable area at &{:04X} in line {}",
+                            address, self.line
+                        ));
+                    }
+                }
+                Region::SystemRam | Region::ReserveArea | Region::DisplayBuffer | Region::Io => {}
+            }
+        }
     }
 
-    fn visit_call(&mut self, _address: u32) {
+    fn visit_call(&mut self, address: &'a Expression, argument: Option<&'a Expression>) {
+        if address.accept(self) != Ty::Int {
+            self.errors.push(format!(
+                "CALL address must be an integer in line {}",
+                self.line
+            ));
+        }
+        if let Some(argument) = argument {
+            if argument.accept(self) != Ty::Int {
+                self.errors.push(format!(
+                    "CALL argument must be an integer in line {}",
+                    self.line
+                ));
+            }
+        }
+
         // TODO: maybe check that there is a matching POKE to the address? Although this is not a strict requirement
+        let Some(address) = self.constant_address(address, "CALL") else {
+            return;
+        };
+        if self.memory_map.region(address) == Region::Unmapped {
+            self.warnings.push(format!(
+                "CALL into unmapped memory at &{:04X} in line {}",
+                address, self.line
+            ));
+        }
+    }
+
+    fn visit_cls(&mut self) {}
+
+    fn visit_cursor(&mut self, column: &'a Expression) {
+        self.integer_argument("CURSOR", column, 0..=DISPLAY_COLUMNS as i32 - 1);
+    }
+
+    fn visit_gcursor(&mut self, column: &'a Expression) {
+        self.integer_argument("GCURSOR", column, 0..=GRAPHICS_COLUMNS as i32 - 1);
+    }
+
+    fn visit_gprint(&mut self, content: &'a [Expression]) {
+        for item in content {
+            // A string gives several columns as pairs of hex digits
+            if let Expression::String(pattern) = item {
+                if !is_column_pattern(pattern) {
+                    self.errors.push(format!(
+                        "GPRINT pattern \"{}\" must be pairs of hex digits up to 7F in line {}",
+                        pattern, self.line
+                    ));
+                }
+                continue;
+            }
+            // Only the 7 low bits are dots on the display, patterns held in
+            // string variables are checked at runtime
+            if item.accept(self) == Ty::String {
+                continue;
+            }
+            if let Some(value) = item.as_constant() {
+                if !(0..=0x7F).contains(&value) {
+                    self.errors.push(format!(
+                        "GPRINT argument {} out of range 0-127 in line {}",
+                        value, self.line
+                    ));
+                }
+            }
+        }
+    }
+
+    fn visit_beep(
+        &mut self,
+        count: &'a Expression,
+        frequency: Option<&'a Expression>,
+        duration: Option<&'a Expression>,
+    ) {
+        self.integer_argument("BEEP", count, 0..=0xFFFF);
+        if let Some(frequency) = frequency {
+            self.integer_argument("BEEP", frequency, 0..=0xFF);
+        }
+        if let Some(duration) = duration {
+            self.integer_argument("BEEP", duration, 0..=0xFFFF);
+        }
+    }
+
+    fn visit_lprint(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
+        self.require(Peripheral::Ce150, "LPRINT");
+        self.print_items("LPRINT", format, content);
+    }
+
+    fn visit_line(
+        &mut self,
+        relative: bool,
+        start: Option<&'a Coordinate>,
+        points: &'a [Coordinate],
+        line_type: Option<&'a Expression>,
+        color: Option<&'a Expression>,
+        boxed: bool,
+    ) {
+        let statement = if relative { "RLINE" } else { "LINE" };
+        self.require(Peripheral::Ce150, statement);
+
+        for point in start.into_iter().chain(points) {
+            self.coordinate(statement, point);
+        }
+        if let Some(line_type) = line_type {
+            self.integer_argument(statement, line_type, 0..=9);
+        }
+        if let Some(color) = color {
+            self.integer_argument(statement, color, 0..=3);
+        }
+        if boxed && start.into_iter().chain(points).count() != 2 {
+            self.errors.push(format!(
+                "{} with B needs exactly two corners in line {}",
+                statement, self.line
+            ));
+        }
+    }
+
+    fn visit_glcursor(&mut self, position: &'a Coordinate) {
+        self.require(Peripheral::Ce150, "GLCURSOR");
+        self.coordinate("GLCURSOR", position);
+    }
+
+    fn visit_color(&mut self, color: &'a Expression) {
+        self.require(Peripheral::Ce150, "COLOR");
+        self.integer_argument("COLOR", color, 0..=3);
+    }
+
+    fn visit_csize(&mut self, size: &'a Expression) {
+        self.require(Peripheral::Ce150, "CSIZE");
+        self.integer_argument("CSIZE", size, 1..=9);
     }
 
-    fn visit_dim(&mut self, variable: &'a str, size: u32, length: Option<u32>) {
+    fn visit_rotate(&mut self, direction: &'a Expression) {
+        self.require(Peripheral::Ce150, "ROTATE");
+        self.integer_argument("ROTATE", direction, 0..=3);
+    }
+
+    fn visit_graph(&mut self) {
+        self.require(Peripheral::Ce150, "GRAPH");
+    }
+
+    fn visit_text(&mut self) {
+        self.require(Peripheral::Ce150, "TEXT");
+    }
+
+    fn visit_sorgn(&mut self) {
+        self.require(Peripheral::Ce150, "SORGN");
+    }
+
+    fn visit_lf(&mut self, lines: &'a Expression) {
+        self.require(Peripheral::Ce150, "LF");
+        // Negative counts feed the paper backwards
+        self.integer_argument("LF", lines, -0xFF..=0xFF);
+    }
+
+    fn visit_tron(&mut self) {}
+
+    fn visit_troff(&mut self) {}
+
+    fn visit_dim(&mut self, variable: &'a str, size: Unsigned, length: Option<Unsigned>) {
         let var_ty = if variable.ends_with("$") {
             Ty::String
         } else {
             Ty::Int
         };
 
-        if size > 255 {
+        if size.value > 255 {
             self.errors
                 .push("Array size must be between 0 and 255".to_owned());
         }
@@ -328,7 +717,7 @@ impl<'a> StatementVisitor<'a> for SemanticChecker<'a> {
         }
 
         if let Some(length) = length {
-            if !(1..=80).contains(&length) {
+            if !(1..=80).contains(&length.value) {
                 self.errors
                     .push("String length must be between 1 and 80".to_owned());
             }
@@ -338,8 +727,90 @@ impl<'a> StatementVisitor<'a> for SemanticChecker<'a> {
 
 impl<'a> ProgramVisitor<'a> for SemanticChecker<'a> {
     fn visit_program(&mut self, program: &'a Program) {
-        for statement in program.values() {
+        for (line, statement) in program.iter() {
+            self.line = *line;
             statement.accept(self);
         }
     }
 }
+
+fn overlaps(area: &Range<u32>, addresses: Range<u32>) -> bool {
+    addresses.start < area.end && area.start < addresses.end
+}
+
+fn is_column_pattern(pattern: &str) -> bool {
+    let digits: Vec<u32> = pattern.chars().filter_map(|c| c.to_digit(16)).collect();
+    digits.len() == pattern.len()
+        && digits.len().is_multiple_of(2)
+        && digits
+            .chunks(2)
+            .all(|pair| pair.first().is_some_and(|high| *high < 8))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::parse;
+
+    fn check(program: &Program, peripherals: &[Peripheral]) -> Diagnostics {
+        SemanticChecker::new(program, MemoryConfig::Pc1500, peripherals).check()
+    }
+
+    #[test]
+    fn plotter_needs_ce150() {
+        let program = parse("10 COLOR 2\n20 LINE (0, 0)-(100, -50), 0, 4, B\n");
+
+        let without = check(&program, &[]);
+        let with = check(&program, &[Peripheral::Ce150]);
+
+        assert_eq!(
+            without.errors,
+            vec![
+                "COLOR needs the CE-150 in line 10, enable it with --peripheral",
+                "LINE needs the CE-150 in line 20, enable it with --peripheral",
+                "LINE argument 4 out of range 0-3 in line 20",
+            ]
+        );
+        assert_eq!(
+            with.errors,
+            vec!["LINE argument 4 out of range 0-3 in line 20"]
+        );
+    }
+
+    #[test]
+    fn wait_range() {
+        let program = parse("10 WAIT 64\n20 WAIT 70000\n");
+
+        assert_eq!(
+            check(&program, &[]).errors,
+            vec!["WAIT argument 70000 out of range 0-65535 in line 20"]
+        );
+    }
+
+    #[test]
+    fn pokes_and_calls_against_the_memory_map() {
+        let program = parse(concat!(
+            "10 DIM A(10)\n",
+            "20 POKE &C000, 1\n",
+            "30 POKE &40C5, 0\n",
+            "40 POKE &47FF, 0\n",
+            "50 POKE &7600, 255: CALL &E000\n",
+            "60 CALL &9000\n",
+        ));
+
+        let diagnostics = check(&program, &[]);
+
+        assert_eq!(
+            diagnostics.errors,
+            vec!["POKE into ROM at &C000 in line 20"]
+        );
+        assert_eq!(
+            diagnostics.warnings,
+            vec![
+                "POKE over the BASIC program at &40C5 in line 30",
+                "POKE over the variable area at &47FF in line 40",
+                "CALL into unmapped memory at &9000 in line 60",
+            ]
+        );
+    }
+}
@@ -0,0 +1,400 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::flow::ControlFlow;
+use super::Statement;
+
+// GOSUB return addresses and FOR frames share the same stack in the ROM
+// interpreter, and nesting deeper than it holds stops the program with an
+// error. The depth is not documented, so this is only a default: set the one
+// measured on the target with set_limit, or --stack-limit
+pub const DEFAULT_STACK_LIMIT: usize = 15;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Depth {
+    pub gosub: usize,
+    pub for_: usize,
+}
+
+impl Depth {
+    pub fn total(self) -> usize {
+        self.gosub + self.for_
+    }
+
+    // FOR frames are bigger than GOSUB frames, so prefer them on ties
+    fn max(self, other: Depth) -> Depth {
+        if (other.total(), other.for_) > (self.total(), self.for_) {
+            other
+        } else {
+            self
+        }
+    }
+}
+
+impl std::fmt::Display for Depth {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} ({} GOSUB + {} FOR)",
+            self.total(),
+            self.gosub,
+            self.for_
+        )
+    }
+}
+
+#[derive(Debug)]
+pub struct Subroutine {
+    // Lines executed between the GOSUB and the matching RETURN
+    pub lines: BTreeSet<u32>,
+    // Entry lines of the subroutines called from this one
+    pub calls: BTreeSet<u32>,
+    // Stack frames pushed by the subroutine and its callees, GOSUB included.
+    // None when recursion makes it unbounded
+    pub depth: Option<Depth>,
+}
+
+#[derive(Debug, Default)]
+pub struct StackReport {
+    // Subroutines by entry line
+    pub subroutines: BTreeMap<u32, Subroutine>,
+    // Worst case for the whole program, None when unbounded
+    pub depth: Option<Depth>,
+    pub errors: Vec<String>,
+    pub warnings: Vec<String>,
+}
+
+impl std::fmt::Display for StackReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (line, subroutine) in &self.subroutines {
+            write!(f, "Subroutine {}: lines", line)?;
+            for body_line in &subroutine.lines {
+                write!(f, " {}", body_line)?;
+            }
+            if !subroutine.calls.is_empty() {
+                write!(f, ", calls")?;
+                for callee in &subroutine.calls {
+                    write!(f, " {}", callee)?;
+                }
+            }
+            match subroutine.depth {
+                Some(depth) => writeln!(f, ", depth {}", depth)?,
+                None => writeln!(f, ", recursive")?,
+            }
+        }
+
+        match self.depth {
+            Some(depth) => writeln!(f, "Worst-case stack depth: {}", depth),
+            None => writeln!(f, "Worst-case stack depth: unbounded"),
+        }
+    }
+}
+
+// Local facts about one procedure: the main program or a subroutine
+struct Procedure {
+    lines: BTreeSet<u32>,
+    // Deepest FOR nesting reached inside the procedure itself
+    for_depth: usize,
+    // (FOR depth at the call site, called subroutine entry line)
+    call_sites: Vec<(usize, u32)>,
+    returns: Vec<u32>,
+}
+
+pub struct StackAnalysis<'a, 'b> {
+    flow: &'b ControlFlow<'a>,
+    procedures: BTreeMap<u32, Procedure>,
+    depths: BTreeMap<u32, Option<Depth>>,
+    limit: usize,
+    report: StackReport,
+}
+
+impl<'a, 'b> StackAnalysis<'a, 'b> {
+    pub fn new(flow: &'b ControlFlow<'a>) -> Self {
+        StackAnalysis {
+            flow,
+            procedures: BTreeMap::new(),
+            depths: BTreeMap::new(),
+            limit: DEFAULT_STACK_LIMIT,
+            report: StackReport::default(),
+        }
+    }
+
+    // GOSUB and FOR frames the ROM's stack holds
+    pub fn set_limit(&mut self, limit: usize) {
+        self.limit = limit;
+    }
+
+    pub fn analyze(mut self) -> StackReport {
+        let Some(entry) = self.flow.entry() else {
+            return self.report;
+        };
+        let main_line = self.flow.node(entry).line;
+
+        // Discover every procedure reachable from the main program
+        let mut worklist = vec![main_line];
+        while let Some(line) = worklist.pop() {
+            if self.procedures.contains_key(&line) {
+                continue;
+            }
+
+            let procedure = self.procedure(line);
+            worklist.extend(procedure.call_sites.iter().map(|(_, callee)| *callee));
+            self.procedures.insert(line, procedure);
+        }
+
+        let main = &self.procedures[&main_line];
+        for line in &main.returns {
+            self.report
+                .errors
+                .push(format!("RETURN without GOSUB at line {}", line));
+        }
+
+        let mut path = Vec::new();
+        let depth = self.depth(main_line, &mut path);
+
+        let lines: Vec<u32> = self.procedures.keys().copied().collect();
+        for line in lines {
+            if line == main_line {
+                continue;
+            }
+
+            let procedure = self
+                .procedures
+                .remove(&line)
+                .expect("procedure was discovered");
+            self.report.subroutines.insert(
+                line,
+                Subroutine {
+                    lines: procedure.lines,
+                    calls: procedure
+                        .call_sites
+                        .iter()
+                        .map(|(_, callee)| *callee)
+                        .collect(),
+                    depth: self.depths.get(&line).copied().flatten(),
+                },
+            );
+        }
+
+        self.report.depth = depth;
+        if let Some(depth) = depth {
+            if depth.total() > self.limit {
+                self.report.warnings.push(format!(
+                    "Worst-case GOSUB/FOR nesting depth {} exceeds the limit of {}",
+                    depth, self.limit
+                ));
+            }
+        }
+
+        self.report
+    }
+
+    // Walks the statements reachable from a procedure entry without following
+    // GOSUBs, tracking the FOR loops open at every statement
+    fn procedure(&self, line: u32) -> Procedure {
+        let mut procedure = Procedure {
+            lines: BTreeSet::new(),
+            for_depth: 0,
+            call_sites: Vec::new(),
+            returns: Vec::new(),
+        };
+
+        let Some(entry) = self.flow.line_entry(line) else {
+            return procedure;
+        };
+
+        // Variables of the open FOR loops, outermost first. Re-entering a FOR
+        // discards its frame and the ones nested inside it, like the ROM does,
+        // so each variable has at most one frame and the walk is finite
+        let mut seen: BTreeSet<(usize, Vec<&str>)> = BTreeSet::new();
+        let mut worklist = vec![(entry, Vec::new())];
+
+        while let Some((id, loops)) = worklist.pop() {
+            if !seen.insert((id, loops.clone())) {
+                continue;
+            }
+
+            let node = self.flow.node(id);
+            procedure.lines.insert(node.line);
+
+            let mut after = loops.clone();
+            match node.statement {
+                Statement::For { variable, .. } => {
+                    if let Some(index) = after.iter().position(|open| open == variable) {
+                        after.truncate(index);
+                    }
+                    after.push(variable.as_str());
+                }
+                Statement::Next { variable } => {
+                    if let Some(index) = after.iter().rposition(|open| open == variable) {
+                        after.truncate(index);
+                    }
+                }
+                _ => {}
+            }
+            procedure.for_depth = procedure.for_depth.max(after.len());
+
+            match node.statement {
+                Statement::GoSub { line_number }
+                    if self.flow.line_entry(line_number.value).is_some() =>
+                {
+                    procedure.call_sites.push((loops.len(), line_number.value));
+                }
+                Statement::Return => procedure.returns.push(node.line),
+                _ => {}
+            }
+
+            for successor in self.flow.successors(id) {
+                worklist.push((successor, after.clone()));
+            }
+        }
+
+        procedure.call_sites.sort_unstable();
+        procedure.call_sites.dedup();
+        procedure.returns.sort_unstable();
+        procedure.returns.dedup();
+
+        procedure
+    }
+
+    // Frames pushed by a procedure and everything it calls. `path` holds the
+    // procedures currently being evaluated, to detect recursion
+    fn depth(&mut self, line: u32, path: &mut Vec<u32>) -> Option<Depth> {
+        if let Some(depth) = self.depths.get(&line) {
+            return *depth;
+        }
+
+        if let Some(start) = path.iter().position(|&l| l == line) {
+            let cycle: Vec<String> = path[start..]
+                .iter()
+                .chain(std::iter::once(&line))
+                .map(u32::to_string)
+                .collect();
+            self.report
+                .warnings
+                .push(format!("Recursive GOSUB: {}", cycle.join(" -> ")));
+            return None;
+        }
+
+        path.push(line);
+
+        let procedure = &self.procedures[&line];
+        let mut result = Some(Depth {
+            gosub: 0,
+            for_: procedure.for_depth,
+        });
+
+        for (for_depth, callee) in procedure.call_sites.clone() {
+            let callee_depth = self.depth(callee, path);
+            result = match (result, callee_depth) {
+                (Some(depth), Some(callee_depth)) => Some(depth.max(Depth {
+                    gosub: callee_depth.gosub + 1,
+                    for_: callee_depth.for_ + for_depth,
+                })),
+                _ => None,
+            };
+        }
+
+        path.pop();
+
+        // The main program has no GOSUB frame of its own; subroutines are
+        // accounted for by their callers
+        self.depths.insert(line, result);
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Program;
+    use crate::test_support::parse;
+
+    fn analyze(program: &Program) -> StackReport {
+        let flow = ControlFlow::new(program);
+        StackAnalysis::new(&flow).analyze()
+    }
+
+    #[test]
+    fn subroutine_body() {
+        let program = parse("10 GOSUB 100\n20 END\n100 REM sub\n110 RETURN\n");
+
+        let report = analyze(&program);
+
+        assert!(report.errors.is_empty());
+        let subroutine = &report.subroutines[&100];
+        assert_eq!(subroutine.lines, BTreeSet::from([100, 110]));
+        assert_eq!(report.depth, Some(Depth { gosub: 1, for_: 0 }));
+    }
+
+    #[test]
+    fn return_without_gosub() {
+        // Missing END: falls into the subroutine
+        let program = parse("10 GOSUB 100\n100 RETURN\n");
+
+        let report = analyze(&program);
+
+        assert_eq!(report.errors, vec!["RETURN without GOSUB at line 100"]);
+    }
+
+    #[test]
+    fn recursion() {
+        let program = parse("10 GOSUB 100\n20 END\n100 GOSUB 100\n110 RETURN\n");
+
+        let report = analyze(&program);
+
+        assert_eq!(report.depth, None);
+        assert_eq!(report.warnings, vec!["Recursive GOSUB: 100 -> 100"]);
+    }
+
+    #[test]
+    fn nested_for_inside_subroutine() {
+        let program = parse(concat!(
+            "10 FOR I = 1 TO 10\n",
+            "20 GOSUB 100\n",
+            "30 NEXT I\n",
+            "40 END\n",
+            "100 FOR J = 1 TO 10\n",
+            "110 NEXT J\n",
+            "120 RETURN\n",
+        ));
+
+        let report = analyze(&program);
+
+        assert_eq!(report.depth, Some(Depth { gosub: 1, for_: 2 }));
+        assert!(report.warnings.is_empty());
+
+        let flow = ControlFlow::new(&program);
+        let mut analysis = StackAnalysis::new(&flow);
+        analysis.set_limit(2);
+        assert_eq!(
+            analysis.analyze().warnings,
+            vec!["Worst-case GOSUB/FOR nesting depth 3 (1 GOSUB + 2 FOR) exceeds the limit of 2"]
+        );
+    }
+
+    #[test]
+    fn search_loop_left_with_goto() {
+        // Every pass through line 10 reuses the frame of I
+        let program = parse(concat!(
+            "10 FOR I = 1 TO 9: IF A(I) = K THEN GOTO 30\n",
+            "20 NEXT I\n",
+            "30 K = K + 1: GOTO 10\n",
+        ));
+
+        let report = analyze(&program);
+
+        assert_eq!(report.depth, Some(Depth { gosub: 0, for_: 1 }));
+        assert!(report.warnings.is_empty());
+    }
+
+    #[test]
+    fn empty_line_falls_through() {
+        let mut program = parse("10 GOSUB 100\n30 END\n100 RETURN\n");
+        program.add_line(20, Statement::Seq { statements: vec![] });
+
+        let report = analyze(&program);
+
+        assert!(report.errors.is_empty());
+        assert_eq!(report.depth, Some(Depth { gosub: 1, for_: 0 }));
+    }
+}
@@ -1,10 +1,14 @@
 use super::{
-    node::{DataItem, LValue, UnaryOperator},
+    node::{Coordinate, DataItem, Function, LValue, PrintItem, UnaryOperator, Unsigned},
     BinaryOperator, Expression, Program, Statement,
 };
 
 pub trait ExpressionVisitor<'a, RetTy = ()> {
     fn visit_number_literal(&mut self, num: i32) -> RetTy;
+    // Only the printers care about the radix
+    fn visit_hex_literal(&mut self, num: i32) -> RetTy {
+        self.visit_number_literal(num)
+    }
     fn visit_string_literal(&mut self, content: &'a str) -> RetTy;
     fn visit_variable(&mut self, lvalue: &'a LValue) -> RetTy;
     fn visit_unary_op(&mut self, op: UnaryOperator, operand: &'a Expression) -> RetTy;
@@ -14,32 +18,35 @@ pub trait ExpressionVisitor<'a, RetTy = ()> {
         op: BinaryOperator,
         right: &'a Expression,
     ) -> RetTy;
+    fn visit_function(&mut self, function: Function, args: &'a [Expression]) -> RetTy;
 }
 
 impl<'a> Expression {
     pub fn accept<V: ExpressionVisitor<'a, RetTy>, RetTy>(&'a self, visitor: &mut V) -> RetTy {
         match self {
             Expression::Number(num) => visitor.visit_number_literal(*num),
+            Expression::HexNumber(num) => visitor.visit_hex_literal(*num),
             Expression::String(content) => visitor.visit_string_literal(content),
             Expression::LValue(variable) => visitor.visit_variable(variable),
             Expression::Unary { op, operand } => visitor.visit_unary_op(*op, operand),
             Expression::Binary { left, op, right } => visitor.visit_binary_op(left, *op, right),
+            Expression::Function { function, args } => visitor.visit_function(*function, args),
         }
     }
 }
 
 pub trait StatementVisitor<'a, RetTy = ()> {
     fn visit_let(&mut self, variable: &'a LValue, expression: &'a Expression) -> RetTy;
-    fn visit_print(&mut self, content: &'a [Expression]) -> RetTy;
-    fn visit_pause(&mut self, content: &'a [Expression]) -> RetTy;
+    fn visit_print(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) -> RetTy;
+    fn visit_pause(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) -> RetTy;
     fn visit_input(&mut self, prompt: Option<&'a Expression>, variable: &'a LValue) -> RetTy;
     fn visit_wait(&mut self, time: Option<&'a Expression>) -> RetTy;
     fn visit_read(&mut self, variables: &'a [LValue]) -> RetTy;
     fn visit_data(&mut self, values: &'a [DataItem]) -> RetTy;
-    fn visit_restore(&mut self, line_number: Option<u32>) -> RetTy;
-    fn visit_poke(&mut self, address: u32, values: &'a [u8]) -> RetTy;
-    fn visit_call(&mut self, address: u32) -> RetTy;
-    fn visit_goto(&mut self, line_number: u32) -> RetTy;
+    fn visit_restore(&mut self, line_number: Option<Unsigned>) -> RetTy;
+    fn visit_poke(&mut self, address: &'a Expression, values: &'a [Expression]) -> RetTy;
+    fn visit_call(&mut self, address: &'a Expression, argument: Option<&'a Expression>) -> RetTy;
+    fn visit_goto(&mut self, line_number: Unsigned) -> RetTy;
     fn visit_for(
         &mut self,
         variable: &'a str,
@@ -49,8 +56,9 @@ pub trait StatementVisitor<'a, RetTy = ()> {
     ) -> RetTy;
     fn visit_next(&mut self, variable: &'a str) -> RetTy;
     fn visit_end(&mut self) -> RetTy;
-    fn visit_gosub(&mut self, line_number: u32) -> RetTy;
+    fn visit_gosub(&mut self, line_number: Unsigned) -> RetTy;
     fn visit_return(&mut self) -> RetTy;
+    fn visit_on_error(&mut self, line_number: Unsigned) -> RetTy;
     fn visit_if(
         &mut self,
         condition: &'a Expression,
@@ -59,7 +67,37 @@ pub trait StatementVisitor<'a, RetTy = ()> {
     ) -> RetTy;
     fn visit_seq(&mut self, statements: &'a [Statement]) -> RetTy;
     fn visit_rem(&mut self, content: &'a str) -> RetTy;
-    fn visit_dim(&mut self, variable: &'a str, size: u32, length: Option<u32>) -> RetTy;
+    fn visit_dim(&mut self, variable: &'a str, size: Unsigned, length: Option<Unsigned>) -> RetTy;
+    fn visit_cls(&mut self) -> RetTy;
+    fn visit_cursor(&mut self, column: &'a Expression) -> RetTy;
+    fn visit_gcursor(&mut self, column: &'a Expression) -> RetTy;
+    fn visit_gprint(&mut self, content: &'a [Expression]) -> RetTy;
+    fn visit_beep(
+        &mut self,
+        count: &'a Expression,
+        frequency: Option<&'a Expression>,
+        duration: Option<&'a Expression>,
+    ) -> RetTy;
+    fn visit_lprint(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) -> RetTy;
+    fn visit_line(
+        &mut self,
+        relative: bool,
+        start: Option<&'a Coordinate>,
+        points: &'a [Coordinate],
+        line_type: Option<&'a Expression>,
+        color: Option<&'a Expression>,
+        boxed: bool,
+    ) -> RetTy;
+    fn visit_glcursor(&mut self, position: &'a Coordinate) -> RetTy;
+    fn visit_color(&mut self, color: &'a Expression) -> RetTy;
+    fn visit_csize(&mut self, size: &'a Expression) -> RetTy;
+    fn visit_rotate(&mut self, direction: &'a Expression) -> RetTy;
+    fn visit_graph(&mut self) -> RetTy;
+    fn visit_text(&mut self) -> RetTy;
+    fn visit_sorgn(&mut self) -> RetTy;
+    fn visit_lf(&mut self, lines: &'a Expression) -> RetTy;
+    fn visit_tron(&mut self) -> RetTy;
+    fn visit_troff(&mut self) -> RetTy;
 }
 
 impl<'a> Statement {
@@ -74,16 +112,21 @@ impl<'a> Statement {
                 variable,
                 expression,
             } => visitor.visit_let(variable, expression),
-            Statement::Print { content } => visitor.visit_print(content.as_slice()),
-            Statement::Pause { content } => visitor.visit_pause(content.as_slice()),
+            Statement::Print { format, content } => {
+                visitor.visit_print(format.as_deref(), content.as_slice())
+            }
+            Statement::Pause { format, content } => {
+                visitor.visit_pause(format.as_deref(), content.as_slice())
+            }
             Statement::Input { prompt, variable } => visitor.visit_input(prompt.as_ref(), variable),
             Statement::Wait { time } => visitor.visit_wait(time.as_ref()),
             Statement::Data { values } => visitor.visit_data(values.as_slice()),
             Statement::Read { variables } => visitor.visit_read(variables.as_slice()),
             Statement::Restore { line_number } => visitor.visit_restore(*line_number),
-            Statement::Poke { address, values } => visitor.visit_poke(*address, values.as_slice()),
-            Statement::Call { address } => visitor.visit_call(*address),
+            Statement::Poke { address, values } => visitor.visit_poke(address, values.as_slice()),
+            Statement::Call { address, argument } => visitor.visit_call(address, argument.as_ref()),
             Statement::Goto { line_number } => visitor.visit_goto(*line_number),
+            Statement::OnErrorGoto { line_number } => visitor.visit_on_error(*line_number),
             Statement::For {
                 variable,
                 from,
@@ -101,6 +144,43 @@ impl<'a> Statement {
             } => visitor.visit_if(condition, then, else_.as_deref()),
             Statement::Seq { statements } => visitor.visit_seq(statements),
             Statement::Rem { content } => visitor.visit_rem(content),
+            Statement::Cls => visitor.visit_cls(),
+            Statement::Cursor { column } => visitor.visit_cursor(column),
+            Statement::GCursor { column } => visitor.visit_gcursor(column),
+            Statement::GPrint { content } => visitor.visit_gprint(content),
+            Statement::Beep {
+                count,
+                frequency,
+                duration,
+            } => visitor.visit_beep(count, frequency.as_ref(), duration.as_ref()),
+            Statement::LPrint { format, content } => {
+                visitor.visit_lprint(format.as_deref(), content.as_slice())
+            }
+            Statement::Line {
+                relative,
+                start,
+                points,
+                line_type,
+                color,
+                boxed,
+            } => visitor.visit_line(
+                *relative,
+                start.as_ref(),
+                points,
+                line_type.as_ref(),
+                color.as_ref(),
+                *boxed,
+            ),
+            Statement::GlCursor { position } => visitor.visit_glcursor(position),
+            Statement::Color { color } => visitor.visit_color(color),
+            Statement::CSize { size } => visitor.visit_csize(size),
+            Statement::Rotate { direction } => visitor.visit_rotate(direction),
+            Statement::Graph => visitor.visit_graph(),
+            Statement::Text => visitor.visit_text(),
+            Statement::Sorgn => visitor.visit_sorgn(),
+            Statement::Lf { lines } => visitor.visit_lf(lines),
+            Statement::Tron => visitor.visit_tron(),
+            Statement::Troff => visitor.visit_troff(),
         }
     }
 }
@@ -0,0 +1,1134 @@
+// LH5801 assembly backend
+//
+// Lowers the optimized IR to assembly for the PC-1500's CPU, followed by the
+// runtime library in runtime.asm. Numbers the range analysis proves to be
+// 16-bit integers are stored and computed natively; everything else keeps the
+// ROM's 8 byte BCD format and goes through its arithmetic. Each line starts
+// with its BASIC source and each instruction with its IR form, so the output
+// can be checked against the program.
+//
+// Runtime routines take pointers: X to the source or left operand, U to the
+// right operand and Y to the destination.
+
+use std::collections::BTreeMap;
+
+use crate::ast::{self, BinaryOperator, Printer, Separator, UnaryOperator};
+use crate::ir::{
+    BlockId, Constant, Device, Instruction, Intrinsic, Operand, Program, Range, RangeReport, Temp,
+    Terminator, Type, Width,
+};
+use crate::machine::decimal::Decimal;
+use crate::machine::error::ErrorCode;
+
+const RUNTIME: &str = include_str!("runtime.asm");
+// Addresses of the ROM entry points the runtime calls
+pub const ROM_SYMBOLS: &str = include_str!("rom.sym");
+
+// Longest string an operation produces
+const STRING_LENGTH: u8 = 80;
+// String variables and array elements without a DIM length
+const DEFAULT_STRING_LENGTH: u8 = 16;
+const IMPLICIT_ARRAY_SIZE: u32 = 11;
+
+// Errors the runtime raises itself
+const RUNTIME_ERRORS: [(&str, ErrorCode); 5] = [
+    ("ERR_NESTING", ErrorCode::Nesting),
+    ("ERR_ARRAY", ErrorCode::Array),
+    ("ERR_TYPE", ErrorCode::TypeMismatch),
+    ("ERR_ARGUMENT", ErrorCode::Argument),
+    ("ERR_DIVISION", ErrorCode::DivisionByZero),
+];
+
+// How a value is kept in memory
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    // 16-bit two's complement, high byte first
+    Integer,
+    // 8 byte BCD
+    Number,
+    // Length byte and room for the given number of characters
+    String(u8),
+}
+
+impl Slot {
+    fn of(ty: Type, range: Range) -> Slot {
+        match ty {
+            Type::String => Slot::String(STRING_LENGTH),
+            Type::Number if range.width() == Width::Int16 => Slot::Integer,
+            Type::Number => Slot::Number,
+        }
+    }
+
+    fn size(self) -> u32 {
+        match self {
+            Slot::Integer => 2,
+            Slot::Number => 8,
+            Slot::String(length) => u32::from(length) + 1,
+        }
+    }
+
+    // Whether a value moves between the slots without conversion
+    fn matches(self, other: Slot) -> bool {
+        matches!(
+            (self, other),
+            (Slot::Integer, Slot::Integer)
+                | (Slot::Number, Slot::Number)
+                | (Slot::String(_), Slot::String(_))
+        )
+    }
+}
+
+impl std::fmt::Display for Slot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Slot::Integer => write!(f, "16-bit integer"),
+            Slot::Number => write!(f, "number"),
+            Slot::String(length) => write!(f, "string of {}", length),
+        }
+    }
+}
+
+// Labelled memory holding a value
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Place {
+    label: String,
+    slot: Slot,
+}
+
+impl Place {
+    fn new(label: &str, slot: Slot) -> Place {
+        Place {
+            label: label.to_owned(),
+            slot,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Array {
+    slot: Slot,
+    // Largest size any DIM gives it, elements are allocated for all of them
+    elements: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Pooled {
+    Integer(i16),
+    Number(Decimal),
+    String(String),
+}
+
+pub struct CodeGenerator<'a> {
+    source: &'a ast::Program,
+    program: &'a Program,
+    origin: u32,
+    variables: BTreeMap<&'a str, Slot>,
+    arrays: BTreeMap<&'a str, Array>,
+    temps: BTreeMap<Temp, Slot>,
+    constants: Vec<Pooled>,
+    // ERL needs the running line, which is only kept up to date when errors
+    // can be trapped
+    track_lines: bool,
+    local_labels: usize,
+    lines: Vec<String>,
+}
+
+impl<'a> CodeGenerator<'a> {
+    pub fn new(
+        source: &'a ast::Program,
+        program: &'a Program,
+        ranges: &RangeReport,
+        origin: u32,
+    ) -> Self {
+        let mut generator = CodeGenerator {
+            source,
+            program,
+            origin,
+            variables: BTreeMap::new(),
+            arrays: BTreeMap::new(),
+            temps: BTreeMap::new(),
+            constants: Vec::new(),
+            track_lines: false,
+            local_labels: 0,
+            lines: Vec::new(),
+        };
+        generator.allocate(ranges);
+        generator
+    }
+
+    pub fn generate(mut self) -> String {
+        self.header();
+        for index in 0..self.program.blocks.len() {
+            self.block(BlockId(index));
+        }
+        self.data();
+        self.pool();
+        self.lines.push(String::new());
+        self.lines.extend(RUNTIME.lines().map(str::to_owned));
+        self.storage();
+
+        let mut output = self.lines.join("\n");
+        output.push('\n');
+        output
+    }
+
+    // Decides how every variable, array and temp is stored
+    fn allocate(&mut self, ranges: &RangeReport) {
+        let program = self.program;
+        let range = |known: &BTreeMap<String, Range>, name: &str| {
+            // Never stored to, so always 0
+            known
+                .get(name)
+                .copied()
+                .unwrap_or(Range::Integer { min: 0, max: 0 })
+        };
+
+        for block in &program.blocks {
+            for instruction in &block.instructions {
+                match instruction {
+                    Instruction::Load { variable, .. }
+                    | Instruction::Store { variable, .. }
+                    | Instruction::ForPush { variable, .. } => {
+                        let slot = match Type::of(variable) {
+                            Type::String => Slot::String(DEFAULT_STRING_LENGTH),
+                            Type::Number => {
+                                Slot::of(Type::Number, range(&ranges.variables, variable))
+                            }
+                        };
+                        self.variables.insert(variable, slot);
+                    }
+                    Instruction::LoadElement { array, .. }
+                    | Instruction::StoreElement { array, .. }
+                    | Instruction::Dim { array, .. } => {
+                        let slot = match Type::of(array) {
+                            Type::String => Slot::String(DEFAULT_STRING_LENGTH),
+                            Type::Number => Slot::of(Type::Number, range(&ranges.arrays, array)),
+                        };
+                        let entry = self.arrays.entry(array).or_insert(Array {
+                            slot,
+                            elements: IMPLICIT_ARRAY_SIZE,
+                        });
+                        if let Instruction::Dim { size, length, .. } = instruction {
+                            entry.elements = entry.elements.max(size + 1);
+                            if let Some(length) = length {
+                                entry.slot =
+                                    Slot::String(u8::try_from(*length).unwrap_or(STRING_LENGTH));
+                            }
+                        }
+                    }
+                    Instruction::SetTrap(Some(_)) => self.track_lines = true,
+                    _ => {}
+                }
+
+                let dest = match instruction {
+                    Instruction::Load { dest, .. }
+                    | Instruction::LoadElement { dest, .. }
+                    | Instruction::Unary { dest, .. }
+                    | Instruction::Binary { dest, .. }
+                    | Instruction::Intrinsic {
+                        dest: Some(dest), ..
+                    } => dest,
+                    _ => continue,
+                };
+                self.temps
+                    .insert(*dest, Slot::of(program.temps[dest.0], ranges.temps[dest.0]));
+            }
+            if let Terminator::Next { variable, .. } = &block.terminator {
+                self.variables.entry(variable).or_insert(Slot::Number);
+            }
+        }
+
+        // FOR frames of integer variables keep the limit and step as
+        // integers too, so both have to be
+        for block in &program.blocks {
+            for instruction in &block.instructions {
+                if let Instruction::ForPush {
+                    variable,
+                    limit,
+                    step,
+                    ..
+                } = instruction
+                {
+                    if self.natural(limit) != Slot::Integer || self.natural(step) != Slot::Integer {
+                        self.variables.insert(variable, Slot::Number);
+                    }
+                }
+            }
+        }
+    }
+
+    fn header(&mut self) {
+        self.lines.extend(
+            [
+                "; LH5801 code generated from the BASIC program",
+                ";",
+                "; CALL the start address to run it. END returns to BASIC",
+                "",
+            ]
+            .map(str::to_owned),
+        );
+        for (name, code) in RUNTIME_ERRORS {
+            self.lines.push(format!("{} EQU {}", name, code.number()));
+        }
+        self.lines.push(String::new());
+        let origin = self.origin;
+        self.op(format_args!("ORG &{:04X}", origin));
+        self.label("START");
+        self.call("RT_INIT");
+        let arrays: Vec<(String, u32)> = self
+            .arrays
+            .iter()
+            .map(|(name, array)| (array_label(name), array.elements))
+            .collect();
+        for (label, elements) in arrays {
+            self.store_word(&label, &elements.to_string());
+        }
+        if self.program.entry != BlockId(0) {
+            let entry = block_label(self.program.entry);
+            self.op(format_args!("JMP {}", entry));
+        }
+    }
+
+    fn block(&mut self, id: BlockId) {
+        let program = self.program;
+        let block = &program.blocks[id.0];
+        let first = program.lines.get(&block.line) == Some(&id);
+        if first {
+            let text = self
+                .source
+                .lookup_line(block.line)
+                .map(|statement| Printer::new().statement(statement))
+                .unwrap_or_default();
+            self.lines.push(String::new());
+            self.lines.push(format!("; {} {}", block.line, text));
+            if let Some(label) = program.labels.get(&block.line) {
+                self.lines.push(format!("; DEF key \"{}\"", label));
+            }
+        }
+        self.label(&block_label(id));
+        if first && self.track_lines {
+            self.store_word("RT_LINE", &block.line.to_string());
+        }
+
+        for instruction in &block.instructions {
+            self.comment(instruction);
+            self.instruction(instruction);
+        }
+        self.comment(&block.terminator);
+        self.terminator(id, &block.terminator);
+    }
+
+    fn instruction(&mut self, instruction: &'a Instruction) {
+        match instruction {
+            Instruction::Load { dest, variable } => {
+                let from = self.variable(variable);
+                let to = self.temp(*dest);
+                self.copy(&to, &from);
+            }
+            Instruction::Store { variable, value } => {
+                let from = self.operand(value);
+                let to = self.variable(variable);
+                self.copy(&to, &from);
+            }
+            Instruction::LoadElement { dest, array, index } => {
+                let slot = self.element(array, index);
+                let to = self.temp(*dest);
+                self.point('Y', &to.label);
+                self.transfer(to.slot, slot);
+            }
+            Instruction::StoreElement {
+                array,
+                index,
+                value,
+            } => {
+                let from = self.operand(value);
+                let slot = self.element(array, index);
+                self.op("STX Y");
+                self.point('X', &from.label);
+                self.transfer(slot, from.slot);
+            }
+            Instruction::Unary { dest, op, operand } => self.unary(*dest, *op, operand),
+            Instruction::Binary {
+                dest,
+                op,
+                left,
+                right,
+            } => self.binary(*dest, *op, left, right),
+            Instruction::Dim { array, size, .. } => {
+                self.store_word(&array_label(array), &(size + 1).to_string());
+            }
+            Instruction::ForPush {
+                variable,
+                limit,
+                step,
+                body,
+                ..
+            } => {
+                let variable = self.variable(variable);
+                let limit = self.operand_as(limit, variable.slot, 0);
+                let step = self.operand_as(step, variable.slot, 1);
+                self.store_word("RT_ARG", &block_label(*body));
+                self.point('Y', &variable.label);
+                self.point('X', &limit.label);
+                self.point('U', &step.label);
+                self.op(format_args!(
+                    "LDI A,{}",
+                    u8::from(variable.slot != Slot::Integer)
+                ));
+                self.call("RT_FOR");
+            }
+            Instruction::SetTrap(target) => {
+                let target = target.map_or("0".to_owned(), block_label);
+                self.store_word("RT_TRAP", &target);
+            }
+            Instruction::Intrinsic {
+                dest,
+                intrinsic,
+                args,
+            } => self.intrinsic(*dest, *intrinsic, args),
+            Instruction::Rem(_) => {}
+        }
+    }
+
+    fn unary(&mut self, dest: Temp, op: UnaryOperator, operand: &Operand) {
+        let dest = self.temp(dest);
+        match op {
+            UnaryOperator::Plus => {
+                let value = self.operand(operand);
+                self.copy(&dest, &value);
+            }
+            UnaryOperator::Minus => {
+                let place = self.operand(operand);
+                if dest.slot == Slot::Integer && place.slot == Slot::Integer {
+                    self.op("SEC");
+                    for byte in ["+1", ""] {
+                        self.op("LDI A,0");
+                        self.op(format_args!("SBC ({}{})", place.label, byte));
+                        self.op(format_args!("STA ({}{})", dest.label, byte));
+                    }
+                    return;
+                }
+                let value = self.operand_as(operand, Slot::Number, 0);
+                let target = self.target(&dest, Slot::Number);
+                self.point('X', &value.label);
+                self.point('Y', &target.label);
+                self.call("RT_DNEG");
+                self.finish(&dest, &target);
+            }
+            UnaryOperator::Not => {
+                let value = self.operand_as(operand, Slot::Integer, 0);
+                let target = self.target(&dest, Slot::Integer);
+                for byte in ["", "+1"] {
+                    self.op(format_args!("LDA ({}{})", value.label, byte));
+                    self.op("EAI &FF");
+                    self.op(format_args!("STA ({}{})", target.label, byte));
+                }
+                self.finish(&dest, &target);
+            }
+        }
+    }
+
+    fn binary(&mut self, dest: Temp, op: BinaryOperator, left: &Operand, right: &Operand) {
+        let dest = self.temp(dest);
+        let (l, r) = (self.operand(left), self.operand(right));
+
+        if let Slot::String(_) = l.slot {
+            self.point('X', &l.label);
+            self.point('U', &r.label);
+            if op == BinaryOperator::Add {
+                self.point('Y', &dest.label);
+                self.call("RT_SCAT");
+            } else {
+                self.call("RT_SCMP");
+                self.compared(op, &dest);
+            }
+            return;
+        }
+
+        match op {
+            BinaryOperator::Add | BinaryOperator::Sub
+                if [dest.slot, l.slot, r.slot] == [Slot::Integer; 3] =>
+            {
+                let (carry, instruction) = if op == BinaryOperator::Add {
+                    ("REC", "ADC")
+                } else {
+                    ("SEC", "SBC")
+                };
+                self.op(carry);
+                for byte in ["+1", ""] {
+                    self.op(format_args!("LDA ({}{})", l.label, byte));
+                    self.op(format_args!("{} ({}{})", instruction, r.label, byte));
+                    self.op(format_args!("STA ({}{})", dest.label, byte));
+                }
+            }
+            BinaryOperator::Mul if [dest.slot, l.slot, r.slot] == [Slot::Integer; 3] => {
+                self.point('X', &l.label);
+                self.point('U', &r.label);
+                self.point('Y', &dest.label);
+                self.call("RT_IMUL");
+            }
+            BinaryOperator::Add
+            | BinaryOperator::Sub
+            | BinaryOperator::Mul
+            | BinaryOperator::Div => {
+                let x = self.operand_as(left, Slot::Number, 0);
+                let y = self.operand_as(right, Slot::Number, 1);
+                let target = self.target(&dest, Slot::Number);
+                self.point('X', &x.label);
+                self.point('U', &y.label);
+                self.point('Y', &target.label);
+                self.call(match op {
+                    BinaryOperator::Add => "RT_DADD",
+                    BinaryOperator::Sub => "RT_DSUB",
+                    BinaryOperator::Mul => "RT_DMUL",
+                    _ => "RT_DDIV",
+                });
+                self.finish(&dest, &target);
+            }
+            BinaryOperator::And | BinaryOperator::Or => {
+                let x = self.operand_as(left, Slot::Integer, 0);
+                let y = self.operand_as(right, Slot::Integer, 1);
+                let target = self.target(&dest, Slot::Integer);
+                let instruction = if op == BinaryOperator::And {
+                    "AND"
+                } else {
+                    "ORA"
+                };
+                for byte in ["", "+1"] {
+                    self.op(format_args!("LDA ({}{})", x.label, byte));
+                    self.op(format_args!("{} ({}{})", instruction, y.label, byte));
+                    self.op(format_args!("STA ({}{})", target.label, byte));
+                }
+                self.finish(&dest, &target);
+            }
+            BinaryOperator::Eq
+            | BinaryOperator::Ne
+            | BinaryOperator::Lt
+            | BinaryOperator::Le
+            | BinaryOperator::Gt
+            | BinaryOperator::Ge => {
+                let routine = if l.slot == Slot::Integer && r.slot == Slot::Integer {
+                    self.point('X', &l.label);
+                    self.point('U', &r.label);
+                    "RT_ICMP"
+                } else {
+                    let x = self.operand_as(left, Slot::Number, 0);
+                    let y = self.operand_as(right, Slot::Number, 1);
+                    self.point('X', &x.label);
+                    self.point('U', &y.label);
+                    "RT_DCMP"
+                };
+                self.call(routine);
+                self.compared(op, &dest);
+            }
+        }
+    }
+
+    // Turns the 1, 2 or 4 the compare routines leave in A for less, equal
+    // and greater into the 1 or 0 of the comparison
+    fn compared(&mut self, op: BinaryOperator, dest: &Place) {
+        let mask = match op {
+            BinaryOperator::Lt => 1,
+            BinaryOperator::Eq => 2,
+            BinaryOperator::Le => 3,
+            BinaryOperator::Gt => 4,
+            BinaryOperator::Ne => 5,
+            _ => 6,
+        };
+        let target = self.target(dest, Slot::Integer);
+        self.op(format_args!("ANI A,{}", mask));
+        self.point('Y', &target.label);
+        self.call("RT_BOOL");
+        self.finish(dest, &target);
+    }
+
+    fn intrinsic(&mut self, dest: Option<Temp>, intrinsic: Intrinsic, args: &'a [Operand]) {
+        match intrinsic {
+            Intrinsic::Point => {
+                self.integers(args);
+                self.value_call(dest, Slot::Integer, "RT_POINT");
+            }
+            Intrinsic::Inkey => self.value_call(dest, Slot::String(STRING_LENGTH), "RT_INKEY"),
+            Intrinsic::Ern => {
+                let dest = self.temp(dest.expect("ERN has a value"));
+                self.copy(&dest, &Place::new("RT_ERN", Slot::Integer));
+            }
+            // Lines go up to 65279, past a signed integer
+            Intrinsic::Erl => self.value_call(dest, Slot::Number, "RT_GETERL"),
+            Intrinsic::Using(device) => {
+                let format = self.operand(&args[0]);
+                self.point('X', &format.label);
+                self.op(format_args!("LDI A,{}", device_code(device)));
+                self.call("RT_USING");
+            }
+            Intrinsic::Print(device, separator) => {
+                let code = device_code(device)
+                    | match separator {
+                        None => 0,
+                        Some(Separator::Semicolon) => 1,
+                        Some(Separator::Comma) => 2,
+                    };
+                self.print(&args[0], code);
+            }
+            Intrinsic::EndPrint(device) => {
+                self.op(format_args!("LDI A,{}", device_code(device)));
+                self.call("RT_END_PRINT");
+            }
+            Intrinsic::Input(ty) => {
+                let prompt = args.first().map(|prompt| self.operand(prompt));
+                match prompt {
+                    Some(Place {
+                        label,
+                        slot: Slot::String(_),
+                    }) => {
+                        self.point('X', &label);
+                        self.op("LDI A,1");
+                    }
+                    // A number prompt is printed like PRINT N; would
+                    Some(_) => {
+                        self.print(&args[0], 1);
+                        self.op("LDI A,0");
+                    }
+                    None => self.op("LDI A,0"),
+                }
+                match ty {
+                    Type::Number => self.value_call(dest, Slot::Number, "RT_INPUT_NUM"),
+                    Type::String => {
+                        self.value_call(dest, Slot::String(STRING_LENGTH), "RT_INPUT_STR");
+                    }
+                }
+            }
+            Intrinsic::Read(Type::Number) => self.value_call(dest, Slot::Number, "RT_READ_NUM"),
+            Intrinsic::Read(Type::String) => {
+                self.op(format_args!("LDI A,{}", STRING_LENGTH));
+                self.value_call(dest, Slot::String(STRING_LENGTH), "RT_READ_STR");
+            }
+            Intrinsic::Restore => {
+                let index = match &args[0] {
+                    Operand::Constant(Constant::Number(index)) => index.to_integer(),
+                    _ => None,
+                };
+                let index = index.expect("RESTORE is lowered with a constant DATA index");
+                self.store_word("RT_DATA", &format!("D{}", index));
+            }
+            Intrinsic::Poke => {
+                let address = self.operand_as(&args[0], Slot::Number, 0);
+                self.point('X', &address.label);
+                self.call("RT_ADDR");
+                for value in &args[1..] {
+                    self.integers(std::slice::from_ref(value));
+                    self.call("RT_POKE");
+                }
+            }
+            Intrinsic::Call => {
+                let address = self.operand_as(&args[0], Slot::Number, 0);
+                self.point('X', &address.label);
+                self.call("RT_ADDR");
+                self.integers(&args[1..]);
+                self.op(format_args!("LDI A,{}", args.len() - 1));
+                self.call("RT_CALL");
+            }
+            Intrinsic::GPrint => {
+                for arg in args {
+                    let place = self.operand(arg);
+                    if let Slot::String(_) = place.slot {
+                        self.point('X', &place.label);
+                        self.call("RT_GPRINT_HEX");
+                    } else {
+                        self.integers(std::slice::from_ref(arg));
+                        self.call("RT_GPRINT_BYTE");
+                    }
+                }
+            }
+            Intrinsic::Line {
+                relative,
+                boxed,
+                start,
+                ..
+            } => {
+                self.integers(args);
+                let flags = u8::from(relative) | u8::from(boxed) << 1 | u8::from(start) << 2;
+                self.op(format_args!("LDI A,{}", flags));
+                self.op("STA (RT_FLAGS)");
+                self.op(format_args!("LDI A,{}", args.len()));
+                self.call("RT_DRAW");
+            }
+            Intrinsic::Wait
+            | Intrinsic::Cursor
+            | Intrinsic::GCursor
+            | Intrinsic::Beep
+            | Intrinsic::GlCursor
+            | Intrinsic::Color
+            | Intrinsic::CSize
+            | Intrinsic::Rotate
+            | Intrinsic::Lf => {
+                self.integers(args);
+                self.op(format_args!("LDI A,{}", args.len()));
+                self.call(&format!("RT_{}", intrinsic.to_string().to_uppercase()));
+            }
+            Intrinsic::Pause
+            | Intrinsic::Cls
+            | Intrinsic::Graph
+            | Intrinsic::Text
+            | Intrinsic::Sorgn
+            | Intrinsic::Tron
+            | Intrinsic::Troff => {
+                self.call(&format!("RT_{}", intrinsic.to_string().to_uppercase()));
+            }
+        }
+    }
+
+    fn print(&mut self, value: &Operand, code: u8) {
+        let place = self.operand(value);
+        let (place, routine) = if let Slot::String(_) = place.slot {
+            (place, "RT_PRINT_STR")
+        } else {
+            (self.operand_as(value, Slot::Number, 0), "RT_PRINT_NUM")
+        };
+        self.point('X', &place.label);
+        self.op(format_args!("LDI A,{}", code));
+        self.call(routine);
+    }
+
+    // Integer arguments of a device routine go to RT_ARG
+    fn integers(&mut self, args: &[Operand]) {
+        for (index, arg) in args.iter().enumerate() {
+            let place = self.operand(arg);
+            let to = Place {
+                label: format!("RT_ARG+{}", 2 * index),
+                slot: Slot::Integer,
+            };
+            self.copy(&to, &place);
+        }
+    }
+
+    // Calls a routine leaving a value of the slot at Y
+    fn value_call(&mut self, dest: Option<Temp>, slot: Slot, routine: &str) {
+        let dest = self.temp(dest.expect("functions have a value"));
+        let target = self.target(&dest, slot);
+        self.point('Y', &target.label);
+        self.call(routine);
+        self.finish(&dest, &target);
+    }
+
+    fn terminator(&mut self, id: BlockId, terminator: &Terminator) {
+        let next = BlockId(id.0 + 1);
+        match terminator {
+            Terminator::Jump(target) => self.jump(*target, next),
+            Terminator::Branch {
+                condition,
+                then,
+                else_,
+            } => {
+                let condition = self.operand(condition);
+                if condition.slot == Slot::Integer {
+                    self.op(format_args!("LDA ({})", condition.label));
+                    self.op(format_args!("ORA ({}+1)", condition.label));
+                } else {
+                    // Only zero has a zero first mantissa byte
+                    self.op(format_args!("LDA ({}+2)", condition.label));
+                }
+                let skip = self.local_label();
+                self.op(format_args!("BZS {}", skip));
+                self.op(format_args!("JMP {}", block_label(*then)));
+                self.label(&skip);
+                self.jump(*else_, next);
+            }
+            Terminator::Gosub { target, ret } => {
+                self.call(&block_label(*target));
+                self.jump(*ret, next);
+            }
+            Terminator::Return => self.op("RTN"),
+            Terminator::Next { variable, exit } => {
+                let variable = self.variable(variable);
+                self.point('Y', &variable.label);
+                self.call("RT_NEXT");
+                self.jump(*exit, next);
+            }
+            Terminator::End => self.op("JMP RT_END"),
+            Terminator::Raise(code) => {
+                self.op(format_args!("LDI A,{}", code.number()));
+                self.op("JMP RT_RAISE");
+            }
+        }
+    }
+
+    fn jump(&mut self, target: BlockId, next: BlockId) {
+        if target != next {
+            self.op(format_args!("JMP {}", block_label(target)));
+        }
+    }
+
+    // DATA items in order, each with a type byte: 0 for a number, 1 for a
+    // string and 2 after the last
+    fn data(&mut self) {
+        let program = self.program;
+        self.lines.push(String::new());
+        self.lines.push("; DATA items".to_owned());
+        self.label("DATA");
+        for (index, (line, item)) in program.data.iter().enumerate() {
+            let bytes = match item {
+                Constant::Number(number) => format!("0,{}", bytes(&number.to_bytes())),
+                Constant::String(string) => string_bytes(1, string),
+            };
+            self.lines
+                .push(format!("D{}: DB {} ; line {}", index, bytes, line));
+        }
+        self.lines.push(format!("D{}: DB 2", program.data.len()));
+    }
+
+    fn pool(&mut self) {
+        self.lines.push(String::new());
+        self.lines.push("; Constants".to_owned());
+        let constants = std::mem::take(&mut self.constants);
+        for (index, constant) in constants.iter().enumerate() {
+            let line = match constant {
+                Pooled::Integer(value) => format!("K{}: DW {}", index, value),
+                Pooled::Number(number) => {
+                    format!("K{}: DB {} ; {}", index, bytes(&number.to_bytes()), number)
+                }
+                Pooled::String(string) => {
+                    format!(
+                        "K{}: DB {}",
+                        index,
+                        string_bytes(string.chars().count(), string)
+                    )
+                }
+            };
+            self.lines.push(line);
+        }
+    }
+
+    // Variables and arrays follow the runtime's own state, which RT_INIT
+    // clears along with them
+    fn storage(&mut self) {
+        let mut lines = vec![String::new(), "; Variables".to_owned()];
+        for (name, slot) in &self.variables {
+            lines.push(format!(
+                "{}: DS {} ; {}, {}",
+                variable_label(name),
+                slot.size(),
+                name,
+                slot
+            ));
+        }
+        for (name, array) in &self.arrays {
+            lines.push(format!(
+                "{}: DS {} ; {}(), {} elements, {}",
+                array_label(name),
+                2 + array.elements * array.slot.size(),
+                name,
+                array.elements,
+                array.slot
+            ));
+        }
+        lines.push("VARS_END:".to_owned());
+        lines.push(String::new());
+        lines.push("; Temps".to_owned());
+        for (temp, slot) in &self.temps {
+            lines.push(format!("{}: DS {}", temp_label(*temp), slot.size()));
+        }
+        self.lines.extend(lines);
+    }
+
+    fn variable(&self, name: &str) -> Place {
+        Place {
+            label: variable_label(name),
+            slot: self.variables[name],
+        }
+    }
+
+    fn temp(&self, temp: Temp) -> Place {
+        Place {
+            label: temp_label(temp),
+            slot: self.temps[&temp],
+        }
+    }
+
+    // Slot a constant is pooled in or a temp is stored in
+    fn natural(&self, operand: &Operand) -> Slot {
+        match operand {
+            Operand::Temp(temp) => self.temps[temp],
+            Operand::Constant(Constant::Number(number)) => {
+                match number.to_integer().map(i16::try_from) {
+                    Some(Ok(_)) => Slot::Integer,
+                    _ => Slot::Number,
+                }
+            }
+            Operand::Constant(Constant::String(_)) => Slot::String(STRING_LENGTH),
+        }
+    }
+
+    fn operand(&mut self, operand: &Operand) -> Place {
+        match operand {
+            Operand::Temp(temp) => self.temp(*temp),
+            Operand::Constant(constant) => {
+                let slot = self.natural(operand);
+                let pooled = match constant {
+                    Constant::Number(number) => match number.to_integer().map(i16::try_from) {
+                        Some(Ok(value)) => Pooled::Integer(value),
+                        _ => Pooled::Number(*number),
+                    },
+                    Constant::String(string) => Pooled::String(string.clone()),
+                };
+                self.constant(pooled, slot)
+            }
+        }
+    }
+
+    // The operand stored in the slot, converted into the scratch area with
+    // the given number if it is not
+    fn operand_as(&mut self, operand: &Operand, slot: Slot, scratch: usize) -> Place {
+        if let (Operand::Constant(Constant::Number(number)), Slot::Number) = (operand, slot) {
+            return self.constant(Pooled::Number(*number), Slot::Number);
+        }
+        let place = self.operand(operand);
+        if place.slot.matches(slot) {
+            return place;
+        }
+        let converted = Place {
+            label: format!("RT_S{}", scratch),
+            slot,
+        };
+        self.copy(&converted, &place);
+        converted
+    }
+
+    fn constant(&mut self, pooled: Pooled, slot: Slot) -> Place {
+        let index = match self.constants.iter().position(|other| *other == pooled) {
+            Some(index) => index,
+            None => {
+                self.constants.push(pooled);
+                self.constants.len() - 1
+            }
+        };
+        Place {
+            label: format!("K{}", index),
+            slot,
+        }
+    }
+
+    // Where an operation giving a value of the slot leaves it: the
+    // destination, or scratch space to convert it from
+    fn target(&self, dest: &Place, slot: Slot) -> Place {
+        if dest.slot.matches(slot) {
+            dest.clone()
+        } else {
+            Place::new("RT_S2", slot)
+        }
+    }
+
+    fn finish(&mut self, dest: &Place, target: &Place) {
+        if dest != target {
+            self.copy(dest, target);
+        }
+    }
+
+    // Leaves the address of the element in X
+    fn element(&mut self, array: &str, index: &Operand) -> Slot {
+        let index = self.operand_as(index, Slot::Integer, 0);
+        let slot = self.arrays[array].slot;
+        self.point('X', &array_label(array));
+        self.point('U', &index.label);
+        self.op(format_args!("LDI A,{}", slot.size()));
+        self.call("RT_INDEX");
+        slot
+    }
+
+    fn copy(&mut self, to: &Place, from: &Place) {
+        if to.slot == Slot::Integer && from.slot == Slot::Integer {
+            for byte in ["", "+1"] {
+                self.op(format_args!("LDA ({}{})", from.label, byte));
+                self.op(format_args!("STA ({}{})", to.label, byte));
+            }
+            return;
+        }
+        self.point('X', &from.label);
+        self.point('Y', &to.label);
+        self.transfer(to.slot, from.slot);
+    }
+
+    // Moves the value at X to Y, converting between the slots
+    fn transfer(&mut self, to: Slot, from: Slot) {
+        match (to, from) {
+            (Slot::Integer, Slot::Integer) => self.call("RT_MOVE2"),
+            (Slot::Number, Slot::Number) => self.call("RT_MOVE8"),
+            (Slot::Number, Slot::Integer) => self.call("RT_ITOD"),
+            (Slot::Integer, Slot::Number) => self.call("RT_DTOI"),
+            (Slot::String(length), Slot::String(_)) => {
+                self.op(format_args!("LDI A,{}", length));
+                self.call("RT_SCOPY");
+            }
+            _ => unreachable!("the semantic checker keeps strings and numbers apart"),
+        }
+    }
+
+    fn point(&mut self, register: char, label: &str) {
+        self.op(format_args!("LDI {}H,HIGH({})", register, label));
+        self.op(format_args!("LDI {}L,LOW({})", register, label));
+    }
+
+    fn store_word(&mut self, label: &str, value: &str) {
+        self.op(format_args!("LDI A,HIGH({})", value));
+        self.op(format_args!("STA ({})", label));
+        self.op(format_args!("LDI A,LOW({})", value));
+        self.op(format_args!("STA ({}+1)", label));
+    }
+
+    fn call(&mut self, routine: &str) {
+        self.op(format_args!("SJP {}", routine));
+    }
+
+    fn local_label(&mut self) -> String {
+        self.local_labels += 1;
+        format!("L{}", self.local_labels)
+    }
+
+    fn label(&mut self, label: &str) {
+        self.lines.push(format!("{}:", label));
+    }
+
+    fn op(&mut self, text: impl std::fmt::Display) {
+        self.lines.push(format!("    {}", text));
+    }
+
+    fn comment(&mut self, text: impl std::fmt::Display) {
+        self.lines.push(format!("    ; {}", text));
+    }
+}
+
+fn device_code(device: Device) -> u8 {
+    match device {
+        Device::Display => 0,
+        Device::Printer => 0x80,
+    }
+}
+
+fn block_label(id: BlockId) -> String {
+    format!("B{}", id.0)
+}
+
+fn temp_label(temp: Temp) -> String {
+    format!("T{}", temp.0)
+}
+
+// A$ is V_A_S, so names stay valid labels
+fn variable_label(name: &str) -> String {
+    format!("V_{}", name.replace('$', "_S"))
+}
+
+fn array_label(name: &str) -> String {
+    format!("A_{}", name.replace('$', "_S"))
+}
+
+fn bytes(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("&{:02X}", byte)).collect();
+    bytes.join(",")
+}
+
+// Type or length byte followed by the characters
+fn string_bytes(first: usize, string: &str) -> String {
+    if string.is_empty() {
+        first.to_string()
+    } else {
+        format!("{},\"{}\"", first, string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::{ConstantFolder, Lowering, RangeAnalysis};
+    use crate::lh5801::{read_symbols, Assembler};
+    use crate::test_support::parse;
+
+    fn generate(program: &ast::Program) -> String {
+        let mut ir = Lowering::new(program).lower();
+        ConstantFolder::new().fold(&mut ir);
+        let ranges = RangeAnalysis::new(&ir).analyze();
+        CodeGenerator::new(program, &ir, &ranges, 0x40C5).generate()
+    }
+
+    // The lines of the block generated for a source line
+    fn section<'b>(output: &'b str, line: &str) -> Vec<&'b str> {
+        output
+            .lines()
+            .skip_while(|text| !text.starts_with(line))
+            .skip(1)
+            .take_while(|text| !text.is_empty())
+            .collect()
+    }
+
+    #[test]
+    fn integers_are_native_and_decimals_use_the_runtime() {
+        let program = parse(concat!(
+            "10 FOR I = 1 TO 10\n",
+            "20 J = I + 1\n",
+            "30 X = X / J\n",
+            "40 NEXT I\n",
+            "50 PRINT X\n",
+        ));
+        let output = generate(&program);
+
+        assert!(output.contains("    ORG &40C5\nSTART:\n    SJP RT_INIT\n"));
+        assert!(output.contains("V_I: DS 2 ; I, 16-bit integer\n"));
+        assert!(output.contains("V_X: DS 8 ; X, number\n"));
+        assert_eq!(
+            section(&output, "; 20 "),
+            vec![
+                "B1:",
+                "    ; %0 = load I",
+                "    LDA (V_I)",
+                "    STA (T0)",
+                "    LDA (V_I+1)",
+                "    STA (T0+1)",
+                "    ; %1 = add %0, 1",
+                "    REC",
+                "    LDA (T0+1)",
+                "    ADC (K0+1)",
+                "    STA (T1+1)",
+                "    LDA (T0)",
+                "    ADC (K0)",
+                "    STA (T1)",
+                "    ; store J, %1",
+                "    LDA (T1)",
+                "    STA (V_J)",
+                "    LDA (T1+1)",
+                "    STA (V_J+1)",
+                "    ; jump b2",
+            ]
+        );
+        // X / J converts J and divides with the ROM
+        let line_30 = section(&output, "; 30 ").join("\n");
+        assert!(line_30.contains("    SJP RT_ITOD\n"));
+        assert!(line_30.contains("    SJP RT_DDIV\n"));
+        assert!(section(&output, "; 40 ").contains(&"    SJP RT_NEXT"));
+        assert!(output.contains("K0: DW 1\n"));
+
+        // Only the ROM is left to link
+        let assembler = Assembler::new(|_| Err(std::io::ErrorKind::NotFound.into()));
+        let assembly = assembler.assemble("test.asm", &output).unwrap();
+        assert_eq!(assembly.origin(), 0x40C5);
+        assert!(assembly
+            .externals()
+            .iter()
+            .all(|name| name.starts_with("ROM_")));
+    }
+
+    #[test]
+    fn rom_symbols_cover_the_runtime() {
+        let output = generate(&parse("10 END\n"));
+        let assembler = Assembler::new(|_| Err(std::io::ErrorKind::NotFound.into()));
+        let externals: Vec<String> = assembler
+            .assemble("test.asm", &output)
+            .unwrap()
+            .externals()
+            .into_iter()
+            .map(str::to_owned)
+            .collect();
+        let symbols = read_symbols("rom.sym", ROM_SYMBOLS).unwrap();
+
+        assert_eq!(externals.len(), 33);
+        assert_eq!(externals, symbols.keys().cloned().collect::<Vec<String>>());
+    }
+}
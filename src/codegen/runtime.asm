@@ -0,0 +1,1254 @@
+; Runtime library of the LH5801 backend
+;
+; Integers are 16-bit two's complement with the high byte first, numbers the
+; 8 byte BCD format of the ROM and strings a length byte followed by the
+; characters. Routines take X as the source or left operand, U as the right
+; operand and Y as the destination, and keep no registers.
+;
+; Decimal arithmetic, number formatting and the devices are left to the ROM.
+; Its entry points move between ROM versions, so they are resolved when
+; linking, from rom.sym unless --rom-symbols gives another symbol file. The
+; comments give what each one takes.
+
+    EXTERN ROM_ARX            ; arithmetic register X, 8 bytes
+    EXTERN ROM_ARY            ; arithmetic register Y, 8 bytes
+    EXTERN ROM_ADD            ; ARX = ARX + ARY
+    EXTERN ROM_SUB            ; ARX = ARX - ARY
+    EXTERN ROM_MUL            ; ARX = ARX * ARY
+    EXTERN ROM_DIV            ; ARX = ARX / ARY
+    EXTERN ROM_VAL            ; ARX = number in the A characters at X
+    EXTERN ROM_ERROR          ; reports error A and returns to BASIC
+    EXTERN ROM_PRINT_TEXT     ; A characters at X, UL separator, UH device
+    EXTERN ROM_PRINT_NUM      ; ARX, UL separator, UH device
+    EXTERN ROM_END_PRINT      ; ends the statement on device A
+    EXTERN ROM_USING          ; format string at X for device A
+    EXTERN ROM_INPUT          ; prompt string at X or none when 0, gives X and A
+    EXTERN ROM_INKEY          ; key in A, 0 for none
+    EXTERN ROM_PAUSE
+    EXTERN ROM_WAIT           ; X ticks, A is 0 to wait for ENTER
+    EXTERN ROM_CLS
+    EXTERN ROM_CURSOR         ; column A
+    EXTERN ROM_GCURSOR        ; dot column X
+    EXTERN ROM_GPRINT         ; dot pattern A
+    EXTERN ROM_POINT          ; dot pattern of column A in A
+    EXTERN ROM_BEEP           ; A arguments: X count, U frequency, Y duration
+    EXTERN ROM_TRON
+    EXTERN ROM_TROFF
+    EXTERN ROM_LINE           ; A integers at X, UL the LINE options
+    EXTERN ROM_GLCURSOR       ; X and U
+    EXTERN ROM_COLOR          ; pen A
+    EXTERN ROM_CSIZE          ; size A
+    EXTERN ROM_ROTATE         ; direction A
+    EXTERN ROM_GRAPH
+    EXTERN ROM_TEXT
+    EXTERN ROM_SORGN
+    EXTERN ROM_LF             ; X lines
+
+; Bytes of a FOR frame: variable 2, kind 1, body 2, limit 8 and step 8
+FRAME EQU 21
+FRAMES EQU 8
+
+; Clears the variables and the runtime state, called first by START
+RT_INIT:
+    LDI XH,HIGH(RT_VARS)
+    LDI XL,LOW(RT_VARS)
+RT_INIT1:
+    LDA XH
+    CPI A,HIGH(VARS_END)
+    BZR RT_INIT2
+    LDA XL
+    CPI A,LOW(VARS_END)
+    BZS RT_INIT3
+RT_INIT2:
+    LDI A,0
+    SIN X
+    BCH RT_INIT1
+RT_INIT3:
+    ; END and trapped errors go back to the stack of the CALL
+    LDX S
+    INC X
+    INC X
+    LDA XH
+    STA (RT_STACK)
+    LDA XL
+    STA (RT_STACK+1)
+    LDI A,HIGH(RT_FRAMES)
+    STA (RT_FOR_SP)
+    LDI A,LOW(RT_FRAMES)
+    STA (RT_FOR_SP+1)
+    LDI A,HIGH(DATA)
+    STA (RT_DATA)
+    LDI A,LOW(DATA)
+    STA (RT_DATA+1)
+    RTN
+
+; Returns to BASIC from any GOSUB depth
+RT_END:
+    LDA (RT_STACK)
+    STA XH
+    LDA (RT_STACK+1)
+    STA XL
+    STX S
+    RTN
+
+RT_MOVE8:
+    LDI UL,7
+    BCH RT_MOVE
+RT_MOVE2:
+    LDI UL,1
+RT_MOVE:
+    TIN
+    LOP UL,RT_MOVE
+    RTN
+
+; Copies A bytes, none when A is 0
+RT_COPYN:
+    CPI A,0
+    BZS RT_COPYN2
+    DEC A
+    STA UL
+RT_COPYN1:
+    TIN
+    LOP UL,RT_COPYN1
+RT_COPYN2:
+    RTN
+
+; Copies a string, cut to the capacity A of the destination
+RT_SCOPY:
+    STA (RT_TMP)
+    LIN X
+    CPA (RT_TMP)
+    BCR RT_SCOPY1
+    LDA (RT_TMP)
+RT_SCOPY1:
+    SIN Y
+    BCH RT_COPYN
+
+; Concatenates, cut to 80 characters
+RT_SCAT:
+    PSH U
+    LDA (U)
+    STA (RT_TMP+1)
+    LIN X
+    STA (RT_TMP+2)
+    REC
+    ADC (RT_TMP+1)
+    CPI A,81
+    BCR RT_SCAT1
+    LDI A,80
+RT_SCAT1:
+    SIN Y
+    SEC
+    SBC (RT_TMP+2)
+    STA (RT_TMP+1)
+    LDA (RT_TMP+2)
+    SJP RT_COPYN
+    POP X
+    INC X
+    LDA (RT_TMP+1)
+    JMP RT_COPYN
+
+; Comparisons leave 1, 2 or 4 in A for less, equal or greater
+
+RT_SCMP:
+    LIN X
+    STA (RT_TMP)
+    LDA (U)
+    STA (RT_TMP+1)
+    INC U
+RT_SCMP1:
+    LDA (RT_TMP)
+    CPI A,0
+    BZS RT_SCMP2
+    LDA (RT_TMP+1)
+    CPI A,0
+    BZS RT_GT
+    LIN X
+    CPA (U)
+    BZR RT_SCMP3
+    INC U
+    LDA (RT_TMP)
+    DEC A
+    STA (RT_TMP)
+    LDA (RT_TMP+1)
+    DEC A
+    STA (RT_TMP+1)
+    BCH RT_SCMP1
+RT_SCMP2:
+    ; the left string ended
+    LDA (RT_TMP+1)
+    CPI A,0
+    BZS RT_EQ
+    BCH RT_LT
+RT_SCMP3:
+    BCS RT_GT
+    BCH RT_LT
+
+RT_ICMP:
+    ; flipping the sign bits compares them unsigned
+    LDA (X)
+    EAI &80
+    STA (RT_TMP)
+    LDA (U)
+    EAI &80
+    STA (RT_TMP+1)
+    LDA (RT_TMP)
+    CPA (RT_TMP+1)
+    BZR RT_ICMP1
+    INC X
+    INC U
+    LDA (X)
+    CPA (U)
+    BZS RT_EQ
+RT_ICMP1:
+    BCS RT_GT
+    BCH RT_LT
+
+RT_DCMP:
+    SJP RT_DLOAD
+    SJP ROM_SUB
+    LDA (ROM_ARX+2)
+    CPI A,0
+    BZS RT_EQ
+    LDA (ROM_ARX+1)
+    ANI A,&80
+    BZS RT_GT
+RT_LT:
+    LDI A,1
+    RTN
+RT_EQ:
+    LDI A,2
+    RTN
+RT_GT:
+    LDI A,4
+    RTN
+
+; Stores 1 when A is not 0, else 0, as an integer
+RT_BOOL:
+    CPI A,0
+    BZS RT_SETA
+    LDI A,1
+RT_SETA:
+    STA (RT_TMP)
+    LDI A,0
+    SIN Y
+    LDA (RT_TMP)
+    STA (Y)
+    RTN
+
+; Integer product, overflow wraps around
+RT_IMUL:
+    LIN X
+    STA (RT_TMP)
+    LDA (X)
+    STA (RT_TMP+1)
+    LDA (U)
+    STA (RT_TMP+2)
+    INC U
+    LDA (U)
+    STA (RT_TMP+3)
+    LDI A,0
+    STA (RT_TMP+4)
+    STA (RT_TMP+5)
+    LDI UL,15
+RT_IMUL1:
+    LDA (RT_TMP+5)
+    SHL
+    STA (RT_TMP+5)
+    LDA (RT_TMP+4)
+    ROL
+    STA (RT_TMP+4)
+    LDA (RT_TMP+1)
+    SHL
+    STA (RT_TMP+1)
+    LDA (RT_TMP)
+    ROL
+    STA (RT_TMP)
+    BCR RT_IMUL2
+    REC
+    LDA (RT_TMP+5)
+    ADC (RT_TMP+3)
+    STA (RT_TMP+5)
+    LDA (RT_TMP+4)
+    ADC (RT_TMP+2)
+    STA (RT_TMP+4)
+RT_IMUL2:
+    LOP UL,RT_IMUL1
+    LDA (RT_TMP+4)
+    SIN Y
+    LDA (RT_TMP+5)
+    STA (Y)
+    RTN
+
+; Integer to number
+RT_ITOD:
+    LDA (X)
+    CPI A,&80
+    BCR RT_UTOD
+    ; negative: convert the magnitude and set the sign
+    SEC
+    INC X
+    LDI A,0
+    SBC (X)
+    STA (RT_TMP+1)
+    LDI A,0
+    DEC X
+    SBC (X)
+    STA (RT_TMP)
+    LDI XH,HIGH(RT_TMP)
+    LDI XL,LOW(RT_TMP)
+    SJP RT_UTOD
+    DEC Y
+    DEC Y
+    DEC Y
+    DEC Y
+    DEC Y
+    DEC Y
+    LDI A,&80
+    STA (Y)
+    RTN
+
+; Unsigned integer to number, Y ends on the last byte
+RT_UTOD:
+    LIN X
+    STA (RT_ACC)
+    LDA (X)
+    STA (RT_ACC+1)
+    LDI A,0
+    STA (RT_BCD)
+    STA (RT_BCD+1)
+    STA (RT_BCD+2)
+    ; double dabble the 16 bits into 5 BCD digits
+    LDI UL,15
+RT_UTOD1:
+    LDA (RT_ACC+1)
+    SHL
+    STA (RT_ACC+1)
+    LDA (RT_ACC)
+    ROL
+    STA (RT_ACC)
+    LDI XH,HIGH(RT_BCD+2)
+    LDI XL,LOW(RT_BCD+2)
+    LDA (X)
+    DCA (X)
+    SDE X
+    LDA (X)
+    DCA (X)
+    SDE X
+    LDA (X)
+    DCA (X)
+    STA (X)
+    LOP UL,RT_UTOD1
+    ; shift the first digit to the top, counting the exponent down from 5
+    LDA (RT_BCD)
+    ORA (RT_BCD+1)
+    ORA (RT_BCD+2)
+    BZS RT_UTOD4
+    LDI A,5
+    STA (RT_EXP)
+RT_UTOD2:
+    LDA (RT_BCD)
+    ANI A,&F0
+    BZR RT_UTOD3
+    LDI UL,3
+RT_UTOD5:
+    LDA (RT_BCD+2)
+    SHL
+    STA (RT_BCD+2)
+    LDA (RT_BCD+1)
+    ROL
+    STA (RT_BCD+1)
+    LDA (RT_BCD)
+    ROL
+    STA (RT_BCD)
+    LOP UL,RT_UTOD5
+    LDA (RT_EXP)
+    DEC A
+    STA (RT_EXP)
+    BCH RT_UTOD2
+RT_UTOD3:
+    LDA (RT_EXP)
+RT_UTOD4:
+    ; zero has all bytes 0
+    SIN Y
+    LDI A,0
+    SIN Y
+    LDA (RT_BCD)
+    SIN Y
+    LDA (RT_BCD+1)
+    SIN Y
+    LDA (RT_BCD+2)
+    SIN Y
+    LDI A,0
+    SIN Y
+    SIN Y
+    STA (Y)
+    RTN
+
+; Integer part of the number at X in RT_ACC, 24 bits high byte first, and
+; its sign in RT_SIGN. A million or more is an argument error
+RT_DECODE:
+    LDI A,0
+    STA (RT_ACC)
+    STA (RT_ACC+1)
+    STA (RT_ACC+2)
+    LIN X
+    STA (RT_EXP)
+    LIN X
+    STA (RT_SIGN)
+    LDA (X)
+    CPI A,0
+    BZS RT_DECODE3
+    LDA (RT_EXP)
+    CPI A,&80
+    BCS RT_DECODE3
+    CPI A,6
+    BCR RT_DECODE1
+    JMP RT_ERR_ARG
+RT_DECODE1:
+    LIN X
+    STA (RT_BCD)
+    LIN X
+    STA (RT_BCD+1)
+    LDA (X)
+    STA (RT_BCD+2)
+RT_DECODE2:
+    ; one digit for each power of ten down to 1
+    LDA (RT_BCD)
+    AEX
+    ANI A,&0F
+    SJP RT_ACC10
+    LDI UL,3
+RT_DECODE4:
+    LDA (RT_BCD+2)
+    SHL
+    STA (RT_BCD+2)
+    LDA (RT_BCD+1)
+    ROL
+    STA (RT_BCD+1)
+    LDA (RT_BCD)
+    ROL
+    STA (RT_BCD)
+    LOP UL,RT_DECODE4
+    LDA (RT_EXP)
+    DEC A
+    STA (RT_EXP)
+    CPI A,&FF
+    BZR RT_DECODE2
+RT_DECODE3:
+    RTN
+
+; RT_ACC = RT_ACC * 10 + A
+RT_ACC10:
+    STA (RT_TMP)
+    SJP RT_ACC2
+    LDA (RT_ACC)
+    STA (RT_TMP+1)
+    LDA (RT_ACC+1)
+    STA (RT_TMP+2)
+    LDA (RT_ACC+2)
+    STA (RT_TMP+3)
+    SJP RT_ACC2
+    SJP RT_ACC2
+    REC
+    LDA (RT_ACC+2)
+    ADC (RT_TMP+3)
+    STA (RT_ACC+2)
+    LDA (RT_ACC+1)
+    ADC (RT_TMP+2)
+    STA (RT_ACC+1)
+    LDA (RT_ACC)
+    ADC (RT_TMP+1)
+    STA (RT_ACC)
+    REC
+    LDA (RT_ACC+2)
+    ADC (RT_TMP)
+    STA (RT_ACC+2)
+    LDA (RT_ACC+1)
+    ADI A,0
+    STA (RT_ACC+1)
+    LDA (RT_ACC)
+    ADI A,0
+    STA (RT_ACC)
+    RTN
+RT_ACC2:
+    LDA (RT_ACC+2)
+    SHL
+    STA (RT_ACC+2)
+    LDA (RT_ACC+1)
+    ROL
+    STA (RT_ACC+1)
+    LDA (RT_ACC)
+    ROL
+    STA (RT_ACC)
+    RTN
+
+; Number to integer, an argument error outside -32768 to 32767
+RT_DTOI:
+    SJP RT_DECODE
+    LDA (RT_ACC)
+    CPI A,0
+    BZR RT_DTOI2
+    LDA (RT_ACC+1)
+    CPI A,&80
+    BCR RT_DTOI1
+    ; only -32768 has the top bit
+    BZR RT_DTOI2
+    LDA (RT_ACC+2)
+    CPI A,0
+    BZR RT_DTOI2
+    LDA (RT_SIGN)
+    CPI A,0
+    BZS RT_DTOI2
+RT_DTOI1:
+    LDA (RT_SIGN)
+    CPI A,0
+    BZS RT_DTOI3
+    SEC
+    LDI A,0
+    SBC (RT_ACC+2)
+    STA (RT_ACC+2)
+    LDI A,0
+    SBC (RT_ACC+1)
+    STA (RT_ACC+1)
+RT_DTOI3:
+    LDA (RT_ACC+1)
+    SIN Y
+    LDA (RT_ACC+2)
+    STA (Y)
+    RTN
+RT_DTOI2:
+    JMP RT_ERR_ARG
+
+; Address of POKE and CALL in RT_PTR, with RT_BANK 1 above &FFFF for ME1
+RT_ADDR:
+    SJP RT_DECODE
+    LDA (RT_SIGN)
+    CPI A,0
+    BZR RT_ADDR1
+    LDA (RT_ACC)
+    CPI A,2
+    BCS RT_ADDR1
+    STA (RT_BANK)
+    LDA (RT_ACC+1)
+    STA (RT_PTR)
+    LDA (RT_ACC+2)
+    STA (RT_PTR+1)
+    RTN
+RT_ADDR1:
+    JMP RT_ERR_ARG
+
+; Stores the byte in RT_ARG at RT_PTR and moves on to the next address
+RT_POKE:
+    LDA (RT_ARG)
+    CPI A,0
+    BZS RT_POKE1
+    JMP RT_ERR_ARG
+RT_POKE1:
+    LDA (RT_PTR)
+    STA XH
+    LDA (RT_PTR+1)
+    STA XL
+    LDA (RT_BANK)
+    CPI A,0
+    BZR RT_POKE2
+    LDA (RT_ARG+1)
+    SIN X
+    BCH RT_POKE3
+RT_POKE2:
+    LDA (RT_ARG+1)
+    STA #(X)
+    INC X
+RT_POKE3:
+    LDA XH
+    STA (RT_PTR)
+    LDA XL
+    STA (RT_PTR+1)
+    RTN
+
+; Calls RT_PTR with the argument, if A is 1, in U
+RT_CALL:
+    CPI A,0
+    BZS RT_CALL1
+    LDA (RT_ARG)
+    STA UH
+    LDA (RT_ARG+1)
+    STA UL
+RT_CALL1:
+    LDA (RT_PTR)
+    STA XH
+    LDA (RT_PTR+1)
+    STA XL
+    STX P
+
+; Address of the element U of the array at X, A bytes each, in X. The array
+; starts with its number of elements
+RT_INDEX:
+    STA (RT_TMP)
+    LDA (U)
+    ANI A,&80
+    BZR RT_INDEX1
+    ; U must be below the number of elements
+    INC U
+    INC X
+    LDA (U)
+    DEC U
+    CPA (X)
+    DEC X
+    LDA (U)
+    SBC (X)
+    BCS RT_INDEX1
+    LDA (U)
+    STA (RT_TMP+1)
+    INC U
+    LDA (U)
+    STA (RT_TMP+2)
+    INC X
+    INC X
+    ; add index * size to X
+    LDI UL,7
+RT_INDEX2:
+    LDA (RT_TMP)
+    SHR
+    STA (RT_TMP)
+    BCR RT_INDEX3
+    LDA (RT_TMP+2)
+    ADR X
+    LDA (RT_TMP+1)
+    REC
+    ADC XH
+    STA XH
+RT_INDEX3:
+    LDA (RT_TMP+2)
+    SHL
+    STA (RT_TMP+2)
+    LDA (RT_TMP+1)
+    ROL
+    STA (RT_TMP+1)
+    LOP UL,RT_INDEX2
+    RTN
+RT_INDEX1:
+    JMP RT_ERR_ARRAY
+
+; Opens a FOR frame for the variable Y with limit X and step U, of kind A: 0
+; for integers and 1 for numbers. The body is in RT_ARG. A frame of the same
+; variable is closed first with those nested in it
+RT_FOR:
+    STA (RT_TMP+7)
+    LDA YH
+    STA (RT_VAR)
+    LDA YL
+    STA (RT_VAR+1)
+    PSH U
+    PSH X
+    SJP RT_FIND
+    BCR RT_FOR1
+    LDA XH
+    STA (RT_FOR_SP)
+    LDA XL
+    STA (RT_FOR_SP+1)
+RT_FOR1:
+    LDA (RT_FOR_SP)
+    STA YH
+    LDA (RT_FOR_SP+1)
+    STA YL
+    LDA YH
+    CPI A,HIGH(RT_FRAMES_END)
+    BZR RT_FOR2
+    LDA YL
+    CPI A,LOW(RT_FRAMES_END)
+    BZR RT_FOR2
+    JMP RT_ERR_NESTING
+RT_FOR2:
+    LDA (RT_VAR)
+    SIN Y
+    LDA (RT_VAR+1)
+    SIN Y
+    LDA (RT_TMP+7)
+    SIN Y
+    LDA (RT_ARG)
+    SIN Y
+    LDA (RT_ARG+1)
+    SIN Y
+    POP X
+    SJP RT_FORVALUE
+    POP X
+    SJP RT_FORVALUE
+    LDA YH
+    STA (RT_FOR_SP)
+    LDA YL
+    STA (RT_FOR_SP+1)
+    RTN
+; Copies a limit or step into the frame, taking 8 bytes either way
+RT_FORVALUE:
+    LDA (RT_TMP+7)
+    CPI A,0
+    BZR RT_FORVALUE1
+    SJP RT_MOVE2
+    LDI A,6
+    ADR Y
+    RTN
+RT_FORVALUE1:
+    JMP RT_MOVE8
+
+; Looks for the frame of the variable in RT_VAR. C set when found, with X at
+; its start
+RT_FIND:
+    LDA (RT_FOR_SP)
+    STA XH
+    LDA (RT_FOR_SP+1)
+    STA XL
+RT_FIND1:
+    LDA XH
+    CPI A,HIGH(RT_FRAMES)
+    BZR RT_FIND2
+    LDA XL
+    CPI A,LOW(RT_FRAMES)
+    BZR RT_FIND2
+    REC
+    RTN
+RT_FIND2:
+    ; X to the frame below
+    LDA XL
+    SEC
+    SBI A,FRAME
+    STA XL
+    LDA XH
+    SBI A,0
+    STA XH
+    LDA (X)
+    CPA (RT_VAR)
+    BZR RT_FIND1
+    INC X
+    LDA (X)
+    DEC X
+    CPA (RT_VAR+1)
+    BZR RT_FIND1
+    SEC
+    RTN
+
+; Steps the FOR variable Y. While it has not passed the limit this goes back
+; to the body, else it returns with the frame and those nested in it closed
+RT_NEXT:
+    LDA YH
+    STA (RT_VAR)
+    LDA YL
+    STA (RT_VAR+1)
+    SJP RT_FIND
+    BCS RT_NEXT1
+    JMP RT_ERR_NESTING
+RT_NEXT1:
+    LDA XH
+    STA (RT_FRAME)
+    LDA XL
+    STA (RT_FRAME+1)
+    LDI A,FRAME
+    ADR X
+    LDA XH
+    STA (RT_FOR_SP)
+    LDA XL
+    STA (RT_FOR_SP+1)
+    ; the kind, the body, and the limit with the step 8 bytes on
+    LDA (RT_FRAME)
+    STA XH
+    LDA (RT_FRAME+1)
+    STA XL
+    INC X
+    INC X
+    LIN X
+    STA (RT_TMP+7)
+    LIN X
+    STA (RT_ARG)
+    LIN X
+    STA (RT_ARG+1)
+    LDA XH
+    STA (RT_LIMIT)
+    LDA XL
+    STA (RT_LIMIT+1)
+    LDI A,8
+    ADR X
+    STX U
+    LDA (RT_VAR)
+    STA YH
+    LDA (RT_VAR+1)
+    STA YL
+    LDA (RT_TMP+7)
+    CPI A,0
+    BZR RT_NEXT3
+    LDA (U)
+    STA (RT_SIGN)
+    INC Y
+    INC U
+    REC
+    LDA (Y)
+    ADC (U)
+    STA (Y)
+    DEC Y
+    DEC U
+    LDA (Y)
+    ADC (U)
+    STA (Y)
+    LDX Y
+    SJP RT_LIMITU
+    SJP RT_ICMP
+    BCH RT_NEXT4
+RT_NEXT3:
+    INC U
+    LDA (U)
+    DEC U
+    STA (RT_SIGN)
+    LDX Y
+    SJP RT_DADD
+    LDA (RT_VAR)
+    STA XH
+    LDA (RT_VAR+1)
+    STA XL
+    SJP RT_LIMITU
+    SJP RT_DCMP
+RT_NEXT4:
+    ; counting up goes on while less or equal, down while greater or equal
+    STA (RT_TMP+7)
+    LDA (RT_SIGN)
+    ANI A,&80
+    BZR RT_NEXT5
+    LDI A,3
+    BCH RT_NEXT6
+RT_NEXT5:
+    LDI A,6
+RT_NEXT6:
+    AND (RT_TMP+7)
+    BZR RT_NEXT7
+    LDA (RT_FRAME)
+    STA (RT_FOR_SP)
+    LDA (RT_FRAME+1)
+    STA (RT_FOR_SP+1)
+    RTN
+RT_NEXT7:
+    ; back to the body, dropping the return address
+    LDX S
+    INC X
+    INC X
+    STX S
+    LDA (RT_ARG)
+    STA XH
+    LDA (RT_ARG+1)
+    STA XL
+    STX P
+RT_LIMITU:
+    LDA (RT_LIMIT)
+    STA UH
+    LDA (RT_LIMIT+1)
+    STA UL
+    RTN
+
+; Errors set ERN and ERL and go to the ON ERROR GOTO handler if there is
+; one, else to the ROM to report them
+RT_ERR_NESTING:
+    LDI A,ERR_NESTING
+    BCH RT_RAISE
+RT_ERR_ARRAY:
+    LDI A,ERR_ARRAY
+    BCH RT_RAISE
+RT_ERR_TYPE:
+    LDI A,ERR_TYPE
+    BCH RT_RAISE
+RT_ERR_ARG:
+    LDI A,ERR_ARGUMENT
+    BCH RT_RAISE
+RT_ERR_DIV:
+    LDI A,ERR_DIVISION
+RT_RAISE:
+    STA (RT_ERN+1)
+    LDI A,0
+    STA (RT_ERN)
+    LDA (RT_LINE)
+    STA (RT_ERL)
+    LDA (RT_LINE+1)
+    STA (RT_ERL+1)
+    ; the handler runs with no GOSUB return left
+    LDA (RT_STACK)
+    STA XH
+    LDA (RT_STACK+1)
+    STA XL
+    STX S
+    LDA (RT_TRAP)
+    ORA (RT_TRAP+1)
+    BZS RT_RAISE1
+    LDA (RT_TRAP)
+    STA XH
+    LDA (RT_TRAP+1)
+    STA XL
+    STX P
+RT_RAISE1:
+    LDA (RT_ERN+1)
+    JMP ROM_ERROR
+
+; ERL as a number at Y, lines go past 32767
+RT_GETERL:
+    LDI XH,HIGH(RT_ERL)
+    LDI XL,LOW(RT_ERL)
+    JMP RT_UTOD
+
+; Next DATA item at X past its type byte, in A
+RT_ITEM:
+    LDA (RT_DATA)
+    STA XH
+    LDA (RT_DATA+1)
+    STA XL
+    LIN X
+    CPI A,2
+    BZR RT_ITEM1
+    JMP RT_ERR_NESTING
+RT_ITEM1:
+    RTN
+RT_SETDATA:
+    LDA XH
+    STA (RT_DATA)
+    LDA XL
+    STA (RT_DATA+1)
+    RTN
+
+RT_READ_NUM:
+    SJP RT_ITEM
+    CPI A,0
+    BZS RT_READ1
+    JMP RT_ERR_TYPE
+RT_READ1:
+    SJP RT_MOVE8
+    BCH RT_SETDATA
+
+; Reads a string into Y of capacity A
+RT_READ_STR:
+    STA (RT_TMP+6)
+    SJP RT_ITEM
+    CPI A,1
+    BZS RT_READ2
+    JMP RT_ERR_TYPE
+RT_READ2:
+    PSH X
+    LDA (X)
+    ADR X
+    INC X
+    SJP RT_SETDATA
+    POP X
+    LDA (RT_TMP+6)
+    JMP RT_SCOPY
+
+; Decimal arithmetic through the ROM's registers
+RT_DLOAD:
+    PSH Y
+    PSH U
+    LDI YH,HIGH(ROM_ARX)
+    LDI YL,LOW(ROM_ARX)
+    SJP RT_MOVE8
+    POP X
+    LDI YH,HIGH(ROM_ARY)
+    LDI YL,LOW(ROM_ARY)
+    SJP RT_MOVE8
+    POP Y
+    RTN
+
+RT_DADD:
+    SJP RT_DLOAD
+    PSH Y
+    SJP ROM_ADD
+    BCH RT_DSTORE
+RT_DSUB:
+    SJP RT_DLOAD
+    PSH Y
+    SJP ROM_SUB
+    BCH RT_DSTORE
+RT_DMUL:
+    SJP RT_DLOAD
+    PSH Y
+    SJP ROM_MUL
+    BCH RT_DSTORE
+RT_DDIV:
+    INC U
+    INC U
+    LDA (U)
+    DEC U
+    DEC U
+    CPI A,0
+    BZR RT_DDIV1
+    JMP RT_ERR_DIV
+RT_DDIV1:
+    SJP RT_DLOAD
+    PSH Y
+    SJP ROM_DIV
+; Pops the destination and stores ARX there
+RT_DSTORE:
+    POP Y
+    LDI XH,HIGH(ROM_ARX)
+    LDI XL,LOW(ROM_ARX)
+    JMP RT_MOVE8
+
+RT_DNEG:
+    PSH Y
+    SJP RT_MOVE8
+    POP Y
+    INC Y
+    INC Y
+    LDA (Y)
+    DEC Y
+    CPI A,0
+    BZS RT_DNEG1
+    LDA (Y)
+    EAI &80
+    STA (Y)
+RT_DNEG1:
+    RTN
+
+; Printing takes the separator after the item in the low bits of A and &80
+; for the printer
+
+RT_MODE:
+    STA (RT_TMP)
+    ANI A,&7F
+    STA UL
+    LDA (RT_TMP)
+    ANI A,&80
+    STA UH
+    RTN
+
+RT_PRINT_STR:
+    SJP RT_MODE
+    LIN X
+    JMP ROM_PRINT_TEXT
+
+RT_PRINT_NUM:
+    STA (RT_TMP+1)
+    LDI YH,HIGH(ROM_ARX)
+    LDI YL,LOW(ROM_ARX)
+    SJP RT_MOVE8
+    LDA (RT_TMP+1)
+    SJP RT_MODE
+    JMP ROM_PRINT_NUM
+
+RT_END_PRINT:
+    JMP ROM_END_PRINT
+
+RT_USING:
+    JMP ROM_USING
+
+; INPUT with the prompt string at X if A is 1
+RT_INPUT:
+    CPI A,0
+    BZR RT_INPUT1
+    LDI XH,0
+    LDI XL,0
+RT_INPUT1:
+    JMP ROM_INPUT
+
+RT_INPUT_NUM:
+    PSH Y
+    SJP RT_INPUT
+    SJP ROM_VAL
+    BCH RT_DSTORE
+
+RT_INPUT_STR:
+    PSH Y
+    SJP RT_INPUT
+    POP Y
+    CPI A,81
+    BCR RT_INPUT2
+    LDI A,80
+RT_INPUT2:
+    SIN Y
+    JMP RT_COPYN
+
+; INKEY$ as a string at Y, empty without a key
+RT_INKEY:
+    PSH Y
+    SJP ROM_INKEY
+    POP Y
+    CPI A,0
+    BZS RT_INKEY1
+    STA (RT_TMP)
+    LDI A,1
+    SIN Y
+    LDA (RT_TMP)
+RT_INKEY1:
+    STA (Y)
+    RTN
+
+; The first two integer arguments in X and U, keeping A
+RT_ARGXU:
+    STA (RT_TMP)
+    LDA (RT_ARG)
+    STA XH
+    LDA (RT_ARG+1)
+    STA XL
+    LDA (RT_ARG+2)
+    STA UH
+    LDA (RT_ARG+3)
+    STA UL
+    LDA (RT_TMP)
+    RTN
+
+; Device statements take their number of integer arguments in A
+
+RT_WAIT:
+    SJP RT_ARGXU
+    JMP ROM_WAIT
+
+RT_PAUSE:
+    JMP ROM_PAUSE
+
+RT_CLS:
+    JMP ROM_CLS
+
+RT_CURSOR:
+    LDA (RT_ARG+1)
+    JMP ROM_CURSOR
+
+RT_GCURSOR:
+    SJP RT_ARGXU
+    JMP ROM_GCURSOR
+
+RT_POINT:
+    PSH Y
+    LDA (RT_ARG+1)
+    SJP ROM_POINT
+    POP Y
+    JMP RT_SETA
+
+RT_GPRINT_BYTE:
+    LDA (RT_ARG)
+    CPI A,0
+    BZS RT_GPRINT_BYTE1
+    JMP RT_ERR_ARG
+RT_GPRINT_BYTE1:
+    LDA (RT_ARG+1)
+    JMP ROM_GPRINT
+
+; Columns given as a string of two hex digits each
+RT_GPRINT_HEX:
+    LIN X
+    STA (RT_TMP+1)
+RT_GPRINT_HEX1:
+    LDA (RT_TMP+1)
+    CPI A,0
+    BZR RT_GPRINT_HEX2
+    RTN
+RT_GPRINT_HEX2:
+    CPI A,1
+    BZR RT_GPRINT_HEX3
+    JMP RT_ERR_ARG
+RT_GPRINT_HEX3:
+    DEC A
+    DEC A
+    STA (RT_TMP+1)
+    LIN X
+    SJP RT_HEX
+    AEX
+    STA (RT_TMP+2)
+    LIN X
+    SJP RT_HEX
+    ORA (RT_TMP+2)
+    PSH X
+    SJP ROM_GPRINT
+    POP X
+    BCH RT_GPRINT_HEX1
+
+; Value of the hex digit A
+RT_HEX:
+    CPI A,&30
+    BCR RT_HEX2
+    CPI A,&3A
+    BCS RT_HEX1
+    SEC
+    SBI A,&30
+    RTN
+RT_HEX1:
+    CPI A,&41
+    BCR RT_HEX2
+    CPI A,&47
+    BCS RT_HEX2
+    SEC
+    SBI A,&37
+    RTN
+RT_HEX2:
+    JMP RT_ERR_ARG
+
+RT_BEEP:
+    SJP RT_ARGXU
+    STA (RT_TMP)
+    LDA (RT_ARG+4)
+    STA YH
+    LDA (RT_ARG+5)
+    STA YL
+    LDA (RT_TMP)
+    JMP ROM_BEEP
+
+RT_TRON:
+    JMP ROM_TRON
+
+RT_TROFF:
+    JMP ROM_TROFF
+
+; LINE options in RT_FLAGS: 1 relative, 2 box and 4 with a start point
+RT_DRAW:
+    STA (RT_TMP)
+    LDA (RT_FLAGS)
+    STA UL
+    LDI XH,HIGH(RT_ARG)
+    LDI XL,LOW(RT_ARG)
+    LDA (RT_TMP)
+    JMP ROM_LINE
+
+RT_GLCURSOR:
+    SJP RT_ARGXU
+    JMP ROM_GLCURSOR
+
+RT_COLOR:
+    LDA (RT_ARG+1)
+    JMP ROM_COLOR
+
+RT_CSIZE:
+    LDA (RT_ARG+1)
+    JMP ROM_CSIZE
+
+RT_ROTATE:
+    LDA (RT_ARG+1)
+    JMP ROM_ROTATE
+
+RT_GRAPH:
+    JMP ROM_GRAPH
+
+RT_TEXT:
+    JMP ROM_TEXT
+
+RT_SORGN:
+    JMP ROM_SORGN
+
+RT_LF:
+    SJP RT_ARGXU
+    JMP ROM_LF
+
+; State of the runtime, cleared by RT_INIT along with the variables after it
+RT_VARS:
+RT_STACK: DS 2            ; S of the CALL that started the program
+RT_TRAP: DS 2             ; ON ERROR GOTO handler, 0 when off
+RT_LINE: DS 2             ; line running, kept when errors can be trapped
+RT_ERN: DS 2
+RT_ERL: DS 2
+RT_DATA: DS 2             ; next DATA item
+RT_PTR: DS 2              ; address for POKE and CALL
+RT_BANK: DS 1             ; 1 for ME1
+RT_FLAGS: DS 1            ; LINE options
+RT_ARG: DS 32             ; integer arguments of the device routines
+RT_S0: DS 8               ; operands converted for an instruction
+RT_S1: DS 8
+RT_S2: DS 8               ; result converted for its destination
+RT_TMP: DS 8              ; work area of the routines
+RT_ACC: DS 3
+RT_BCD: DS 3
+RT_EXP: DS 1
+RT_SIGN: DS 1
+RT_VAR: DS 2              ; FOR variable looked for
+RT_FRAME: DS 2
+RT_LIMIT: DS 2
+RT_FOR_SP: DS 2           ; free FOR frame
+RT_FRAMES: DS FRAME*FRAMES
+RT_FRAMES_END:
@@ -0,0 +1,77 @@
+// Virtual time of a run
+//
+// Time is counted in microseconds, which holds the 1/64 s ticks of WAIT
+// exactly. An instant clock only counts, a real-time clock also sleeps so the
+// host keeps pace with the device.
+
+use std::time::{Duration, Instant};
+
+pub const TICK_MICROS: u64 = 15_625;
+// How long PAUSE holds the display
+pub const PAUSE_MICROS: u64 = 850_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Timing {
+    Instant,
+    RealTime,
+}
+
+impl clap::ValueEnum for Timing {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Timing::Instant, Timing::RealTime]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        match self {
+            Timing::Instant => Some(clap::builder::PossibleValue::new("instant")),
+            Timing::RealTime => Some(clap::builder::PossibleValue::new("realtime")),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Clock {
+    now: u64,
+    timing: Timing,
+    start: Instant,
+}
+
+impl Clock {
+    pub fn new(timing: Timing) -> Self {
+        Clock {
+            now: 0,
+            timing,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn now(&self) -> u64 {
+        self.now
+    }
+
+    pub fn advance(&mut self, micros: u64) {
+        self.advance_to(self.now.saturating_add(micros));
+    }
+
+    // Never goes back in time
+    pub fn advance_to(&mut self, time: u64) {
+        self.now = self.now.max(time);
+        if self.timing == Timing::RealTime {
+            let target = self.start + Duration::from_micros(self.now);
+            let host = Instant::now();
+            if target > host {
+                std::thread::sleep(target - host);
+            }
+        }
+    }
+}
+
+// Seconds with millisecond precision, as transcripts show them
+pub struct Timestamp(pub u64);
+
+impl std::fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let millis = self.0 / 1000;
+        write!(f, "{}.{:03}", millis / 1000, millis % 1000)
+    }
+}
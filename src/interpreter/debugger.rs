@@ -0,0 +1,324 @@
+// Source-level debugger for the run pass
+//
+// Reads one command per line:
+//
+//     break LINE, break "LABEL"   stop before the line runs
+//     delete LINE                 remove a breakpoint
+//     step                        run one statement, the parts of a line
+//                                 joined with ':' stop one by one
+//     continue                    run to the next breakpoint or the end
+//     watch EXPR                  show the expression at every stop
+//     print EXPR                  show the expression once
+//     set VAR = EXPR              change a variable or an array element
+//     vars                        show variables and arrays
+//     stack                       show the GOSUB and FOR stacks
+//     where                       show the statement about to run
+//     quit
+//
+// Commands can be shortened to their first letter.
+
+use std::collections::BTreeSet;
+use std::io::{self, BufRead, Write};
+
+use super::{Interpreter, RuntimeError, Value};
+use crate::ast::{BinaryOperator, Expression, ExpressionParser, Printer, Program};
+use crate::tokens::Lexer;
+
+pub struct Debugger<'a> {
+    interpreter: Interpreter<'a>,
+    program: &'a Program,
+    breakpoints: BTreeSet<u32>,
+    watches: Vec<Expression>,
+    // How the program ended, None while it can still run
+    outcome: Option<Result<(), RuntimeError>>,
+}
+
+impl<'a> Debugger<'a> {
+    pub fn new(program: &'a Program, interpreter: Interpreter<'a>) -> Self {
+        Debugger {
+            interpreter,
+            program,
+            breakpoints: BTreeSet::new(),
+            watches: Vec::new(),
+            outcome: None,
+        }
+    }
+
+    pub fn into_interpreter(self) -> Interpreter<'a> {
+        self.interpreter
+    }
+
+    // Runs commands until the input ends or QUIT. Returns how the program
+    // ended, Ok when it was left unfinished
+    pub fn run(
+        &mut self,
+        input: impl BufRead,
+        output: &mut impl Write,
+    ) -> io::Result<Result<(), RuntimeError>> {
+        self.stop(output)?;
+        let mut lines = input.lines();
+        loop {
+            write!(output, "> ")?;
+            output.flush()?;
+            let Some(input_line) = lines.next().transpose()? else {
+                break;
+            };
+
+            let (command, argument) = input_line
+                .trim()
+                .split_once(' ')
+                .map_or((input_line.trim(), ""), |(command, argument)| {
+                    (command, argument.trim())
+                });
+            match command.to_ascii_lowercase().as_str() {
+                "" => {}
+                "b" | "break" => match self.line_number(argument) {
+                    Some(line) => {
+                        self.breakpoints.insert(line);
+                        writeln!(output, "Breakpoint at line {}", line)?;
+                    }
+                    None => writeln!(output, "No line {}", argument)?,
+                },
+                "d" | "delete" => match self.line_number(argument) {
+                    Some(line) if self.breakpoints.remove(&line) => {
+                        writeln!(output, "Deleted breakpoint at line {}", line)?;
+                    }
+                    _ => writeln!(output, "No breakpoint at {}", argument)?,
+                },
+                "s" | "step" => {
+                    self.step();
+                    self.stop(output)?;
+                }
+                "c" | "continue" => {
+                    self.step();
+                    while self.outcome.is_none() && !self.at_breakpoint() {
+                        self.step();
+                    }
+                    self.stop(output)?;
+                }
+                "w" | "watch" => match parse_expression(argument) {
+                    Ok(expression) => {
+                        show_watch(&mut self.interpreter, &expression, output)?;
+                        self.watches.push(expression);
+                    }
+                    Err(error) => writeln!(output, "{}", error)?,
+                },
+                "p" | "print" => match parse_expression(argument) {
+                    Ok(expression) => show_watch(&mut self.interpreter, &expression, output)?,
+                    Err(error) => writeln!(output, "{}", error)?,
+                },
+                "set" => self.set(argument, output)?,
+                "v" | "vars" => self.show_variables(output)?,
+                "stack" => self.show_stack(output)?,
+                "where" => self.stop(output)?,
+                "q" | "quit" => break,
+                _ => writeln!(output, "Unknown command {}", command)?,
+            }
+        }
+
+        Ok(self.outcome.clone().unwrap_or(Ok(())))
+    }
+
+    fn step(&mut self) {
+        if self.outcome.is_some() {
+            return;
+        }
+        match self.interpreter.step() {
+            Ok(true) => {}
+            Ok(false) => self.outcome = Some(Ok(())),
+            Err(error) => self.outcome = Some(Err(error)),
+        }
+    }
+
+    fn at_breakpoint(&self) -> bool {
+        self.interpreter
+            .position()
+            .is_some_and(|(line, _, first)| first && self.breakpoints.contains(&line))
+    }
+
+    // Line number or quoted label
+    fn line_number(&self, argument: &str) -> Option<u32> {
+        let line = match argument.strip_prefix('"') {
+            Some(label) => self.program.label_line(label.trim_end_matches('"'))?,
+            None => argument.parse().ok()?,
+        };
+        self.program.lookup_line(line).map(|_| line)
+    }
+
+    // Where the program stopped and the watched expressions
+    fn stop(&mut self, output: &mut impl Write) -> io::Result<()> {
+        match (&self.outcome, self.interpreter.position()) {
+            (Some(Err(error)), _) => writeln!(output, "{}", error)?,
+            (Some(Ok(())), _) | (None, None) => writeln!(output, "Program ended")?,
+            (None, Some((line, statement, _))) => {
+                writeln!(
+                    output,
+                    "Line {}: {}",
+                    line,
+                    Printer::new().statement(statement)
+                )?;
+            }
+        }
+        for expression in &self.watches {
+            show_watch(&mut self.interpreter, expression, output)?;
+        }
+        Ok(())
+    }
+
+    // SET A = 5 or SET B(2) = A + 1
+    fn set(&mut self, argument: &str, output: &mut impl Write) -> io::Result<()> {
+        let expression = match parse_expression(argument) {
+            Ok(expression) => expression,
+            Err(error) => return writeln!(output, "{}", error),
+        };
+        let Expression::Binary {
+            left,
+            op: BinaryOperator::Eq,
+            right,
+        } = expression
+        else {
+            return writeln!(output, "Expected VAR = EXPR");
+        };
+        let Expression::LValue(variable) = left.as_ref() else {
+            return writeln!(output, "{} is not a variable", left);
+        };
+
+        let result = self
+            .interpreter
+            .evaluate(&right)
+            .and_then(|value| self.interpreter.set(variable, value));
+        match result {
+            Ok(()) => show_watch(&mut self.interpreter, &left, output),
+            Err(error) => writeln!(output, "Cannot set {}: {}", variable, error),
+        }
+    }
+
+    fn show_variables(&self, output: &mut impl Write) -> io::Result<()> {
+        for (name, value) in self.interpreter.variables() {
+            writeln!(output, "{} = {}", name, value.quoted())?;
+        }
+        for (name, cells) in self.interpreter.arrays() {
+            let cells: Vec<String> = cells.iter().map(Value::quoted).collect();
+            writeln!(output, "{}() = {}", name, cells.join(", "))?;
+        }
+        Ok(())
+    }
+
+    fn show_stack(&self, output: &mut impl Write) -> io::Result<()> {
+        for line in self.interpreter.gosub_stack() {
+            match line {
+                Some(line) => writeln!(output, "GOSUB, returns to line {}", line)?,
+                None => writeln!(output, "GOSUB, returns to the end")?,
+            }
+        }
+        for (variable, to, step, line) in self.interpreter.for_stack() {
+            writeln!(
+                output,
+                "FOR {} TO {} STEP {}, loops to line {}",
+                variable, to, step, line
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn show_watch(
+    interpreter: &mut Interpreter,
+    expression: &Expression,
+    output: &mut impl Write,
+) -> io::Result<()> {
+    match interpreter.evaluate(expression) {
+        Ok(value) => writeln!(output, "{} = {}", expression, value.quoted()),
+        Err(error) => writeln!(output, "{} = <{}>", expression, error),
+    }
+}
+
+// Text the lexer cannot read comes back as an error, like any other typo
+fn parse_expression(text: &str) -> Result<Expression, String> {
+    let mut parser = ExpressionParser::new(Lexer::new(text));
+    match parser.parse() {
+        Ok(Some(expression)) if parser.lexer.peek().is_none() => Ok(expression),
+        Ok(_) => Err(format!("Invalid expression {}", text)),
+        Err(error) => Err(error.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::interpreter::Terminal;
+    use crate::machine::MemoryConfig;
+    use crate::test_support::parse;
+
+    #[test]
+    fn breakpoints_and_stacks() {
+        let program = parse(
+            "\
+10 FOR I = 1 TO 3
+20 GOSUB 100
+30 NEXT I
+40 END
+100 \"SUM\" S = S + I: RETURN
+",
+        );
+
+        let interpreter = Interpreter::new(&program, MemoryConfig::Pc1500, Box::new(Terminal));
+        let mut debugger = Debugger::new(&program, interpreter);
+        let commands = "break \"SUM\"\ncontinue\nwatch S + 1\nstack\nstep\nstep\n\
+                        set S = 10\ncontinue\ndelete 100\ncontinue\nvars\n";
+        let mut output = Vec::new();
+        let result = debugger
+            .run(commands.as_bytes(), &mut output)
+            .expect("write to memory");
+
+        assert_eq!(result, Ok(()));
+        let output = String::from_utf8(output).unwrap();
+        let expected = [
+            "Line 10: FOR I = 1 TO 3",
+            "> Breakpoint at line 100",
+            "> Line 100: LET S = (S + I)",
+            "> S + 1 = 1",
+            "> GOSUB, returns to line 30",
+            "FOR I TO 3 STEP 1, loops to line 20",
+            "> Line 100: RETURN",
+            "S + 1 = 2",
+            "> Line 30: NEXT I",
+            "S + 1 = 2",
+            "> S = 10",
+            "> Line 100: LET S = (S + I)",
+            "S + 1 = 11",
+            "> Deleted breakpoint at line 100",
+            "> Program ended",
+            "S + 1 = 16",
+            "> I = 4",
+            "S = 15",
+            "> ",
+        ];
+        assert_eq!(output, expected.join("\n"));
+    }
+
+    #[test]
+    fn bad_input_prompts_again() {
+        let program = parse("10 A = 1\n");
+        let interpreter = Interpreter::new(&program, MemoryConfig::Pc1500, Box::new(Terminal));
+        let mut debugger = Debugger::new(&program, interpreter);
+        let commands = "print A#\nprint \"AB\nwatch A +\nset N = 2\nprint N * A\n";
+        let mut output = Vec::new();
+        let result = debugger
+            .run(commands.as_bytes(), &mut output)
+            .expect("write to memory");
+
+        assert_eq!(result, Ok(()));
+        let output = String::from_utf8(output).unwrap();
+        let expected = [
+            "Line 10: LET A = 1",
+            "> Invalid expression A#",
+            "> Error at line 1: Unterminated string",
+            "> Error at line 1: Expected expression",
+            "> N = 2",
+            "> N * A = 0",
+            "> ",
+        ];
+        assert_eq!(output, expected.join("\n"));
+    }
+}
@@ -0,0 +1,406 @@
+// The 156x7 dot LCD, as seen through its display RAM
+//
+// Columns 0-77 live at 0x7600-0x764D and columns 78-155 at 0x7700-0x774D, one
+// byte per column with bit 0 as the top row. The bytes right after each half
+// drive the status annunciators. Since the dots are plain memory, POKEs into
+// the display buffer show up on screen like on the device.
+
+use crate::machine::format::{DISPLAY_COLUMNS, GRAPHICS_COLUMNS};
+
+pub const ROWS: usize = 7;
+const BANKS: [usize; 2] = [0x7600, 0x7700];
+const BANK_COLUMNS: usize = GRAPHICS_COLUMNS / 2;
+// Glyphs are 5 columns wide with a blank column after them
+const CELL_COLUMNS: usize = GRAPHICS_COLUMNS / DISPLAY_COLUMNS;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Annunciator {
+    Busy,
+    Shift,
+    Small,
+    Def,
+    // Reserve key groups
+    Group1,
+    Group2,
+    Group3,
+    De,
+    G,
+    Rad,
+    Run,
+    Pro,
+    Reserve,
+}
+
+impl Annunciator {
+    pub const ALL: [Annunciator; 13] = [
+        Annunciator::Busy,
+        Annunciator::Shift,
+        Annunciator::Small,
+        Annunciator::Def,
+        Annunciator::Group1,
+        Annunciator::Group2,
+        Annunciator::Group3,
+        Annunciator::De,
+        Annunciator::G,
+        Annunciator::Rad,
+        Annunciator::Run,
+        Annunciator::Pro,
+        Annunciator::Reserve,
+    ];
+
+    // Byte and bit that light the annunciator
+    fn location(self) -> (usize, u8) {
+        match self {
+            Annunciator::Busy => (0x764E, 0),
+            Annunciator::Shift => (0x764E, 1),
+            Annunciator::Small => (0x764E, 2),
+            Annunciator::Def => (0x764E, 3),
+            Annunciator::Group1 => (0x764E, 4),
+            Annunciator::Group2 => (0x764E, 5),
+            Annunciator::Group3 => (0x764E, 6),
+            Annunciator::De => (0x764F, 0),
+            Annunciator::G => (0x764F, 1),
+            Annunciator::Rad => (0x764F, 2),
+            Annunciator::Run => (0x764F, 4),
+            Annunciator::Pro => (0x764F, 5),
+            Annunciator::Reserve => (0x764F, 6),
+        }
+    }
+}
+
+impl std::fmt::Display for Annunciator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Annunciator::Busy => write!(f, "BUSY"),
+            Annunciator::Shift => write!(f, "SHIFT"),
+            Annunciator::Small => write!(f, "SML"),
+            Annunciator::Def => write!(f, "DEF"),
+            Annunciator::Group1 => write!(f, "I"),
+            Annunciator::Group2 => write!(f, "II"),
+            Annunciator::Group3 => write!(f, "III"),
+            Annunciator::De => write!(f, "DE"),
+            Annunciator::G => write!(f, "G"),
+            Annunciator::Rad => write!(f, "RAD"),
+            Annunciator::Run => write!(f, "RUN"),
+            Annunciator::Pro => write!(f, "PRO"),
+            Annunciator::Reserve => write!(f, "RESERVE"),
+        }
+    }
+}
+
+fn column_address(column: usize) -> usize {
+    BANKS[column / BANK_COLUMNS] + column % BANK_COLUMNS
+}
+
+// Dots of a column, 0 past the right edge
+pub fn column(memory: &[u8], column: usize) -> u8 {
+    if column >= GRAPHICS_COLUMNS {
+        return 0;
+    }
+    memory
+        .get(column_address(column))
+        .map_or(0, |dots| dots & 0x7F)
+}
+
+pub fn set_column(memory: &mut [u8], column: usize, dots: u8) {
+    if column >= GRAPHICS_COLUMNS {
+        return;
+    }
+    if let Some(byte) = memory.get_mut(column_address(column)) {
+        *byte = dots & 0x7F;
+    }
+}
+
+// Blanks the dots, the annunciators stay as they are
+pub fn clear(memory: &mut [u8]) {
+    for i in 0..GRAPHICS_COLUMNS {
+        set_column(memory, i, 0);
+    }
+}
+
+// Replaces the screen with a line of text
+pub fn show_text(memory: &mut [u8], text: &str) {
+    clear(memory);
+    for (i, c) in text.chars().take(DISPLAY_COLUMNS).enumerate() {
+        for (offset, dots) in glyph(c).iter().enumerate() {
+            set_column(memory, i * CELL_COLUMNS + offset, *dots);
+        }
+    }
+}
+
+pub fn set_annunciator(memory: &mut [u8], annunciator: Annunciator, on: bool) {
+    let (address, bit) = annunciator.location();
+    if let Some(byte) = memory.get_mut(address) {
+        if on {
+            *byte |= 1 << bit;
+        } else {
+            *byte &= !(1 << bit);
+        }
+    }
+}
+
+pub fn snapshot(memory: &[u8]) -> Snapshot {
+    Snapshot {
+        columns: (0..GRAPHICS_COLUMNS).map(|i| column(memory, i)).collect(),
+        annunciators: Annunciator::ALL
+            .into_iter()
+            .filter(|annunciator| {
+                let (address, bit) = annunciator.location();
+                memory
+                    .get(address)
+                    .is_some_and(|byte| byte & (1 << bit) != 0)
+            })
+            .collect(),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SnapshotFormat {
+    Png,
+    Ascii,
+}
+
+impl clap::ValueEnum for SnapshotFormat {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[SnapshotFormat::Png, SnapshotFormat::Ascii]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        match self {
+            SnapshotFormat::Png => Some(clap::builder::PossibleValue::new("png")),
+            SnapshotFormat::Ascii => Some(clap::builder::PossibleValue::new("ascii")),
+        }
+    }
+}
+
+// What the LCD showed at some point of the run
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pub columns: Vec<u8>,
+    pub annunciators: Vec<Annunciator>,
+}
+
+impl Snapshot {
+    fn dot(&self, column: usize, row: usize) -> bool {
+        self.columns
+            .get(column)
+            .is_some_and(|dots| dots & (1 << row) != 0)
+    }
+
+    // The lit annunciators on the first line, then a line per dot row with
+    // '#' for the dots that are on
+    pub fn ascii(&self) -> String {
+        let names: Vec<String> = self.annunciators.iter().map(ToString::to_string).collect();
+        let mut art = names.join(" ");
+        art.push('\n');
+        for row in 0..ROWS {
+            for column in 0..self.columns.len() {
+                art.push(if self.dot(column, row) { '#' } else { '.' });
+            }
+            art.push('\n');
+        }
+        art
+    }
+
+    // Grayscale PNG with every dot drawn as a square of the given size. The
+    // annunciators are bars above the dots, in Annunciator::ALL order
+    pub fn png(&self, scale: usize) -> Vec<u8> {
+        const LIT: u8 = 0x20;
+        const UNLIT: u8 = 0xC8;
+        let slot = GRAPHICS_COLUMNS / Annunciator::ALL.len();
+        // One row for the annunciators, one blank row, then the dots
+        let width = GRAPHICS_COLUMNS * scale;
+        let height = (ROWS + 2) * scale;
+
+        let mut pixels = vec![UNLIT; width * height];
+        let mut light = |x: usize, y: usize| {
+            for dy in 0..scale {
+                let start = (y * scale + dy) * width + x * scale;
+                if let Some(line) = pixels.get_mut(start..start + scale) {
+                    line.fill(LIT);
+                }
+            }
+        };
+        for annunciator in &self.annunciators {
+            let index = Annunciator::ALL
+                .iter()
+                .position(|a| a == annunciator)
+                .unwrap_or(0);
+            for x in index * slot + 1..(index + 1) * slot - 1 {
+                light(x, 0);
+            }
+        }
+        for column in 0..GRAPHICS_COLUMNS {
+            for row in 0..ROWS {
+                if self.dot(column, row) {
+                    light(column, row + 2);
+                }
+            }
+        }
+
+        png::encode(width, height, &pixels)
+    }
+}
+
+// 5x7 glyphs of the character generator for the printable ASCII range,
+// column by column with bit 0 at the top
+fn glyph(c: char) -> [u8; 5] {
+    let index = (c as usize).wrapping_sub(0x20);
+    FONT.get(index).copied().unwrap_or([0; 5])
+}
+
+#[rustfmt::skip]
+const FONT: [[u8; 5]; 95] = [
+    [0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5F, 0x00, 0x00], // space !
+    [0x00, 0x07, 0x00, 0x07, 0x00], [0x14, 0x7F, 0x14, 0x7F, 0x14], // " #
+    [0x24, 0x2A, 0x7F, 0x2A, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62], // $ %
+    [0x36, 0x49, 0x55, 0x22, 0x50], [0x00, 0x05, 0x03, 0x00, 0x00], // & '
+    [0x00, 0x1C, 0x22, 0x41, 0x00], [0x00, 0x41, 0x22, 0x1C, 0x00], // ( )
+    [0x08, 0x2A, 0x1C, 0x2A, 0x08], [0x08, 0x08, 0x3E, 0x08, 0x08], // * +
+    [0x00, 0x50, 0x30, 0x00, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], // , -
+    [0x00, 0x60, 0x60, 0x00, 0x00], [0x20, 0x10, 0x08, 0x04, 0x02], // . /
+    [0x3E, 0x51, 0x49, 0x45, 0x3E], [0x00, 0x42, 0x7F, 0x40, 0x00], // 0 1
+    [0x42, 0x61, 0x51, 0x49, 0x46], [0x21, 0x41, 0x45, 0x4B, 0x31], // 2 3
+    [0x18, 0x14, 0x12, 0x7F, 0x10], [0x27, 0x45, 0x45, 0x45, 0x39], // 4 5
+    [0x3C, 0x4A, 0x49, 0x49, 0x30], [0x01, 0x71, 0x09, 0x05, 0x03], // 6 7
+    [0x36, 0x49, 0x49, 0x49, 0x36], [0x06, 0x49, 0x49, 0x29, 0x1E], // 8 9
+    [0x00, 0x36, 0x36, 0x00, 0x00], [0x00, 0x56, 0x36, 0x00, 0x00], // : ;
+    [0x08, 0x14, 0x22, 0x41, 0x00], [0x14, 0x14, 0x14, 0x14, 0x14], // < =
+    [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x51, 0x09, 0x06], // > ?
+    [0x32, 0x49, 0x79, 0x41, 0x3E], [0x7E, 0x11, 0x11, 0x11, 0x7E], // @ A
+    [0x7F, 0x49, 0x49, 0x49, 0x36], [0x3E, 0x41, 0x41, 0x41, 0x22], // B C
+    [0x7F, 0x41, 0x41, 0x22, 0x1C], [0x7F, 0x49, 0x49, 0x49, 0x41], // D E
+    [0x7F, 0x09, 0x09, 0x09, 0x01], [0x3E, 0x41, 0x49, 0x49, 0x7A], // F G
+    [0x7F, 0x08, 0x08, 0x08, 0x7F], [0x00, 0x41, 0x7F, 0x41, 0x00], // H I
+    [0x20, 0x40, 0x41, 0x3F, 0x01], [0x7F, 0x08, 0x14, 0x22, 0x41], // J K
+    [0x7F, 0x40, 0x40, 0x40, 0x40], [0x7F, 0x02, 0x0C, 0x02, 0x7F], // L M
+    [0x7F, 0x04, 0x08, 0x10, 0x7F], [0x3E, 0x41, 0x41, 0x41, 0x3E], // N O
+    [0x7F, 0x09, 0x09, 0x09, 0x06], [0x3E, 0x41, 0x51, 0x21, 0x5E], // P Q
+    [0x7F, 0x09, 0x19, 0x29, 0x46], [0x46, 0x49, 0x49, 0x49, 0x31], // R S
+    [0x01, 0x01, 0x7F, 0x01, 0x01], [0x3F, 0x40, 0x40, 0x40, 0x3F], // T U
+    [0x1F, 0x20, 0x40, 0x20, 0x1F], [0x3F, 0x40, 0x38, 0x40, 0x3F], // V W
+    [0x63, 0x14, 0x08, 0x14, 0x63], [0x07, 0x08, 0x70, 0x08, 0x07], // X Y
+    [0x61, 0x51, 0x49, 0x45, 0x43], [0x00, 0x7F, 0x41, 0x41, 0x00], // Z [
+    [0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x7F, 0x00], // \ ]
+    [0x04, 0x02, 0x01, 0x02, 0x04], [0x40, 0x40, 0x40, 0x40, 0x40], // ^ _
+    [0x00, 0x01, 0x02, 0x04, 0x00], [0x20, 0x54, 0x54, 0x54, 0x78], // ` a
+    [0x7F, 0x48, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x20], // b c
+    [0x38, 0x44, 0x44, 0x48, 0x7F], [0x38, 0x54, 0x54, 0x54, 0x18], // d e
+    [0x08, 0x7E, 0x09, 0x01, 0x02], [0x0C, 0x52, 0x52, 0x52, 0x3E], // f g
+    [0x7F, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7D, 0x40, 0x00], // h i
+    [0x20, 0x40, 0x44, 0x3D, 0x00], [0x7F, 0x10, 0x28, 0x44, 0x00], // j k
+    [0x00, 0x41, 0x7F, 0x40, 0x00], [0x7C, 0x04, 0x18, 0x04, 0x78], // l m
+    [0x7C, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], // n o
+    [0x7C, 0x14, 0x14, 0x14, 0x08], [0x08, 0x14, 0x14, 0x18, 0x7C], // p q
+    [0x7C, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x20], // r s
+    [0x04, 0x3F, 0x44, 0x40, 0x20], [0x3C, 0x40, 0x40, 0x20, 0x7C], // t u
+    [0x1C, 0x20, 0x40, 0x20, 0x1C], [0x3C, 0x40, 0x30, 0x40, 0x3C], // v w
+    [0x44, 0x28, 0x10, 0x28, 0x44], [0x0C, 0x50, 0x50, 0x50, 0x3C], // x y
+    [0x44, 0x64, 0x54, 0x4C, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], // z {
+    [0x00, 0x00, 0x7F, 0x00, 0x00], [0x00, 0x41, 0x36, 0x08, 0x00], // | }
+    [0x08, 0x04, 0x08, 0x10, 0x08],                                 // ~
+];
+
+// Just enough PNG to store a grayscale image, with uncompressed deflate
+// blocks so no compression library is needed
+mod png {
+    pub const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+    // Largest stored deflate block
+    const BLOCK: usize = 0xFFFF;
+
+    pub fn encode(width: usize, height: usize, pixels: &[u8]) -> Vec<u8> {
+        let mut header = Vec::new();
+        header.extend_from_slice(&(width as u32).to_be_bytes());
+        header.extend_from_slice(&(height as u32).to_be_bytes());
+        // 8 bit grayscale, default compression and filters, no interlace
+        header.extend_from_slice(&[8, 0, 0, 0, 0]);
+
+        // Every scanline starts with filter type 0
+        let mut raw = Vec::with_capacity((width + 1) * height);
+        for line in pixels.chunks(width) {
+            raw.push(0);
+            raw.extend_from_slice(line);
+        }
+
+        let mut png = SIGNATURE.to_vec();
+        chunk(&mut png, b"IHDR", &header);
+        chunk(&mut png, b"IDAT", &zlib(&raw));
+        chunk(&mut png, b"IEND", &[]);
+        png
+    }
+
+    fn chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+        png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+        let start = png.len();
+        png.extend_from_slice(kind);
+        png.extend_from_slice(data);
+        let crc = crc32(png.get(start..).unwrap_or_default());
+        png.extend_from_slice(&crc.to_be_bytes());
+    }
+
+    fn zlib(data: &[u8]) -> Vec<u8> {
+        let mut stream = vec![0x78, 0x01];
+        let blocks: Vec<&[u8]> = data.chunks(BLOCK).collect();
+        if blocks.is_empty() {
+            stream.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+        }
+        for (i, block) in blocks.iter().enumerate() {
+            stream.push(u8::from(i + 1 == blocks.len()));
+            let length = block.len() as u16;
+            stream.extend_from_slice(&length.to_le_bytes());
+            stream.extend_from_slice(&(!length).to_le_bytes());
+            stream.extend_from_slice(block);
+        }
+        stream.extend_from_slice(&adler32(data).to_be_bytes());
+        stream
+    }
+
+    fn adler32(data: &[u8]) -> u32 {
+        // Largest prime below 2^16
+        const MODULO: u32 = 0xFFF1;
+        let (mut a, mut b) = (1_u32, 0_u32);
+        for byte in data {
+            a = (a + u32::from(*byte)) % MODULO;
+            b = (b + a) % MODULO;
+        }
+        (b << 16) | a
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        let mut crc = !0_u32;
+        for byte in data {
+            crc ^= u32::from(*byte);
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+        }
+        !crc
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn png_layout() {
+        let mut memory = vec![0; 0x8000];
+        set_column(&mut memory, 0, 0x01);
+        set_annunciator(&mut memory, Annunciator::Run, true);
+
+        let png = snapshot(&memory).png(1);
+
+        assert_eq!(png.get(..8), Some(&png::SIGNATURE[..]));
+        // IHDR with the size of the screen and the annunciator rows
+        assert_eq!(png.get(16..24), Some(&[0, 0, 0, 156, 0, 0, 0, 9][..]));
+        // IEND and its fixed CRC close the file
+        assert_eq!(
+            png.get(png.len() - 8..),
+            Some(&[b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82][..])
+        );
+    }
+}
@@ -0,0 +1,292 @@
+// Scripted PC-1500 keyboard for running programs without a user
+//
+// A key script lists key presses in the order they happen, separated by
+// blanks or newlines:
+//
+//     # Pick the second menu entry, then type a name
+//     +500 2
+//     +1000 "JOHN" ENTER
+//
+// `+N` waits N milliseconds before the next key. Special keys are named
+// ENTER, CL, MODE, UP, DOWN, LEFT, RIGHT, SHIFT, SML, DEF and SPACE, any
+// other single character is the key with that label, and quoted text types
+// its characters one key at a time. `#` starts a comment, a `#` key has to be
+// quoted.
+//
+// SHIFT and DEF apply to the next key only, SML switches between capital and
+// small letters until it is pressed again. SHIFT inverts the letter case for
+// one key.
+
+use std::collections::VecDeque;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+    Char(char),
+    Enter,
+    Clear,
+    Mode,
+    Up,
+    Down,
+    Left,
+    Right,
+    Shift,
+    Small,
+    Def,
+}
+
+impl Key {
+    fn parse(name: &str) -> Option<Key> {
+        let key = match name.to_ascii_uppercase().as_str() {
+            "ENTER" => Key::Enter,
+            "CL" => Key::Clear,
+            "MODE" => Key::Mode,
+            "UP" => Key::Up,
+            "DOWN" => Key::Down,
+            "LEFT" => Key::Left,
+            "RIGHT" => Key::Right,
+            "SHIFT" => Key::Shift,
+            "SML" => Key::Small,
+            "DEF" => Key::Def,
+            "SPACE" => Key::Char(' '),
+            _ => {
+                let mut chars = name.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) if c.is_ascii_graphic() => Key::Char(c.to_ascii_uppercase()),
+                    _ => return None,
+                }
+            }
+        };
+        Some(key)
+    }
+
+    fn is_modifier(self) -> bool {
+        matches!(self, Key::Shift | Key::Small | Key::Def)
+    }
+
+    // What INKEY$ returns for the key, keys without a character give
+    // control codes
+    fn code(self) -> char {
+        match self {
+            Key::Char(c) => c,
+            Key::Enter => '\r',
+            Key::Clear => '\u{2}',
+            Key::Mode => '\u{3}',
+            Key::Left => '\u{8}',
+            Key::Down => '\u{a}',
+            Key::Up => '\u{b}',
+            Key::Right => '\u{c}',
+            // Modifiers only change the next key
+            Key::Shift | Key::Small | Key::Def => '\0',
+        }
+    }
+}
+
+impl std::fmt::Display for Key {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Key::Char(' ') => write!(f, "SPACE"),
+            Key::Char(c) => write!(f, "{}", c),
+            Key::Enter => write!(f, "ENTER"),
+            Key::Clear => write!(f, "CL"),
+            Key::Mode => write!(f, "MODE"),
+            Key::Up => write!(f, "UP"),
+            Key::Down => write!(f, "DOWN"),
+            Key::Left => write!(f, "LEFT"),
+            Key::Right => write!(f, "RIGHT"),
+            Key::Shift => write!(f, "SHIFT"),
+            Key::Small => write!(f, "SML"),
+            Key::Def => write!(f, "DEF"),
+        }
+    }
+}
+
+// A key press and the time it happens, in milliseconds from the start
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyPress {
+    pub time: u64,
+    pub key: Key,
+}
+
+pub fn parse_script(script: &str) -> Result<Vec<KeyPress>, String> {
+    let mut presses = Vec::new();
+    let mut time = 0_u64;
+
+    for (number, line) in script.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+        loop {
+            while chars.next_if(|c| c.is_whitespace()).is_some() {}
+            let Some(first) = chars.next() else {
+                break;
+            };
+
+            match first {
+                '#' => break,
+                '"' => {
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '"' {
+                            closed = true;
+                            break;
+                        }
+                        presses.push(KeyPress {
+                            time,
+                            key: Key::Char(c.to_ascii_uppercase()),
+                        });
+                    }
+                    if !closed {
+                        return Err(format!("unterminated text in line {}", number + 1));
+                    }
+                }
+                _ => {
+                    let mut word = first.to_string();
+                    while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                        word.push(c);
+                    }
+
+                    if let Some(delay) = word.strip_prefix('+').filter(|delay| !delay.is_empty()) {
+                        let delay: u64 = delay.parse().map_err(|_e| {
+                            format!("invalid delay '{}' in line {}", word, number + 1)
+                        })?;
+                        time += delay;
+                    } else {
+                        let key = Key::parse(&word).ok_or_else(|| {
+                            format!("unknown key '{}' in line {}", word, number + 1)
+                        })?;
+                        presses.push(KeyPress { time, key });
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(presses)
+}
+
+#[derive(Debug)]
+pub struct Keyboard {
+    presses: VecDeque<KeyPress>,
+    shift: bool,
+    def: bool,
+    small: bool,
+    // Time of the last key taken from the script
+    last: u64,
+}
+
+impl Keyboard {
+    pub fn new(presses: Vec<KeyPress>) -> Self {
+        Keyboard {
+            presses: presses.into(),
+            shift: false,
+            def: false,
+            small: false,
+            last: 0,
+        }
+    }
+
+    // INKEY$, the next key pressed up to the given time
+    pub fn poll(&mut self, now: u64) -> Option<char> {
+        while self.presses.front().is_some_and(|press| press.time <= now) {
+            let key = self.next().map(|press| self.apply(press.key))?;
+            if !key.is_modifier() {
+                return Some(key.code());
+            }
+        }
+        None
+    }
+
+    // Next key of the script no matter when it is pressed, for INPUT, which
+    // waits for keys. None when the script has run out
+    pub fn wait(&mut self) -> Option<KeyPress> {
+        loop {
+            let press = self.next()?;
+            let key = self.apply(press.key);
+            if !key.is_modifier() {
+                return Some(KeyPress { key, ..press });
+            }
+        }
+    }
+
+    // True when no more keys will come and the script ended long enough
+    // before the given time
+    pub fn exhausted(&self, now: u64, idle: u64) -> bool {
+        self.presses.is_empty() && now.saturating_sub(self.last) >= idle
+    }
+
+    pub fn shift(&self) -> bool {
+        self.shift
+    }
+
+    pub fn def(&self) -> bool {
+        self.def
+    }
+
+    pub fn small(&self) -> bool {
+        self.small
+    }
+
+    fn next(&mut self) -> Option<KeyPress> {
+        let press = self.presses.pop_front()?;
+        self.last = press.time;
+        Some(press)
+    }
+
+    // The key after applying and updating the modifiers
+    fn apply(&mut self, key: Key) -> Key {
+        match key {
+            Key::Shift => self.shift = !self.shift,
+            Key::Def => self.def = !self.def,
+            Key::Small => self.small = !self.small,
+            Key::Char(c) => {
+                let shifted = std::mem::take(&mut self.shift);
+                self.def = false;
+                if c.is_ascii_alphabetic() && self.small != shifted {
+                    return Key::Char(c.to_ascii_lowercase());
+                }
+            }
+            _ => {
+                self.shift = false;
+                self.def = false;
+            }
+        }
+        key
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn script_timing() {
+        let script = parse_script("# menu\n+500 2\n+250 \"ab\" ENTER SHIFT x").unwrap();
+        let keys: Vec<(u64, String)> = script
+            .iter()
+            .map(|press| (press.time, press.key.to_string()))
+            .collect();
+
+        assert_eq!(
+            keys,
+            vec![
+                (500, "2".to_owned()),
+                (750, "A".to_owned()),
+                (750, "B".to_owned()),
+                (750, "ENTER".to_owned()),
+                (750, "SHIFT".to_owned()),
+                (750, "X".to_owned()),
+            ]
+        );
+        parse_script("ESCAPE").unwrap_err();
+        parse_script("\"open").unwrap_err();
+    }
+
+    #[test]
+    fn modifiers() {
+        let mut keyboard = Keyboard::new(parse_script("+10 SML A SHIFT B C SML D").unwrap());
+
+        assert_eq!(keyboard.poll(5), None);
+        assert!(!keyboard.small());
+        let keys: String = std::iter::from_fn(|| keyboard.poll(10)).collect();
+        assert_eq!(keys, "aBcD");
+        assert!(keyboard.exhausted(20, 10));
+    }
+}
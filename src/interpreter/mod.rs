@@ -0,0 +1,1624 @@
+// Runs BASIC programs on the host, statement by statement, with the numeric
+// and memory semantics of the PC-1500 ROM interpreter
+
+pub mod clock;
+pub mod debugger;
+pub mod display;
+pub mod keyboard;
+pub mod plotter;
+pub mod profile;
+
+use std::collections::HashMap;
+use std::io::{BufRead, Write};
+use std::path::PathBuf;
+
+use crate::ast::{
+    BinaryOperator, ControlFlow, Coordinate, DataItem, Expression, ExpressionVisitor, Function,
+    LValue, PrintItem, Printer, Program, Separator, Statement, UnaryOperator,
+};
+use crate::machine::decimal::{Decimal, DecimalError};
+use crate::machine::error::ErrorCode;
+use crate::machine::format::{justify, Format, DISPLAY_COLUMNS, GRAPHICS_COLUMNS};
+use crate::machine::{MemoryConfig, MemoryMap, Region, MAX_ADDRESS, STRING_LENGTH};
+use clock::{Clock, Timestamp, Timing, PAUSE_MICROS, TICK_MICROS};
+use display::{Annunciator, Snapshot, SnapshotFormat};
+use keyboard::{Key, Keyboard};
+use plotter::Plotter;
+use profile::Profile;
+
+// Arrays used without DIM have indices 0 to 10
+const IMPLICIT_ARRAY_SIZE: usize = 11;
+// Pen positions the CE-150 accepts, in 0.2 mm steps
+const PLOTTER_RANGE: std::ops::RangeInclusive<i64> = -2048..=2047;
+// Rough speed of the ROM interpreter, in microseconds per statement
+const STATEMENT_MICROS: u64 = 5000;
+// INKEY$ on a key script that ran out this long ago ends the run, so polling
+// loops do not spin forever
+const KEYBOARD_IDLE_MILLIS: u64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value {
+    Number(Decimal),
+    String(String),
+}
+
+impl std::fmt::Display for Value {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Value::Number(number) => write!(f, "{}", number),
+            Value::String(string) => write!(f, "{}", string),
+        }
+    }
+}
+
+impl Value {
+    // Strings in quotes, as the debugger and traces show them
+    pub fn quoted(&self) -> String {
+        match self {
+            Value::Number(number) => number.to_string(),
+            Value::String(string) => format!("\"{}\"", string),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RuntimeErrorKind {
+    AddressOutOfRange(Decimal),
+    BadSubscript,
+    DivisionByZero,
+    InvalidFormat,
+    InvalidInput,
+    InvalidPattern,
+    NextWithoutFor,
+    OutOfData,
+    Overflow,
+    ReturnWithoutGosub,
+    StringTooLong,
+    TypeMismatch,
+    UndefinedLine(u32),
+    ValueOutOfRange(Decimal),
+}
+
+impl RuntimeErrorKind {
+    // Number the ROM reports for the error
+    pub fn code(&self) -> ErrorCode {
+        match self {
+            RuntimeErrorKind::NextWithoutFor
+            | RuntimeErrorKind::OutOfData
+            | RuntimeErrorKind::ReturnWithoutGosub => ErrorCode::Nesting,
+            RuntimeErrorKind::BadSubscript => ErrorCode::Array,
+            RuntimeErrorKind::UndefinedLine(_) => ErrorCode::UndefinedLine,
+            RuntimeErrorKind::InvalidInput | RuntimeErrorKind::TypeMismatch => {
+                ErrorCode::TypeMismatch
+            }
+            RuntimeErrorKind::AddressOutOfRange(_)
+            | RuntimeErrorKind::InvalidPattern
+            | RuntimeErrorKind::ValueOutOfRange(_) => ErrorCode::Argument,
+            RuntimeErrorKind::InvalidFormat => ErrorCode::UsingFormat,
+            // The closest code in the table
+            RuntimeErrorKind::Overflow | RuntimeErrorKind::StringTooLong => ErrorCode::Overflow,
+            RuntimeErrorKind::DivisionByZero => ErrorCode::DivisionByZero,
+        }
+    }
+}
+
+impl From<DecimalError> for RuntimeErrorKind {
+    fn from(error: DecimalError) -> Self {
+        match error {
+            DecimalError::Overflow => RuntimeErrorKind::Overflow,
+            DecimalError::DivisionByZero => RuntimeErrorKind::DivisionByZero,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RuntimeError {
+    pub kind: RuntimeErrorKind,
+    pub line: u32,
+}
+
+impl std::fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Runtime error at line {} ({}): {}",
+            self.line,
+            self.kind.code(),
+            self.kind
+        )
+    }
+}
+
+impl std::fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuntimeErrorKind::AddressOutOfRange(address) => {
+                write!(f, "Address {} out of range", address)
+            }
+            RuntimeErrorKind::BadSubscript => write!(f, "Bad subscript"),
+            RuntimeErrorKind::DivisionByZero => write!(f, "Division by zero"),
+            RuntimeErrorKind::InvalidFormat => write!(f, "Invalid USING format"),
+            RuntimeErrorKind::InvalidInput => write!(f, "Invalid input"),
+            RuntimeErrorKind::InvalidPattern => write!(f, "Invalid GPRINT pattern"),
+            RuntimeErrorKind::NextWithoutFor => write!(f, "NEXT without FOR"),
+            RuntimeErrorKind::OutOfData => write!(f, "Out of DATA"),
+            RuntimeErrorKind::Overflow => write!(f, "Overflow"),
+            RuntimeErrorKind::ReturnWithoutGosub => write!(f, "RETURN without GOSUB"),
+            RuntimeErrorKind::StringTooLong => {
+                write!(f, "String longer than {} characters", STRING_LENGTH)
+            }
+            RuntimeErrorKind::TypeMismatch => write!(f, "Type mismatch"),
+            RuntimeErrorKind::UndefinedLine(line) => write!(f, "Undefined line {}", line),
+            RuntimeErrorKind::ValueOutOfRange(value) => write!(f, "Value {} out of range", value),
+        }
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+// A line the display showed and when, in microseconds from the start
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TranscriptLine {
+    pub time: u64,
+    pub text: String,
+}
+
+impl std::fmt::Display for TranscriptLine {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} {}", Timestamp(self.time), self.text)
+    }
+}
+
+// Machine code routine entered through CALL. There is no CPU to run it, so
+// calls are only recorded
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Call {
+    pub line: u32,
+    pub address: u32,
+    pub argument: Option<Decimal>,
+}
+
+// Where PRINT output goes and INPUT comes from
+pub trait Console {
+    fn print(&mut self, text: &str);
+    // None when there is no more input
+    fn input(&mut self, prompt: &str) -> Option<String>;
+    // Called with the LCD contents at each PAUSE and WAIT
+    fn snapshot(&mut self, _: &Snapshot) {}
+}
+
+// Console on the standard input and output of the host
+pub struct Terminal;
+
+impl Console for Terminal {
+    fn print(&mut self, text: &str) {
+        println!("{}", text);
+    }
+
+    fn input(&mut self, prompt: &str) -> Option<String> {
+        print!("{}", prompt);
+        std::io::stdout().flush().ok()?;
+
+        let mut line = String::new();
+        match std::io::stdin().lock().read_line(&mut line) {
+            Ok(0) | Err(_) => None,
+            Ok(_) => Some(line.trim_end_matches(['\r', '\n']).to_owned()),
+        }
+    }
+}
+
+// Terminal that also saves the screen at each PAUSE and WAIT, as numbered
+// files in a directory
+pub struct Recorder {
+    directory: PathBuf,
+    format: SnapshotFormat,
+    count: usize,
+}
+
+impl Recorder {
+    pub fn new(directory: PathBuf, format: SnapshotFormat) -> Self {
+        Recorder {
+            directory,
+            format,
+            count: 0,
+        }
+    }
+}
+
+impl Console for Recorder {
+    fn print(&mut self, text: &str) {
+        Terminal.print(text);
+    }
+
+    fn input(&mut self, prompt: &str) -> Option<String> {
+        Terminal.input(prompt)
+    }
+
+    fn snapshot(&mut self, snapshot: &Snapshot) {
+        self.count += 1;
+        let (extension, contents) = match self.format {
+            SnapshotFormat::Png => ("png", snapshot.png(4)),
+            SnapshotFormat::Ascii => ("txt", snapshot.ascii().into_bytes()),
+        };
+        let path = self
+            .directory
+            .join(format!("{:03}.{}", self.count, extension));
+        if let Err(error) = std::fs::write(&path, contents) {
+            println!("Cannot write snapshot {}: {}", path.display(), error);
+        }
+    }
+}
+
+#[derive(Debug)]
+struct ForFrame<'a> {
+    variable: &'a str,
+    to: Decimal,
+    step: Decimal,
+    // First statement of the loop body
+    body: usize,
+}
+
+pub struct Interpreter<'a> {
+    flow: ControlFlow<'a>,
+    // DATA items in program order with the line they come from
+    data: Vec<(u32, &'a DataItem)>,
+    data_pointer: usize,
+    // Owned names, so the debugger can set variables it parsed itself
+    variables: HashMap<String, Value>,
+    arrays: HashMap<String, Vec<Value>>,
+    // Statement to continue with after RETURN, None at the end of the program
+    gosub_stack: Vec<Option<usize>>,
+    for_stack: Vec<ForFrame<'a>>,
+    // Both memory banks, ME1 right after ME0
+    memory: Vec<u8>,
+    memory_map: MemoryMap,
+    calls: Vec<Call>,
+    console: Box<dyn Console + 'a>,
+    // Display line left open by a trailing separator
+    pending: String,
+    // Next dot column for GPRINT
+    gcursor: usize,
+    plotter: Plotter,
+    // Printer line left open by a trailing separator
+    printer_line: String,
+    // Replaces the console for INPUT and feeds INKEY$ when set
+    keyboard: Option<Keyboard>,
+    clock: Clock,
+    transcript: Vec<TranscriptLine>,
+    // Set by INKEY$ once the key script is over
+    out_of_keys: bool,
+    // Line of the active ON ERROR GOTO
+    error_trap: Option<u32>,
+    // ERN and ERL, the last trapped error
+    error_code: u8,
+    error_line: u32,
+    // Set by TRON, cleared by TROFF
+    tracing: bool,
+    // Where traced statements go, dropped when writing to it fails
+    trace: Option<Box<dyn Write + 'a>>,
+    // Assignments of the statement being traced
+    changes: Vec<String>,
+    profile: Option<Profile>,
+    // Statement about to run, None once the program has stopped
+    current: Option<usize>,
+    line: u32,
+}
+
+impl<'a> Interpreter<'a> {
+    pub fn new(program: &'a Program, memory: MemoryConfig, console: Box<dyn Console + 'a>) -> Self {
+        let flow = ControlFlow::new(program);
+
+        let mut data = Vec::new();
+        for node in flow.nodes() {
+            if let Statement::Data { values } = node.statement {
+                data.extend(values.iter().map(|value| (node.line, value)));
+            }
+        }
+
+        let mut ram = vec![0; MAX_ADDRESS as usize + 1];
+        display::set_annunciator(&mut ram, Annunciator::Run, true);
+        display::set_annunciator(&mut ram, Annunciator::Busy, true);
+
+        Interpreter {
+            current: flow.entry(),
+            flow,
+            data,
+            data_pointer: 0,
+            variables: HashMap::new(),
+            arrays: HashMap::new(),
+            gosub_stack: Vec::new(),
+            for_stack: Vec::new(),
+            memory: ram,
+            memory_map: memory.memory_map(),
+            calls: Vec::new(),
+            console,
+            pending: String::new(),
+            gcursor: 0,
+            plotter: Plotter::new(),
+            printer_line: String::new(),
+            keyboard: None,
+            clock: Clock::new(Timing::Instant),
+            transcript: Vec::new(),
+            out_of_keys: false,
+            error_trap: None,
+            error_code: 0,
+            error_line: 0,
+            tracing: false,
+            trace: None,
+            changes: Vec::new(),
+            profile: None,
+            line: 0,
+        }
+    }
+
+    pub fn set_keyboard(&mut self, keyboard: Keyboard) {
+        self.keyboard = Some(keyboard);
+    }
+
+    pub fn set_timing(&mut self, timing: Timing) {
+        self.clock = Clock::new(timing);
+    }
+
+    // Output for TRON, tracing every statement from the start when asked
+    pub fn set_trace(&mut self, output: Box<dyn Write + 'a>, from_start: bool) {
+        self.trace = Some(output);
+        self.tracing = from_start;
+    }
+
+    pub fn enable_profile(&mut self) {
+        self.profile = Some(Profile::new(&self.flow));
+    }
+
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    pub fn run(&mut self) -> Result<(), RuntimeError> {
+        while self.step()? {}
+        Ok(())
+    }
+
+    // Runs a single statement. Returns false once the program has stopped
+    pub fn step(&mut self) -> Result<bool, RuntimeError> {
+        let Some(id) = self.current else {
+            return Ok(false);
+        };
+
+        let node = self.flow.node(id);
+        let (statement, next, then) = (node.statement, node.next, node.then);
+        self.line = node.line;
+        let first = self.flow.line_entry(node.line) == Some(id);
+
+        let result = self.execute(statement, next, then);
+        self.trace_statement(statement);
+        if let Some(profile) = &mut self.profile {
+            profile.record(id, self.line, first);
+        }
+        self.current = match result {
+            Ok(current) => current,
+            // Taking the trap disarms it, so an error in the handler stops the
+            // program instead of looping
+            Err(kind) => match self.error_trap.take() {
+                Some(trap) => {
+                    self.error_code = kind.code().number();
+                    self.error_line = self.line;
+                    let target = self.jump(trap).map_err(|undefined| RuntimeError {
+                        kind: undefined,
+                        line: self.line,
+                    })?;
+                    Some(target)
+                }
+                None => {
+                    return Err(RuntimeError {
+                        kind,
+                        line: self.line,
+                    })
+                }
+            },
+        };
+        self.clock.advance(STATEMENT_MICROS);
+        if self.out_of_keys {
+            self.current = None;
+        }
+        if self.current.is_none() {
+            display::set_annunciator(&mut self.memory, Annunciator::Busy, false);
+        }
+        Ok(self.current.is_some())
+    }
+
+    // Line and statement about to run, and whether it starts its line
+    pub fn position(&self) -> Option<(u32, &'a Statement, bool)> {
+        let id = self.current?;
+        let node = self.flow.node(id);
+        let first = self.flow.line_entry(node.line) == Some(id);
+        Some((node.line, node.statement, first))
+    }
+
+    pub fn evaluate(&mut self, expression: &Expression) -> Result<Value, RuntimeErrorKind> {
+        expression.accept(self)
+    }
+
+    pub fn set(&mut self, lvalue: &LValue, value: Value) -> Result<(), RuntimeErrorKind> {
+        self.assign(lvalue, value)
+    }
+
+    // Variables and arrays in name order
+    pub fn variables(&self) -> Vec<(&str, &Value)> {
+        let mut variables: Vec<(&str, &Value)> = self
+            .variables
+            .iter()
+            .map(|(name, value)| (name.as_str(), value))
+            .collect();
+        variables.sort_unstable_by_key(|(name, _)| *name);
+        variables
+    }
+
+    pub fn arrays(&self) -> Vec<(&str, &[Value])> {
+        let mut arrays: Vec<(&str, &[Value])> = self
+            .arrays
+            .iter()
+            .map(|(name, cells)| (name.as_str(), cells.as_slice()))
+            .collect();
+        arrays.sort_unstable_by_key(|(name, _)| *name);
+        arrays
+    }
+
+    // Lines RETURN goes back to, innermost last. None returns to the end of
+    // the program
+    pub fn gosub_stack(&self) -> Vec<Option<u32>> {
+        self.gosub_stack
+            .iter()
+            .map(|next| next.map(|id| self.flow.node(id).line))
+            .collect()
+    }
+
+    // Variable, limit, step and first line of the body of each open FOR,
+    // innermost last
+    pub fn for_stack(&self) -> Vec<(&str, Decimal, Decimal, u32)> {
+        self.for_stack
+            .iter()
+            .map(|frame| {
+                let line = self.flow.node(frame.body).line;
+                (frame.variable, frame.to, frame.step, line)
+            })
+            .collect()
+    }
+
+    pub fn calls(&self) -> &[Call] {
+        &self.calls
+    }
+
+    pub fn plotter(&self) -> &Plotter {
+        &self.plotter
+    }
+
+    pub fn transcript(&self) -> &[TranscriptLine] {
+        &self.transcript
+    }
+
+    pub fn screen(&self) -> Snapshot {
+        display::snapshot(&self.memory)
+    }
+
+    // Returns the statement to continue with
+    fn execute(
+        &mut self,
+        statement: &'a Statement,
+        next: Option<usize>,
+        then: Option<usize>,
+    ) -> Result<Option<usize>, RuntimeErrorKind> {
+        match statement {
+            Statement::Let {
+                variable,
+                expression,
+            } => {
+                let value = expression.accept(self)?;
+                self.assign(variable, value)?;
+            }
+            Statement::Dim { variable, size, .. } => {
+                let cells = vec![default_value(variable); size.value as usize + 1];
+                self.arrays.insert(variable.clone(), cells);
+            }
+            Statement::Print { format, content } | Statement::Pause { format, content } => {
+                let line = std::mem::take(&mut self.pending);
+                let (line, open) =
+                    self.layout(format.as_deref(), content, line, DISPLAY_COLUMNS)?;
+                display::show_text(&mut self.memory, &line);
+                if open {
+                    self.pending = line;
+                } else {
+                    self.show(line.trim_end());
+                }
+                if let Statement::Pause { .. } = statement {
+                    self.console.snapshot(&self.screen());
+                    self.clock.advance(PAUSE_MICROS);
+                }
+            }
+            Statement::Input { prompt, variable } => {
+                let prompt = match prompt {
+                    Some(prompt) => prompt.accept(self)?.to_string(),
+                    None => "?".to_owned(),
+                };
+                let answer = match self.keyboard {
+                    Some(_) => self.read_line(&prompt),
+                    None => self.console.input(&prompt),
+                };
+                let Some(answer) = answer else {
+                    return Ok(None);
+                };
+
+                let value = if is_string(lvalue_name(variable)) {
+                    Value::String(answer)
+                } else {
+                    Value::Number(Decimal::parse(&answer).ok_or(RuntimeErrorKind::InvalidInput)?)
+                };
+                self.assign(variable, value)?;
+            }
+            Statement::Wait { time } => {
+                self.console.snapshot(&self.screen());
+                if let Some(time) = time {
+                    let ticks =
+                        self.integer(time, 0..=0xFFFF, RuntimeErrorKind::ValueOutOfRange)?;
+                    self.clock.advance(ticks as u64 * TICK_MICROS);
+                }
+            }
+            Statement::Data { .. } | Statement::Rem { .. } | Statement::Seq { .. } => {}
+            Statement::Read { variables } => {
+                for variable in variables {
+                    let (_, item) = self
+                        .data
+                        .get(self.data_pointer)
+                        .ok_or(RuntimeErrorKind::OutOfData)?;
+                    let value = match item {
+                        DataItem::Number(num) | DataItem::HexNumber(num) => {
+                            Value::Number(Decimal::from(*num))
+                        }
+                        DataItem::String(string) => Value::String(string.clone()),
+                    };
+                    self.assign(variable, value)?;
+                    self.data_pointer += 1;
+                }
+            }
+            Statement::Restore { line_number } => {
+                let line = line_number.map_or(0, |line| line.value);
+                self.data_pointer = self
+                    .data
+                    .iter()
+                    .position(|(item_line, _)| *item_line >= line)
+                    .unwrap_or(self.data.len());
+            }
+            Statement::Poke { address, values } => {
+                let start = self.address(address)?;
+                for (offset, value) in values.iter().enumerate() {
+                    let byte = self.byte(value)?;
+                    let target = start + offset as u32;
+                    if target > MAX_ADDRESS {
+                        return Err(RuntimeErrorKind::AddressOutOfRange(Decimal::from(
+                            target as i32,
+                        )));
+                    }
+                    // Writes into ROM are silently lost, as on the device
+                    if self.memory_map.region(target) != Region::Rom {
+                        self.memory[target as usize] = byte;
+                    }
+                }
+            }
+            Statement::Call { address, argument } => {
+                let address = self.address(address)?;
+                let argument = match argument {
+                    Some(argument) => Some(self.number(argument)?),
+                    None => None,
+                };
+                self.calls.push(Call {
+                    line: self.line,
+                    address,
+                    argument,
+                });
+            }
+            Statement::Cls => {
+                self.pending.clear();
+                display::clear(&mut self.memory);
+                self.gcursor = 0;
+            }
+            Statement::Cursor { column } => {
+                let column = self.column(column, DISPLAY_COLUMNS)?;
+                // The next PRINT continues from the given column
+                let mut line: String = self.pending.chars().take(column).collect();
+                while line.chars().count() < column {
+                    line.push(' ');
+                }
+                self.pending = line;
+            }
+            Statement::GCursor { column } => {
+                self.gcursor = self.column(column, GRAPHICS_COLUMNS)?;
+            }
+            Statement::GPrint { content } => {
+                for item in content {
+                    let columns = match item.accept(self)? {
+                        Value::Number(number) => vec![Interpreter::pattern(number)?],
+                        Value::String(pattern) => {
+                            parse_pattern(&pattern).ok_or(RuntimeErrorKind::InvalidPattern)?
+                        }
+                    };
+                    // Columns past the right edge are lost
+                    for dots in columns {
+                        display::set_column(&mut self.memory, self.gcursor, dots);
+                        self.gcursor += 1;
+                    }
+                }
+            }
+            Statement::Beep {
+                count,
+                frequency,
+                duration,
+            } => {
+                // There is no speaker, the arguments are only checked
+                self.integer(count, 0..=0xFFFF, RuntimeErrorKind::ValueOutOfRange)?;
+                if let Some(frequency) = frequency {
+                    self.integer(frequency, 0..=0xFF, RuntimeErrorKind::ValueOutOfRange)?;
+                }
+                if let Some(duration) = duration {
+                    self.integer(duration, 0..=0xFFFF, RuntimeErrorKind::ValueOutOfRange)?;
+                }
+            }
+            Statement::LPrint { format, content } => {
+                let line = std::mem::take(&mut self.printer_line);
+                let columns = self.plotter.columns();
+                let (line, open) = self.layout(format.as_deref(), content, line, columns)?;
+                if open {
+                    self.printer_line = line;
+                } else {
+                    self.plotter.print(line.trim_end());
+                }
+            }
+            Statement::Line {
+                relative,
+                start,
+                points,
+                line_type,
+                color,
+                boxed,
+            } => {
+                let start = match start {
+                    Some(start) => Some(self.coordinate(start)?),
+                    None => None,
+                };
+                let points = points
+                    .iter()
+                    .map(|point| self.coordinate(point))
+                    .collect::<Result<Vec<_>, _>>()?;
+                let line_type = match line_type {
+                    Some(line_type) => self.setting(line_type, 0..=9)?,
+                    None => 0,
+                };
+                let color = match color {
+                    Some(color) => Some(self.setting(color, 0..=3)?),
+                    None => None,
+                };
+                self.plotter
+                    .line(start, &points, *relative, line_type, color, *boxed);
+            }
+            Statement::GlCursor { position } => {
+                let position = self.coordinate(position)?;
+                self.plotter.move_to(position);
+            }
+            Statement::Color { color } => {
+                let color = self.setting(color, 0..=3)?;
+                self.plotter.set_color(color);
+            }
+            Statement::CSize { size } => {
+                let size = self.setting(size, 1..=9)?;
+                self.plotter.set_size(size);
+            }
+            Statement::Rotate { direction } => {
+                let rotation = self.setting(direction, 0..=3)?;
+                self.plotter.set_rotation(rotation);
+            }
+            Statement::Tron => self.tracing = true,
+            Statement::Troff => self.tracing = false,
+            Statement::Graph => self.plotter.graph(),
+            Statement::Text => self.plotter.text(),
+            Statement::Sorgn => self.plotter.set_origin(),
+            Statement::Lf { lines } => {
+                let lines = self.integer(lines, -0xFF..=0xFF, RuntimeErrorKind::ValueOutOfRange)?;
+                self.plotter.feed(lines as i32);
+            }
+            Statement::Goto { line_number } => return self.jump(line_number.value).map(Some),
+            Statement::For {
+                variable,
+                from,
+                to,
+                step,
+            } => {
+                let from = self.number(from)?;
+                let to = self.number(to)?;
+                let step = match step {
+                    Some(step) => self.number(step)?,
+                    None => Decimal::ONE,
+                };
+                self.record_change(variable, &Value::Number(from));
+                self.store(variable, Value::Number(from));
+
+                // Re-entering a FOR with the same variable discards its frame
+                // and every frame nested inside it
+                if let Some(index) = self.for_stack.iter().position(|f| f.variable == variable) {
+                    self.for_stack.truncate(index);
+                }
+                let Some(body) = next else {
+                    return Ok(None);
+                };
+                self.for_stack.push(ForFrame {
+                    variable,
+                    to,
+                    step,
+                    body,
+                });
+            }
+            Statement::Next { variable } => {
+                let index = self
+                    .for_stack
+                    .iter()
+                    .rposition(|f| f.variable == variable)
+                    .ok_or(RuntimeErrorKind::NextWithoutFor)?;
+                self.for_stack.truncate(index + 1);
+                let frame = &self.for_stack[index];
+                let (to, step, body) = (frame.to, frame.step, frame.body);
+
+                let value = match self.variables.get(variable.as_str()) {
+                    Some(Value::Number(value)) => value.add(step)?,
+                    _ => return Err(RuntimeErrorKind::TypeMismatch),
+                };
+                self.record_change(variable, &Value::Number(value));
+                self.store(variable, Value::Number(value));
+
+                let repeat = if step.is_negative() {
+                    value >= to
+                } else {
+                    value <= to
+                };
+                if repeat {
+                    return Ok(Some(body));
+                }
+                self.for_stack.pop();
+            }
+            Statement::End => return Ok(None),
+            Statement::OnErrorGoto { line_number } => {
+                self.error_trap = Some(line_number.value).filter(|line| *line != 0);
+            }
+            Statement::GoSub { line_number } => {
+                let target = self.jump(line_number.value)?;
+                self.gosub_stack.push(next);
+                return Ok(Some(target));
+            }
+            Statement::Return => {
+                return self
+                    .gosub_stack
+                    .pop()
+                    .ok_or(RuntimeErrorKind::ReturnWithoutGosub);
+            }
+            Statement::If { condition, .. } => {
+                if !self.number(condition)?.is_zero() {
+                    return Ok(then);
+                }
+            }
+        }
+
+        Ok(next)
+    }
+
+    // Lays the items out on a display or printer line the way the ROM does,
+    // continuing the given line. Also returns whether a trailing separator
+    // keeps the line open
+    fn layout(
+        &mut self,
+        format: Option<&str>,
+        content: &'a [PrintItem],
+        mut line: String,
+        columns: usize,
+    ) -> Result<(String, bool), RuntimeErrorKind> {
+        let format = format
+            .map(Format::parse)
+            .transpose()
+            .map_err(|_e| RuntimeErrorKind::InvalidFormat)?;
+
+        // A lone number is right-justified on the whole line, a comma moves
+        // to the next half
+        let lone = line.is_empty() && content.len() == 1 && content[0].separator.is_none();
+        let zones = content
+            .iter()
+            .any(|item| item.separator == Some(Separator::Comma));
+        let zone_columns = columns / 2;
+
+        let mut zone = String::new();
+        let mut numeric = true;
+        for item in content {
+            let value = item.expression.accept(self)?;
+            let text = match (format, &value) {
+                (Some(format), Value::Number(number)) => format.number(*number),
+                (Some(format), Value::String(string)) => format.string(string),
+                (None, _) => Some(value.to_string()),
+            };
+            zone.push_str(&text.ok_or(RuntimeErrorKind::TypeMismatch)?);
+            numeric &= matches!(value, Value::Number(_)) && format.is_none();
+
+            if item.separator == Some(Separator::Comma) {
+                let width = zone_columns - line.chars().count() % zone_columns;
+                line.push_str(&justify(&zone, width, numeric));
+                zone.clear();
+                numeric = true;
+            }
+        }
+
+        if lone {
+            line.push_str(&justify(&zone, columns, numeric));
+        } else if zones && !zone.is_empty() {
+            let width = zone_columns - line.chars().count() % zone_columns;
+            line.push_str(&justify(&zone, width, numeric));
+        } else {
+            line.push_str(&zone);
+        }
+
+        let open = content.last().is_some_and(|item| item.separator.is_some());
+        Ok((line, open))
+    }
+
+    // INPUT from the key script, echoed to the console with its prompt. None
+    // when the script runs out before ENTER
+    fn read_line(&mut self, prompt: &str) -> Option<String> {
+        let keyboard = self.keyboard.as_mut()?;
+        let mut line: Vec<char> = Vec::new();
+        let mut cursor = 0;
+        loop {
+            let press = keyboard.wait()?;
+            self.clock.advance_to(press.time * 1000);
+            match press.key {
+                Key::Char(c) => {
+                    // Typing over the line replaces characters
+                    if cursor < line.len() {
+                        line[cursor] = c;
+                    } else {
+                        line.push(c);
+                    }
+                    cursor += 1;
+                }
+                Key::Left => cursor = cursor.saturating_sub(1),
+                Key::Right => cursor = (cursor + 1).min(line.len()),
+                Key::Clear => {
+                    line.clear();
+                    cursor = 0;
+                }
+                Key::Enter => break,
+                Key::Up | Key::Down | Key::Mode | Key::Shift | Key::Small | Key::Def => {}
+            }
+        }
+        self.show_modifiers();
+
+        let answer: String = line.into_iter().collect();
+        self.show(&format!("{}{}", prompt, answer));
+        Some(answer)
+    }
+
+    // Prints a finished display line, keeping it in the transcript
+    fn show(&mut self, text: &str) {
+        self.transcript.push(TranscriptLine {
+            time: self.clock.now(),
+            text: text.to_owned(),
+        });
+        self.console.print(text);
+    }
+
+    // Lights the annunciators of the pending keyboard modifiers
+    fn show_modifiers(&mut self) {
+        let Some(keyboard) = &self.keyboard else {
+            return;
+        };
+        let modifiers = [
+            (Annunciator::Shift, keyboard.shift()),
+            (Annunciator::Def, keyboard.def()),
+            (Annunciator::Small, keyboard.small()),
+        ];
+        for (annunciator, on) in modifiers {
+            display::set_annunciator(&mut self.memory, annunciator, on);
+        }
+    }
+
+    fn jump(&self, line_number: u32) -> Result<usize, RuntimeErrorKind> {
+        self.flow
+            .line_entry(line_number)
+            .ok_or(RuntimeErrorKind::UndefinedLine(line_number))
+    }
+
+    fn number(&mut self, expression: &Expression) -> Result<Decimal, RuntimeErrorKind> {
+        match expression.accept(self)? {
+            Value::Number(number) => Ok(number),
+            Value::String(_) => Err(RuntimeErrorKind::TypeMismatch),
+        }
+    }
+
+    // Integral value of an expression within the given range
+    fn integer(
+        &mut self,
+        expression: &Expression,
+        range: std::ops::RangeInclusive<i64>,
+        error: fn(Decimal) -> RuntimeErrorKind,
+    ) -> Result<i64, RuntimeErrorKind> {
+        let number = self.number(expression)?;
+        number
+            .trunc()
+            .to_integer()
+            .filter(|value| range.contains(value))
+            .ok_or_else(|| error(number))
+    }
+
+    fn address(&mut self, expression: &Expression) -> Result<u32, RuntimeErrorKind> {
+        let address = self.integer(
+            expression,
+            0..=i64::from(MAX_ADDRESS),
+            RuntimeErrorKind::AddressOutOfRange,
+        )?;
+        Ok(address as u32)
+    }
+
+    fn byte(&mut self, expression: &Expression) -> Result<u8, RuntimeErrorKind> {
+        let byte = self.integer(expression, 0..=255, RuntimeErrorKind::ValueOutOfRange)?;
+        Ok(byte as u8)
+    }
+
+    // Display column below the given width
+    fn column(&mut self, expression: &Expression, width: usize) -> Result<usize, RuntimeErrorKind> {
+        let column = self.integer(
+            expression,
+            0..=width as i64 - 1,
+            RuntimeErrorKind::ValueOutOfRange,
+        )?;
+        Ok(column as usize)
+    }
+
+    // A numeric GPRINT item sets the 7 dots of one column
+    fn pattern(number: Decimal) -> Result<u8, RuntimeErrorKind> {
+        number
+            .trunc()
+            .to_integer()
+            .filter(|value| (0..=0x7F).contains(value))
+            .map(|value| value as u8)
+            .ok_or(RuntimeErrorKind::ValueOutOfRange(number))
+    }
+
+    // Plotter position in steps
+    fn coordinate(&mut self, coordinate: &'a Coordinate) -> Result<(i32, i32), RuntimeErrorKind> {
+        let x = self.integer(
+            &coordinate.x,
+            PLOTTER_RANGE,
+            RuntimeErrorKind::ValueOutOfRange,
+        )?;
+        let y = self.integer(
+            &coordinate.y,
+            PLOTTER_RANGE,
+            RuntimeErrorKind::ValueOutOfRange,
+        )?;
+        Ok((x as i32, y as i32))
+    }
+
+    // Pen colour, character size and the like
+    fn setting(
+        &mut self,
+        expression: &'a Expression,
+        range: std::ops::RangeInclusive<i64>,
+    ) -> Result<u8, RuntimeErrorKind> {
+        let value = self.integer(expression, range, RuntimeErrorKind::ValueOutOfRange)?;
+        Ok(value as u8)
+    }
+
+    fn assign(&mut self, lvalue: &LValue, value: Value) -> Result<(), RuntimeErrorKind> {
+        if is_string(lvalue_name(lvalue)) != matches!(value, Value::String(_)) {
+            return Err(RuntimeErrorKind::TypeMismatch);
+        }
+
+        match lvalue {
+            LValue::Variable(name) => {
+                self.record_change(name, &value);
+                self.store(name, value);
+            }
+            LValue::ArrayElement { variable, index } => {
+                let index = self.index(index)?;
+                self.record_change(&format!("{}({})", variable, index), &value);
+                if !self.arrays.contains_key(variable.as_str()) {
+                    let cells = vec![default_value(variable); IMPLICIT_ARRAY_SIZE];
+                    self.arrays.insert(variable.clone(), cells);
+                }
+                let cell = self
+                    .arrays
+                    .get_mut(variable.as_str())
+                    .and_then(|cells| cells.get_mut(index));
+                *cell.ok_or(RuntimeErrorKind::BadSubscript)? = value;
+            }
+        }
+        Ok(())
+    }
+
+    // Allocates the name only for a new variable
+    fn store(&mut self, name: &str, value: Value) {
+        match self.variables.get_mut(name) {
+            Some(slot) => *slot = value,
+            None => {
+                self.variables.insert(name.to_owned(), value);
+            }
+        }
+    }
+
+    fn record_change(&mut self, target: &str, value: &Value) {
+        if self.tracing && self.trace.is_some() {
+            self.changes
+                .push(format!("{} = {}", target, value.quoted()));
+        }
+    }
+
+    // Writes "line: statement -> changes" for the statement that just ran
+    fn trace_statement(&mut self, statement: &Statement) {
+        let changes = std::mem::take(&mut self.changes);
+        if !self.tracing {
+            return;
+        }
+        let Some(output) = &mut self.trace else {
+            return;
+        };
+        let text = Printer::new().statement(statement);
+        let written = if changes.is_empty() {
+            writeln!(output, "{}: {}", self.line, text)
+        } else {
+            writeln!(output, "{}: {} -> {}", self.line, text, changes.join(", "))
+        };
+        if written.is_err() {
+            self.trace = None;
+        }
+    }
+
+    fn index(&mut self, index: &Expression) -> Result<usize, RuntimeErrorKind> {
+        let index = self.integer(index, 0..=i64::from(u16::MAX), |_| {
+            RuntimeErrorKind::BadSubscript
+        })?;
+        Ok(index as usize)
+    }
+
+    // Operand of AND, OR and NOT, which work bitwise on 16 bit integers
+    fn bits(number: Decimal) -> Result<i16, RuntimeErrorKind> {
+        number
+            .trunc()
+            .to_integer()
+            .and_then(|value| i16::try_from(value).ok())
+            .ok_or(RuntimeErrorKind::ValueOutOfRange(number))
+    }
+}
+
+fn lvalue_name(lvalue: &LValue) -> &str {
+    match lvalue {
+        LValue::Variable(name) => name,
+        LValue::ArrayElement { variable, .. } => variable,
+    }
+}
+
+fn is_string(name: &str) -> bool {
+    name.ends_with('$')
+}
+
+// Pairs of hex digits, one column each
+fn parse_pattern(pattern: &str) -> Option<Vec<u8>> {
+    let digits: Vec<u8> = pattern
+        .chars()
+        .map(|c| c.to_digit(16).map(|digit| digit as u8))
+        .collect::<Option<_>>()?;
+    if !digits.len().is_multiple_of(2) {
+        return None;
+    }
+    digits
+        .chunks(2)
+        .map(|pair| match pair {
+            [high, low] if *high < 8 => Some(high * 16 + low),
+            _ => None,
+        })
+        .collect()
+}
+
+fn default_value(name: &str) -> Value {
+    if is_string(name) {
+        Value::String(String::new())
+    } else {
+        Value::Number(Decimal::ZERO)
+    }
+}
+
+// Expressions need not come from the program, the debugger evaluates its own
+impl<'e> ExpressionVisitor<'e, Result<Value, RuntimeErrorKind>> for Interpreter<'_> {
+    fn visit_number_literal(&mut self, num: i32) -> Result<Value, RuntimeErrorKind> {
+        Ok(Value::Number(Decimal::from(num)))
+    }
+
+    fn visit_string_literal(&mut self, content: &'e str) -> Result<Value, RuntimeErrorKind> {
+        Ok(Value::String(content.to_owned()))
+    }
+
+    fn visit_variable(&mut self, lvalue: &'e LValue) -> Result<Value, RuntimeErrorKind> {
+        match lvalue {
+            LValue::Variable(name) => Ok(self
+                .variables
+                .get(name.as_str())
+                .cloned()
+                .unwrap_or_else(|| default_value(name))),
+            LValue::ArrayElement { variable, index } => {
+                let index = self.index(index)?;
+                let size = self
+                    .arrays
+                    .get(variable.as_str())
+                    .map_or(IMPLICIT_ARRAY_SIZE, Vec::len);
+                if index >= size {
+                    return Err(RuntimeErrorKind::BadSubscript);
+                }
+                Ok(self
+                    .arrays
+                    .get(variable.as_str())
+                    .and_then(|cells| cells.get(index))
+                    .cloned()
+                    .unwrap_or_else(|| default_value(variable)))
+            }
+        }
+    }
+
+    fn visit_unary_op(
+        &mut self,
+        op: UnaryOperator,
+        operand: &'e Expression,
+    ) -> Result<Value, RuntimeErrorKind> {
+        let operand = self.number(operand)?;
+        let result = match op {
+            UnaryOperator::Plus => operand,
+            UnaryOperator::Minus => operand.neg(),
+            UnaryOperator::Not => Decimal::from(i32::from(!Interpreter::bits(operand)?)),
+        };
+        Ok(Value::Number(result))
+    }
+
+    fn visit_binary_op(
+        &mut self,
+        left: &'e Expression,
+        op: BinaryOperator,
+        right: &'e Expression,
+    ) -> Result<Value, RuntimeErrorKind> {
+        let left = left.accept(self)?;
+        let right = right.accept(self)?;
+
+        let (left, right) = match (left, right) {
+            (Value::Number(left), Value::Number(right)) => (left, right),
+            (Value::String(left), Value::String(right)) => {
+                let ordering = left.cmp(&right);
+                let result = match op {
+                    BinaryOperator::Add => {
+                        if left.chars().count() + right.chars().count() > STRING_LENGTH {
+                            return Err(RuntimeErrorKind::StringTooLong);
+                        }
+                        let mut joined = left;
+                        joined.push_str(&right);
+                        return Ok(Value::String(joined));
+                    }
+                    BinaryOperator::Eq => ordering.is_eq(),
+                    BinaryOperator::Ne => ordering.is_ne(),
+                    BinaryOperator::Lt => ordering.is_lt(),
+                    BinaryOperator::Le => ordering.is_le(),
+                    BinaryOperator::Gt => ordering.is_gt(),
+                    BinaryOperator::Ge => ordering.is_ge(),
+                    BinaryOperator::Sub
+                    | BinaryOperator::Mul
+                    | BinaryOperator::Div
+                    | BinaryOperator::And
+                    | BinaryOperator::Or => return Err(RuntimeErrorKind::TypeMismatch),
+                };
+                return Ok(Value::Number(Decimal::from(result)));
+            }
+            _ => return Err(RuntimeErrorKind::TypeMismatch),
+        };
+
+        let result = match op {
+            BinaryOperator::Add => left.add(right)?,
+            BinaryOperator::Sub => left.sub(right)?,
+            BinaryOperator::Mul => left.mul(right)?,
+            BinaryOperator::Div => left.div(right)?,
+            BinaryOperator::And => Decimal::from(i32::from(
+                Interpreter::bits(left)? & Interpreter::bits(right)?,
+            )),
+            BinaryOperator::Or => Decimal::from(i32::from(
+                Interpreter::bits(left)? | Interpreter::bits(right)?,
+            )),
+            // Comparisons are true as 1, like on the device
+            BinaryOperator::Eq => Decimal::from(left == right),
+            BinaryOperator::Ne => Decimal::from(left != right),
+            BinaryOperator::Lt => Decimal::from(left < right),
+            BinaryOperator::Le => Decimal::from(left <= right),
+            BinaryOperator::Gt => Decimal::from(left > right),
+            BinaryOperator::Ge => Decimal::from(left >= right),
+        };
+        Ok(Value::Number(result))
+    }
+
+    fn visit_function(
+        &mut self,
+        function: Function,
+        args: &'e [Expression],
+    ) -> Result<Value, RuntimeErrorKind> {
+        match (function, args) {
+            (Function::Point, [column]) => {
+                let column = self.column(column, GRAPHICS_COLUMNS)?;
+                let dots = display::column(&self.memory, column);
+                Ok(Value::Number(Decimal::from(i32::from(dots))))
+            }
+            (Function::Point, _) => Err(RuntimeErrorKind::TypeMismatch),
+            (Function::Ern, _) => Ok(Value::Number(Decimal::from(i32::from(self.error_code)))),
+            (Function::Erl, _) => Ok(Value::Number(Decimal::from(self.error_line as i32))),
+            (Function::Inkey, _) => {
+                let Some(keyboard) = &mut self.keyboard else {
+                    return Ok(Value::String(String::new()));
+                };
+                let now = self.clock.now() / 1000;
+                self.out_of_keys = keyboard.exhausted(now, KEYBOARD_IDLE_MILLIS);
+                let key = keyboard.poll(now);
+                self.show_modifiers();
+                Ok(Value::String(key.map(String::from).unwrap_or_default()))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::test_support::parse;
+
+    // Console fed from a list of answers, keeping everything printed
+    struct Script {
+        output: Rc<RefCell<Vec<String>>>,
+        answers: Vec<String>,
+        // ASCII art of each snapshot
+        screens: Rc<RefCell<Vec<String>>>,
+    }
+
+    impl Console for Script {
+        fn print(&mut self, text: &str) {
+            self.output.borrow_mut().push(text.to_owned());
+        }
+
+        fn input(&mut self, _: &str) -> Option<String> {
+            self.answers.pop()
+        }
+
+        fn snapshot(&mut self, snapshot: &Snapshot) {
+            self.screens.borrow_mut().push(snapshot.ascii());
+        }
+    }
+
+    fn run(program: &Program) -> (Result<(), RuntimeError>, Vec<String>, Vec<Call>) {
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let console = Script {
+            output: Rc::clone(&output),
+            answers: Vec::new(),
+            screens: Rc::new(RefCell::new(Vec::new())),
+        };
+        let mut interpreter = Interpreter::new(program, MemoryConfig::Pc1500, Box::new(console));
+        let result = interpreter.run();
+        let calls = interpreter.calls().to_vec();
+        drop(interpreter);
+
+        let lines = output.borrow().clone();
+        (result, lines, calls)
+    }
+
+    #[test]
+    fn loops_and_arithmetic() {
+        let program = parse("10 FOR I = 1 TO 3: PRINT \"I/4=\"; I / 4: NEXT I\n");
+
+        let (result, output, _) = run(&program);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(output, vec!["I/4=0.25", "I/4=0.5", "I/4=0.75"]);
+    }
+
+    #[test]
+    fn poke_value_out_of_range() {
+        let program = parse("10 B = 256\n20 POKE &7600, B\n");
+
+        let (result, _, _) = run(&program);
+
+        assert_eq!(
+            result,
+            Err(RuntimeError {
+                kind: RuntimeErrorKind::ValueOutOfRange(Decimal::from(256)),
+                line: 20,
+            })
+        );
+    }
+
+    #[test]
+    fn concatenation_longer_than_a_string() {
+        let program = parse(concat!(
+            "10 A$ = \"0123456789\"\n",
+            "20 B$ = A$ + A$ + A$ + A$ + A$ + A$ + A$ + A$\n",
+            "30 C$ = B$ + \"!\"\n",
+        ));
+
+        let (result, _, _) = run(&program);
+
+        assert_eq!(
+            result,
+            Err(RuntimeError {
+                kind: RuntimeErrorKind::StringTooLong,
+                line: 30,
+            })
+        );
+    }
+
+    #[test]
+    fn call_with_argument() {
+        let program = parse("10 CALL &7A00 + 2, 5\n");
+
+        let (result, _, calls) = run(&program);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            calls,
+            vec![Call {
+                line: 10,
+                address: 0x7A02,
+                argument: Some(Decimal::from(5)),
+            }]
+        );
+    }
+
+    #[test]
+    fn print_layout() {
+        let program = parse(
+            "10 PRINT 42\n20 PRINT \"A\", 7\n30 PRINT USING \"###.##\"; 3;\n40 PRINT \"!\"\n",
+        );
+
+        let (result, output, _) = run(&program);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            output,
+            vec![
+                format!("{:>26}", "42"),
+                format!("{:<13}{:>13}", "A", "7"),
+                "  3.00!".to_owned(),
+            ]
+        );
+    }
+
+    #[test]
+    fn gprint_and_point() {
+        // The third column of line 20 falls off the right edge
+        let program = parse(
+            "\
+10 GCURSOR 154
+20 GPRINT \"1F7F\"; 8
+30 PRINT POINT(154); POINT(155); POINT(0)
+40 GPRINT \"80\"
+",
+        );
+
+        let (result, output, _) = run(&program);
+
+        assert_eq!(output, vec!["311270"]);
+        assert_eq!(
+            result,
+            Err(RuntimeError {
+                kind: RuntimeErrorKind::InvalidPattern,
+                line: 40,
+            })
+        );
+    }
+
+    #[test]
+    fn plot_matches_golden_svg() {
+        let program = parse(include_str!("../../test/plot.bas"));
+
+        let console = Script {
+            output: Rc::new(RefCell::new(Vec::new())),
+            answers: Vec::new(),
+            screens: Rc::new(RefCell::new(Vec::new())),
+        };
+        let mut interpreter = Interpreter::new(&program, MemoryConfig::Pc1500, Box::new(console));
+        let result = interpreter.run();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(
+            interpreter.plotter().to_string(),
+            include_str!("../../test/plot.svg")
+        );
+    }
+
+    #[test]
+    fn screen_snapshots() {
+        // A POKE into the display buffer lights a whole column
+        let program = parse("10 PAUSE \"HI\"\n20 POKE &760C, &7F\n30 WAIT\n");
+
+        let screens = Rc::new(RefCell::new(Vec::new()));
+        let console = Script {
+            output: Rc::new(RefCell::new(Vec::new())),
+            answers: Vec::new(),
+            screens: Rc::clone(&screens),
+        };
+        let mut interpreter = Interpreter::new(&program, MemoryConfig::Pc1500, Box::new(console));
+        let result = interpreter.run();
+        drop(interpreter);
+
+        assert_eq!(result, Ok(()));
+        let screens: Vec<Vec<String>> = screens
+            .borrow()
+            .iter()
+            .map(|art| {
+                art.lines()
+                    .map(|line| line.chars().take(14).collect())
+                    .collect()
+            })
+            .collect();
+        assert_eq!(
+            screens,
+            vec![
+                vec![
+                    "BUSY RUN",
+                    "#...#..###....",
+                    "#...#...#.....",
+                    "#...#...#.....",
+                    "#####...#.....",
+                    "#...#...#.....",
+                    "#...#...#.....",
+                    "#...#..###....",
+                ],
+                vec![
+                    "BUSY RUN",
+                    "#...#..###..#.",
+                    "#...#...#...#.",
+                    "#...#...#...#.",
+                    "#####...#...#.",
+                    "#...#...#...#.",
+                    "#...#...#...#.",
+                    "#...#..###..#.",
+                ],
+            ]
+        );
+    }
+
+    #[test]
+    fn scripted_keyboard() {
+        // Line 50 waits for a key that never comes
+        let program = parse(
+            "\
+10 A$ = INKEY$
+20 IF A$ = \"\" THEN 10
+30 INPUT \"NAME?\"; N$
+40 PRINT A$; N$
+50 IF INKEY$ = \"\" THEN 50
+",
+        );
+
+        let output = Rc::new(RefCell::new(Vec::new()));
+        let console = Script {
+            output: Rc::clone(&output),
+            answers: Vec::new(),
+            screens: Rc::new(RefCell::new(Vec::new())),
+        };
+        let mut interpreter = Interpreter::new(&program, MemoryConfig::Pc1500, Box::new(console));
+        let script = keyboard::parse_script("+100 SML y +50 \"ABX\" LEFT \"C\" ENTER").unwrap();
+        interpreter.set_keyboard(Keyboard::new(script));
+        let result = interpreter.run();
+        drop(interpreter);
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(*output.borrow(), vec!["NAME?abc", "yabc"]);
+    }
+
+    #[test]
+    fn display_timing() {
+        // WAIT 64 is one second in ticks
+        let program = parse("10 PRINT \"A\"\n20 PAUSE \"B\"\n30 WAIT 64\n40 PRINT \"C\"\n");
+
+        let console = Script {
+            output: Rc::new(RefCell::new(Vec::new())),
+            answers: Vec::new(),
+            screens: Rc::new(RefCell::new(Vec::new())),
+        };
+        let mut interpreter = Interpreter::new(&program, MemoryConfig::Pc1500, Box::new(console));
+        let result = interpreter.run();
+        let transcript: Vec<String> = interpreter
+            .transcript()
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+
+        assert_eq!(result, Ok(()));
+        assert_eq!(transcript, vec!["0.000 A", "0.005 B", "1.865 C"]);
+    }
+
+    #[test]
+    fn error_trap() {
+        // The trap is gone once taken, so line 120 stops the program
+        let program = parse(
+            "\
+10 ON ERROR GOTO 100
+20 A = 1 / 0
+30 END
+100 PRINT ERN
+110 PRINT ERL
+120 READ X
+",
+        );
+
+        let (result, output, _) = run(&program);
+        let output: Vec<&str> = output.iter().map(|line| line.trim_start()).collect();
+
+        assert_eq!(output, vec!["38", "20"]);
+        let error = result.unwrap_err();
+        assert_eq!(error.kind, RuntimeErrorKind::OutOfData);
+        assert_eq!(
+            error.to_string(),
+            "Runtime error at line 120 (ERROR 2): Out of DATA"
+        );
+    }
+
+    // Trace sink the test can read after the interpreter is gone
+    struct Sink(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Sink {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn trace_and_profile() {
+        let program = parse(
+            "\
+10 A = 5
+20 TRON
+30 FOR I = 1 TO 2
+40 B(I) = A * I
+50 NEXT I
+60 TROFF
+70 A = 0
+",
+        );
+
+        let trace = Rc::new(RefCell::new(Vec::new()));
+        let mut interpreter = Interpreter::new(&program, MemoryConfig::Pc1500, Box::new(Terminal));
+        interpreter.set_trace(Box::new(Sink(Rc::clone(&trace))), false);
+        interpreter.enable_profile();
+        let result = interpreter.run();
+
+        assert_eq!(result, Ok(()));
+        let trace = String::from_utf8(trace.borrow().clone()).unwrap();
+        let expected = [
+            "20: TRON",
+            "30: FOR I = 1 TO 2 -> I = 1",
+            "40: LET B(I) = (A * I) -> B(1) = 5",
+            "50: NEXT I -> I = 2",
+            "40: LET B(I) = (A * I) -> B(2) = 10",
+            "50: NEXT I -> I = 3",
+            "",
+        ];
+        assert_eq!(trace, expected.join("\n"));
+
+        let profile = interpreter.profile().unwrap();
+        let hotspots: Vec<(u32, u64, u64)> = profile
+            .hotspots()
+            .iter()
+            .map(|(line, profile)| (*line, profile.hits, profile.statements))
+            .collect();
+        assert_eq!(&hotspots[..3], [(40, 2, 2), (50, 2, 2), (30, 1, 1)]);
+        assert_eq!(hotspots.len(), 7);
+        assert!(profile.to_string().starts_with(" Line       Hits"));
+    }
+}
@@ -0,0 +1,336 @@
+// Virtual CE-150 printer and plotter
+//
+// The pen moves in steps of 0.2 mm. X goes across the 216 step wide paper and
+// is clamped to it, Y grows towards the top of the page, so feeding paper
+// moves the pen to lower Y values. Everything drawn is kept as strokes and
+// labels and rendered to SVG at the end.
+
+// 43.2 mm of usable paper width
+pub const PAPER_STEPS: i32 = 216;
+// A character cell is 6 steps wide and 10 steps high at CSIZE 1
+const CELL_WIDTH: i32 = 6;
+const LINE_PITCH: i32 = 10;
+
+const PEN_COLORS: [&str; 4] = ["black", "blue", "green", "red"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Text,
+    Graph,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mark {
+    Stroke {
+        points: Vec<(i32, i32)>,
+        color: u8,
+        line_type: u8,
+    },
+    Label {
+        position: (i32, i32),
+        text: String,
+        color: u8,
+        size: u8,
+        rotation: u8,
+    },
+}
+
+#[derive(Debug)]
+pub struct Plotter {
+    marks: Vec<Mark>,
+    mode: Mode,
+    pen: (i32, i32),
+    // Where LINE and GLCURSOR coordinates are measured from
+    origin: (i32, i32),
+    color: u8,
+    size: u8,
+    rotation: u8,
+    // Lowest Y reached, where text continues after a drawing
+    bottom: i32,
+}
+
+impl Plotter {
+    pub fn new() -> Self {
+        Plotter {
+            marks: Vec::new(),
+            mode: Mode::Text,
+            pen: (0, 0),
+            origin: (0, 0),
+            color: 0,
+            // 18 characters per line after power on
+            size: 2,
+            rotation: 0,
+            bottom: 0,
+        }
+    }
+
+    // Characters that fit on a line of text with the current size
+    pub fn columns(&self) -> usize {
+        (PAPER_STEPS / (CELL_WIDTH * i32::from(self.size))) as usize
+    }
+
+    pub fn graph(&mut self) {
+        self.mode = Mode::Graph;
+        self.origin = self.pen;
+    }
+
+    // Back to text mode, on a new line below everything drawn so far
+    pub fn text(&mut self) {
+        self.mode = Mode::Text;
+        self.pen = (0, self.bottom);
+        self.origin = self.pen;
+    }
+
+    pub fn set_origin(&mut self) {
+        self.origin = self.pen;
+    }
+
+    pub fn set_color(&mut self, color: u8) {
+        self.color = color;
+    }
+
+    pub fn set_size(&mut self, size: u8) {
+        self.size = size;
+    }
+
+    pub fn set_rotation(&mut self, rotation: u8) {
+        self.rotation = rotation;
+    }
+
+    // GLCURSOR, moves the pen without drawing
+    pub fn move_to(&mut self, position: (i32, i32)) {
+        let target = self.absolute(position);
+        self.travel(target);
+    }
+
+    // LINE and RLINE. Relative points are offsets from the previous one
+    pub fn line(
+        &mut self,
+        start: Option<(i32, i32)>,
+        points: &[(i32, i32)],
+        relative: bool,
+        line_type: u8,
+        color: Option<u8>,
+        boxed: bool,
+    ) {
+        if let Some(start) = start {
+            let target = if relative {
+                self.offset(start)
+            } else {
+                self.absolute(start)
+            };
+            self.travel(target);
+        }
+
+        let mut path = vec![self.pen];
+        for point in points {
+            let target = if relative {
+                self.offset(*point)
+            } else {
+                self.absolute(*point)
+            };
+            self.travel(target);
+            path.push(self.pen);
+        }
+
+        // The box has the first and the last point as opposite corners
+        if boxed {
+            if let (Some(&(x1, y1)), Some(&(x2, y2))) = (path.first(), path.last()) {
+                path = vec![(x1, y1), (x2, y1), (x2, y2), (x1, y2), (x1, y1)];
+            }
+        }
+        self.marks.push(Mark::Stroke {
+            points: path,
+            color: color.unwrap_or(self.color),
+            line_type,
+        });
+    }
+
+    // LPRINT. Text mode prints a line and feeds the paper, graph mode writes
+    // at the pen in the ROTATE direction and leaves the pen after the text
+    pub fn print(&mut self, text: &str) {
+        let size = i32::from(self.size);
+        match self.mode {
+            Mode::Text => {
+                self.label((0, self.pen.1 - LINE_PITCH * size), text);
+                self.feed(1);
+            }
+            Mode::Graph => {
+                self.label(self.pen, text);
+                let width = CELL_WIDTH * size * text.chars().count() as i32;
+                let (x, y) = self.pen;
+                let target = match self.rotation {
+                    0 => (x + width, y),
+                    1 => (x, y - width),
+                    2 => (x - width, y),
+                    _ => (x, y + width),
+                };
+                self.travel(target);
+            }
+        }
+    }
+
+    // LF, negative counts feed backwards
+    pub fn feed(&mut self, lines: i32) {
+        let target = (0, self.pen.1 - lines * LINE_PITCH * i32::from(self.size));
+        self.travel(target);
+    }
+
+    fn label(&mut self, position: (i32, i32), text: &str) {
+        if text.is_empty() {
+            return;
+        }
+        self.marks.push(Mark::Label {
+            position,
+            text: text.to_owned(),
+            color: self.color,
+            size: self.size,
+            rotation: self.rotation,
+        });
+        self.bottom = self.bottom.min(position.1);
+    }
+
+    fn absolute(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        (self.origin.0 + x, self.origin.1 + y)
+    }
+
+    fn offset(&self, (x, y): (i32, i32)) -> (i32, i32) {
+        (self.pen.0 + x, self.pen.1 + y)
+    }
+
+    // The carriage stops at the edges of the paper
+    fn travel(&mut self, (x, y): (i32, i32)) {
+        self.pen = (x.clamp(0, PAPER_STEPS - 1), y);
+        self.bottom = self.bottom.min(y);
+    }
+}
+
+// The plot as an SVG document, one user unit per step
+impl std::fmt::Display for Plotter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let top = self
+            .marks
+            .iter()
+            .flat_map(Mark::extent)
+            .map(|(_, y)| y)
+            .chain([self.pen.1, 0])
+            .max()
+            .unwrap_or(0);
+        let bottom = self.bottom.min(top - LINE_PITCH);
+        let height = top - bottom;
+
+        writeln!(
+            f,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}mm" height="{}mm" viewBox="0 {} {} {}">"#,
+            millimeters(PAPER_STEPS),
+            millimeters(height),
+            -top,
+            PAPER_STEPS,
+            height
+        )?;
+        writeln!(
+            f,
+            r#"<rect x="0" y="{}" width="{}" height="{}" fill="white"/>"#,
+            -top, PAPER_STEPS, height
+        )?;
+        for mark in &self.marks {
+            mark.render(f)?;
+        }
+        writeln!(f, "</svg>")
+    }
+}
+
+impl Mark {
+    fn extent(&self) -> Vec<(i32, i32)> {
+        match self {
+            Mark::Stroke { points, .. } => points.clone(),
+            Mark::Label { position, size, .. } => {
+                vec![
+                    *position,
+                    (position.0, position.1 + LINE_PITCH * i32::from(*size)),
+                ]
+            }
+        }
+    }
+
+    fn render(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Mark::Stroke {
+                points,
+                color,
+                line_type,
+            } => {
+                let points: Vec<String> = points
+                    .iter()
+                    .map(|(x, y)| format!("{},{}", x, -y))
+                    .collect();
+                write!(
+                    f,
+                    r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="1""#,
+                    points.join(" "),
+                    pen_color(*color)
+                )?;
+                // Types 1 to 9 are dashes of increasing length
+                if *line_type > 0 {
+                    write!(f, r#" stroke-dasharray="{0} {0}""#, line_type)?;
+                }
+                writeln!(f, "/>")
+            }
+            Mark::Label {
+                position: (x, y),
+                text,
+                color,
+                size,
+                rotation,
+            } => {
+                let size = i32::from(*size);
+                let width = CELL_WIDTH * size * text.chars().count() as i32;
+                write!(
+                    f,
+                    r#"<text x="{}" y="{}" font-family="monospace" font-size="{}" textLength="{}" lengthAdjust="spacingAndGlyphs" fill="{}""#,
+                    x,
+                    -y,
+                    LINE_PITCH * size,
+                    width,
+                    pen_color(*color)
+                )?;
+                if *rotation > 0 {
+                    write!(
+                        f,
+                        r#" transform="rotate({} {} {})""#,
+                        u32::from(*rotation) * 90,
+                        x,
+                        -y
+                    )?;
+                }
+                writeln!(f, ">{}</text>", escape(text))
+            }
+        }
+    }
+}
+
+fn pen_color(color: u8) -> &'static str {
+    PEN_COLORS
+        .get(usize::from(color))
+        .copied()
+        .unwrap_or("black")
+}
+
+// Steps of 0.2 mm as a decimal number of millimeters
+fn millimeters(steps: i32) -> String {
+    let tenths = steps.unsigned_abs() * 2;
+    format!("{}.{}", tenths / 10, tenths % 10)
+}
+
+fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            other => escaped.push(other),
+        }
+    }
+    escaped
+}
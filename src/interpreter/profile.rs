@@ -0,0 +1,98 @@
+// Line-hit profile of a run
+//
+// Counts how often each line is entered and how many of its statements run,
+// and adds up the estimated device cycles of those statements. The report
+// ranks lines by cycles, which finds hotspots better than the hit count
+// alone: one line of divisions can cost more than ten lines of GOTOs.
+
+use std::collections::BTreeMap;
+
+use super::clock::Timestamp;
+use crate::ast::{ControlFlow, CycleEstimator};
+
+// The LH5801 of the PC-1500 runs at 1.3 MHz
+const CYCLES_PER_MILLI: u64 = 1300;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct LineProfile {
+    // Times the line was entered at its first statement
+    pub hits: u64,
+    pub statements: u64,
+    pub cycles: u64,
+}
+
+#[derive(Debug)]
+pub struct Profile {
+    // Estimated cycles of each control flow node
+    costs: Vec<u64>,
+    lines: BTreeMap<u32, LineProfile>,
+}
+
+impl Profile {
+    pub fn new(flow: &ControlFlow) -> Self {
+        let mut lines: Vec<u32> = flow.nodes().iter().map(|node| node.line).collect();
+        lines.dedup();
+        let mut estimator = CycleEstimator::new(&lines);
+        let costs = flow
+            .nodes()
+            .iter()
+            .map(|node| estimator.estimate(node.statement))
+            .collect();
+
+        Profile {
+            costs,
+            lines: BTreeMap::new(),
+        }
+    }
+
+    // Counts one run of the node, which starts its line when first is set
+    pub fn record(&mut self, id: usize, line: u32, first: bool) {
+        let entry = self.lines.entry(line).or_default();
+        if first {
+            entry.hits += 1;
+        }
+        entry.statements += 1;
+        entry.cycles += self.costs[id];
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.lines.values().map(|line| line.cycles).sum()
+    }
+
+    // Lines that ran, most expensive first
+    pub fn hotspots(&self) -> Vec<(u32, LineProfile)> {
+        let mut lines: Vec<(u32, LineProfile)> = self
+            .lines
+            .iter()
+            .map(|(line, profile)| (*line, *profile))
+            .collect();
+        lines.sort_by(|a, b| b.1.cycles.cmp(&a.1.cycles).then(a.0.cmp(&b.0)));
+        lines
+    }
+}
+
+impl std::fmt::Display for Profile {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let total = self.cycles();
+        writeln!(f, " Line       Hits Statements     Cycles  Share")?;
+        for (line, profile) in self.hotspots() {
+            let share = (profile.cycles * 1000).checked_div(total).unwrap_or(0);
+            writeln!(
+                f,
+                "{:>5} {:>10} {:>10} {:>10} {:>3}.{}%",
+                line,
+                profile.hits,
+                profile.statements,
+                profile.cycles,
+                share / 10,
+                share % 10
+            )?;
+        }
+        writeln!(
+            f,
+            "Total: {} cycles, about {} s on the device",
+            total,
+            Timestamp(total * 1000 / CYCLES_PER_MILLI)
+        )
+    }
+}
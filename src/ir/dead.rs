@@ -0,0 +1,229 @@
+// Jump threading and dead code elimination
+//
+// REM statements are dropped, jumps to blocks that only jump on are sent
+// straight to the final target, and blocks no path from the entry reaches
+// are removed, taking unused subroutines and lines skipped by GOTO with them.
+// Lines started from outside the program, like DEF key labels and RUN n,
+// can be kept as extra entry points.
+
+use std::collections::BTreeSet;
+
+use super::node::{BlockId, Instruction, Program, Terminator};
+
+pub struct DeadCodeEliminator {
+    // Lines kept reachable besides the entry
+    roots: Vec<u32>,
+    changes: Vec<String>,
+}
+
+impl DeadCodeEliminator {
+    pub fn new(roots: Vec<u32>) -> Self {
+        DeadCodeEliminator {
+            roots,
+            changes: Vec::new(),
+        }
+    }
+
+    // Returns what was changed
+    pub fn eliminate(mut self, program: &mut Program) -> Vec<String> {
+        self.drop_comments(program);
+        self.thread_jumps(program);
+        let reachable = self.reachable(program);
+        self.remove(program, &reachable);
+        self.changes
+    }
+
+    fn drop_comments(&mut self, program: &mut Program) {
+        for block in &mut program.blocks {
+            let count = block.instructions.len();
+            block
+                .instructions
+                .retain(|instruction| !matches!(instruction, Instruction::Rem(_)));
+            if block.instructions.len() < count {
+                self.changes
+                    .push(format!("line {}: REM dropped", block.line));
+            }
+        }
+    }
+
+    // Final target of a jump to the block, skipping blocks without
+    // instructions that only jump on
+    fn forward(program: &Program, mut id: BlockId) -> BlockId {
+        // Bounded so a GOTO loop to itself ends
+        for _ in 0..program.blocks.len() {
+            let block = &program.blocks[id.0];
+            match block.terminator {
+                Terminator::Jump(next) if block.instructions.is_empty() && next != id => id = next,
+                _ => break,
+            }
+        }
+        id
+    }
+
+    fn thread_jumps(&mut self, program: &mut Program) {
+        for index in 0..program.blocks.len() {
+            let line = program.blocks[index].line;
+            let mut block = program.blocks[index].clone();
+            let targets = block.terminator.targets_mut().into_iter().chain(
+                block
+                    .instructions
+                    .iter_mut()
+                    .flat_map(Instruction::targets_mut),
+            );
+            for target in targets {
+                let forwarded = DeadCodeEliminator::forward(program, *target);
+                let (from, to) = (
+                    program.blocks[target.0].line,
+                    program.blocks[forwarded.0].line,
+                );
+                if from != to {
+                    self.changes.push(format!(
+                        "line {}: jump to line {} threaded to line {}",
+                        line, from, to
+                    ));
+                }
+                *target = forwarded;
+            }
+
+            // A jump to a block that only ends or returns does so itself
+            if let Terminator::Jump(target) = block.terminator {
+                let target = &program.blocks[target.0];
+                if target.instructions.is_empty()
+                    && matches!(target.terminator, Terminator::End | Terminator::Return)
+                {
+                    block.terminator = target.terminator.clone();
+                }
+            }
+            program.blocks[index] = block;
+        }
+        program.entry = DeadCodeEliminator::forward(program, program.entry);
+    }
+
+    fn reachable(&self, program: &mut Program) -> BTreeSet<BlockId> {
+        let mut reachable = BTreeSet::new();
+        let mut work = vec![program.entry];
+        work.extend(
+            self.roots
+                .iter()
+                .filter_map(|line| program.lines.get(line).copied()),
+        );
+
+        while let Some(id) = work.pop() {
+            if !reachable.insert(id) {
+                continue;
+            }
+            let block = &mut program.blocks[id.0];
+            work.extend(
+                block
+                    .terminator
+                    .targets_mut()
+                    .into_iter()
+                    .map(|target| *target),
+            );
+            for instruction in &mut block.instructions {
+                work.extend(instruction.targets_mut().into_iter().map(|target| *target));
+            }
+        }
+        reachable
+    }
+
+    // Removes the unreachable blocks and renumbers the rest in order
+    fn remove(&mut self, program: &mut Program, reachable: &BTreeSet<BlockId>) {
+        let mut renumbered = vec![None; program.blocks.len()];
+        for (new, old) in reachable.iter().enumerate() {
+            renumbered[old.0] = Some(BlockId(new));
+        }
+        let renumber = |id: &mut BlockId| {
+            *id = renumbered[id.0].expect("reachable blocks only refer to reachable blocks");
+        };
+
+        let lines_before: BTreeSet<u32> = program.blocks.iter().map(|block| block.line).collect();
+        let blocks = std::mem::take(&mut program.blocks);
+        for (index, mut block) in blocks.into_iter().enumerate() {
+            if renumbered[index].is_none() {
+                continue;
+            }
+            for target in block.terminator.targets_mut() {
+                renumber(target);
+            }
+            for instruction in &mut block.instructions {
+                for target in instruction.targets_mut() {
+                    renumber(target);
+                }
+            }
+            program.blocks.push(block);
+        }
+        renumber(&mut program.entry);
+
+        // A line stays a jump target if its first block is still there
+        program.lines.retain(|_, id| match renumbered[id.0] {
+            Some(new) => {
+                *id = new;
+                true
+            }
+            None => false,
+        });
+        let lines_after: BTreeSet<u32> = program.blocks.iter().map(|block| block.line).collect();
+        for line in lines_before.difference(&lines_after) {
+            self.changes
+                .push(format!("line {}: unreachable, removed", line));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Lowering;
+    use crate::test_support::parse;
+
+    // A subroutine nothing calls at 1000, and a DEF key entry at 2000
+    const PROGRAM: &str = "\
+10 GOTO 100
+20 REM NEVER RUN
+100 GOTO 500
+500 CLS: REM MAIN
+510 END
+1000 RETURN
+2000 \"A\" CLS
+";
+
+    #[test]
+    fn threads_jumps_and_removes_dead_lines() {
+        let program = parse(PROGRAM);
+        let mut ir = Lowering::new(&program).lower();
+        let changes = DeadCodeEliminator::new(vec![]).eliminate(&mut ir);
+
+        assert_eq!(
+            changes,
+            vec![
+                "line 20: REM dropped",
+                "line 500: REM dropped",
+                "line 10: jump to line 100 threaded to line 500",
+                "line 20: jump to line 100 threaded to line 500",
+                "line 10: unreachable, removed",
+                "line 20: unreachable, removed",
+                "line 100: unreachable, removed",
+                "line 510: unreachable, removed",
+                "line 1000: unreachable, removed",
+                "line 2000: unreachable, removed",
+            ]
+        );
+        let expected = ["entry b0", "b0: ; line 500", "    call cls", "    end", ""];
+        assert_eq!(ir.to_string(), expected.join("\n"));
+        assert_eq!(ir.lines.keys().copied().collect::<Vec<u32>>(), vec![500]);
+    }
+
+    #[test]
+    fn keeps_outside_entries() {
+        let program = parse(PROGRAM);
+        let mut ir = Lowering::new(&program).lower();
+        DeadCodeEliminator::new(vec![2000]).eliminate(&mut ir);
+
+        assert_eq!(
+            ir.lines.keys().copied().collect::<Vec<u32>>(),
+            vec![500, 2000]
+        );
+        assert_eq!(ir.blocks[1].terminator, Terminator::End);
+    }
+}
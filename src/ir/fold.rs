@@ -0,0 +1,306 @@
+// Constant folding and algebraic simplification
+//
+// Arithmetic is done with the device's 10 digit decimals, so a folded result
+// is exactly what the ROM would have computed. Operations that fail at
+// runtime, like a division by zero or an overflow, are left in place to fail
+// there, except for string concatenations too long for the device, which
+// are reported as errors. Temps are only used after their definition, so a
+// single pass in block order sees every constant before its uses.
+
+use std::collections::HashMap;
+
+use super::node::{Constant, Instruction, Operand, Program, Temp, Terminator};
+use crate::ast::{BinaryOperator, UnaryOperator};
+use crate::machine::decimal::Decimal;
+use crate::machine::STRING_LENGTH;
+
+#[derive(Debug, Default)]
+pub struct FoldReport {
+    // What was changed, for verbose output
+    pub changes: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+pub struct ConstantFolder {
+    // Temps known to hold another operand
+    values: HashMap<Temp, Operand>,
+    report: FoldReport,
+}
+
+impl ConstantFolder {
+    pub fn new() -> Self {
+        ConstantFolder {
+            values: HashMap::new(),
+            report: FoldReport::default(),
+        }
+    }
+
+    pub fn fold(mut self, program: &mut Program) -> FoldReport {
+        for block in &mut program.blocks {
+            let line = block.line;
+            for mut instruction in std::mem::take(&mut block.instructions) {
+                for operand in instruction.operands_mut() {
+                    self.substitute(operand);
+                }
+                match self.simplify(line, &instruction) {
+                    Some((dest, value)) => {
+                        self.values.insert(dest, value);
+                    }
+                    None => block.instructions.push(instruction),
+                }
+            }
+
+            if let Terminator::Branch {
+                condition,
+                then,
+                else_,
+            } = &mut block.terminator
+            {
+                self.substitute(condition);
+                if let Operand::Constant(Constant::Number(condition)) = condition {
+                    let (target, outcome) = if condition.is_zero() {
+                        (*else_, "false")
+                    } else {
+                        (*then, "true")
+                    };
+                    self.report
+                        .changes
+                        .push(format!("line {}: IF is always {}", line, outcome));
+                    block.terminator = Terminator::Jump(target);
+                }
+            }
+        }
+        self.report
+    }
+
+    fn substitute(&self, operand: &mut Operand) {
+        if let Operand::Temp(temp) = operand {
+            if let Some(value) = self.values.get(temp) {
+                *operand = value.clone();
+            }
+        }
+    }
+
+    // The temp an instruction defines and the operand it can be replaced
+    // with
+    fn simplify(&mut self, line: u32, instruction: &Instruction) -> Option<(Temp, Operand)> {
+        match instruction {
+            Instruction::Binary {
+                op: BinaryOperator::Add,
+                left: Operand::Constant(left),
+                right: Operand::Constant(right),
+                ..
+            } if concatenation_length(left, right).is_some_and(|length| length > STRING_LENGTH) => {
+                self.report.errors.push(format!(
+                    "line {}: {} + {} is longer than the {} characters of a string",
+                    line, left, right, STRING_LENGTH
+                ));
+                None
+            }
+            Instruction::Binary {
+                dest,
+                op,
+                left: Operand::Constant(left),
+                right: Operand::Constant(right),
+            } => {
+                let value = binary(*op, left, right)?;
+                self.report.changes.push(format!(
+                    "line {}: {} {} {} folded to {}",
+                    line, left, op, right, value
+                ));
+                Some((*dest, Operand::Constant(value)))
+            }
+            Instruction::Binary {
+                dest,
+                op,
+                left,
+                right,
+            } => {
+                let value = identity(*op, left, right)?;
+                self.report.changes.push(format!(
+                    "line {}: {} {} {} simplified to {}",
+                    line, left, op, right, value
+                ));
+                Some((*dest, value))
+            }
+            Instruction::Unary {
+                dest,
+                op,
+                operand: Operand::Constant(Constant::Number(operand)),
+            } => {
+                let value = match op {
+                    UnaryOperator::Plus => *operand,
+                    UnaryOperator::Minus => operand.neg(),
+                    UnaryOperator::Not => Decimal::from(i32::from(!bits(*operand)?)),
+                };
+                self.report.changes.push(format!(
+                    "line {}: {}{} folded to {}",
+                    line, op, operand, value
+                ));
+                Some((*dest, Operand::Constant(Constant::Number(value))))
+            }
+            _ => None,
+        }
+    }
+}
+
+fn binary(op: BinaryOperator, left: &Constant, right: &Constant) -> Option<Constant> {
+    let (left, right) = match (left, right) {
+        (Constant::Number(left), Constant::Number(right)) => (*left, *right),
+        (Constant::String(left), Constant::String(right)) => {
+            let ordering = left.cmp(right);
+            let result = match op {
+                BinaryOperator::Add => return Some(Constant::String(format!("{}{}", left, right))),
+                BinaryOperator::Eq => ordering.is_eq(),
+                BinaryOperator::Ne => ordering.is_ne(),
+                BinaryOperator::Lt => ordering.is_lt(),
+                BinaryOperator::Le => ordering.is_le(),
+                BinaryOperator::Gt => ordering.is_gt(),
+                BinaryOperator::Ge => ordering.is_ge(),
+                BinaryOperator::Sub
+                | BinaryOperator::Mul
+                | BinaryOperator::Div
+                | BinaryOperator::And
+                | BinaryOperator::Or => return None,
+            };
+            return Some(Constant::Number(Decimal::from(result)));
+        }
+        _ => return None,
+    };
+
+    let result = match op {
+        BinaryOperator::Add => left.add(right).ok()?,
+        BinaryOperator::Sub => left.sub(right).ok()?,
+        BinaryOperator::Mul => left.mul(right).ok()?,
+        BinaryOperator::Div => left.div(right).ok()?,
+        BinaryOperator::And => Decimal::from(i32::from(bits(left)? & bits(right)?)),
+        BinaryOperator::Or => Decimal::from(i32::from(bits(left)? | bits(right)?)),
+        BinaryOperator::Eq => Decimal::from(left == right),
+        BinaryOperator::Ne => Decimal::from(left != right),
+        BinaryOperator::Lt => Decimal::from(left < right),
+        BinaryOperator::Le => Decimal::from(left <= right),
+        BinaryOperator::Gt => Decimal::from(left > right),
+        BinaryOperator::Ge => Decimal::from(left >= right),
+    };
+    Some(Constant::Number(result))
+}
+
+fn concatenation_length(left: &Constant, right: &Constant) -> Option<usize> {
+    match (left, right) {
+        (Constant::String(left), Constant::String(right)) => {
+            Some(left.chars().count() + right.chars().count())
+        }
+        _ => None,
+    }
+}
+
+// X * 1, 1 * X, X / 1, X + 0, 0 + X, X - 0 and X$ + "" are X
+fn identity(op: BinaryOperator, left: &Operand, right: &Operand) -> Option<Operand> {
+    let is =
+        |operand: &Operand, value: Decimal| *operand == Operand::Constant(Constant::Number(value));
+    let empty = Operand::Constant(Constant::String(String::new()));
+    let value = match op {
+        BinaryOperator::Mul if is(left, Decimal::ONE) => right,
+        BinaryOperator::Mul | BinaryOperator::Div if is(right, Decimal::ONE) => left,
+        BinaryOperator::Add if is(left, Decimal::ZERO) || *left == empty => right,
+        BinaryOperator::Add | BinaryOperator::Sub if is(right, Decimal::ZERO) => left,
+        BinaryOperator::Add if *right == empty => left,
+        _ => return None,
+    };
+    Some(value.clone())
+}
+
+// AND, OR and NOT work on 16 bit integers, other values fail at runtime
+fn bits(number: Decimal) -> Option<i16> {
+    number
+        .trunc()
+        .to_integer()
+        .and_then(|value| i16::try_from(value).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::SemanticChecker;
+    use crate::ir::node::BlockId;
+    use crate::ir::Lowering;
+    use crate::machine::MemoryConfig;
+    use crate::test_support::parse;
+
+    #[test]
+    fn folds_with_device_arithmetic() {
+        // Line 20 is not 1 with 10 digits
+        let program = parse(concat!(
+            "10 X = 2 * 3 + 1\n",
+            "20 Y = 1 / 3 * 3\n",
+            "30 Z = X * 1 + 0: A$ = \"A\" + \"B\": E = 1 / 0\n",
+            "40 IF 2 > 1 THEN END\n",
+        ));
+
+        let mut ir = Lowering::new(&program).lower();
+        let report = ConstantFolder::new().fold(&mut ir);
+
+        assert!(report.errors.is_empty());
+        assert_eq!(
+            report.changes,
+            vec![
+                "line 10: 2 * 3 folded to 6",
+                "line 10: 6 + 1 folded to 7",
+                "line 20: 1 / 3 folded to 0.3333333333",
+                "line 20: 0.3333333333 * 3 folded to 0.9999999999",
+                "line 30: %4 * 1 simplified to %4",
+                "line 30: %4 + 0 simplified to %4",
+                "line 30: \"A\" + \"B\" folded to \"AB\"",
+                "line 40: 2 > 1 folded to 1",
+                "line 40: IF is always true",
+            ]
+        );
+        let stores: Vec<String> = ir
+            .blocks
+            .iter()
+            .flat_map(|block| &block.instructions)
+            .map(ToString::to_string)
+            .collect();
+        assert_eq!(
+            stores,
+            vec![
+                "store X, 7",
+                "store Y, 0.9999999999",
+                "%4 = load X",
+                "store Z, %4",
+                "store A$, \"AB\"",
+                "%8 = div 1, 0",
+                "store E, %8",
+            ]
+        );
+        assert_eq!(ir.blocks[3].terminator, Terminator::Jump(BlockId(4)));
+    }
+
+    #[test]
+    fn concatenations_from_checked_source() {
+        // Line 20 would make a string of 81 characters
+        let left = "A".repeat(50);
+        let right = "B".repeat(31);
+        let program = parse(&format!(
+            "10 A$ = \"AB\" + \"CD\"\n20 B$ = \"{}\" + \"{}\"\n",
+            left, right
+        ));
+
+        let diagnostics = SemanticChecker::new(&program, MemoryConfig::Pc1500, &[]).check();
+        let mut ir = Lowering::new(&program).lower();
+        let report = ConstantFolder::new().fold(&mut ir);
+
+        assert!(diagnostics.errors.is_empty());
+        assert_eq!(
+            report.changes,
+            vec!["line 10: \"AB\" + \"CD\" folded to \"ABCD\""]
+        );
+        assert_eq!(
+            report.errors,
+            vec![format!(
+                "line 20: \"{}\" + \"{}\" is longer than the 80 characters of a string",
+                left, right
+            )]
+        );
+    }
+}
@@ -0,0 +1,702 @@
+// Lowers the AST into basic blocks of three-address code
+//
+// Every source line starts a block, so GOTO and GOSUB jump to blocks. An IF
+// ends its block with a branch, and both branches continue with a block for
+// the rest of the line. Code after a GOTO, RETURN or END on the same line is
+// kept in a block of its own that nothing jumps to.
+
+use std::collections::BTreeMap;
+
+use super::node::{
+    Block, BlockId, Constant, Device, Instruction, Intrinsic, Operand, Program, Temp, Terminator,
+    Type,
+};
+use crate::ast::{
+    self, BinaryOperator, Coordinate, DataItem, Expression, ExpressionVisitor, Function, LValue,
+    PrintItem, Statement, StatementVisitor, UnaryOperator, Unsigned,
+};
+use crate::machine::decimal::Decimal;
+use crate::machine::error::ErrorCode;
+
+struct PendingBlock {
+    line: u32,
+    instructions: Vec<Instruction>,
+    terminator: Option<Terminator>,
+}
+
+pub struct Lowering<'a> {
+    program: &'a ast::Program,
+    blocks: Vec<PendingBlock>,
+    lines: BTreeMap<u32, BlockId>,
+    temps: Vec<Type>,
+    data: Vec<(u32, Constant)>,
+    current: BlockId,
+    line: u32,
+    // Shared target of jumps to lines that do not exist
+    undefined_line: Option<BlockId>,
+}
+
+impl<'a> Lowering<'a> {
+    pub fn new(program: &'a ast::Program) -> Self {
+        Lowering {
+            program,
+            blocks: Vec::new(),
+            lines: BTreeMap::new(),
+            temps: Vec::new(),
+            data: Vec::new(),
+            current: BlockId(0),
+            line: 0,
+            undefined_line: None,
+        }
+    }
+
+    pub fn lower(mut self) -> Program {
+        for (line, statement) in self.program.iter() {
+            let id = self.new_block(*line);
+            self.lines.insert(*line, id);
+            collect_data(*line, statement, &mut self.data);
+        }
+
+        let entry = match self.lines.values().next() {
+            Some(entry) => *entry,
+            None => {
+                let id = self.new_block(0);
+                self.terminate(Terminator::End);
+                id
+            }
+        };
+
+        let lines: Vec<(u32, BlockId)> = self.lines.iter().map(|(l, b)| (*l, *b)).collect();
+        for (index, (line, block)) in lines.iter().enumerate() {
+            self.current = *block;
+            self.line = *line;
+            if let Some(statement) = self.program.lookup_line(*line) {
+                statement.accept(&mut self);
+            }
+            if self.is_open() {
+                let fallthrough = match lines.get(index + 1) {
+                    Some((_, next)) => Terminator::Jump(*next),
+                    None => Terminator::End,
+                };
+                self.terminate(fallthrough);
+            }
+        }
+
+        let blocks = self
+            .blocks
+            .into_iter()
+            .map(|block| Block {
+                line: block.line,
+                instructions: block.instructions,
+                terminator: block.terminator.unwrap_or(Terminator::End),
+            })
+            .collect();
+        Program {
+            blocks,
+            entry,
+            lines: self.lines,
+            labels: self.program.labels.clone(),
+            temps: self.temps,
+            data: self.data,
+        }
+    }
+
+    fn new_block(&mut self, line: u32) -> BlockId {
+        self.blocks.push(PendingBlock {
+            line,
+            instructions: Vec::new(),
+            terminator: None,
+        });
+        BlockId(self.blocks.len() - 1)
+    }
+
+    fn is_open(&self) -> bool {
+        self.blocks[self.current.0].terminator.is_none()
+    }
+
+    // Code after a terminator goes to a new block, which nothing jumps to
+    fn open(&mut self) -> &mut PendingBlock {
+        if !self.is_open() {
+            self.current = self.new_block(self.line);
+        }
+        &mut self.blocks[self.current.0]
+    }
+
+    fn emit(&mut self, instruction: Instruction) {
+        self.open().instructions.push(instruction);
+    }
+
+    fn terminate(&mut self, terminator: Terminator) {
+        self.open().terminator = Some(terminator);
+    }
+
+    // Ends the current block and continues in the given one
+    fn switch_to(&mut self, block: BlockId) {
+        if self.is_open() {
+            self.terminate(Terminator::Jump(block));
+        }
+        self.current = block;
+    }
+
+    fn temp(&mut self, ty: Type) -> Temp {
+        self.temps.push(ty);
+        Temp(self.temps.len() - 1)
+    }
+
+    fn operand_type(&self, operand: &Operand) -> Type {
+        match operand {
+            Operand::Temp(temp) => self.temps[temp.0],
+            Operand::Constant(constant) => constant.ty(),
+        }
+    }
+
+    fn target(&mut self, line_number: u32) -> BlockId {
+        if let Some(block) = self.lines.get(&line_number) {
+            return *block;
+        }
+        match self.undefined_line {
+            Some(block) => block,
+            None => {
+                let block = self.new_block(line_number);
+                self.blocks[block.0].terminator = Some(Terminator::Raise(ErrorCode::UndefinedLine));
+                self.undefined_line = Some(block);
+                block
+            }
+        }
+    }
+
+    fn call(&mut self, intrinsic: Intrinsic, args: Vec<Operand>) {
+        self.emit(Instruction::Intrinsic {
+            dest: None,
+            intrinsic,
+            args,
+        });
+    }
+
+    fn call_value(&mut self, ty: Type, intrinsic: Intrinsic, args: Vec<Operand>) -> Operand {
+        let dest = self.temp(ty);
+        self.emit(Instruction::Intrinsic {
+            dest: Some(dest),
+            intrinsic,
+            args,
+        });
+        Operand::Temp(dest)
+    }
+
+    fn expressions(&mut self, expressions: &'a [Expression]) -> Vec<Operand> {
+        expressions.iter().map(|e| e.accept(self)).collect()
+    }
+
+    fn optional(&mut self, expression: Option<&'a Expression>) -> Vec<Operand> {
+        expression.map(|e| e.accept(self)).into_iter().collect()
+    }
+
+    fn coordinate(&mut self, coordinate: &'a Coordinate) -> [Operand; 2] {
+        [coordinate.x.accept(self), coordinate.y.accept(self)]
+    }
+
+    fn assign(&mut self, lvalue: &'a LValue, value: Operand) {
+        match lvalue {
+            LValue::Variable(variable) => self.emit(Instruction::Store {
+                variable: variable.clone(),
+                value,
+            }),
+            LValue::ArrayElement { variable, index } => {
+                let index = index.accept(self);
+                self.emit(Instruction::StoreElement {
+                    array: variable.clone(),
+                    index,
+                    value,
+                });
+            }
+        }
+    }
+
+    fn print(&mut self, device: Device, format: Option<&'a str>, content: &'a [PrintItem]) {
+        if let Some(format) = format {
+            let format = Operand::Constant(Constant::String(format.to_owned()));
+            self.call(Intrinsic::Using(device), vec![format]);
+        }
+        for item in content {
+            let value = item.expression.accept(self);
+            self.call(Intrinsic::Print(device, item.separator), vec![value]);
+        }
+        self.call(Intrinsic::EndPrint(device), vec![]);
+    }
+}
+
+fn number(value: i32) -> Operand {
+    Operand::Constant(Constant::Number(Decimal::from(value)))
+}
+
+fn lvalue_type(lvalue: &LValue) -> Type {
+    match lvalue {
+        LValue::Variable(name) | LValue::ArrayElement { variable: name, .. } => Type::of(name),
+    }
+}
+
+// DATA inside IF and SEQ counts too
+fn collect_data(line: u32, statement: &Statement, data: &mut Vec<(u32, Constant)>) {
+    match statement {
+        Statement::Data { values } => {
+            data.extend(values.iter().map(|value| {
+                let constant = match value {
+                    DataItem::Number(num) | DataItem::HexNumber(num) => {
+                        Constant::Number(Decimal::from(*num))
+                    }
+                    DataItem::String(string) => Constant::String(string.clone()),
+                };
+                (line, constant)
+            }));
+        }
+        Statement::Seq { statements } => {
+            for child in statements {
+                collect_data(line, child, data);
+            }
+        }
+        Statement::If { then, else_, .. } => {
+            collect_data(line, then, data);
+            if let Some(else_) = else_ {
+                collect_data(line, else_, data);
+            }
+        }
+        _ => {}
+    }
+}
+
+impl<'a> ExpressionVisitor<'a, Operand> for Lowering<'a> {
+    fn visit_number_literal(&mut self, num: i32) -> Operand {
+        number(num)
+    }
+
+    fn visit_string_literal(&mut self, content: &'a str) -> Operand {
+        Operand::Constant(Constant::String(content.to_owned()))
+    }
+
+    fn visit_variable(&mut self, lvalue: &'a LValue) -> Operand {
+        let dest = match lvalue {
+            LValue::Variable(variable) => {
+                let dest = self.temp(Type::of(variable));
+                self.emit(Instruction::Load {
+                    dest,
+                    variable: variable.clone(),
+                });
+                dest
+            }
+            LValue::ArrayElement { variable, index } => {
+                let index = index.accept(self);
+                let dest = self.temp(Type::of(variable));
+                self.emit(Instruction::LoadElement {
+                    dest,
+                    array: variable.clone(),
+                    index,
+                });
+                dest
+            }
+        };
+        Operand::Temp(dest)
+    }
+
+    fn visit_unary_op(&mut self, op: UnaryOperator, operand: &'a Expression) -> Operand {
+        let operand = operand.accept(self);
+        // Unary plus only checks that the operand is a number, which the
+        // semantic checker already did
+        if op == UnaryOperator::Plus {
+            return operand;
+        }
+        let dest = self.temp(Type::Number);
+        self.emit(Instruction::Unary { dest, op, operand });
+        Operand::Temp(dest)
+    }
+
+    fn visit_binary_op(
+        &mut self,
+        left: &'a Expression,
+        op: BinaryOperator,
+        right: &'a Expression,
+    ) -> Operand {
+        let left = left.accept(self);
+        let right = right.accept(self);
+        let ty = match op {
+            BinaryOperator::Add => self.operand_type(&left),
+            _ => Type::Number,
+        };
+        let dest = self.temp(ty);
+        self.emit(Instruction::Binary {
+            dest,
+            op,
+            left,
+            right,
+        });
+        Operand::Temp(dest)
+    }
+
+    fn visit_function(&mut self, function: Function, args: &'a [Expression]) -> Operand {
+        let args = self.expressions(args);
+        let (ty, intrinsic) = match function {
+            Function::Point => (Type::Number, Intrinsic::Point),
+            Function::Inkey => (Type::String, Intrinsic::Inkey),
+            Function::Ern => (Type::Number, Intrinsic::Ern),
+            Function::Erl => (Type::Number, Intrinsic::Erl),
+        };
+        self.call_value(ty, intrinsic, args)
+    }
+}
+
+impl<'a> StatementVisitor<'a, ()> for Lowering<'a> {
+    fn visit_let(&mut self, variable: &'a LValue, expression: &'a Expression) {
+        let value = expression.accept(self);
+        self.assign(variable, value);
+    }
+
+    fn visit_print(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
+        self.print(Device::Display, format, content);
+    }
+
+    fn visit_pause(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
+        self.print(Device::Display, format, content);
+        self.call(Intrinsic::Pause, vec![]);
+    }
+
+    fn visit_input(&mut self, prompt: Option<&'a Expression>, variable: &'a LValue) {
+        let prompt = self.optional(prompt);
+        let ty = lvalue_type(variable);
+        let answer = self.call_value(ty, Intrinsic::Input(ty), prompt);
+        self.assign(variable, answer);
+    }
+
+    fn visit_wait(&mut self, time: Option<&'a Expression>) {
+        let args = self.optional(time);
+        self.call(Intrinsic::Wait, args);
+    }
+
+    fn visit_read(&mut self, variables: &'a [LValue]) {
+        for variable in variables {
+            let ty = lvalue_type(variable);
+            let value = self.call_value(ty, Intrinsic::Read(ty), vec![]);
+            self.assign(variable, value);
+        }
+    }
+
+    fn visit_data(&mut self, _: &'a [DataItem]) {}
+
+    // Restarts at the first item in or after the line, like the interpreter
+    fn visit_restore(&mut self, line_number: Option<Unsigned>) {
+        let line = line_number.map_or(0, |line| line.value);
+        let index = self
+            .data
+            .iter()
+            .position(|(item_line, _)| *item_line >= line)
+            .unwrap_or(self.data.len());
+        self.call(Intrinsic::Restore, vec![number(index as i32)]);
+    }
+
+    fn visit_poke(&mut self, address: &'a Expression, values: &'a [Expression]) {
+        let mut args = vec![address.accept(self)];
+        args.extend(self.expressions(values));
+        self.call(Intrinsic::Poke, args);
+    }
+
+    fn visit_call(&mut self, address: &'a Expression, argument: Option<&'a Expression>) {
+        let mut args = vec![address.accept(self)];
+        args.extend(self.optional(argument));
+        self.call(Intrinsic::Call, args);
+    }
+
+    fn visit_goto(&mut self, line_number: Unsigned) {
+        let target = self.target(line_number.value);
+        self.terminate(Terminator::Jump(target));
+    }
+
+    fn visit_for(
+        &mut self,
+        variable: &'a str,
+        from: &'a Expression,
+        to: &'a Expression,
+        step: Option<&'a Expression>,
+    ) {
+        let from = from.accept(self);
+        let limit = to.accept(self);
+        let step = match step {
+            Some(step) => step.accept(self),
+            None => number(1),
+        };
+        self.emit(Instruction::Store {
+            variable: variable.to_owned(),
+            value: from.clone(),
+        });
+        let body = self.new_block(self.line);
+        self.emit(Instruction::ForPush {
+            variable: variable.to_owned(),
+            start: from,
+            limit,
+            step,
+            body,
+        });
+        self.switch_to(body);
+    }
+
+    fn visit_next(&mut self, variable: &'a str) {
+        let exit = self.new_block(self.line);
+        self.terminate(Terminator::Next {
+            variable: variable.to_owned(),
+            exit,
+        });
+        self.current = exit;
+    }
+
+    fn visit_end(&mut self) {
+        self.terminate(Terminator::End);
+    }
+
+    fn visit_gosub(&mut self, line_number: Unsigned) {
+        let target = self.target(line_number.value);
+        let ret = self.new_block(self.line);
+        self.terminate(Terminator::Gosub { target, ret });
+        self.current = ret;
+    }
+
+    fn visit_return(&mut self) {
+        self.terminate(Terminator::Return);
+    }
+
+    // The trap line is only looked up when an error happens, so an undefined
+    // line raises then
+    fn visit_on_error(&mut self, line_number: Unsigned) {
+        let line_number = line_number.value;
+        let target = (line_number != 0).then(|| self.target(line_number));
+        self.emit(Instruction::SetTrap(target));
+    }
+
+    fn visit_if(
+        &mut self,
+        condition: &'a Expression,
+        then: &'a Statement,
+        else_: Option<&'a Statement>,
+    ) {
+        let condition = condition.accept(self);
+        let then_block = self.new_block(self.line);
+        let rest = self.new_block(self.line);
+        let else_block = match else_ {
+            Some(_) => self.new_block(self.line),
+            None => rest,
+        };
+        self.terminate(Terminator::Branch {
+            condition,
+            then: then_block,
+            else_: else_block,
+        });
+
+        self.current = then_block;
+        then.accept(self);
+        self.switch_to(rest);
+        if let Some(else_) = else_ {
+            self.current = else_block;
+            else_.accept(self);
+            self.switch_to(rest);
+        }
+    }
+
+    fn visit_seq(&mut self, statements: &'a [Statement]) {
+        for statement in statements {
+            statement.accept(self);
+        }
+    }
+
+    fn visit_rem(&mut self, content: &'a str) {
+        self.emit(Instruction::Rem(content.to_owned()));
+    }
+
+    fn visit_dim(&mut self, variable: &'a str, size: Unsigned, length: Option<Unsigned>) {
+        self.emit(Instruction::Dim {
+            array: variable.to_owned(),
+            size: size.value,
+            length: length.map(|length| length.value),
+        });
+    }
+
+    fn visit_cls(&mut self) {
+        self.call(Intrinsic::Cls, vec![]);
+    }
+
+    fn visit_cursor(&mut self, column: &'a Expression) {
+        let column = column.accept(self);
+        self.call(Intrinsic::Cursor, vec![column]);
+    }
+
+    fn visit_gcursor(&mut self, column: &'a Expression) {
+        let column = column.accept(self);
+        self.call(Intrinsic::GCursor, vec![column]);
+    }
+
+    fn visit_gprint(&mut self, content: &'a [Expression]) {
+        let args = self.expressions(content);
+        self.call(Intrinsic::GPrint, args);
+    }
+
+    fn visit_beep(
+        &mut self,
+        count: &'a Expression,
+        frequency: Option<&'a Expression>,
+        duration: Option<&'a Expression>,
+    ) {
+        let mut args = vec![count.accept(self)];
+        args.extend(self.optional(frequency));
+        args.extend(self.optional(duration));
+        self.call(Intrinsic::Beep, args);
+    }
+
+    fn visit_lprint(&mut self, format: Option<&'a str>, content: &'a [PrintItem]) {
+        self.print(Device::Printer, format, content);
+    }
+
+    fn visit_line(
+        &mut self,
+        relative: bool,
+        start: Option<&'a Coordinate>,
+        points: &'a [Coordinate],
+        line_type: Option<&'a Expression>,
+        color: Option<&'a Expression>,
+        boxed: bool,
+    ) {
+        let mut args = Vec::new();
+        if let Some(start) = start {
+            args.extend(self.coordinate(start));
+        }
+        for point in points {
+            args.extend(self.coordinate(point));
+        }
+        // Solid lines unless a type is given
+        match line_type {
+            Some(line_type) => args.push(line_type.accept(self)),
+            None => args.push(number(0)),
+        }
+        args.extend(self.optional(color));
+        let intrinsic = Intrinsic::Line {
+            relative,
+            boxed,
+            start: start.is_some(),
+            points: points.len(),
+        };
+        self.call(intrinsic, args);
+    }
+
+    fn visit_glcursor(&mut self, position: &'a Coordinate) {
+        let args = self.coordinate(position).to_vec();
+        self.call(Intrinsic::GlCursor, args);
+    }
+
+    fn visit_color(&mut self, color: &'a Expression) {
+        let color = color.accept(self);
+        self.call(Intrinsic::Color, vec![color]);
+    }
+
+    fn visit_csize(&mut self, size: &'a Expression) {
+        let size = size.accept(self);
+        self.call(Intrinsic::CSize, vec![size]);
+    }
+
+    fn visit_rotate(&mut self, direction: &'a Expression) {
+        let direction = direction.accept(self);
+        self.call(Intrinsic::Rotate, vec![direction]);
+    }
+
+    fn visit_graph(&mut self) {
+        self.call(Intrinsic::Graph, vec![]);
+    }
+
+    fn visit_text(&mut self) {
+        self.call(Intrinsic::Text, vec![]);
+    }
+
+    fn visit_sorgn(&mut self) {
+        self.call(Intrinsic::Sorgn, vec![]);
+    }
+
+    fn visit_lf(&mut self, lines: &'a Expression) {
+        let lines = lines.accept(self);
+        self.call(Intrinsic::Lf, vec![lines]);
+    }
+
+    fn visit_tron(&mut self) {
+        self.call(Intrinsic::Tron, vec![]);
+    }
+
+    fn visit_troff(&mut self) {
+        self.call(Intrinsic::Troff, vec![]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::parse;
+
+    #[test]
+    fn loops_subroutines_and_data() {
+        let program = parse(concat!(
+            "10 FOR I = 1 TO 3\n",
+            "20 IF I = 2 THEN GOSUB 100\n",
+            "30 NEXT I\n",
+            "40 READ A$: END\n",
+            "100 \"SUB\" PRINT B$ + \"!\": RETURN\n",
+            "110 DATA \"HI\"\n",
+        ));
+
+        let ir = Lowering::new(&program).lower();
+
+        let expected = [
+            "data",
+            "    0: \"HI\" ; line 110",
+            "entry b0",
+            "b0: ; line 10",
+            "    store I, 1",
+            "    for I = 1 to 3 step 1, body b6",
+            "    jump b6",
+            "b1: ; line 20",
+            "    %0 = load I : num",
+            "    %1 = eq %0, 2 : num",
+            "    branch %1, b7, b8",
+            "b2: ; line 30",
+            "    next I, exit b10",
+            "b3: ; line 40",
+            "    %2 = call read.str : str",
+            "    store A$, %2",
+            "    end",
+            "b4: ; line 100 \"SUB\"",
+            "    %3 = load B$ : str",
+            "    %4 = add %3, \"!\" : str",
+            "    call print.display %4",
+            "    call end_print.display",
+            "    return",
+            "b5: ; line 110",
+            "    end",
+            "b6: ; line 10",
+            "    jump b1",
+            "b7: ; line 20",
+            "    gosub b4, return to b9",
+            "b8: ; line 20",
+            "    jump b2",
+            "b9: ; line 20",
+            "    jump b8",
+            "b10: ; line 30",
+            "    jump b3",
+            "",
+        ];
+        assert_eq!(ir.to_string(), expected.join("\n"));
+    }
+
+    #[test]
+    fn undefined_lines_raise() {
+        let program = parse("10 GOTO 99\n20 ON ERROR GOTO 0\n");
+
+        let ir = Lowering::new(&program).lower();
+
+        assert_eq!(ir.blocks[0].terminator, Terminator::Jump(BlockId(2)));
+        assert_eq!(
+            ir.blocks[2].terminator,
+            Terminator::Raise(ErrorCode::UndefinedLine)
+        );
+        assert_eq!(ir.blocks[1].instructions, vec![Instruction::SetTrap(None)]);
+    }
+}
@@ -0,0 +1,17 @@
+// Mid-level representation shared by the backends: basic blocks of typed
+// three-address instructions, with the GOSUB stack, FOR frames and DATA table
+// made explicit
+
+mod dead;
+mod fold;
+mod lower;
+mod node;
+mod range;
+
+pub use dead::DeadCodeEliminator;
+pub use fold::ConstantFolder;
+pub use lower::Lowering;
+pub use node::{
+    BlockId, Constant, Device, Instruction, Intrinsic, Operand, Program, Temp, Terminator, Type,
+};
+pub use range::{Range, RangeAnalysis, RangeReport, Width};
@@ -0,0 +1,515 @@
+use std::collections::BTreeMap;
+
+use crate::ast::{BinaryOperator, Separator, UnaryOperator};
+use crate::machine::decimal::Decimal;
+use crate::machine::error::ErrorCode;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Type {
+    Number,
+    String,
+}
+
+impl Type {
+    // Names ending in $ hold strings
+    pub fn of(name: &str) -> Type {
+        if name.ends_with('$') {
+            Type::String
+        } else {
+            Type::Number
+        }
+    }
+}
+
+impl std::fmt::Display for Type {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Type::Number => write!(f, "num"),
+            Type::String => write!(f, "str"),
+        }
+    }
+}
+
+// Value computed by an instruction, assigned exactly once
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Temp(pub usize);
+
+impl std::fmt::Display for Temp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "%{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct BlockId(pub usize);
+
+impl std::fmt::Display for BlockId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "b{}", self.0)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Constant {
+    Number(Decimal),
+    String(String),
+}
+
+impl Constant {
+    pub fn ty(&self) -> Type {
+        match self {
+            Constant::Number(_) => Type::Number,
+            Constant::String(_) => Type::String,
+        }
+    }
+}
+
+impl std::fmt::Display for Constant {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Constant::Number(number) => write!(f, "{}", number),
+            Constant::String(string) => write!(f, "\"{}\"", string),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Operand {
+    Temp(Temp),
+    Constant(Constant),
+}
+
+impl std::fmt::Display for Operand {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Operand::Temp(temp) => write!(f, "{}", temp),
+            Operand::Constant(constant) => write!(f, "{}", constant),
+        }
+    }
+}
+
+// Where PRINT-like statements send their line
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Device {
+    Display,
+    Printer,
+}
+
+impl std::fmt::Display for Device {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Device::Display => write!(f, "display"),
+            Device::Printer => write!(f, "printer"),
+        }
+    }
+}
+
+// Operations the runtime library provides. Optional trailing arguments of the
+// statement are left out of the argument list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Intrinsic {
+    // Functions, returning a value
+    Point,
+    Inkey,
+    Ern,
+    Erl,
+    // USING format for the rest of the statement
+    Using(Device),
+    // One item, and the separator after it
+    Print(Device, Option<Separator>),
+    // Ends the statement, the line stays open after a trailing separator
+    EndPrint(Device),
+    Pause,
+    // Optional prompt, returns the answer
+    Input(Type),
+    // Optional time in ticks
+    Wait,
+    // Next DATA item, a type mismatch is a runtime error
+    Read(Type),
+    // Index of the next DATA item to read
+    Restore,
+    // Address and bytes
+    Poke,
+    // Address and optional argument
+    Call,
+    Cls,
+    Cursor,
+    GCursor,
+    GPrint,
+    // Count, optional frequency and duration
+    Beep,
+    // Optional start, the points, the line type and an optional color
+    Line {
+        relative: bool,
+        boxed: bool,
+        start: bool,
+        points: usize,
+    },
+    GlCursor,
+    Color,
+    CSize,
+    Rotate,
+    Graph,
+    Text,
+    Sorgn,
+    Lf,
+    Tron,
+    Troff,
+}
+
+impl std::fmt::Display for Intrinsic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Intrinsic::Point => write!(f, "point"),
+            Intrinsic::Inkey => write!(f, "inkey"),
+            Intrinsic::Ern => write!(f, "ern"),
+            Intrinsic::Erl => write!(f, "erl"),
+            Intrinsic::Using(device) => write!(f, "using.{}", device),
+            Intrinsic::Print(device, None) => write!(f, "print.{}", device),
+            Intrinsic::Print(device, Some(separator)) => {
+                write!(f, "print.{} \"{}\"", device, separator)
+            }
+            Intrinsic::EndPrint(device) => write!(f, "end_print.{}", device),
+            Intrinsic::Pause => write!(f, "pause"),
+            Intrinsic::Input(ty) => write!(f, "input.{}", ty),
+            Intrinsic::Wait => write!(f, "wait"),
+            Intrinsic::Read(ty) => write!(f, "read.{}", ty),
+            Intrinsic::Restore => write!(f, "restore"),
+            Intrinsic::Poke => write!(f, "poke"),
+            Intrinsic::Call => write!(f, "call"),
+            Intrinsic::Cls => write!(f, "cls"),
+            Intrinsic::Cursor => write!(f, "cursor"),
+            Intrinsic::GCursor => write!(f, "gcursor"),
+            Intrinsic::GPrint => write!(f, "gprint"),
+            Intrinsic::Beep => write!(f, "beep"),
+            Intrinsic::Line {
+                relative,
+                boxed,
+                start,
+                points,
+            } => {
+                write!(f, "{}", if *relative { "rline" } else { "line" })?;
+                if *start {
+                    write!(f, ".from")?;
+                }
+                if *boxed {
+                    write!(f, ".box")?;
+                }
+                write!(f, " {}", points)
+            }
+            Intrinsic::GlCursor => write!(f, "glcursor"),
+            Intrinsic::Color => write!(f, "color"),
+            Intrinsic::CSize => write!(f, "csize"),
+            Intrinsic::Rotate => write!(f, "rotate"),
+            Intrinsic::Graph => write!(f, "graph"),
+            Intrinsic::Text => write!(f, "text"),
+            Intrinsic::Sorgn => write!(f, "sorgn"),
+            Intrinsic::Lf => write!(f, "lf"),
+            Intrinsic::Tron => write!(f, "tron"),
+            Intrinsic::Troff => write!(f, "troff"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Instruction {
+    Load {
+        dest: Temp,
+        variable: String,
+    },
+    Store {
+        variable: String,
+        value: Operand,
+    },
+    // Arrays used without DIM are created with 11 elements on first use
+    LoadElement {
+        dest: Temp,
+        array: String,
+        index: Operand,
+    },
+    StoreElement {
+        array: String,
+        index: Operand,
+        value: Operand,
+    },
+    Unary {
+        dest: Temp,
+        op: UnaryOperator,
+        operand: Operand,
+    },
+    // Add on strings concatenates, comparisons give 1 or 0
+    Binary {
+        dest: Temp,
+        op: BinaryOperator,
+        left: Operand,
+        right: Operand,
+    },
+    Dim {
+        array: String,
+        size: u32,
+        length: Option<u32>,
+    },
+    // Opens a FOR frame, dropping any frame of the same variable and the
+    // frames nested in it. The variable already holds the start value, which
+    // is repeated here for the analyses
+    ForPush {
+        variable: String,
+        start: Operand,
+        limit: Operand,
+        step: Operand,
+        body: BlockId,
+    },
+    // ON ERROR GOTO, None disables the trap
+    SetTrap(Option<BlockId>),
+    Intrinsic {
+        dest: Option<Temp>,
+        intrinsic: Intrinsic,
+        args: Vec<Operand>,
+    },
+    Rem(String),
+}
+
+impl Instruction {
+    // Operands the instruction reads
+    pub fn operands_mut(&mut self) -> Vec<&mut Operand> {
+        match self {
+            Instruction::Store { value, .. } => vec![value],
+            Instruction::LoadElement { index, .. } => vec![index],
+            Instruction::StoreElement { index, value, .. } => vec![index, value],
+            Instruction::Unary { operand, .. } => vec![operand],
+            Instruction::Binary { left, right, .. } => vec![left, right],
+            Instruction::ForPush {
+                start, limit, step, ..
+            } => vec![start, limit, step],
+            Instruction::Intrinsic { args, .. } => args.iter_mut().collect(),
+            Instruction::Load { .. }
+            | Instruction::Dim { .. }
+            | Instruction::SetTrap(_)
+            | Instruction::Rem(_) => vec![],
+        }
+    }
+
+    // Blocks the instruction refers to
+    pub fn targets_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Instruction::ForPush { body, .. } => vec![body],
+            Instruction::SetTrap(Some(target)) => vec![target],
+            _ => vec![],
+        }
+    }
+}
+
+impl std::fmt::Display for Instruction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Instruction::Load { dest, variable } => write!(f, "{} = load {}", dest, variable),
+            Instruction::Store { variable, value } => write!(f, "store {}, {}", variable, value),
+            Instruction::LoadElement { dest, array, index } => {
+                write!(f, "{} = load {}({})", dest, array, index)
+            }
+            Instruction::StoreElement {
+                array,
+                index,
+                value,
+            } => write!(f, "store {}({}), {}", array, index, value),
+            Instruction::Unary { dest, op, operand } => {
+                write!(f, "{} = {} {}", dest, unary_name(*op), operand)
+            }
+            Instruction::Binary {
+                dest,
+                op,
+                left,
+                right,
+            } => write!(f, "{} = {} {}, {}", dest, binary_name(*op), left, right),
+            Instruction::Dim {
+                array,
+                size,
+                length: None,
+            } => write!(f, "dim {}({})", array, size),
+            Instruction::Dim {
+                array,
+                size,
+                length: Some(length),
+            } => write!(f, "dim {}({}) * {}", array, size, length),
+            Instruction::ForPush {
+                variable,
+                start,
+                limit,
+                step,
+                body,
+            } => write!(
+                f,
+                "for {} = {} to {} step {}, body {}",
+                variable, start, limit, step, body
+            ),
+            Instruction::SetTrap(Some(target)) => write!(f, "trap {}", target),
+            Instruction::SetTrap(None) => write!(f, "trap off"),
+            Instruction::Intrinsic {
+                dest,
+                intrinsic,
+                args,
+            } => {
+                if let Some(dest) = dest {
+                    write!(f, "{} = ", dest)?;
+                }
+                write!(f, "call {}", intrinsic)?;
+                for (i, arg) in args.iter().enumerate() {
+                    write!(f, "{}{}", if i == 0 { " " } else { ", " }, arg)?;
+                }
+                Ok(())
+            }
+            Instruction::Rem(content) => write!(f, "rem {}", content),
+        }
+    }
+}
+
+fn unary_name(op: UnaryOperator) -> &'static str {
+    match op {
+        UnaryOperator::Plus => "plus",
+        UnaryOperator::Minus => "neg",
+        UnaryOperator::Not => "not",
+    }
+}
+
+fn binary_name(op: BinaryOperator) -> &'static str {
+    match op {
+        BinaryOperator::Add => "add",
+        BinaryOperator::Sub => "sub",
+        BinaryOperator::Mul => "mul",
+        BinaryOperator::Div => "div",
+        BinaryOperator::And => "and",
+        BinaryOperator::Or => "or",
+        BinaryOperator::Eq => "eq",
+        BinaryOperator::Ne => "ne",
+        BinaryOperator::Lt => "lt",
+        BinaryOperator::Le => "le",
+        BinaryOperator::Gt => "gt",
+        BinaryOperator::Ge => "ge",
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum Terminator {
+    Jump(BlockId),
+    // Takes then when the condition is not zero
+    Branch {
+        condition: Operand,
+        then: BlockId,
+        else_: BlockId,
+    },
+    // Pushes the return block and jumps to the target
+    Gosub {
+        target: BlockId,
+        ret: BlockId,
+    },
+    Return,
+    // Steps the innermost frame of the variable and jumps to its body while
+    // the limit is not passed, continuing with exit once it is
+    Next {
+        variable: String,
+        exit: BlockId,
+    },
+    End,
+    // Runtime error, trapped by ON ERROR GOTO like any other
+    Raise(ErrorCode),
+}
+
+impl Terminator {
+    // Blocks the terminator continues with. RETURN and NEXT also go to
+    // blocks only known at runtime, which GOSUB and FOR refer to
+    pub fn targets_mut(&mut self) -> Vec<&mut BlockId> {
+        match self {
+            Terminator::Jump(target) => vec![target],
+            Terminator::Branch { then, else_, .. } => vec![then, else_],
+            Terminator::Gosub { target, ret } => vec![target, ret],
+            Terminator::Next { exit, .. } => vec![exit],
+            Terminator::Return | Terminator::End | Terminator::Raise(_) => vec![],
+        }
+    }
+}
+
+impl std::fmt::Display for Terminator {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Terminator::Jump(target) => write!(f, "jump {}", target),
+            Terminator::Branch {
+                condition,
+                then,
+                else_,
+            } => write!(f, "branch {}, {}, {}", condition, then, else_),
+            Terminator::Gosub { target, ret } => write!(f, "gosub {}, return to {}", target, ret),
+            Terminator::Return => write!(f, "return"),
+            Terminator::Next { variable, exit } => write!(f, "next {}, exit {}", variable, exit),
+            Terminator::End => write!(f, "end"),
+            Terminator::Raise(code) => write!(f, "raise {}", code.number()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Block {
+    // Source line the code comes from
+    pub line: u32,
+    pub instructions: Vec<Instruction>,
+    pub terminator: Terminator,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Program {
+    pub blocks: Vec<Block>,
+    pub entry: BlockId,
+    // First block of each source line, GOTO and GOSUB targets
+    pub lines: BTreeMap<u32, BlockId>,
+    pub labels: BTreeMap<u32, String>,
+    // Type of each temp
+    pub temps: Vec<Type>,
+    // DATA items in program order with the line they come from
+    pub data: Vec<(u32, Constant)>,
+}
+
+impl std::fmt::Display for Program {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if !self.data.is_empty() {
+            writeln!(f, "data")?;
+            for (index, (line, item)) in self.data.iter().enumerate() {
+                writeln!(f, "    {}: {} ; line {}", index, item, line)?;
+            }
+        }
+        writeln!(f, "entry {}", self.entry)?;
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            let id = BlockId(index);
+            write!(f, "{}: ; line {}", id, block.line)?;
+            if self.lines.get(&block.line) == Some(&id) {
+                if let Some(label) = self.labels.get(&block.line) {
+                    write!(f, " \"{}\"", label)?;
+                }
+            }
+            writeln!(f)?;
+            for instruction in &block.instructions {
+                writeln!(f, "    {}{}", instruction, TypeNote(self, instruction))?;
+            }
+            writeln!(f, "    {}", block.terminator)?;
+        }
+        Ok(())
+    }
+}
+
+// Type of the temp an instruction defines, as a trailing comment
+struct TypeNote<'a>(&'a Program, &'a Instruction);
+
+impl std::fmt::Display for TypeNote<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let dest = match self.1 {
+            Instruction::Load { dest, .. }
+            | Instruction::LoadElement { dest, .. }
+            | Instruction::Unary { dest, .. }
+            | Instruction::Binary { dest, .. }
+            | Instruction::Intrinsic {
+                dest: Some(dest), ..
+            } => dest,
+            _ => return Ok(()),
+        };
+        write!(f, " : {}", self.0.temps[dest.0])
+    }
+}
@@ -0,0 +1,374 @@
+// Integer range inference for numeric variables
+//
+// The device computes everything in 10 digit BCD. Where a variable provably
+// only ever holds integers in a small range, like a loop counter or an array
+// index, a backend can keep it in a native 16 or 32 bit integer instead. The
+// analysis is flow-insensitive: a variable's range covers every value stored
+// to it anywhere, starting with the 0 every variable holds before its first
+// assignment.
+//
+// A FOR loop adds the values NEXT steps its variable through, which can end
+// one step past the limit, or one step past the start when the body runs
+// just once. Stores to the variable inside the body are not stepped.
+
+use std::collections::{BTreeMap, HashMap};
+
+use super::node::{Constant, Instruction, Intrinsic, Operand, Program, Type};
+use crate::ast::{BinaryOperator, UnaryOperator};
+
+// Integers the 10 digit mantissa holds exactly
+const MAX_EXACT: i64 = 9_999_999_999;
+// Ranges still growing after this many rounds are given up on, so loops
+// like X = X + 1 end
+const MAX_ROUNDS: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Range {
+    // Integers from min to max
+    Integer { min: i64, max: i64 },
+    // Anything, including fractions
+    Decimal,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Width {
+    Int16,
+    Int32,
+    Decimal,
+}
+
+impl Range {
+    fn integer(min: i64, max: i64) -> Range {
+        if min < -MAX_EXACT || max > MAX_EXACT {
+            Range::Decimal
+        } else {
+            Range::Integer { min, max }
+        }
+    }
+
+    fn constant(value: i64) -> Range {
+        Range::integer(value, value)
+    }
+
+    pub fn join(self, other: Range) -> Range {
+        match (self, other) {
+            (Range::Integer { min: a, max: b }, Range::Integer { min: c, max: d }) => {
+                Range::integer(a.min(c), b.max(d))
+            }
+            _ => Range::Decimal,
+        }
+    }
+
+    pub fn width(self) -> Width {
+        match self {
+            Range::Integer { min, max } if min >= i16::MIN.into() && max <= i16::MAX.into() => {
+                Width::Int16
+            }
+            Range::Integer { min, max } if min >= i32::MIN.into() && max <= i32::MAX.into() => {
+                Width::Int32
+            }
+            _ => Width::Decimal,
+        }
+    }
+
+    // Applies f to the bounds of both ranges, the result spans the extremes
+    fn combine(self, other: Range, f: impl Fn(i64, i64) -> Option<i64>) -> Range {
+        let (Range::Integer { min: a, max: b }, Range::Integer { min: c, max: d }) = (self, other)
+        else {
+            return Range::Decimal;
+        };
+        let corners = [f(a, c), f(a, d), f(b, c), f(b, d)];
+        let corners: Option<Vec<i64>> = corners.into_iter().collect();
+        match corners {
+            Some(corners) => Range::integer(
+                corners.iter().copied().min().unwrap_or(0),
+                corners.iter().copied().max().unwrap_or(0),
+            ),
+            None => Range::Decimal,
+        }
+    }
+}
+
+impl std::fmt::Display for Range {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Range::Integer { min, max } => write!(f, "{} to {}", min, max),
+            Range::Decimal => write!(f, "any number"),
+        }
+    }
+}
+
+impl std::fmt::Display for Width {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Width::Int16 => write!(f, "16-bit integer"),
+            Width::Int32 => write!(f, "32-bit integer"),
+            Width::Decimal => write!(f, "decimal"),
+        }
+    }
+}
+
+// Integers AND, OR and NOT work on and give
+const BITS: Range = Range::Integer {
+    min: i16::MIN as i64,
+    max: i16::MAX as i64,
+};
+const BOOLEAN: Range = Range::Integer { min: 0, max: 1 };
+const BYTE: Range = Range::Integer { min: 0, max: 0xFF };
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RangeReport {
+    pub variables: BTreeMap<String, Range>,
+    pub arrays: BTreeMap<String, Range>,
+    // Range of each temp
+    pub temps: Vec<Range>,
+}
+
+impl std::fmt::Display for RangeReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (name, range) in &self.variables {
+            writeln!(f, "{}: {} ({})", name, range.width(), range)?;
+        }
+        for (name, range) in &self.arrays {
+            writeln!(f, "{}(): {} ({})", name, range.width(), range)?;
+        }
+        Ok(())
+    }
+}
+
+type Ranges<'a> = HashMap<&'a str, Range>;
+
+pub struct RangeAnalysis<'a> {
+    program: &'a Program,
+    variables: Ranges<'a>,
+    arrays: Ranges<'a>,
+    temps: Vec<Range>,
+}
+
+impl<'a> RangeAnalysis<'a> {
+    pub fn new(program: &'a Program) -> Self {
+        RangeAnalysis {
+            program,
+            variables: HashMap::new(),
+            arrays: HashMap::new(),
+            temps: vec![Range::Decimal; program.temps.len()],
+        }
+    }
+
+    pub fn analyze(mut self) -> RangeReport {
+        for round in 0.. {
+            let (variables, arrays) = self.round();
+            if variables == self.variables && arrays == self.arrays {
+                break;
+            }
+            if round >= MAX_ROUNDS {
+                widen(&mut self.variables, variables);
+                widen(&mut self.arrays, arrays);
+            } else {
+                self.variables = variables;
+                self.arrays = arrays;
+            }
+        }
+
+        RangeReport {
+            variables: owned(self.variables),
+            arrays: owned(self.arrays),
+            temps: self.temps,
+        }
+    }
+
+    // Ranges after one more pass over the program
+    fn round(&mut self) -> (Ranges<'a>, Ranges<'a>) {
+        let program = self.program;
+        let mut variables = self.variables.clone();
+        let mut arrays = self.arrays.clone();
+        let store = |ranges: &mut Ranges<'a>, name: &'a str, range: Range| {
+            if Type::of(name) == Type::Number {
+                let entry = ranges.entry(name).or_insert(Range::constant(0));
+                *entry = entry.join(range);
+            }
+        };
+
+        for block in &program.blocks {
+            for instruction in &block.instructions {
+                match instruction {
+                    Instruction::Load { dest, variable } => {
+                        self.temps[dest.0] = self.variable(variable);
+                    }
+                    Instruction::LoadElement { dest, array, .. } => {
+                        self.temps[dest.0] = self.array(array);
+                    }
+                    Instruction::Store { variable, value } => {
+                        store(&mut variables, variable, self.operand(value));
+                    }
+                    Instruction::StoreElement { array, value, .. } => {
+                        store(&mut arrays, array, self.operand(value));
+                    }
+                    Instruction::Unary { dest, op, operand } => {
+                        let operand = self.operand(operand);
+                        self.temps[dest.0] = match op {
+                            UnaryOperator::Plus => operand,
+                            UnaryOperator::Minus => {
+                                operand.combine(Range::constant(0), |a, _| a.checked_neg())
+                            }
+                            UnaryOperator::Not => BITS,
+                        };
+                    }
+                    Instruction::Binary {
+                        dest,
+                        op,
+                        left,
+                        right,
+                    } => {
+                        let (left, right) = (self.operand(left), self.operand(right));
+                        self.temps[dest.0] = match op {
+                            BinaryOperator::Add => left.combine(right, i64::checked_add),
+                            BinaryOperator::Sub => left.combine(right, i64::checked_sub),
+                            BinaryOperator::Mul => left.combine(right, i64::checked_mul),
+                            BinaryOperator::Div => Range::Decimal,
+                            BinaryOperator::And | BinaryOperator::Or => BITS,
+                            BinaryOperator::Eq
+                            | BinaryOperator::Ne
+                            | BinaryOperator::Lt
+                            | BinaryOperator::Le
+                            | BinaryOperator::Gt
+                            | BinaryOperator::Ge => BOOLEAN,
+                        };
+                    }
+                    Instruction::ForPush {
+                        variable,
+                        start,
+                        limit,
+                        step,
+                        ..
+                    } => {
+                        let range = self.stepped(start, limit, step);
+                        store(&mut variables, variable, range);
+                    }
+                    Instruction::Intrinsic {
+                        dest: Some(dest),
+                        intrinsic,
+                        ..
+                    } => {
+                        self.temps[dest.0] = match intrinsic {
+                            Intrinsic::Point | Intrinsic::Ern => BYTE,
+                            Intrinsic::Erl => Range::integer(0, 0xFFFF),
+                            Intrinsic::Read(Type::Number) => self.data(),
+                            _ => Range::Decimal,
+                        };
+                    }
+                    Instruction::Intrinsic { dest: None, .. }
+                    | Instruction::Dim { .. }
+                    | Instruction::SetTrap(_)
+                    | Instruction::Rem(_) => {}
+                }
+            }
+        }
+        (variables, arrays)
+    }
+
+    fn variable(&self, name: &str) -> Range {
+        self.variables
+            .get(name)
+            .copied()
+            .unwrap_or(Range::constant(0))
+    }
+
+    fn array(&self, name: &str) -> Range {
+        self.arrays.get(name).copied().unwrap_or(Range::constant(0))
+    }
+
+    fn operand(&self, operand: &Operand) -> Range {
+        match operand {
+            Operand::Temp(temp) => self.temps[temp.0],
+            Operand::Constant(Constant::Number(number)) => {
+                number.to_integer().map_or(Range::Decimal, Range::constant)
+            }
+            Operand::Constant(Constant::String(_)) => Range::Decimal,
+        }
+    }
+
+    // Values a FOR variable takes: the start, and what NEXT leaves after
+    // adding the step. The body always runs once, so a start already past
+    // the limit is stepped once too, otherwise NEXT stops at most one step
+    // past the limit
+    fn stepped(&self, start: &Operand, limit: &Operand, step: &Operand) -> Range {
+        let (start, limit, step) = (self.operand(start), self.operand(limit), self.operand(step));
+        start
+            .join(start.combine(step, i64::checked_add))
+            .join(limit.combine(step, i64::checked_add))
+    }
+
+    // Numbers READ can return
+    fn data(&self) -> Range {
+        self.program
+            .data
+            .iter()
+            .filter_map(|(_, item)| match item {
+                Constant::Number(number) => {
+                    Some(number.to_integer().map_or(Range::Decimal, Range::constant))
+                }
+                Constant::String(_) => None,
+            })
+            .reduce(Range::join)
+            .unwrap_or(Range::Decimal)
+    }
+}
+
+// Gives up on ranges that keep changing
+fn widen<'a>(ranges: &mut Ranges<'a>, next: Ranges<'a>) {
+    for (name, range) in next {
+        if ranges.get(name) != Some(&range) {
+            ranges.insert(name, Range::Decimal);
+        }
+    }
+}
+
+fn owned(ranges: Ranges) -> BTreeMap<String, Range> {
+    ranges
+        .into_iter()
+        .map(|(name, range)| (name.to_owned(), range))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ir::Lowering;
+    use crate::test_support::parse;
+
+    #[test]
+    fn narrows_loop_counters_and_indices() {
+        let program = parse(concat!(
+            "10 FOR I = 1 TO 10: B(I) = I * 30000: NEXT I\n",
+            "20 X = 1 / 3\n",
+            "30 N = N + 1\n",
+            "40 GOTO 30\n",
+        ));
+
+        let ir = Lowering::new(&program).lower();
+        let report = RangeAnalysis::new(&ir).analyze();
+
+        assert_eq!(
+            report.to_string(),
+            "I: 16-bit integer (0 to 11)\n\
+             N: decimal (any number)\n\
+             X: decimal (any number)\n\
+             B(): 32-bit integer (0 to 330000)\n"
+        );
+    }
+
+    #[test]
+    fn loops_starting_past_their_limit_still_step() {
+        let program = parse("10 FOR I = 32767 TO 0: NEXT I\n20 FOR J = 5 TO 1 STEP 2: NEXT J\n");
+
+        let ir = Lowering::new(&program).lower();
+        let report = RangeAnalysis::new(&ir).analyze();
+
+        assert_eq!(
+            report.to_string(),
+            "I: 32-bit integer (0 to 32768)\n\
+             J: 16-bit integer (0 to 7)\n"
+        );
+    }
+}
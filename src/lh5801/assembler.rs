@@ -0,0 +1,1443 @@
+// Two-pass assembler for the LH5801
+//
+// The first pass reads the source with its INCLUDEs into statements and gives
+// each label its address, which only depends on the form of the operands.
+// The second evaluates the operands and emits the code, noting where it
+// depends on its own address or on external symbols so the object output can
+// be relocated and linked.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::ops::Range;
+
+use super::opcode::{Immediate, Mode, Opcode, Register, OPCODES};
+
+// Deeper than this a file most likely includes itself
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErrorKind {
+    AddressOutOfRange(i64),
+    BranchOutOfRange(i64),
+    DuplicateSymbol(String),
+    ExpectedExpression,
+    ExpectedOperand,
+    IncludeDepth,
+    IncludeFailed(String),
+    InvalidOperands(String),
+    InvalidVector(i64),
+    MissingName,
+    Overflow,
+    Relocation,
+    UndefinedSymbol(String),
+    UnexpectedCharacter(char),
+    UnknownInstruction(String),
+    UnterminatedString,
+    ValueOutOfRange(i64),
+}
+
+impl std::fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ErrorKind::AddressOutOfRange(address) => {
+                write!(f, "Address {} is outside the 64K of a bank", address)
+            }
+            ErrorKind::BranchOutOfRange(distance) => {
+                write!(f, "Branch of {} bytes is out of range", distance)
+            }
+            ErrorKind::DuplicateSymbol(name) => write!(f, "Symbol {} is already defined", name),
+            ErrorKind::ExpectedExpression => write!(f, "Expected expression"),
+            ErrorKind::ExpectedOperand => write!(f, "Expected operand"),
+            ErrorKind::IncludeDepth => write!(f, "INCLUDE nested too deeply"),
+            ErrorKind::IncludeFailed(error) => write!(f, "Cannot INCLUDE file: {}", error),
+            ErrorKind::InvalidOperands(mnemonic) => {
+                write!(f, "Invalid operands for {}", mnemonic)
+            }
+            ErrorKind::InvalidVector(vector) => {
+                write!(f, "Vector {} is not an even number from &C0 to &F6", vector)
+            }
+            ErrorKind::MissingName => write!(f, "EQU needs a name"),
+            ErrorKind::Overflow => write!(f, "Value overflows"),
+            ErrorKind::Relocation => {
+                write!(
+                    f,
+                    "Expression mixes addresses in a way that cannot be relocated"
+                )
+            }
+            ErrorKind::UndefinedSymbol(name) => write!(f, "Undefined symbol {}", name),
+            ErrorKind::UnexpectedCharacter(c) => write!(f, "Unexpected character '{}'", c),
+            ErrorKind::UnknownInstruction(mnemonic) => {
+                write!(f, "Unknown instruction {}", mnemonic)
+            }
+            ErrorKind::UnterminatedString => write!(f, "Unterminated string"),
+            ErrorKind::ValueOutOfRange(value) => write!(f, "Value {} does not fit", value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Error {
+    pub kind: ErrorKind,
+    pub file: String,
+    pub line: usize,
+    // Characters of the line in error, from 0
+    pub span: Range<usize>,
+    pub text: String,
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "Error in {} line {}: {}",
+            self.file, self.line, self.kind
+        )?;
+        writeln!(f, "    {}", self.text)?;
+        let width = self.span.end.saturating_sub(self.span.start).max(1);
+        write!(
+            f,
+            "    {}{}",
+            " ".repeat(self.span.start),
+            "^".repeat(width)
+        )
+    }
+}
+
+impl std::error::Error for Error {}
+
+type Failure = (ErrorKind, Range<usize>);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Operator {
+    Add,
+    Sub,
+    Mul,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expression {
+    Number(i64),
+    Symbol(String),
+    // $, the address of the statement
+    Here,
+    Negate(Box<Expression>),
+    Binary(Operator, Box<Expression>, Box<Expression>),
+    High(Box<Expression>),
+    Low(Box<Expression>),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum OperandKind {
+    Register(Register),
+    Indirect(Register),
+    Me1Indirect(Register),
+    Absolute(Expression),
+    Me1Absolute(Expression),
+    Value(Expression),
+    Text(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Operand {
+    kind: OperandKind,
+    span: Range<usize>,
+}
+
+// A source line taken apart
+struct Line {
+    label: Option<(String, Range<usize>)>,
+    operation: Option<(String, Range<usize>)>,
+    operands: Vec<Operand>,
+}
+
+struct Cursor {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl Cursor {
+    fn new(text: &str) -> Self {
+        Cursor {
+            chars: text.chars().collect(),
+            position: 0,
+        }
+    }
+
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek().is_some_and(char::is_whitespace) {
+            self.position += 1;
+        }
+    }
+
+    // Comments run to the end of the line
+    fn at_end(&mut self) -> bool {
+        self.skip_spaces();
+        matches!(self.peek(), None | Some(';'))
+    }
+
+    fn at_operand_end(&mut self) -> bool {
+        self.at_end() || self.peek() == Some(',')
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        self.skip_spaces();
+        let found = self.peek() == Some(c);
+        if found {
+            self.position += 1;
+        }
+        found
+    }
+
+    fn expect(&mut self, c: char) -> Result<(), Failure> {
+        if self.eat(c) {
+            Ok(())
+        } else {
+            Err(self.unexpected())
+        }
+    }
+
+    fn unexpected(&self) -> Failure {
+        let span = self.position..self.position + 1;
+        match self.peek() {
+            Some(c) => (ErrorKind::UnexpectedCharacter(c), span),
+            None => (ErrorKind::ExpectedOperand, span),
+        }
+    }
+
+    fn take_while(&mut self, accept: impl Fn(char) -> bool) -> String {
+        let start = self.position;
+        while self.peek().is_some_and(&accept) {
+            self.position += 1;
+        }
+        self.chars[start..self.position].iter().collect()
+    }
+
+    fn word(&mut self) -> Option<(String, Range<usize>)> {
+        self.skip_spaces();
+        let start = self.position;
+        if !self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+        {
+            return None;
+        }
+        let word = self.take_while(|c| c.is_ascii_alphanumeric() || c == '_');
+        Some((word, start..self.position))
+    }
+
+    fn line(&mut self) -> Result<Line, Failure> {
+        let mut label = None;
+        let mut operation = None;
+        if self.peek().is_some_and(|c| !c.is_whitespace() && c != ';') {
+            let word = self.word().ok_or_else(|| self.unexpected())?;
+            if self.eat(':') {
+                label = Some(word);
+            } else {
+                operation = Some(word);
+            }
+        }
+
+        match operation.take() {
+            // NAME EQU value, or an instruction at the start of the line
+            Some(word) => {
+                let position = self.position;
+                match self.word() {
+                    Some(next) if next.0.eq_ignore_ascii_case("EQU") => {
+                        label = Some(word);
+                        operation = Some(next);
+                    }
+                    _ => {
+                        self.position = position;
+                        operation = Some(word);
+                    }
+                }
+            }
+            None if !self.at_end() => {
+                operation = Some(self.word().ok_or_else(|| self.unexpected())?);
+            }
+            None => {}
+        }
+
+        let mut operands = Vec::new();
+        if !self.at_end() {
+            loop {
+                operands.push(self.operand()?);
+                if !self.eat(',') {
+                    break;
+                }
+            }
+            if !self.at_end() {
+                return Err(self.unexpected());
+            }
+        }
+        Ok(Line {
+            label,
+            operation,
+            operands,
+        })
+    }
+
+    fn operand(&mut self) -> Result<Operand, Failure> {
+        self.skip_spaces();
+        let start = self.position;
+        let kind = match self.peek() {
+            Some('"') => OperandKind::Text(self.text()?),
+            Some('#') => {
+                self.position += 1;
+                self.expect('(')?;
+                match self.inner()? {
+                    Ok(register) => OperandKind::Me1Indirect(register),
+                    Err(expression) => OperandKind::Me1Absolute(expression),
+                }
+            }
+            Some('(') => {
+                self.position += 1;
+                match self.inner()? {
+                    Ok(register) => OperandKind::Indirect(register),
+                    Err(expression) => OperandKind::Absolute(expression),
+                }
+            }
+            _ => {
+                let position = self.position;
+                match self.word().and_then(|(name, _)| Register::parse(&name)) {
+                    Some(register) if self.at_operand_end() => OperandKind::Register(register),
+                    _ => {
+                        self.position = position;
+                        OperandKind::Value(self.expression()?)
+                    }
+                }
+            }
+        };
+        Ok(Operand {
+            kind,
+            span: start..self.position,
+        })
+    }
+
+    // Inside the parentheses of (X) or (ab), after the opening one
+    fn inner(&mut self) -> Result<Result<Register, Expression>, Failure> {
+        let position = self.position;
+        if let Some(register) = self.word().and_then(|(name, _)| Register::parse(&name)) {
+            if self.eat(')') {
+                return Ok(Ok(register));
+            }
+        }
+        self.position = position;
+        let expression = self.expression()?;
+        self.expect(')')?;
+        Ok(Err(expression))
+    }
+
+    fn text(&mut self) -> Result<String, Failure> {
+        let start = self.position;
+        self.position += 1;
+        let text = self.take_while(|c| c != '"');
+        if self.peek().is_none() {
+            return Err((ErrorKind::UnterminatedString, start..self.position));
+        }
+        self.position += 1;
+        Ok(text)
+    }
+
+    fn expression(&mut self) -> Result<Expression, Failure> {
+        let mut left = self.term()?;
+        loop {
+            self.skip_spaces();
+            let op = match self.peek() {
+                Some('+') => Operator::Add,
+                Some('-') => Operator::Sub,
+                _ => return Ok(left),
+            };
+            self.position += 1;
+            let right = self.term()?;
+            left = Expression::Binary(op, Box::new(left), Box::new(right));
+        }
+    }
+
+    fn term(&mut self) -> Result<Expression, Failure> {
+        let mut left = self.unary()?;
+        while self.eat('*') {
+            let right = self.unary()?;
+            left = Expression::Binary(Operator::Mul, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expression, Failure> {
+        if self.eat('-') {
+            return Ok(Expression::Negate(Box::new(self.unary()?)));
+        }
+        self.primary()
+    }
+
+    fn primary(&mut self) -> Result<Expression, Failure> {
+        self.skip_spaces();
+        let start = self.position;
+        let expected = (ErrorKind::ExpectedExpression, start..start + 1);
+        match self.peek() {
+            Some('&') => {
+                self.position += 1;
+                let digits = self.take_while(|c| c.is_ascii_hexdigit());
+                if digits.is_empty() {
+                    return Err(expected);
+                }
+                i64::from_str_radix(&digits, 16)
+                    .ok()
+                    .map(Expression::Number)
+                    .ok_or((ErrorKind::Overflow, start..self.position))
+            }
+            Some('$') => {
+                self.position += 1;
+                Ok(Expression::Here)
+            }
+            Some('\'') => {
+                self.position += 1;
+                let c = self.peek().ok_or(expected)?;
+                self.position += 1;
+                self.expect('\'')?;
+                Ok(Expression::Number(i64::from(u32::from(c))))
+            }
+            Some(c) if c.is_ascii_digit() => {
+                let digits = self.take_while(|digit| digit.is_ascii_digit());
+                digits
+                    .parse()
+                    .ok()
+                    .map(Expression::Number)
+                    .ok_or((ErrorKind::Overflow, start..self.position))
+            }
+            _ => {
+                let (name, _) = self.word().ok_or(expected)?;
+                let function = name.to_uppercase();
+                if (function == "HIGH" || function == "LOW") && self.eat('(') {
+                    let argument = Box::new(self.expression()?);
+                    self.expect(')')?;
+                    if function == "HIGH" {
+                        Ok(Expression::High(argument))
+                    } else {
+                        Ok(Expression::Low(argument))
+                    }
+                } else {
+                    Ok(Expression::Symbol(name))
+                }
+            }
+        }
+    }
+}
+
+// Which bytes of an address a value stands for
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Part {
+    Word,
+    High,
+    Low,
+}
+
+impl std::fmt::Display for Part {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Part::Word => write!(f, "W"),
+            Part::High => write!(f, "H"),
+            Part::Low => write!(f, "L"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Base {
+    Absolute,
+    // An address in the code, which moves with it
+    Relocatable,
+    // An EXTERN no symbol file gave, the number is added to it
+    External(String),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Value {
+    number: i64,
+    base: Base,
+    part: Part,
+}
+
+impl Value {
+    fn absolute(number: i64) -> Value {
+        Value {
+            number,
+            base: Base::Absolute,
+            part: Part::Word,
+        }
+    }
+
+    fn address(address: u32) -> Value {
+        Value {
+            number: i64::from(address),
+            base: Base::Relocatable,
+            part: Part::Word,
+        }
+    }
+
+    // Plain number, as ORG and DS need
+    fn number(&self) -> Result<i64, ErrorKind> {
+        if self.base == Base::Absolute {
+            Ok(self.number)
+        } else {
+            Err(ErrorKind::Relocation)
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Target {
+    // Offset in the code
+    Code(i64),
+    External(String, i64),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Relocation {
+    // Offset in the code of the bytes to fix
+    offset: u32,
+    part: Part,
+    target: Target,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Body {
+    Empty,
+    Instruction {
+        mnemonic: String,
+        operands: Vec<Operand>,
+        length: u32,
+    },
+    Data {
+        word: bool,
+        items: Vec<Operand>,
+    },
+    Space(u32),
+    Include(String),
+    // EQU, its value for the listing
+    Equate(i64),
+}
+
+impl Body {
+    fn length(&self) -> u32 {
+        match self {
+            Body::Instruction { length, .. } => *length,
+            Body::Data { word, items } => items
+                .iter()
+                .map(|item| match &item.kind {
+                    OperandKind::Text(text) => u32::try_from(text.chars().count()).unwrap_or(0),
+                    _ if *word => 2,
+                    _ => 1,
+                })
+                .sum(),
+            Body::Space(length) => *length,
+            Body::Empty | Body::Include(_) | Body::Equate(_) => 0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Source {
+    file: String,
+    number: usize,
+    text: String,
+}
+
+struct Statement {
+    source: usize,
+    address: u32,
+    labelled: bool,
+    body: Body,
+}
+
+// Operands as an opcode sees them: its mode, the address of an (ab) form and
+// the immediate value
+struct Shape<'o> {
+    mode: Mode,
+    address: Option<&'o Expression>,
+    value: Option<&'o Expression>,
+}
+
+impl<'o> Shape<'o> {
+    fn of(mnemonic: &str, operands: &'o [Operand]) -> Option<Shape<'o>> {
+        let first = |operand: &'o Operand| match &operand.kind {
+            OperandKind::Register(register) => Some((Mode::Register(*register), None)),
+            OperandKind::Indirect(register) => Some((Mode::Indirect(*register), None)),
+            OperandKind::Me1Indirect(register) => Some((Mode::Me1Indirect(*register), None)),
+            OperandKind::Absolute(address) => Some((Mode::Absolute, Some(address))),
+            OperandKind::Me1Absolute(address) => Some((Mode::Me1Absolute, Some(address))),
+            OperandKind::Value(_) | OperandKind::Text(_) => None,
+        };
+        let value = |operand: &'o Operand| match &operand.kind {
+            OperandKind::Value(value) => Some(value),
+            _ => None,
+        };
+        let (mode, address, value) = match operands {
+            [] => (Mode::Implied, None, None),
+            [operand] => match first(operand) {
+                // The vector of VEJ (n) is written like an address
+                Some((Mode::Absolute, address)) if mnemonic == "VEJ" => {
+                    (Mode::Vector, None, address)
+                }
+                Some((mode, address)) => (mode, address, None),
+                None => (Mode::Implied, None, Some(value(operand)?)),
+            },
+            [operand, immediate] => {
+                let (mode, address) = first(operand)?;
+                (mode, address, Some(value(immediate)?))
+            }
+            _ => return None,
+        };
+        Some(Shape {
+            mode,
+            address,
+            value,
+        })
+    }
+
+    fn candidates(&self, mnemonic: &str) -> Vec<&'static Opcode> {
+        OPCODES
+            .iter()
+            .filter(|opcode| {
+                opcode.mnemonic == mnemonic
+                    && opcode.mode == self.mode
+                    && (opcode.mode == Mode::Vector
+                        || (opcode.immediate == Immediate::None) == self.value.is_none())
+            })
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Listed {
+    source: Source,
+    address: u32,
+    // The address, or the value of an EQU
+    column: String,
+    bytes: Vec<u8>,
+}
+
+// Code assembled for its origin, with what is needed to move and link it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Assembly {
+    origin: u32,
+    bytes: Vec<u8>,
+    symbols: BTreeMap<String, Value>,
+    relocations: Vec<Relocation>,
+    listing: Vec<Listed>,
+}
+
+impl Assembly {
+    pub fn origin(&self) -> u32 {
+        self.origin
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    // EXTERN symbols no symbol file gave, left for the linker
+    pub fn externals(&self) -> BTreeSet<&str> {
+        self.relocations
+            .iter()
+            .filter_map(|relocation| match &relocation.target {
+                Target::External(name, _) => Some(name.as_str()),
+                Target::Code(_) => None,
+            })
+            .collect()
+    }
+
+    // Addresses and bytes next to the source
+    pub fn listing(&self) -> String {
+        let mut lines = Vec::new();
+        let mut file = None;
+        for listed in &self.listing {
+            if file != Some(&listed.source.file) {
+                file = Some(&listed.source.file);
+                lines.push(format!("; {}", listed.source.file));
+            }
+            let mut chunks = listed.bytes.chunks(4);
+            let line = format!(
+                "{:<5} {:<12}{:>5}  {}",
+                listed.column,
+                chunks.next().map(hex).unwrap_or_default(),
+                listed.source.number,
+                listed.source.text
+            );
+            lines.push(line.trim_end().to_owned());
+            for (index, chunk) in (1..).zip(chunks) {
+                lines.push(format!(
+                    "{:04X}  {}",
+                    listed.address + 4 * index,
+                    hex(chunk)
+                ));
+            }
+        }
+        format!("{}\n", lines.join("\n"))
+    }
+
+    // NAME EQU &ADDR for each label and EQU, which read_symbols reads back
+    pub fn symbol_file(&self) -> String {
+        self.symbols
+            .iter()
+            .filter_map(|(name, value)| match value.base {
+                Base::External(_) => None,
+                _ if (0..=0xFFFF).contains(&value.number) => {
+                    Some(format!("{} EQU &{:04X}\n", name, value.number))
+                }
+                _ => Some(format!("{} EQU {}\n", name, value.number)),
+            })
+            .collect()
+    }
+
+    // Relocatable object in text records, offsets in hex from the origin:
+    //   H name origin size
+    //   T offset bytes
+    //   R offset part target   bytes holding an address in the code
+    //   X offset part symbol addend   bytes holding an external symbol
+    //   S symbol offset        labels for other objects
+    // Parts are W for a word, H and L for its high and low byte
+    pub fn object(&self, name: &str) -> String {
+        let mut lines = vec![format!(
+            "H {} {:04X} {:04X}",
+            name,
+            self.origin,
+            self.bytes.len()
+        )];
+        for (index, chunk) in (0..).zip(self.bytes.chunks(16)) {
+            let bytes: String = chunk.iter().map(|byte| format!("{:02X}", byte)).collect();
+            lines.push(format!("T {:04X} {}", 16 * index, bytes));
+        }
+        for relocation in &self.relocations {
+            lines.push(match &relocation.target {
+                Target::Code(offset) => {
+                    format!(
+                        "R {:04X} {} {:04X}",
+                        relocation.offset, relocation.part, offset
+                    )
+                }
+                Target::External(symbol, addend) => format!(
+                    "X {:04X} {} {} {}",
+                    relocation.offset, relocation.part, symbol, addend
+                ),
+            });
+        }
+        for (symbol, value) in &self.symbols {
+            if value.base == Base::Relocatable {
+                lines.push(format!(
+                    "S {} {:04X}",
+                    symbol,
+                    value.number - i64::from(self.origin)
+                ));
+            }
+        }
+        format!("{}\n", lines.join("\n"))
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    let bytes: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+    bytes.join(" ")
+}
+
+fn low_byte(number: i64) -> u8 {
+    number.to_le_bytes()[0]
+}
+
+// Reads INCLUDE files by name
+type Loader<'a> = Box<dyn Fn(&str) -> std::io::Result<String> + 'a>;
+
+pub struct Assembler<'a> {
+    loader: Loader<'a>,
+    // Addresses EXTERN symbols resolve to, like the ROM's entry points
+    known: BTreeMap<String, u32>,
+}
+
+impl<'a> Assembler<'a> {
+    pub fn new(loader: impl Fn(&str) -> std::io::Result<String> + 'a) -> Self {
+        Assembler {
+            loader: Box::new(loader),
+            known: BTreeMap::new(),
+        }
+    }
+
+    pub fn define(&mut self, symbols: BTreeMap<String, u32>) {
+        self.known.extend(symbols);
+    }
+
+    pub fn assemble(&self, file: &str, source: &str) -> Result<Assembly, Vec<Error>> {
+        let mut pass = Pass {
+            assembler: self,
+            sources: Vec::new(),
+            statements: Vec::new(),
+            symbols: BTreeMap::new(),
+            errors: Vec::new(),
+            address: 0,
+        };
+        pass.read(file, source, 0);
+        let assembly = pass.emit();
+        if pass.errors.is_empty() {
+            Ok(assembly)
+        } else {
+            Err(pass.errors)
+        }
+    }
+}
+
+// Reads a symbol file of NAME EQU value lines, like Assembly::symbol_file
+// writes
+pub fn read_symbols(file: &str, text: &str) -> Result<BTreeMap<String, u32>, Vec<Error>> {
+    let assembler = Assembler::new(|_| {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            "symbol files cannot INCLUDE",
+        ))
+    });
+    let assembly = assembler.assemble(file, text)?;
+    Ok(assembly
+        .symbols
+        .into_iter()
+        .filter(|(_, value)| value.base == Base::Absolute)
+        .filter_map(|(name, value)| Some((name, u32::try_from(value.number).ok()?)))
+        .collect())
+}
+
+struct Pass<'p, 'a> {
+    assembler: &'p Assembler<'a>,
+    sources: Vec<Source>,
+    statements: Vec<Statement>,
+    symbols: BTreeMap<String, Value>,
+    errors: Vec<Error>,
+    address: u32,
+}
+
+impl Pass<'_, '_> {
+    fn error(&mut self, source: usize, (kind, span): Failure) {
+        let source = &self.sources[source];
+        self.errors.push(Error {
+            kind,
+            file: source.file.clone(),
+            line: source.number,
+            span,
+            text: source.text.clone(),
+        });
+    }
+
+    // First pass
+    fn read(&mut self, file: &str, text: &str, depth: usize) {
+        for (number, line) in (1..).zip(text.lines()) {
+            let source = self.sources.len();
+            self.sources.push(Source {
+                file: file.to_owned(),
+                number,
+                text: line.to_owned(),
+            });
+            let address = self.address;
+            let mut labelled = false;
+            let body = Cursor::new(line).line().and_then(|line| {
+                labelled = line.label.is_some();
+                self.declare(line)
+            });
+            let body = match body {
+                Ok(body) => body,
+                Err(failure) => {
+                    self.error(source, failure);
+                    Body::Empty
+                }
+            };
+            let end = u64::from(address) + u64::from(body.length());
+            if end > 0x10000 {
+                self.error(
+                    source,
+                    (
+                        ErrorKind::AddressOutOfRange(i64::try_from(end).unwrap_or(i64::MAX)),
+                        0..line.chars().count(),
+                    ),
+                );
+            } else {
+                self.address += body.length();
+            }
+            let include = match &body {
+                Body::Include(name) => Some(name.clone()),
+                _ => None,
+            };
+            self.statements.push(Statement {
+                source,
+                address,
+                labelled,
+                body,
+            });
+
+            if let Some(name) = include {
+                let span = 0..line.chars().count();
+                if depth == MAX_INCLUDE_DEPTH {
+                    self.error(source, (ErrorKind::IncludeDepth, span));
+                    continue;
+                }
+                match (self.assembler.loader)(&name) {
+                    Ok(included) => self.read(&name, &included, depth + 1),
+                    Err(error) => {
+                        self.error(source, (ErrorKind::IncludeFailed(error.to_string()), span));
+                    }
+                }
+            }
+        }
+    }
+
+    fn define(&mut self, name: String, value: Value, span: Range<usize>) -> Result<(), Failure> {
+        if self.symbols.contains_key(&name) {
+            return Err((ErrorKind::DuplicateSymbol(name), span));
+        }
+        self.symbols.insert(name, value);
+        Ok(())
+    }
+
+    fn declare(&mut self, line: Line) -> Result<Body, Failure> {
+        let Line {
+            label,
+            operation,
+            operands,
+        } = line;
+        let Some((operation, span)) = operation else {
+            if let Some((label, span)) = label {
+                self.define(label, Value::address(self.address), span)?;
+            }
+            return Ok(Body::Empty);
+        };
+        let operation = operation.to_uppercase();
+        let whole = span.start..operands.last().map_or(span.end, |operand| operand.span.end);
+
+        if operation == "EQU" {
+            let (label, place) = label.ok_or((ErrorKind::MissingName, span))?;
+            let value = self.single(&operands, &whole)?;
+            let number = value.number;
+            self.define(label, value, place)?;
+            return Ok(Body::Equate(number));
+        }
+        if let Some((label, place)) = label {
+            self.define(label, Value::address(self.address), place)?;
+        }
+
+        match operation.as_str() {
+            "ORG" => {
+                let origin = self.single(&operands, &whole)?;
+                let origin = origin.number().map_err(|kind| (kind, whole.clone()))?;
+                self.address = u32::try_from(origin)
+                    .ok()
+                    .filter(|origin| *origin <= 0xFFFF)
+                    .ok_or((ErrorKind::AddressOutOfRange(origin), whole))?;
+                Ok(Body::Empty)
+            }
+            "DS" => {
+                let length = self.single(&operands, &whole)?;
+                let length = length.number().map_err(|kind| (kind, whole.clone()))?;
+                let length = u32::try_from(length)
+                    .ok()
+                    .ok_or((ErrorKind::ValueOutOfRange(length), whole))?;
+                Ok(Body::Space(length))
+            }
+            "DB" | "DW" => {
+                if operands.is_empty() {
+                    return Err((ErrorKind::ExpectedOperand, span));
+                }
+                Ok(Body::Data {
+                    word: operation == "DW",
+                    items: operands,
+                })
+            }
+            "INCLUDE" => match operands.as_slice() {
+                [Operand {
+                    kind: OperandKind::Text(name),
+                    ..
+                }] => Ok(Body::Include(name.clone())),
+                _ => Err((ErrorKind::InvalidOperands(operation), whole)),
+            },
+            "EXTERN" => {
+                for operand in operands {
+                    let OperandKind::Value(Expression::Symbol(name)) = operand.kind else {
+                        return Err((ErrorKind::InvalidOperands(operation), operand.span));
+                    };
+                    let value = match self.assembler.known.get(&name) {
+                        Some(address) => Value::absolute(i64::from(*address)),
+                        None => Value {
+                            number: 0,
+                            base: Base::External(name.clone()),
+                            part: Part::Word,
+                        },
+                    };
+                    self.define(name, value, operand.span)?;
+                }
+                Ok(Body::Empty)
+            }
+            _ => {
+                let shape = Shape::of(&operation, &operands).ok_or_else(|| {
+                    (ErrorKind::InvalidOperands(operation.clone()), whole.clone())
+                })?;
+                let length = match shape.candidates(&operation).first() {
+                    Some(opcode) => opcode.length(),
+                    None if OPCODES.iter().any(|opcode| opcode.mnemonic == operation) => {
+                        return Err((ErrorKind::InvalidOperands(operation), whole));
+                    }
+                    None => return Err((ErrorKind::UnknownInstruction(operation), span)),
+                };
+                Ok(Body::Instruction {
+                    mnemonic: operation,
+                    operands,
+                    length,
+                })
+            }
+        }
+    }
+
+    // Directives taking one value, evaluated in the first pass
+    fn single(&self, operands: &[Operand], whole: &Range<usize>) -> Result<Value, Failure> {
+        match operands {
+            [Operand {
+                kind: OperandKind::Value(expression),
+                span,
+            }] => self
+                .evaluate(expression, self.address)
+                .map_err(|kind| (kind, span.clone())),
+            [] => Err((ErrorKind::ExpectedOperand, whole.clone())),
+            [operand, ..] => Err((ErrorKind::ExpectedExpression, operand.span.clone())),
+        }
+    }
+
+    fn evaluate(&self, expression: &Expression, here: u32) -> Result<Value, ErrorKind> {
+        match expression {
+            Expression::Number(number) => Ok(Value::absolute(*number)),
+            Expression::Here => Ok(Value::address(here)),
+            Expression::Symbol(name) => self
+                .symbols
+                .get(name)
+                .cloned()
+                .ok_or_else(|| ErrorKind::UndefinedSymbol(name.clone())),
+            Expression::Negate(operand) => {
+                let number = self.evaluate(operand, here)?.number()?;
+                Ok(Value::absolute(
+                    number.checked_neg().ok_or(ErrorKind::Overflow)?,
+                ))
+            }
+            Expression::Binary(op, left, right) => {
+                let left = self.evaluate(left, here)?;
+                let right = self.evaluate(right, here)?;
+                combine(*op, left, right)
+            }
+            Expression::High(operand) | Expression::Low(operand) => {
+                let value = self.evaluate(operand, here)?;
+                let high = matches!(expression, Expression::High(_));
+                match (&value.base, value.part) {
+                    (Base::Absolute, _) if high => {
+                        Ok(Value::absolute(i64::from(low_byte(value.number >> 8))))
+                    }
+                    (Base::Absolute, _) => Ok(Value::absolute(i64::from(low_byte(value.number)))),
+                    (_, Part::Word) => Ok(Value {
+                        part: if high { Part::High } else { Part::Low },
+                        ..value
+                    }),
+                    _ => Err(ErrorKind::Relocation),
+                }
+            }
+        }
+    }
+
+    // Second pass
+    fn emit(&mut self) -> Assembly {
+        let origin = self
+            .statements
+            .iter()
+            .filter(|statement| statement.body.length() > 0)
+            .map(|statement| statement.address)
+            .min()
+            .unwrap_or(0);
+        let mut assembly = Assembly {
+            origin,
+            bytes: Vec::new(),
+            symbols: BTreeMap::new(),
+            relocations: Vec::new(),
+            listing: Vec::new(),
+        };
+
+        let statements = std::mem::take(&mut self.statements);
+        for statement in &statements {
+            let mut bytes = Vec::new();
+            let mut relocations = Vec::new();
+            if let Err(failure) = self.encode(statement, &mut bytes, &mut relocations) {
+                self.error(statement.source, failure);
+            }
+            for mut relocation in relocations {
+                relocation.offset -= origin;
+                if let Target::Code(address) = &mut relocation.target {
+                    *address -= i64::from(origin);
+                }
+                assembly.relocations.push(relocation);
+            }
+
+            // Keeps the layout of the first pass after an error
+            let length = usize::try_from(statement.body.length()).unwrap_or(0);
+            bytes.resize(length, 0);
+            if length > 0 {
+                let start = usize::try_from(statement.address - origin).unwrap_or(0);
+                let end = start + length;
+                if assembly.bytes.len() < end {
+                    assembly.bytes.resize(end, 0);
+                }
+                assembly.bytes[start..end].copy_from_slice(&bytes);
+            }
+            let column = match statement.body {
+                Body::Equate(value) => format!("={:04X}", value),
+                Body::Space(_) => {
+                    bytes.clear();
+                    format!("{:04X}", statement.address)
+                }
+                _ if statement.labelled || !bytes.is_empty() => {
+                    format!("{:04X}", statement.address)
+                }
+                _ => String::new(),
+            };
+            assembly.listing.push(Listed {
+                source: self.sources[statement.source].clone(),
+                address: statement.address,
+                column,
+                bytes,
+            });
+        }
+        assembly.symbols = self.symbols.clone();
+        assembly
+    }
+
+    fn encode(
+        &self,
+        statement: &Statement,
+        bytes: &mut Vec<u8>,
+        relocations: &mut Vec<Relocation>,
+    ) -> Result<(), Failure> {
+        let here = statement.address;
+        match &statement.body {
+            Body::Empty | Body::Space(_) | Body::Include(_) | Body::Equate(_) => Ok(()),
+            Body::Data { word, items } => {
+                for item in items {
+                    let fail = |kind| (kind, item.span.clone());
+                    match &item.kind {
+                        OperandKind::Text(text) if !word => {
+                            for c in text.chars() {
+                                let byte = u8::try_from(u32::from(c))
+                                    .ok()
+                                    .ok_or_else(|| fail(ErrorKind::UnexpectedCharacter(c)))?;
+                                bytes.push(byte);
+                            }
+                        }
+                        OperandKind::Value(expression) => {
+                            let value = self.evaluate(expression, here).map_err(fail)?;
+                            let offset = here + u32::try_from(bytes.len()).unwrap_or(0);
+                            let part = if *word { Part::Word } else { Part::Low };
+                            put(value, part, offset, bytes, relocations).map_err(fail)?;
+                        }
+                        _ => {
+                            let directive = if *word { "DW" } else { "DB" };
+                            return Err(fail(ErrorKind::InvalidOperands(directive.to_owned())));
+                        }
+                    }
+                }
+                Ok(())
+            }
+            Body::Instruction {
+                mnemonic,
+                operands,
+                length,
+            } => {
+                let whole = operands.first().map_or(0..0, |first| {
+                    first.span.start..operands.last().map_or(first.span.end, |last| last.span.end)
+                });
+                let fail = |kind| (kind, whole.clone());
+                let shape = Shape::of(mnemonic, operands)
+                    .ok_or_else(|| fail(ErrorKind::InvalidOperands(mnemonic.clone())))?;
+                let candidates = shape.candidates(mnemonic);
+                let value = shape
+                    .value
+                    .map(|value| self.evaluate(value, here))
+                    .transpose()
+                    .map_err(fail)?;
+
+                let opcode = match (candidates[0].immediate, &value) {
+                    (Immediate::Forward | Immediate::Backward, Some(target)) => {
+                        if let Base::External(_) = target.base {
+                            return Err(fail(ErrorKind::Relocation));
+                        }
+                        let distance = target.number - i64::from(here + length);
+                        let direction = if distance < 0 {
+                            Immediate::Backward
+                        } else {
+                            Immediate::Forward
+                        };
+                        candidates
+                            .iter()
+                            .find(|opcode| opcode.immediate == direction)
+                            .filter(|_| distance.abs() <= 0xFF)
+                            .ok_or(fail(ErrorKind::BranchOutOfRange(distance)))?
+                    }
+                    (_, Some(vector)) if shape.mode == Mode::Vector => {
+                        let vector = vector.number().map_err(fail)?;
+                        candidates
+                            .iter()
+                            .find(|opcode| i64::from(opcode.code) == vector)
+                            .ok_or(fail(ErrorKind::InvalidVector(vector)))?
+                    }
+                    _ => candidates[0],
+                };
+
+                if opcode.prefixed() {
+                    bytes.push(0xFD);
+                }
+                bytes.push(low_byte(i64::from(opcode.code)));
+                if let Some(address) = shape.address {
+                    let address = self.evaluate(address, here).map_err(fail)?;
+                    let offset = here + u32::try_from(bytes.len()).unwrap_or(0);
+                    put(address, Part::Word, offset, bytes, relocations).map_err(fail)?;
+                }
+                if let Some(value) = value {
+                    let offset = here + u32::try_from(bytes.len()).unwrap_or(0);
+                    match opcode.immediate {
+                        Immediate::Forward | Immediate::Backward => {
+                            let distance = value.number - i64::from(here + length);
+                            bytes.push(low_byte(distance.abs()));
+                        }
+                        Immediate::Word => {
+                            put(value, Part::Word, offset, bytes, relocations).map_err(fail)?;
+                        }
+                        Immediate::Byte | Immediate::None => {
+                            put(value, Part::Low, offset, bytes, relocations).map_err(fail)?;
+                        }
+                    }
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+fn combine(op: Operator, left: Value, right: Value) -> Result<Value, ErrorKind> {
+    if left.part != Part::Word || right.part != Part::Word {
+        return Err(ErrorKind::Relocation);
+    }
+    let number = match op {
+        Operator::Add => left.number.checked_add(right.number),
+        Operator::Sub => left.number.checked_sub(right.number),
+        Operator::Mul => left.number.checked_mul(right.number),
+    }
+    .ok_or(ErrorKind::Overflow)?;
+    let base = match (op, left.base, right.base) {
+        (_, Base::Absolute, Base::Absolute) => Base::Absolute,
+        (Operator::Add | Operator::Sub, base, Base::Absolute) => base,
+        (Operator::Add, Base::Absolute, base) => base,
+        // The distance between two addresses stays the same when they move
+        (Operator::Sub, Base::Relocatable, Base::Relocatable) => Base::Absolute,
+        _ => return Err(ErrorKind::Relocation),
+    };
+    Ok(Value {
+        number,
+        base,
+        part: Part::Word,
+    })
+}
+
+// Emits a word, or a byte for the given part, with the relocation its
+// value needs
+fn put(
+    value: Value,
+    part: Part,
+    offset: u32,
+    bytes: &mut Vec<u8>,
+    relocations: &mut Vec<Relocation>,
+) -> Result<(), ErrorKind> {
+    let (part, number) = match (&value.base, part, value.part) {
+        (Base::Absolute, Part::Word, _) if (-0x8000..=0xFFFF).contains(&value.number) => {
+            (Part::Word, value.number)
+        }
+        (Base::Absolute, _, _) if part != Part::Word && (-0x80..=0xFF).contains(&value.number) => {
+            (Part::Low, value.number)
+        }
+        (Base::Absolute, _, _) => return Err(ErrorKind::ValueOutOfRange(value.number)),
+        (_, Part::Word, Part::Word) => (Part::Word, value.number),
+        (_, Part::Low, Part::High) => (Part::High, value.number >> 8),
+        (_, Part::Low, Part::Low) => (Part::Low, value.number),
+        _ => return Err(ErrorKind::Relocation),
+    };
+
+    match value.base {
+        Base::Absolute => {}
+        Base::Relocatable => relocations.push(Relocation {
+            offset,
+            part,
+            target: Target::Code(value.number),
+        }),
+        Base::External(name) => relocations.push(Relocation {
+            offset,
+            part,
+            target: Target::External(name, value.number),
+        }),
+    }
+    if part == Part::Word {
+        bytes.push(low_byte(number >> 8));
+    }
+    bytes.push(low_byte(number));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assemble(source: &str) -> Result<Assembly, Vec<Error>> {
+        Assembler::new(|name| match name {
+            "constants.asm" => Ok("SCREEN EQU &7600\nCOLUMNS EQU 26\n".to_owned()),
+            _ => Err(std::io::Error::from(std::io::ErrorKind::NotFound)),
+        })
+        .assemble("test.asm", source)
+    }
+
+    #[test]
+    fn instructions() {
+        let source = "\
+    ORG &4000
+START:
+    LDI A,&12
+    STA (&7800)
+    LDA #(X)
+    ADI #(&1234),5
+    LDX S
+    STX P
+    ANI (Y),&0F
+    LDI S,&7FFF
+    VEJ (&C0)
+    SJP START
+";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(assembly.origin, 0x4000);
+        assert_eq!(
+            assembly.bytes,
+            [
+                0xB5, 0x12, 0xAE, 0x78, 0x00, 0xFD, 0x05, 0xFD, 0xEF, 0x12, 0x34, 0x05, 0xFD, 0x48,
+                0xFD, 0x5E, 0x59, 0x0F, 0xAA, 0x7F, 0xFF, 0xC0, 0xBE, 0x40, 0x00
+            ]
+        );
+    }
+
+    #[test]
+    fn branches_pick_their_direction() {
+        let source = "\
+    ORG &4000
+BACK:
+    NOP
+    BZS BACK
+    BCH AHEAD
+    NOP
+AHEAD:
+    LOP UL,BACK
+";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(
+            assembly.bytes,
+            [0x38, 0x9B, 0x03, 0x8E, 0x01, 0x38, 0x88, 0x08]
+        );
+
+        let errors = assemble("    ORG &4000\nHERE:\n    DS 300\n    BZR HERE\n").unwrap_err();
+        assert_eq!(errors[0].kind, ErrorKind::BranchOutOfRange(-302));
+    }
+
+    #[test]
+    fn directives() {
+        let source = "\
+    INCLUDE \"constants.asm\"
+SIZE EQU COLUMNS*2+1
+    ORG SCREEN-SIZE
+TABLE: DB 1,\"AB;C\",-1 ; a comment
+    DW TABLE,&1234
+    DS 2
+END:
+    LDI XH,HIGH(END)
+    LDI XL,LOW(END)
+    DW END-TABLE, $
+";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(assembly.origin, 0x75CB);
+        assert_eq!(
+            assembly.bytes,
+            [
+                0x01, b'A', b'B', b';', b'C', 0xFF, 0x75, 0xCB, 0x12, 0x34, 0, 0, 0x48, 0x75, 0x4A,
+                0xD7, 0x00, 0x0C, 0x75, 0xDB
+            ]
+        );
+        assert!(assembly.symbol_file().contains("END EQU &75D7\n"));
+        let listing = assembly.listing();
+        assert!(listing.contains("=0035                 2  SIZE EQU COLUMNS*2+1\n"));
+        assert!(listing.contains(
+            "75CB  01 41 42 3B     4  TABLE: DB 1,\"AB;C\",-1 ; a comment\n75CF  43 FF\n"
+        ));
+    }
+
+    #[test]
+    fn errors_point_at_the_operand() {
+        let source = "\
+    ORG &4000
+    LDA (MISSING)
+    FOO A
+    LDI A,300
+    STA #(A)
+";
+        let errors = assemble(source).unwrap_err();
+        let found: Vec<_> = errors
+            .into_iter()
+            .map(|error| (error.kind, error.line, error.span))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (ErrorKind::UnknownInstruction("FOO".to_owned()), 3, 4..7),
+                (ErrorKind::InvalidOperands("STA".to_owned()), 5, 4..12),
+                (ErrorKind::UndefinedSymbol("MISSING".to_owned()), 2, 8..17),
+                (ErrorKind::ValueOutOfRange(300), 4, 8..13),
+            ]
+        );
+    }
+
+    #[test]
+    fn relocatable_object() {
+        let source = "\
+    EXTERN ROM_ADD, ROM_ARX
+    ORG &4000
+START:
+    SJP ROM_ADD
+    LDI YH,HIGH(ROM_ARX+2)
+    JMP START
+    LDI A,LOW(DONE)
+DONE:
+    RTN
+";
+        let assembly = assemble(source).unwrap();
+        assert_eq!(assembly.externals(), BTreeSet::from(["ROM_ADD", "ROM_ARX"]));
+        assert_eq!(
+            assembly.object("test"),
+            "\
+H test 4000 000B
+T 0000 BE00005800BA4000B50A9A
+X 0001 W ROM_ADD 0
+X 0004 H ROM_ARX 2
+R 0006 W 0000
+R 0009 L 000A
+S DONE 000A
+S START 0000
+"
+        );
+
+        let mut assembler = Assembler::new(|_| Err(std::io::ErrorKind::NotFound.into()));
+        assembler
+            .define(read_symbols("rom.sym", "ROM_ADD EQU &F6B9\nROM_ARX EQU &7A00\n").unwrap());
+        let linked = assembler.assemble("test.asm", source).unwrap();
+        assert!(linked.externals().is_empty());
+        assert_eq!(linked.bytes[..5], [0xBE, 0xF6, 0xB9, 0x58, 0x7A]);
+    }
+}
@@ -0,0 +1,1017 @@
+// LH5801 CPU core
+//
+// Runs one instruction at a time against a Bus, which holds the memory of
+// both banks and the I/O ports. Instructions are decoded with the opcode
+// table of the assembler and take its cycles, plus the extra cycles of a
+// taken branch, loop or vector call. The 9-bit timer counts along with the
+// cycles and raises its interrupt when it comes round to &1FF.
+
+use super::opcode::{Immediate, Mode, Opcode, Register, OPCODES};
+use crate::machine::ME1;
+
+// Flags in T
+pub const HALF_CARRY: u8 = 0x10;
+pub const OVERFLOW: u8 = 0x08;
+pub const ZERO: u8 = 0x04;
+pub const INTERRUPTS: u8 = 0x02;
+pub const CARRY: u8 = 0x01;
+
+// Added to the cycles of the table when the jump is taken
+const BRANCH_TAKEN: u32 = 2;
+const LOOP_TAKEN: u32 = 3;
+const VECTOR_TAKEN: u32 = 13;
+
+// Pushing T and P and loading the vector
+const INTERRUPT_CYCLES: u32 = 14;
+// Passed at a time while HLT waits for an interrupt
+const HALT_CYCLES: u32 = 2;
+
+// The timer counts at 31.25 kHz from the 1.3 MHz clock
+const TIMER_DIVIDER: u32 = 42;
+const TIMER_MASK: u16 = 0x1FF;
+
+const RESET_VECTOR: u16 = 0xFFFE;
+// VEJ and VMJ vectors are at &FF00 plus the vector number
+const VECTOR_PAGE: u16 = 0xFF00;
+
+// Memory and I/O seen by the CPU. Addresses are 17 bits, the ME1 bank above
+// &FFFF
+pub trait Bus {
+    fn read(&mut self, address: u32) -> u8;
+    fn write(&mut self, address: u32, value: u8);
+    // IN0-IN7 read by ITA, the keyboard on the PC-1500
+    fn input(&mut self) -> u8 {
+        0
+    }
+    // Port written by ATP
+    fn output(&mut self, _value: u8) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interrupt {
+    // IR0, taken even with interrupts disabled
+    NonMaskable,
+    // IR1, raised by the timer
+    Timer,
+    // IR2, the keyboard and peripherals on the PC-1500
+    Maskable,
+}
+
+impl Interrupt {
+    // In order of priority
+    const ALL: [Interrupt; 3] = [
+        Interrupt::NonMaskable,
+        Interrupt::Timer,
+        Interrupt::Maskable,
+    ];
+
+    fn index(self) -> usize {
+        match self {
+            Interrupt::NonMaskable => 0,
+            Interrupt::Timer => 1,
+            Interrupt::Maskable => 2,
+        }
+    }
+
+    fn vector(self) -> u16 {
+        match self {
+            Interrupt::NonMaskable => 0xFFFC,
+            Interrupt::Timer => 0xFFFA,
+            Interrupt::Maskable => 0xFFF8,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fault {
+    // The bytes at the address are no instruction
+    IllegalOpcode { address: u16, code: u16 },
+}
+
+impl std::fmt::Display for Fault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fault::IllegalOpcode { address, code } if *code > 0xFF => {
+                write!(
+                    f,
+                    "Illegal opcode &FD &{:02X} at &{:04X}",
+                    code & 0xFF,
+                    address
+                )
+            }
+            Fault::IllegalOpcode { address, code } => {
+                write!(f, "Illegal opcode &{:02X} at &{:04X}", code, address)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Fault {}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Registers {
+    pub a: u8,
+    pub x: u16,
+    pub y: u16,
+    pub u: u16,
+    pub s: u16,
+    pub p: u16,
+    // H, V, Z, IE and C flags
+    pub t: u8,
+}
+
+impl std::fmt::Display for Registers {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let flags: String = [
+            (HALF_CARRY, 'H'),
+            (OVERFLOW, 'V'),
+            (ZERO, 'Z'),
+            (INTERRUPTS, 'I'),
+            (CARRY, 'C'),
+        ]
+        .iter()
+        .map(|(flag, name)| if self.t & flag == 0 { '-' } else { *name })
+        .collect();
+        write!(
+            f,
+            "A=&{:02X} X=&{:04X} Y=&{:04X} U=&{:04X} S=&{:04X} P=&{:04X} T={}",
+            self.a, self.x, self.y, self.u, self.s, self.p, flags
+        )
+    }
+}
+
+// Where an instruction takes its operand from
+#[derive(Debug, Clone, Copy)]
+enum Operand {
+    None,
+    Register(Register),
+    Memory(u32),
+}
+
+pub struct Cpu {
+    registers: Registers,
+    // Opcodes by code, those with the &FD prefix in the upper half
+    decode: Vec<Option<&'static Opcode>>,
+    timer: u16,
+    // Cycles towards the next count of the timer
+    divider: u32,
+    // Flip-flops driving the PU and PV outputs and the display
+    pu: bool,
+    pv: bool,
+    display: bool,
+    halted: bool,
+    pending: [bool; 3],
+    cycles: u64,
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        let mut decode = vec![None; 0x200];
+        for opcode in OPCODES {
+            decode[usize::from(opcode.code & 0xFF) + usize::from(opcode.prefixed()) * 0x100] =
+                Some(opcode);
+        }
+        Cpu {
+            registers: Registers::default(),
+            decode,
+            timer: 0,
+            divider: 0,
+            pu: false,
+            pv: false,
+            display: false,
+            halted: false,
+            pending: [false; 3],
+            cycles: 0,
+        }
+    }
+
+    // Clears the state and starts at the reset vector
+    pub fn reset(&mut self, bus: &mut impl Bus) {
+        self.registers = Registers::default();
+        self.timer = 0;
+        self.divider = 0;
+        self.pu = false;
+        self.pv = false;
+        self.display = false;
+        self.halted = false;
+        self.pending = [false; 3];
+        self.registers.p = read_word(bus, RESET_VECTOR);
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn registers_mut(&mut self) -> &mut Registers {
+        &mut self.registers
+    }
+
+    // Cycles run since the CPU was made
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Waiting in HLT for an interrupt
+    pub fn halted(&self) -> bool {
+        self.halted
+    }
+
+    pub fn interrupts_enabled(&self) -> bool {
+        self.flag(INTERRUPTS)
+    }
+
+    // Latches the interrupt until the CPU takes it
+    pub fn interrupt(&mut self, interrupt: Interrupt) {
+        self.pending[interrupt.index()] = true;
+    }
+
+    // Pushes P and jumps, like SJP
+    pub fn call(&mut self, bus: &mut impl Bus, target: u16) {
+        self.push_word(bus, self.registers.p);
+        self.registers.p = target;
+    }
+
+    // Pops P, like RTN
+    pub fn ret(&mut self, bus: &mut impl Bus) {
+        self.registers.p = self.pop_word(bus);
+    }
+
+    // Takes a pending interrupt or runs the next instruction, giving the
+    // cycles it took
+    pub fn step(&mut self, bus: &mut impl Bus) -> Result<u32, Fault> {
+        let cycles = if let Some(interrupt) = self.accept() {
+            self.enter(bus, interrupt);
+            INTERRUPT_CYCLES
+        } else if self.halted {
+            HALT_CYCLES
+        } else {
+            self.execute(bus)?
+        };
+        self.cycles += u64::from(cycles);
+        self.count(cycles);
+        Ok(cycles)
+    }
+
+    fn accept(&mut self) -> Option<Interrupt> {
+        let enabled = self.flag(INTERRUPTS);
+        let interrupt = Interrupt::ALL.into_iter().find(|interrupt| {
+            self.pending[interrupt.index()] && (enabled || *interrupt == Interrupt::NonMaskable)
+        })?;
+        self.pending[interrupt.index()] = false;
+        Some(interrupt)
+    }
+
+    fn enter(&mut self, bus: &mut impl Bus, interrupt: Interrupt) {
+        self.push(bus, self.registers.t);
+        self.set_flag(INTERRUPTS, false);
+        self.halted = false;
+        let target = read_word(bus, interrupt.vector());
+        self.call(bus, target);
+    }
+
+    fn count(&mut self, cycles: u32) {
+        self.divider += cycles;
+        while self.divider >= TIMER_DIVIDER {
+            self.divider -= TIMER_DIVIDER;
+            self.timer = (self.timer + 1) & TIMER_MASK;
+            if self.timer == TIMER_MASK {
+                self.interrupt(Interrupt::Timer);
+            }
+        }
+    }
+
+    fn fetch(&mut self, bus: &mut impl Bus) -> u8 {
+        let byte = bus.read(u32::from(self.registers.p));
+        self.registers.p = self.registers.p.wrapping_add(1);
+        byte
+    }
+
+    fn fetch_word(&mut self, bus: &mut impl Bus) -> u16 {
+        let high = self.fetch(bus);
+        u16::from(high) << 8 | u16::from(self.fetch(bus))
+    }
+
+    fn execute(&mut self, bus: &mut impl Bus) -> Result<u32, Fault> {
+        let address = self.registers.p;
+        let first = self.fetch(bus);
+        let code = if first == 0xFD {
+            0xFD00 | u16::from(self.fetch(bus))
+        } else {
+            u16::from(first)
+        };
+        let index = usize::from(code & 0xFF) + if code > 0xFF { 0x100 } else { 0 };
+        let Some(opcode) = self.decode[index] else {
+            return Err(Fault::IllegalOpcode { address, code });
+        };
+        let operand = match opcode.mode {
+            Mode::Implied | Mode::Vector => Operand::None,
+            Mode::Register(register) => Operand::Register(register),
+            Mode::Indirect(register) => Operand::Memory(u32::from(self.word(register))),
+            Mode::Me1Indirect(register) => Operand::Memory(ME1 | u32::from(self.word(register))),
+            Mode::Absolute => Operand::Memory(u32::from(self.fetch_word(bus))),
+            Mode::Me1Absolute => Operand::Memory(ME1 | u32::from(self.fetch_word(bus))),
+        };
+        let immediate = match opcode.immediate {
+            Immediate::None => 0,
+            Immediate::Word => self.fetch_word(bus),
+            Immediate::Byte | Immediate::Forward | Immediate::Backward => {
+                u16::from(self.fetch(bus))
+            }
+        };
+        match self.run(bus, opcode, operand, immediate) {
+            Some(extra) => Ok(u32::from(opcode.cycles) + extra),
+            None => Err(Fault::IllegalOpcode { address, code }),
+        }
+    }
+
+    // Gives the extra cycles of a taken jump, None for a mnemonic the core
+    // does not know
+    fn run(
+        &mut self,
+        bus: &mut impl Bus,
+        opcode: &Opcode,
+        operand: Operand,
+        immediate: u16,
+    ) -> Option<u32> {
+        let a = self.registers.a;
+        let carry = self.flag(CARRY);
+        // The 8-bit immediate forms
+        let byte = immediate.to_be_bytes()[1];
+        match opcode.mnemonic {
+            "ADC" => {
+                let value = self.value(bus, operand);
+                self.registers.a = self.add(a, value, carry);
+            }
+            "SBC" => {
+                let value = self.value(bus, operand);
+                self.registers.a = self.add(a, !value, carry);
+            }
+            "DCA" => {
+                let value = self.value(bus, operand);
+                self.registers.a = self.decimal(a.wrapping_add(0x66), value, carry);
+            }
+            "DCS" => {
+                let value = self.value(bus, operand);
+                self.registers.a = self.decimal(a, !value, carry);
+            }
+            "CPA" => {
+                let value = self.value(bus, operand);
+                self.compare(a, value);
+            }
+            "AND" | "ORA" | "EOR" | "LDA" => {
+                let value = self.value(bus, operand);
+                let result = logic(opcode.mnemonic, a, value);
+                self.registers.a = result;
+                self.set_zero(result);
+            }
+            "BIT" => {
+                let value = self.value(bus, operand);
+                self.set_zero(a & value);
+            }
+            "STA" => self.store(bus, operand, a),
+            "INC" | "DEC" => {
+                let up = opcode.mnemonic == "INC";
+                match operand {
+                    Operand::Register(register @ (Register::X | Register::Y | Register::U)) => {
+                        let word = self.word(register);
+                        let word = if up {
+                            word.wrapping_add(1)
+                        } else {
+                            word.wrapping_sub(1)
+                        };
+                        self.set_word(register, word);
+                    }
+                    _ => {
+                        let value = self.value(bus, operand);
+                        let result = self.add(value, if up { 1 } else { 0xFF }, false);
+                        self.store(bus, operand, result);
+                    }
+                }
+            }
+            "LDI" => match operand {
+                Operand::Register(Register::S) => self.registers.s = immediate,
+                Operand::Register(Register::A) => {
+                    self.registers.a = byte;
+                    self.set_zero(byte);
+                }
+                _ => self.store(bus, operand, byte),
+            },
+            "CPI" => {
+                let value = self.value(bus, operand);
+                self.compare(value, byte);
+            }
+            "ADI" | "SBI" | "ANI" | "ORI" | "EAI" | "BII" => {
+                // EAI only works on A
+                let operand = match operand {
+                    Operand::None => Operand::Register(Register::A),
+                    _ => operand,
+                };
+                let value = self.value(bus, operand);
+                let on_a = matches!(operand, Operand::Register(Register::A));
+                let result = match opcode.mnemonic {
+                    // Only ADI A takes the carry
+                    "ADI" => self.add(value, byte, on_a && carry),
+                    "SBI" => self.add(value, !byte, carry),
+                    "EAI" => value ^ byte,
+                    "ORI" => value | byte,
+                    _ => value & byte,
+                };
+                if !matches!(opcode.mnemonic, "ADI" | "SBI") {
+                    self.set_zero(result);
+                }
+                if opcode.mnemonic != "BII" {
+                    self.store(bus, operand, result);
+                }
+            }
+            "SIN" | "SDE" | "LIN" | "LDE" => {
+                let Operand::Register(register) = operand else {
+                    return None;
+                };
+                let address = self.word(register);
+                if opcode.mnemonic.starts_with('S') {
+                    bus.write(u32::from(address), a);
+                } else {
+                    let value = bus.read(u32::from(address));
+                    self.registers.a = value;
+                    self.set_zero(value);
+                }
+                let address = if opcode.mnemonic.ends_with("IN") {
+                    address.wrapping_add(1)
+                } else {
+                    address.wrapping_sub(1)
+                };
+                self.set_word(register, address);
+            }
+            "TIN" | "CIN" => {
+                let x = self.registers.x;
+                let value = bus.read(u32::from(x));
+                if opcode.mnemonic == "TIN" {
+                    let y = self.registers.y;
+                    bus.write(u32::from(y), value);
+                    self.registers.y = y.wrapping_add(1);
+                } else {
+                    self.compare(a, value);
+                }
+                self.registers.x = x.wrapping_add(1);
+            }
+            "BCH" | "BCR" | "BCS" | "BHR" | "BHS" | "BZR" | "BZS" | "BVR" | "BVS" => {
+                if self.condition(opcode.mnemonic) {
+                    self.branch(opcode.immediate, immediate);
+                    return Some(BRANCH_TAKEN);
+                }
+            }
+            "LOP" => {
+                let counter = self.registers.u.to_be_bytes()[1];
+                self.set_byte(Register::Ul, counter.wrapping_sub(1));
+                if counter != 0 {
+                    self.branch(opcode.immediate, immediate);
+                    return Some(LOOP_TAKEN);
+                }
+            }
+            "JMP" => self.registers.p = immediate,
+            "SJP" => self.call(bus, immediate),
+            "RTN" => self.ret(bus),
+            "RTI" => {
+                self.ret(bus);
+                self.registers.t = self.pop(bus);
+            }
+            "VEJ" | "VMJ" | "VCR" | "VCS" | "VHR" | "VHS" | "VZR" | "VZS" | "VVS" => {
+                let (taken, vector) = match opcode.mnemonic {
+                    "VEJ" => (true, opcode.code & 0xFF),
+                    "VMJ" => (true, immediate),
+                    _ => (self.condition(opcode.mnemonic), immediate),
+                };
+                let mut extra = 0;
+                if taken {
+                    let target = read_word(bus, VECTOR_PAGE | vector);
+                    self.call(bus, target);
+                    extra = if opcode.mnemonic.ends_with('J') {
+                        0
+                    } else {
+                        VECTOR_TAKEN
+                    };
+                }
+                // The device clears Z on every vector call
+                self.set_flag(ZERO, false);
+                return Some(extra);
+            }
+            "LDX" => {
+                let Operand::Register(register) = operand else {
+                    return None;
+                };
+                self.registers.x = self.word(register);
+            }
+            "STX" => {
+                let Operand::Register(register) = operand else {
+                    return None;
+                };
+                self.set_word(register, self.registers.x);
+            }
+            "PSH" => match operand {
+                Operand::Register(Register::A) => self.push(bus, a),
+                Operand::Register(register) => self.push_word(bus, self.word(register)),
+                _ => return None,
+            },
+            "POP" => match operand {
+                Operand::Register(Register::A) => {
+                    let value = self.pop(bus);
+                    self.registers.a = value;
+                    self.set_zero(value);
+                }
+                Operand::Register(register) => {
+                    let word = self.pop_word(bus);
+                    self.set_word(register, word);
+                }
+                _ => return None,
+            },
+            "ADR" => {
+                let Operand::Register(register) = operand else {
+                    return None;
+                };
+                let [high, low] = self.word(register).to_be_bytes();
+                let low = self.add(low, a, false);
+                let high = high.wrapping_add(u8::from(self.flag(CARRY)));
+                self.set_word(register, u16::from_be_bytes([high, low]));
+            }
+            "ROL" | "SHL" => {
+                let carry_in = u8::from(opcode.mnemonic == "ROL" && carry);
+                let result = a << 1 | carry_in;
+                self.shift(a, result, a & 0x80 != 0, a & 0x08 != 0);
+            }
+            "ROR" | "SHR" => {
+                let carry_in = if opcode.mnemonic == "ROR" && carry {
+                    0x80
+                } else {
+                    0
+                };
+                let result = a >> 1 | carry_in;
+                self.shift(a, result, a & 0x01 != 0, a & 0x10 != 0);
+            }
+            "DRL" | "DRR" => {
+                let value = self.value(bus, operand);
+                // Four bits move from A into memory and four out of it,
+                // while A takes the byte memory had
+                let rotated = if opcode.mnemonic == "DRL" {
+                    value << 4 | a >> 4
+                } else {
+                    a << 4 | value >> 4
+                };
+                self.store(bus, operand, rotated);
+                self.registers.a = value;
+            }
+            "AEX" => self.registers.a = a.rotate_left(4),
+            "ATT" => self.registers.t = a & 0x1F,
+            "TTA" => {
+                let flags = self.registers.t & 0x1F;
+                self.registers.a = flags;
+                self.set_zero(flags);
+            }
+            "ITA" => {
+                let value = bus.input();
+                self.registers.a = value;
+                self.set_zero(value);
+            }
+            "ATP" => bus.output(a),
+            "SEC" | "REC" => self.set_flag(CARRY, opcode.mnemonic == "SEC"),
+            "SIE" | "RIE" => self.set_flag(INTERRUPTS, opcode.mnemonic == "SIE"),
+            "SPU" | "RPU" => self.pu = opcode.mnemonic == "SPU",
+            "SPV" | "RPV" => self.pv = opcode.mnemonic == "SPV",
+            "SDP" | "RDP" => self.display = opcode.mnemonic == "SDP",
+            "AM0" | "AM1" => {
+                let high = if opcode.mnemonic == "AM1" { 0x100 } else { 0 };
+                self.timer = high | u16::from(a);
+            }
+            "CDV" => self.divider = 0,
+            "HLT" => self.halted = true,
+            // OFF resets the power flip-flop, which is outside the core
+            "NOP" | "OFF" => {}
+            _ => return None,
+        }
+        Some(0)
+    }
+
+    fn flag(&self, flag: u8) -> bool {
+        self.registers.t & flag != 0
+    }
+
+    fn set_flag(&mut self, flag: u8, set: bool) {
+        if set {
+            self.registers.t |= flag;
+        } else {
+            self.registers.t &= !flag;
+        }
+    }
+
+    fn set_zero(&mut self, value: u8) {
+        self.set_flag(ZERO, value == 0);
+    }
+
+    // Whether the condition of a conditional branch or vector call holds
+    fn condition(&self, mnemonic: &str) -> bool {
+        // BCH branches always, it is no condition on C
+        if mnemonic == "BCH" {
+            return true;
+        }
+        let flag = match mnemonic.get(1..2) {
+            Some("C") => CARRY,
+            Some("H") => HALF_CARRY,
+            Some("Z") => ZERO,
+            Some("V") => OVERFLOW,
+            _ => return true,
+        };
+        self.flag(flag) == mnemonic.ends_with('S')
+    }
+
+    fn branch(&mut self, direction: Immediate, offset: u16) {
+        let p = self.registers.p;
+        self.registers.p = if direction == Immediate::Backward {
+            p.wrapping_sub(offset)
+        } else {
+            p.wrapping_add(offset)
+        };
+    }
+
+    // Adds with the carry in, setting all four arithmetic flags
+    fn add(&mut self, left: u8, right: u8, carry: bool) -> u8 {
+        let carry = u16::from(carry);
+        let sum = u16::from(left) + u16::from(right) + carry;
+        let half = (u16::from(left & 0x0F) + u16::from(right & 0x0F) + carry) & 0x10 != 0;
+        let into_sign = (u16::from(left & 0x7F) + u16::from(right & 0x7F) + carry) & 0x80 != 0;
+        let out = sum & 0x100 != 0;
+        let result = sum.to_be_bytes()[1];
+        self.set_flag(CARRY, out);
+        self.set_flag(HALF_CARRY, half);
+        self.set_flag(OVERFLOW, out != into_sign);
+        self.set_zero(result);
+        result
+    }
+
+    // Sets the flags of left - right, the carry meaning no borrow
+    fn compare(&mut self, left: u8, right: u8) {
+        self.add(left, !right, true);
+    }
+
+    // Binary addition corrected to BCD by the carries out of each digit;
+    // DCA adds &66 to A first so that they happen
+    fn decimal(&mut self, left: u8, right: u8, carry: bool) -> u8 {
+        let sum = self.add(left, right, carry);
+        let correction = match (self.flag(CARRY), self.flag(HALF_CARRY)) {
+            (false, false) => 0x9A,
+            (false, true) => 0xA0,
+            (true, false) => 0xFA,
+            (true, true) => 0x00,
+        };
+        sum.wrapping_add(correction)
+    }
+
+    fn shift(&mut self, before: u8, result: u8, carry: bool, half: bool) {
+        self.registers.a = result;
+        self.set_flag(CARRY, carry);
+        self.set_flag(HALF_CARRY, half);
+        self.set_flag(OVERFLOW, (before ^ result) & 0x80 != 0);
+        self.set_zero(result);
+    }
+
+    fn byte(&self, register: Register) -> u8 {
+        let registers = &self.registers;
+        match register {
+            Register::A => registers.a,
+            Register::Xh => registers.x.to_be_bytes()[0],
+            Register::Xl => registers.x.to_be_bytes()[1],
+            Register::Yh => registers.y.to_be_bytes()[0],
+            Register::Yl => registers.y.to_be_bytes()[1],
+            Register::Uh => registers.u.to_be_bytes()[0],
+            Register::Ul => registers.u.to_be_bytes()[1],
+            Register::X | Register::Y | Register::U | Register::S | Register::P => {
+                unreachable!("{} is a 16-bit register", register)
+            }
+        }
+    }
+
+    fn set_byte(&mut self, register: Register, value: u8) {
+        let registers = &mut self.registers;
+        let (word, high) = match register {
+            Register::A => {
+                registers.a = value;
+                return;
+            }
+            Register::Xh => (&mut registers.x, true),
+            Register::Xl => (&mut registers.x, false),
+            Register::Yh => (&mut registers.y, true),
+            Register::Yl => (&mut registers.y, false),
+            Register::Uh => (&mut registers.u, true),
+            Register::Ul => (&mut registers.u, false),
+            Register::X | Register::Y | Register::U | Register::S | Register::P => {
+                unreachable!("{} is a 16-bit register", register)
+            }
+        };
+        let [old_high, old_low] = word.to_be_bytes();
+        *word = if high {
+            u16::from_be_bytes([value, old_low])
+        } else {
+            u16::from_be_bytes([old_high, value])
+        };
+    }
+
+    fn word(&self, register: Register) -> u16 {
+        let registers = &self.registers;
+        match register {
+            Register::X => registers.x,
+            Register::Y => registers.y,
+            Register::U => registers.u,
+            Register::S => registers.s,
+            Register::P => registers.p,
+            _ => unreachable!("{} is an 8-bit register", register),
+        }
+    }
+
+    fn set_word(&mut self, register: Register, value: u16) {
+        let registers = &mut self.registers;
+        match register {
+            Register::X => registers.x = value,
+            Register::Y => registers.y = value,
+            Register::U => registers.u = value,
+            Register::S => registers.s = value,
+            Register::P => registers.p = value,
+            _ => unreachable!("{} is an 8-bit register", register),
+        }
+    }
+
+    fn value(&self, bus: &mut impl Bus, operand: Operand) -> u8 {
+        match operand {
+            Operand::Register(register) => self.byte(register),
+            Operand::Memory(address) => bus.read(address),
+            Operand::None => self.registers.a,
+        }
+    }
+
+    fn store(&mut self, bus: &mut impl Bus, operand: Operand, value: u8) {
+        match operand {
+            Operand::Register(register) => self.set_byte(register, value),
+            Operand::Memory(address) => bus.write(address, value),
+            Operand::None => self.registers.a = value,
+        }
+    }
+
+    // The stack grows down in ME0, S pointing below the top byte
+    fn push(&mut self, bus: &mut impl Bus, value: u8) {
+        bus.write(u32::from(self.registers.s), value);
+        self.registers.s = self.registers.s.wrapping_sub(1);
+    }
+
+    fn pop(&mut self, bus: &mut impl Bus) -> u8 {
+        self.registers.s = self.registers.s.wrapping_add(1);
+        bus.read(u32::from(self.registers.s))
+    }
+
+    // Leaves the high byte first in memory
+    fn push_word(&mut self, bus: &mut impl Bus, value: u16) {
+        let [high, low] = value.to_be_bytes();
+        self.push(bus, low);
+        self.push(bus, high);
+    }
+
+    fn pop_word(&mut self, bus: &mut impl Bus) -> u16 {
+        let high = self.pop(bus);
+        u16::from_be_bytes([high, self.pop(bus)])
+    }
+}
+
+impl std::fmt::Display for Cpu {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} TM=&{:03X}", self.registers, self.timer)?;
+        for (set, name) in [(self.pu, "PU"), (self.pv, "PV"), (self.display, "DISP")] {
+            if set {
+                write!(f, " {}", name)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Cpu::new()
+    }
+}
+
+// Vectors keep their high byte first, like all addresses
+fn read_word(bus: &mut impl Bus, address: u16) -> u16 {
+    let high = bus.read(u32::from(address));
+    u16::from_be_bytes([high, bus.read(u32::from(address.wrapping_add(1)))])
+}
+
+fn logic(mnemonic: &str, a: u8, value: u8) -> u8 {
+    match mnemonic {
+        "AND" => a & value,
+        "ORA" => a | value,
+        "EOR" => a ^ value,
+        _ => value,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lh5801::Assembler;
+
+    struct Ram(Vec<u8>);
+
+    impl Bus for Ram {
+        fn read(&mut self, address: u32) -> u8 {
+            self.0[address as usize]
+        }
+
+        fn write(&mut self, address: u32, value: u8) {
+            self.0[address as usize] = value;
+        }
+    }
+
+    // The CPU at the start of the assembled source, with the stack at &7BFF
+    fn load(source: &str) -> (Cpu, Ram) {
+        let assembler = Assembler::new(|_| Err(std::io::ErrorKind::NotFound.into()));
+        let assembly = assembler.assemble("test.asm", source).unwrap();
+        let mut ram = Ram(vec![0; 0x20000]);
+        let origin = assembly.origin() as usize;
+        ram.0[origin..origin + assembly.bytes().len()].copy_from_slice(assembly.bytes());
+        let mut cpu = Cpu::new();
+        cpu.registers.p = u16::try_from(origin).unwrap();
+        cpu.registers.s = 0x7BFF;
+        (cpu, ram)
+    }
+
+    // Runs the instructions, giving their cycles
+    fn run(cpu: &mut Cpu, ram: &mut Ram, instructions: usize) -> u32 {
+        (0..instructions).map(|_| cpu.step(ram).unwrap()).sum()
+    }
+
+    #[test]
+    fn every_opcode_runs() {
+        for opcode in OPCODES {
+            let mut ram = Ram(vec![0; 0x20000]);
+            let [prefix, code] = opcode.code.to_be_bytes();
+            let start = if opcode.prefixed() {
+                ram.0[0x4000] = prefix;
+                0x4001
+            } else {
+                0x4000
+            };
+            ram.0[start] = code;
+            let mut cpu = Cpu::new();
+            cpu.registers.p = 0x4000;
+            cpu.registers.s = 0x7BFF;
+            let cycles = cpu.step(&mut ram).unwrap();
+            assert!(cycles >= u32::from(opcode.cycles), "{:04X}", opcode.code);
+        }
+    }
+
+    fn registers_after(source: &str, instructions: usize) -> Registers {
+        let (mut cpu, mut ram) = load(source);
+        run(&mut cpu, &mut ram, instructions);
+        cpu.registers
+    }
+
+    #[test]
+    fn arithmetic_sets_the_flags() {
+        let added = registers_after("    ORG &4000\n    LDI A,&7F\n    REC\n    ADI A,1\n", 3);
+        assert_eq!(added.a, 0x80);
+        assert_eq!(added.t, HALF_CARRY | OVERFLOW);
+
+        // Compares subtract without a borrow, which sets the carry
+        let compared = registers_after("    ORG &4000\n    LDI A,&80\n    CPI A,&80\n", 2);
+        assert_eq!(compared.t & (ZERO | CARRY), ZERO | CARRY);
+        let borrowed = registers_after("    ORG &4000\n    LDI A,&10\n    SEC\n    SBI A,&20\n", 3);
+        assert_eq!(borrowed.a, 0xF0);
+        assert_eq!(borrowed.t & CARRY, 0);
+    }
+
+    #[test]
+    fn decimal_arithmetic() {
+        let source = "\
+    ORG &4000
+    LDI XH,&78
+    LDI XL,&00
+    LDI A,&28
+    STA (X)
+    LDI A,&19
+    REC
+    DCA (X)
+    STA (&7801)
+    SEC
+    DCS (X)
+";
+        let (mut cpu, mut ram) = load(source);
+        run(&mut cpu, &mut ram, 8);
+        assert_eq!(ram.0[0x7801], 0x47);
+        run(&mut cpu, &mut ram, 2);
+        assert_eq!(cpu.registers.a, 0x19);
+    }
+
+    #[test]
+    fn calls_and_the_stack() {
+        let source = "\
+    ORG &4000
+    LDI UH,&12
+    LDI UL,&34
+    PSH U
+    SJP SUB
+    POP X
+    HLT
+SUB:
+    LDI A,&55
+    RTN
+";
+        let (mut cpu, mut ram) = load(source);
+        run(&mut cpu, &mut ram, 4);
+        // High byte first, like every address
+        assert_eq!(ram.0[0x7BFE..0x7C00], [0x12, 0x34]);
+        assert_eq!(ram.0[0x7BFC..0x7BFE], [0x40, 0x09]);
+        assert_eq!(cpu.registers.p, 0x400D);
+        run(&mut cpu, &mut ram, 3);
+        assert_eq!(cpu.registers.x, 0x1234);
+        assert_eq!(cpu.registers.a, 0x55);
+        assert_eq!(cpu.registers.s, 0x7BFF);
+    }
+
+    #[test]
+    fn loops_and_branches_take_extra_cycles() {
+        let source = "\
+    ORG &4000
+    LDI UL,2
+    LDI A,0
+AGAIN:
+    INC A
+    LOP UL,AGAIN
+    CPI A,3
+    BZS &4000
+";
+        let (mut cpu, mut ram) = load(source);
+        let cycles = run(&mut cpu, &mut ram, 10);
+        assert_eq!(cpu.registers.a, 3);
+        assert_eq!(cpu.registers.p, 0x4000);
+        // Two taken loops and a taken branch
+        assert_eq!(
+            cycles,
+            6 + 6 + 3 * (5 + 8) + 2 * LOOP_TAKEN + 7 + 8 + BRANCH_TAKEN
+        );
+    }
+
+    #[test]
+    fn interrupts_wait_for_enable() {
+        let source = "\
+    ORG &4000
+    NOP
+    SIE
+    HLT
+    NOP
+HANDLER:
+    RTI
+";
+        let (mut cpu, mut ram) = load(source);
+        ram.0[0xFFF8..0xFFFA].copy_from_slice(&[0x40, 0x06]);
+        cpu.interrupt(Interrupt::Maskable);
+        run(&mut cpu, &mut ram, 2);
+        assert_eq!(cpu.registers.p, 0x4003);
+
+        // Taken once enabled, with IE cleared until RTI
+        run(&mut cpu, &mut ram, 1);
+        assert_eq!(cpu.registers.p, 0x4006);
+        assert!(!cpu.interrupts_enabled());
+        run(&mut cpu, &mut ram, 1);
+        assert_eq!(cpu.registers.p, 0x4003);
+        assert!(cpu.interrupts_enabled());
+
+        // HLT waits for the next one
+        run(&mut cpu, &mut ram, 2);
+        assert!(cpu.halted());
+        assert_eq!(cpu.registers.p, 0x4005);
+        cpu.interrupt(Interrupt::Maskable);
+        run(&mut cpu, &mut ram, 2);
+        assert!(!cpu.halted());
+        assert_eq!(cpu.registers.p, 0x4005);
+    }
+
+    #[test]
+    fn timer_raises_its_interrupt() {
+        let (mut cpu, mut ram) = load("    ORG &4000\n    LDI A,&FE\n    AM1\n    SIE\n    HLT\n");
+        ram.0[0xFFFA..0xFFFC].copy_from_slice(&[0x50, 0x00]);
+        run(&mut cpu, &mut ram, 4);
+        assert_eq!(cpu.timer, 0x1FE);
+        while cpu.registers.p != 0x5000 {
+            cpu.step(&mut ram).unwrap();
+        }
+        assert_eq!(cpu.timer, 0x1FF);
+    }
+
+    #[test]
+    fn illegal_opcodes_fault() {
+        let (mut cpu, mut ram) = load("    ORG &4000\n    DB &FD,&00\n");
+        assert_eq!(
+            cpu.step(&mut ram),
+            Err(Fault::IllegalOpcode {
+                address: 0x4000,
+                code: 0xFD00
+            })
+        );
+    }
+}
@@ -0,0 +1,393 @@
+// Disassembler for the LH5801
+//
+// Code is found by following the jumps, calls and branches from the entry
+// points, so the bytes they never reach are listed as data. Jump targets get
+// labels, named from the symbol file when it has them.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::opcode::{Immediate, Mode, Opcode, Register};
+use crate::ast::{Program, Statement};
+
+// Data bytes on a line, as many as the listing has room for
+const DATA_PER_LINE: u32 = 4;
+
+struct Instruction {
+    opcode: &'static Opcode,
+    length: u32,
+    // Of the (ab) forms
+    address: Option<u32>,
+    immediate: Option<u32>,
+}
+
+impl Instruction {
+    // Where a jump, call or branch goes
+    fn target(&self, address: u32) -> Option<u32> {
+        let next = address + self.length;
+        match (self.opcode.immediate, self.immediate) {
+            (Immediate::Forward, Some(offset)) => Some((next + offset) & 0xFFFF),
+            (Immediate::Backward, Some(offset)) => Some(next.wrapping_sub(offset) & 0xFFFF),
+            (Immediate::Word, Some(target)) if matches!(self.opcode.mnemonic, "JMP" | "SJP") => {
+                Some(target)
+            }
+            _ => None,
+        }
+    }
+
+    // Whether the next instruction can run after this one
+    fn falls_through(&self) -> bool {
+        let jumps = matches!(self.opcode.mnemonic, "JMP" | "BCH" | "RTN" | "RTI");
+        // STX P jumps to X
+        let loads_p =
+            self.opcode.mnemonic == "STX" && self.opcode.mode == Mode::Register(Register::P);
+        !(jumps || loads_p)
+    }
+}
+
+pub struct Disassembler<'b> {
+    origin: u32,
+    bytes: &'b [u8],
+    symbols: BTreeMap<u32, String>,
+}
+
+impl<'b> Disassembler<'b> {
+    pub fn new(origin: u32, bytes: &'b [u8]) -> Self {
+        Disassembler {
+            origin,
+            bytes,
+            symbols: BTreeMap::new(),
+        }
+    }
+
+    // Names for addresses, like a symbol file gives; the first name of an
+    // address is kept
+    pub fn define(&mut self, symbols: &BTreeMap<String, u32>) {
+        for (name, address) in symbols {
+            self.symbols.entry(*address).or_insert_with(|| name.clone());
+        }
+    }
+
+    fn byte(&self, address: u32) -> Option<u8> {
+        let offset = usize::try_from(address.checked_sub(self.origin)?).ok()?;
+        self.bytes.get(offset).copied()
+    }
+
+    fn contains(&self, address: u32) -> bool {
+        self.byte(address).is_some()
+    }
+
+    // High byte first
+    fn word(&self, address: u32) -> Option<u32> {
+        Some(u32::from(self.byte(address)?) << 8 | u32::from(self.byte(address + 1)?))
+    }
+
+    fn decode(&self, address: u32) -> Option<Instruction> {
+        let first = self.byte(address)?;
+        let (code, mut next) = if first == 0xFD {
+            (0xFD00 | u16::from(self.byte(address + 1)?), address + 2)
+        } else {
+            (u16::from(first), address + 1)
+        };
+        let opcode = Opcode::find(code)?;
+        let operand = match opcode.mode {
+            Mode::Absolute | Mode::Me1Absolute => {
+                next += 2;
+                Some(self.word(next - 2)?)
+            }
+            _ => None,
+        };
+        let immediate = match opcode.immediate {
+            Immediate::None => None,
+            Immediate::Word => Some(self.word(next)?),
+            Immediate::Byte | Immediate::Forward | Immediate::Backward => {
+                Some(u32::from(self.byte(next)?))
+            }
+        };
+        Some(Instruction {
+            opcode,
+            length: opcode.length(),
+            address: operand,
+            immediate,
+        })
+    }
+
+    // Instructions reached from the entry points, by address
+    fn trace(&self, entries: &[u32]) -> (BTreeMap<u32, Instruction>, BTreeSet<u32>) {
+        let mut code = BTreeMap::new();
+        let mut targets = BTreeSet::new();
+        let mut pending: Vec<u32> = entries.to_vec();
+        let mut covered = BTreeSet::new();
+        while let Some(mut address) = pending.pop() {
+            while !covered.contains(&address) {
+                let Some(instruction) = self.decode(address) else {
+                    break;
+                };
+                covered.extend(address..address + instruction.length);
+                if let Some(target) = instruction.target(address) {
+                    targets.insert(target);
+                    pending.push(target);
+                }
+                let falls_through = instruction.falls_through();
+                let length = instruction.length;
+                code.insert(address, instruction);
+                if !falls_through {
+                    break;
+                }
+                address += length;
+            }
+        }
+        (code, targets)
+    }
+
+    fn name(&self, address: u32, labels: &BTreeMap<u32, String>) -> String {
+        labels
+            .get(&address)
+            .cloned()
+            .unwrap_or_else(|| format!("&{:04X}", address))
+    }
+
+    fn text(
+        &self,
+        address: u32,
+        instruction: &Instruction,
+        labels: &BTreeMap<u32, String>,
+    ) -> String {
+        let mut operands = Vec::new();
+        match instruction.opcode.mode {
+            Mode::Implied => {}
+            Mode::Register(register) => operands.push(register.to_string()),
+            Mode::Indirect(register) => operands.push(format!("({})", register)),
+            Mode::Me1Indirect(register) => operands.push(format!("#({})", register)),
+            Mode::Absolute | Mode::Me1Absolute => {
+                let prefix = if instruction.opcode.mode == Mode::Me1Absolute {
+                    "#"
+                } else {
+                    ""
+                };
+                let name = self.name(instruction.address.unwrap_or(0), labels);
+                operands.push(format!("{}({})", prefix, name));
+            }
+            Mode::Vector => operands.push(format!("(&{:02X})", instruction.opcode.code)),
+        }
+        match (instruction.target(address), instruction.immediate) {
+            (Some(target), _) => operands.push(self.name(target, labels)),
+            (None, Some(value)) if instruction.opcode.immediate == Immediate::Word => {
+                operands.push(format!("&{:04X}", value));
+            }
+            (None, Some(value)) => operands.push(format!("&{:02X}", value)),
+            (None, None) => {}
+        }
+        if operands.is_empty() {
+            instruction.opcode.mnemonic.to_owned()
+        } else {
+            format!("{} {}", instruction.opcode.mnemonic, operands.join(","))
+        }
+    }
+
+    // Listing of the code reached from the entry points and the data between
+    pub fn disassemble(&self, entries: &[u32]) -> String {
+        let (code, targets) = self.trace(entries);
+        let mut labels = self.symbols.clone();
+        for target in targets.iter().chain(entries) {
+            if self.contains(*target) {
+                labels
+                    .entry(*target)
+                    .or_insert_with(|| format!("L{:04X}", target));
+            }
+        }
+
+        let end = self.origin + u32::try_from(self.bytes.len()).unwrap_or(0);
+        let mut lines = vec![format!("    ORG &{:04X}", self.origin)];
+        let mut address = self.origin;
+        while address < end {
+            if let Some(label) = labels.get(&address) {
+                lines.push(format!("{}:", label));
+            }
+            let (length, text) = match code.get(&address) {
+                Some(instruction) => (instruction.length, self.text(address, instruction, &labels)),
+                None => {
+                    // Data up to the next label or instruction
+                    let mut length = 0;
+                    while length < DATA_PER_LINE
+                        && address + length < end
+                        && (length == 0
+                            || !(labels.contains_key(&(address + length))
+                                || code.contains_key(&(address + length))))
+                    {
+                        length += 1;
+                    }
+                    let values: Vec<String> = (address..address + length)
+                        .filter_map(|at| self.byte(at))
+                        .map(|byte| format!("&{:02X}", byte))
+                        .collect();
+                    (length, format!("DB {}", values.join(",")))
+                }
+            };
+            let bytes: Vec<String> = (address..address + length)
+                .filter_map(|at| self.byte(at))
+                .map(|byte| format!("{:02X}", byte))
+                .collect();
+            lines.push(format!(
+                "{:04X}  {:<12}    {}",
+                address,
+                bytes.join(" "),
+                text
+            ));
+            address += length;
+        }
+        format!("{}\n", lines.join("\n"))
+    }
+}
+
+// Machine code a program POKEs, by address, with the bytes of POKEs that
+// follow each other joined
+pub fn poked(program: &Program) -> BTreeMap<u32, Vec<u8>> {
+    fn collect(statement: &Statement, blocks: &mut Vec<(u32, Vec<u8>)>) {
+        match statement {
+            Statement::Poke { address, values } => {
+                let address = address
+                    .as_constant()
+                    .and_then(|address| u32::try_from(address).ok());
+                let bytes: Option<Vec<u8>> = values
+                    .iter()
+                    .map(|value| {
+                        value
+                            .as_constant()
+                            .and_then(|value| u8::try_from(value).ok())
+                    })
+                    .collect();
+                if let (Some(address), Some(bytes)) = (address, bytes) {
+                    blocks.push((address, bytes));
+                }
+            }
+            Statement::Seq { statements } => {
+                for inner in statements {
+                    collect(inner, blocks);
+                }
+            }
+            Statement::If { then, else_, .. } => {
+                collect(then, blocks);
+                if let Some(else_) = else_ {
+                    collect(else_, blocks);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut blocks = Vec::new();
+    for statement in program.lines.values() {
+        collect(statement, &mut blocks);
+    }
+    let mut joined: BTreeMap<u32, Vec<u8>> = BTreeMap::new();
+    let mut last: Option<(u32, u32)> = None;
+    for (address, bytes) in blocks {
+        match last {
+            Some((start, end)) if end == address => {
+                joined.entry(start).or_default().extend(&bytes);
+                last = Some((start, end + u32::try_from(bytes.len()).unwrap_or(0)));
+            }
+            _ => {
+                last = Some((address, address + u32::try_from(bytes.len()).unwrap_or(0)));
+                joined.insert(address, bytes);
+            }
+        }
+    }
+    joined
+}
+
+// Addresses a program CALLs, the entry points of its machine code
+pub fn called(program: &Program) -> BTreeSet<u32> {
+    fn collect(statement: &Statement, calls: &mut BTreeSet<u32>) {
+        match statement {
+            Statement::Call { address, .. } => {
+                if let Some(address) = address
+                    .as_constant()
+                    .and_then(|address| u32::try_from(address).ok())
+                {
+                    calls.insert(address);
+                }
+            }
+            Statement::Seq { statements } => {
+                for inner in statements {
+                    collect(inner, calls);
+                }
+            }
+            Statement::If { then, else_, .. } => {
+                collect(then, calls);
+                if let Some(else_) = else_ {
+                    collect(else_, calls);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    let mut calls = BTreeSet::new();
+    for statement in program.lines.values() {
+        collect(statement, &mut calls);
+    }
+    calls
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lh5801::Assembler;
+    use crate::test_support::parse;
+
+    #[test]
+    fn follows_jumps_to_find_code() {
+        let source = "\
+    ORG &40C5
+    LDI A,&12
+    BZS DONE
+    SJP &E243
+    JMP LOOP
+    DB 1,2,3,4,5
+LOOP:
+    STA #(&7800)
+DONE:
+    RTN
+    DB &FD
+";
+        let assembler = Assembler::new(|_| Err(std::io::ErrorKind::NotFound.into()));
+        let assembly = assembler.assemble("test.asm", source).unwrap();
+        let mut disassembler = Disassembler::new(0x40C5, assembly.bytes());
+        disassembler.define(&BTreeMap::from([("ROM_BEEP".to_owned(), 0xE243)]));
+
+        assert_eq!(
+            disassembler.disassemble(&[0x40C5]),
+            "    ORG &40C5
+L40C5:
+40C5  B5 12           LDI A,&12
+40C7  8B 0F           BZS L40D8
+40C9  BE E2 43        SJP ROM_BEEP
+40CC  BA 40 D4        JMP L40D4
+40CF  01 02 03 04     DB &01,&02,&03,&04
+40D3  05              DB &05
+L40D4:
+40D4  FD AE 78 00     STA #(&7800)
+L40D8:
+40D8  9A              RTN
+40D9  FD              DB &FD
+"
+        );
+    }
+
+    #[test]
+    fn pokes_are_joined_into_blocks() {
+        let program = parse(concat!(
+            "10 POKE &40C5, &B5, &12\n",
+            "20 POKE &40C7, &9A: CALL &40C5\n",
+            "30 POKE &7000, 1\n",
+            "40 POKE &7100, A\n",
+        ));
+
+        assert_eq!(
+            poked(&program),
+            BTreeMap::from([(0x40C5, vec![0xB5, 0x12, 0x9A]), (0x7000, vec![1])])
+        );
+        assert_eq!(called(&program), BTreeSet::from([0x40C5]));
+    }
+}
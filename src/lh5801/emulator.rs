@@ -0,0 +1,401 @@
+// Host harness for the LH5801 core
+//
+// Gives the CPU RAM over both banks, with ROM images loaded read-only on
+// top, and runs machine code the way BASIC CALLs it: the routine returns
+// with RTN to an address nothing else jumps to. ROM entry points can be
+// hooked with host functions, so generated code that calls the ROM runs
+// without an image of it.
+
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+use super::cpu::{Bus, Cpu, Fault, Registers};
+use crate::machine::MAX_ADDRESS;
+
+// The CPU stack of the PC-1500, in the system RAM
+const STACK_TOP: u16 = 0x7BFF;
+
+// Return address of the CALL, the low byte of the reset vector
+const RETURN: u16 = 0xFFFF;
+
+// A million instructions or so
+const DEFAULT_CYCLE_LIMIT: u64 = 0x80_0000;
+
+// Cycles charged for a hook, the SJP and RTN of a short routine
+const HOOK_CYCLES: u64 = 30;
+
+pub type Hook = Box<dyn FnMut(&mut Registers, &mut Memory)>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Error {
+    Fault(Fault),
+    // The routine did not return within the limit
+    CycleLimit(u64),
+    // HLT with interrupts disabled, which only a non-maskable interrupt
+    // would end
+    Halted(u16),
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Error::Fault(fault) => write!(f, "{}", fault),
+            Error::CycleLimit(limit) => {
+                write!(f, "The routine did not return within {} cycles", limit)
+            }
+            Error::Halted(address) => {
+                write!(
+                    f,
+                    "HLT at &{:04X} with interrupts disabled never ends",
+                    address
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+pub struct Memory {
+    bytes: Vec<u8>,
+    rom: Vec<Range<u32>>,
+    // Bytes written by ATP, in order
+    output: Vec<u8>,
+}
+
+impl Memory {
+    fn new() -> Self {
+        Memory {
+            bytes: vec![0; MAX_ADDRESS as usize + 1],
+            rom: Vec::new(),
+            output: Vec::new(),
+        }
+    }
+
+    fn range(address: u32, bytes: &[u8]) -> Result<Range<u32>, String> {
+        let end = u32::try_from(bytes.len())
+            .ok()
+            .and_then(|length| address.checked_add(length))
+            .filter(|end| *end <= MAX_ADDRESS + 1);
+        match end {
+            Some(end) => Ok(address..end),
+            None => Err(format!(
+                "{} bytes at &{:05X} go past the end of memory",
+                bytes.len(),
+                address
+            )),
+        }
+    }
+
+    // Writes the bytes even over ROM
+    fn load(&mut self, address: u32, bytes: &[u8]) -> Result<Range<u32>, String> {
+        let range = Memory::range(address, bytes)?;
+        self.bytes[range.start as usize..range.end as usize].copy_from_slice(bytes);
+        Ok(range)
+    }
+
+    pub fn peek(&self, address: u32) -> u8 {
+        self.bytes[(address & MAX_ADDRESS) as usize]
+    }
+
+    // Writes RAM; ROM keeps its bytes
+    pub fn poke(&mut self, address: u32, value: u8) {
+        let address = address & MAX_ADDRESS;
+        if !self.rom.iter().any(|range| range.contains(&address)) {
+            self.bytes[address as usize] = value;
+        }
+    }
+
+    pub fn written(&self) -> &[u8] {
+        &self.output
+    }
+}
+
+impl Bus for Memory {
+    fn read(&mut self, address: u32) -> u8 {
+        self.peek(address)
+    }
+
+    fn write(&mut self, address: u32, value: u8) {
+        self.poke(address, value);
+    }
+
+    fn output(&mut self, value: u8) {
+        self.output.push(value);
+    }
+}
+
+pub struct Emulator {
+    cpu: Cpu,
+    memory: Memory,
+    hooks: BTreeMap<u16, Hook>,
+    cycle_limit: u64,
+}
+
+impl Emulator {
+    pub fn new() -> Self {
+        let mut cpu = Cpu::new();
+        cpu.registers_mut().s = STACK_TOP;
+        Emulator {
+            cpu,
+            memory: Memory::new(),
+            hooks: BTreeMap::new(),
+            cycle_limit: DEFAULT_CYCLE_LIMIT,
+        }
+    }
+
+    // Machine code or data, in RAM of either bank
+    pub fn load(&mut self, address: u32, bytes: &[u8]) -> Result<(), String> {
+        self.memory.load(address, bytes).map(|_| ())
+    }
+
+    // ROM image, which the CPU cannot write
+    pub fn load_rom(&mut self, address: u32, bytes: &[u8]) -> Result<(), String> {
+        let range = self.memory.load(address, bytes)?;
+        self.memory.rom.push(range);
+        Ok(())
+    }
+
+    // Runs the function on the host instead of the code at the address, then
+    // returns like RTN
+    pub fn hook(&mut self, address: u16, hook: impl FnMut(&mut Registers, &mut Memory) + 'static) {
+        self.hooks.insert(address, Box::new(hook));
+    }
+
+    // Cycles a call may take before it is stopped
+    pub fn set_cycle_limit(&mut self, limit: u64) {
+        self.cycle_limit = limit;
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    // Calls the routine and runs it until it returns, giving the cycles it
+    // took
+    pub fn call(&mut self, entry: u16) -> Result<u64, Error> {
+        self.cpu.registers_mut().p = RETURN;
+        let top = self.cpu.registers().s;
+        self.cpu.call(&mut self.memory, entry);
+        self.run(|registers| registers.p == RETURN && registers.s == top)
+    }
+
+    // Starts the ROM at its reset vector and runs it up to the cycle limit
+    pub fn boot(&mut self) -> Result<u64, Error> {
+        self.cpu.reset(&mut self.memory);
+        match self.run(|_| false) {
+            Err(Error::CycleLimit(limit)) => Ok(limit),
+            result => result,
+        }
+    }
+
+    // Runs until the registers are done, giving the cycles
+    fn run(&mut self, done: impl Fn(&Registers) -> bool) -> Result<u64, Error> {
+        let start = self.cpu.cycles();
+        let mut hooked = 0;
+        loop {
+            let registers = *self.cpu.registers();
+            let cycles = self.cpu.cycles() - start + hooked;
+            if done(&registers) {
+                return Ok(cycles);
+            }
+            if cycles >= self.cycle_limit {
+                return Err(Error::CycleLimit(self.cycle_limit));
+            }
+            if let Some(hook) = self.hooks.get_mut(&registers.p) {
+                hook(self.cpu.registers_mut(), &mut self.memory);
+                self.cpu.ret(&mut self.memory);
+                hooked += HOOK_CYCLES;
+                continue;
+            }
+            if self.cpu.halted() && !self.cpu.interrupts_enabled() {
+                return Err(Error::Halted(registers.p.wrapping_sub(2)));
+            }
+            self.cpu.step(&mut self.memory).map_err(Error::Fault)?;
+        }
+    }
+}
+
+impl Default for Emulator {
+    fn default() -> Self {
+        Emulator::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use super::*;
+    use crate::codegen::{CodeGenerator, ROM_SYMBOLS};
+    use crate::interpreter::{Interpreter, Terminal, Value};
+    use crate::ir::{ConstantFolder, Lowering, RangeAnalysis, Width};
+    use crate::lh5801::{read_symbols, Assembler, Assembly};
+    use crate::machine::decimal::Decimal;
+    use crate::machine::MemoryConfig;
+    use crate::test_support::parse;
+
+    fn assemble(source: &str, symbols: BTreeMap<String, u32>) -> Assembly {
+        let mut assembler = Assembler::new(|_| Err(std::io::ErrorKind::NotFound.into()));
+        assembler.define(symbols);
+        assembler.assemble("test.asm", source).unwrap()
+    }
+
+    fn load(source: &str) -> Emulator {
+        let assembly = assemble(source, BTreeMap::new());
+        let mut emulator = Emulator::new();
+        emulator.load(assembly.origin(), assembly.bytes()).unwrap();
+        emulator
+    }
+
+    #[test]
+    fn calls_return_with_their_cycles() {
+        let mut emulator = load("    ORG &40C5\n    LDI A,5\n    RTN\n");
+        assert_eq!(emulator.call(0x40C5), Ok(6 + 11));
+        assert_eq!(emulator.cpu().registers().a, 5);
+        assert_eq!(emulator.cpu().registers().s, STACK_TOP);
+
+        let mut spinning = load("    ORG &40C5\nSPIN:\n    JMP SPIN\n");
+        spinning.set_cycle_limit(1000);
+        assert_eq!(spinning.call(0x40C5), Err(Error::CycleLimit(1000)));
+        let mut halting = load("    ORG &40C5\n    HLT\n");
+        assert_eq!(halting.call(0x40C5), Err(Error::Halted(0x40C5)));
+    }
+
+    #[test]
+    fn roms_are_read_only_and_hooks_run_on_the_host() {
+        let source = "\
+    ORG &40C5
+    SJP &C000
+    SJP &E000
+    RTN
+";
+        let mut emulator = load(source);
+        // LDI A,&21; STA (&C000); RTN
+        let rom = [0xB5, 0x21, 0xAE, 0xC0, 0x00, 0x9A];
+        emulator.load_rom(0xC000, &rom).unwrap();
+        emulator.hook(0xE000, |registers, memory| {
+            registers.a *= 2;
+            memory.poke(0x7800, registers.a);
+        });
+        emulator.call(0x40C5).unwrap();
+        assert_eq!(emulator.memory().peek(0xC000), 0xB5);
+        assert_eq!(emulator.memory().peek(0x7800), 0x42);
+        assert!(emulator.load_rom(0x1FFFF, &rom).is_err());
+    }
+
+    // A number in the ROM's 8 byte format
+    fn number(memory: &Memory, address: u32) -> Decimal {
+        let bytes: Vec<u8> = (address..address + 8).map(|at| memory.peek(at)).collect();
+        if bytes[2] == 0 {
+            return Decimal::ZERO;
+        }
+        let digits: String = bytes[3..7]
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        let sign = if bytes[1] & 0x80 == 0 { "" } else { "-" };
+        let exponent = i8::from_le_bytes([bytes[0]]);
+        let text = format!(
+            "{}{:X}.{:X}{}E{}",
+            sign,
+            bytes[2] >> 4,
+            bytes[2] & 0x0F,
+            digits,
+            exponent
+        );
+        Decimal::parse(&text).unwrap()
+    }
+
+    fn set_number(memory: &mut Memory, address: u32, value: Decimal) {
+        for (at, byte) in (address..).zip(value.to_bytes()) {
+            memory.poke(at, byte);
+        }
+    }
+
+    #[test]
+    fn compiled_programs_match_the_interpreter() {
+        let program = parse(concat!(
+            "10 A = 7\n",
+            "20 B = A / 2\n",
+            "30 C = B * 3 - A\n",
+            "40 D = C + 1 / 3\n",
+            "50 PRINT D\n",
+        ));
+
+        let mut interpreter = Interpreter::new(&program, MemoryConfig::Pc1500, Box::new(Terminal));
+        interpreter.run().unwrap();
+        let expected: BTreeMap<String, Value> = interpreter
+            .variables()
+            .into_iter()
+            .map(|(name, value)| (name.to_owned(), value.clone()))
+            .collect();
+        drop(interpreter);
+
+        let mut ir = Lowering::new(&program).lower();
+        ConstantFolder::new().fold(&mut ir);
+        let ranges = RangeAnalysis::new(&ir).analyze();
+        let source = CodeGenerator::new(&program, &ir, &ranges, 0x4200).generate();
+        let rom = read_symbols("rom.sym", ROM_SYMBOLS).unwrap();
+        let assembly = assemble(&source, rom.clone());
+        assert!(assembly.externals().is_empty());
+        let mut emulator = Emulator::new();
+        emulator.load(assembly.origin(), assembly.bytes()).unwrap();
+
+        // The ROM routines the program needs run on the host, calling any
+        // other one fails the test
+        let (x, y) = (rom["ROM_ARX"], rom["ROM_ARY"]);
+        let printed = Rc::new(RefCell::new(Vec::new()));
+        for (name, address) in &rom {
+            let address = u16::try_from(*address).unwrap();
+            match name.as_str() {
+                // Registers, not routines
+                "ROM_ARX" | "ROM_ARY" => {}
+                "ROM_ADD" | "ROM_SUB" | "ROM_MUL" | "ROM_DIV" => {
+                    let routine = name.clone();
+                    emulator.hook(address, move |_, memory| {
+                        let (a, b) = (number(memory, x), number(memory, y));
+                        let result = match routine.as_str() {
+                            "ROM_ADD" => a.add(b),
+                            "ROM_SUB" => a.sub(b),
+                            "ROM_MUL" => a.mul(b),
+                            _ => a.div(b),
+                        };
+                        set_number(memory, x, result.unwrap());
+                    });
+                }
+                "ROM_PRINT_NUM" => {
+                    let printed = Rc::clone(&printed);
+                    emulator.hook(address, move |_, memory| {
+                        printed.borrow_mut().push(number(memory, x));
+                    });
+                }
+                "ROM_END_PRINT" => emulator.hook(address, |_, _| {}),
+                _ => {
+                    let name = name.clone();
+                    emulator.hook(address, move |_, _| panic!("{} was called", name));
+                }
+            }
+        }
+        emulator.call(0x4200).unwrap();
+
+        let symbols = read_symbols("test.sym", &assembly.symbol_file()).unwrap();
+        let memory = emulator.memory();
+        for name in ["A", "B", "C", "D"] {
+            let address = symbols[&format!("V_{}", name)];
+            let value = if ranges.variables[name].width() == Width::Decimal {
+                number(memory, address)
+            } else {
+                let value = i16::from_be_bytes([memory.peek(address), memory.peek(address + 1)]);
+                Decimal::from(i32::from(value))
+            };
+            assert_eq!(Value::Number(value), expected[name], "{}", name);
+        }
+        assert_eq!(*printed.borrow(), vec![number(memory, symbols["V_D"])]);
+    }
+}
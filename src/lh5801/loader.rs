@@ -0,0 +1,240 @@
+// BASIC program that loads machine code
+//
+// The bytes go in DATA lines, each starting with its count of bytes and
+// ending with their sum. The loader READs a line into an array and checks
+// its sum before it POKEs the bytes, and CALLs the code once all are in. It
+// is how routines are typed in on the device.
+
+use crate::ast::{MemoryEstimator, Parser, Program};
+use crate::machine::{MemoryConfig, Region};
+use crate::tokens::Lexer;
+
+// Highest line number the device takes
+const LAST_LINE: u32 = 65279;
+
+// Lines of the loader before the DATA lines
+const LOADER_LINES: u32 = 8;
+
+pub struct Loader<'b> {
+    origin: u32,
+    bytes: &'b [u8],
+    memory: MemoryConfig,
+    entry: u32,
+    first_line: u32,
+    step: u32,
+    per_line: usize,
+}
+
+impl<'b> Loader<'b> {
+    pub fn new(origin: u32, bytes: &'b [u8]) -> Self {
+        Loader {
+            origin,
+            bytes,
+            memory: MemoryConfig::Pc1500,
+            entry: origin,
+            first_line: 10,
+            step: 10,
+            per_line: 8,
+        }
+    }
+
+    // Machine the loader runs on, which decides where the code may go
+    pub fn set_memory(&mut self, memory: MemoryConfig) {
+        self.memory = memory;
+    }
+
+    // Address the loader CALLs, the origin by default
+    pub fn set_entry(&mut self, entry: u32) {
+        self.entry = entry;
+    }
+
+    pub fn set_numbering(&mut self, first_line: u32, step: u32) {
+        self.first_line = first_line;
+        self.step = step;
+    }
+
+    pub fn set_bytes_per_line(&mut self, per_line: usize) {
+        self.per_line = per_line;
+    }
+
+    pub fn generate(&self) -> Result<Program, String> {
+        if self.bytes.is_empty() {
+            return Err("There are no bytes to load".to_owned());
+        }
+        if self.step == 0 || self.per_line == 0 {
+            return Err("The line step and bytes per line must be at least 1".to_owned());
+        }
+        let chunks: Vec<&[u8]> = self.bytes.chunks(self.per_line).collect();
+        let count = u32::try_from(chunks.len())
+            .ok()
+            .filter(|count| {
+                let last = (LOADER_LINES - 1)
+                    .checked_add(*count)
+                    .and_then(|index| index.checked_mul(self.step))
+                    .and_then(|last| last.checked_add(self.first_line));
+                matches!(last, Some(last) if last <= LAST_LINE)
+            })
+            .ok_or_else(|| {
+                format!(
+                    "{} DATA lines do not fit below line {} from line {} in steps of {}",
+                    chunks.len(),
+                    LAST_LINE,
+                    self.first_line,
+                    self.step
+                )
+            })?;
+        let line = |index: u32| self.first_line + index * self.step;
+
+        let longest = chunks.iter().map(|chunk| chunk.len()).max().unwrap_or(0);
+        let mut lines = vec![
+            format!(
+                "{} REM LOADS {} BYTES AT &{:04X}\n",
+                line(0),
+                self.bytes.len(),
+                self.origin
+            ),
+            format!("{} A = &{:04X}: DIM D({})\n", line(1), self.origin, longest),
+            format!("{} FOR L = 0 TO {}: READ C: S = 0\n", line(2), count - 1),
+            format!(
+                "{} FOR I = 1 TO C: READ D(I): S = S + D(I): NEXT I\n",
+                line(3)
+            ),
+            format!(
+                "{} READ K: IF K <> S THEN PRINT \"DATA ERROR IN LINE \"; {} + L * {}: END\n",
+                line(4),
+                line(LOADER_LINES),
+                self.step
+            ),
+            format!(
+                "{} FOR I = 1 TO C: POKE A, D(I): A = A + 1: NEXT I\n",
+                line(5)
+            ),
+            format!("{} NEXT L\n", line(6)),
+            format!("{} CALL &{:04X}: END\n", line(7), self.entry),
+        ];
+        for (index, chunk) in (LOADER_LINES..).zip(&chunks) {
+            let sum: u32 = chunk.iter().copied().map(u32::from).sum();
+            let bytes: Vec<String> = chunk.iter().map(u8::to_string).collect();
+            lines.push(format!(
+                "{} DATA {}, {}, {}\n",
+                line(index),
+                chunk.len(),
+                bytes.join(", "),
+                sum
+            ));
+        }
+
+        let source = lines.concat();
+        let (program, errors) = Parser::new(Lexer::new(&source)).parse();
+        if let Some(error) = errors.first() {
+            return Err(format!("The loader does not parse: {}", error));
+        }
+        self.check_origin(&program)?;
+        Ok(program)
+    }
+
+    // The code has to go between the loader at the start of user RAM and its
+    // variables at the end
+    fn check_origin(&self, program: &Program) -> Result<(), String> {
+        let code = format!("{} bytes at &{:04X}", self.bytes.len(), self.origin);
+        let Some(end) = u32::try_from(self.bytes.len())
+            .ok()
+            .and_then(|length| self.origin.checked_add(length))
+        else {
+            return Err(format!("The {} go past the end of memory", code));
+        };
+        let regions = self.memory.memory_map().regions(self.origin..end);
+        if let Some(region) = regions.iter().find(|region| **region != Region::UserRam) {
+            return Err(format!(
+                "The {} go to {} of the {}, not user RAM",
+                code, region, self.memory
+            ));
+        }
+        let report = MemoryEstimator::new(program).estimate(self.memory);
+        let (program_area, variable_area) = (report.program_area(), report.variable_area());
+        if self.origin < program_area.end {
+            return Err(format!(
+                "The {} overwrite the loader, load them from &{:04X}",
+                code, program_area.end
+            ));
+        }
+        if end > variable_area.start {
+            return Err(format!(
+                "The {} overwrite the loader's variables, which start at &{:04X}",
+                code, variable_area.start
+            ));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ast::Printer;
+
+    #[test]
+    fn data_lines_carry_count_and_sum() {
+        let bytes = [0xB5, 0x12, 0xAE, 0x78, 0x00, 0x9A];
+        let mut loader = Loader::new(0x4400, &bytes);
+        loader.set_numbering(100, 5);
+        loader.set_bytes_per_line(4);
+        let program = loader.generate().unwrap();
+
+        // Each line's sum is checked before any of its bytes is POKEd
+        assert_eq!(
+            Printer::new().build(&program),
+            "\
+100 REM LOADS 6 BYTES AT &4400
+105 LET A = &4400: DIM D(4)
+110 FOR L = 0 TO 1: READ C: LET S = 0
+115 FOR I = 1 TO C: READ D(I): LET S = (S + D(I)): NEXT I
+120 READ K: IF (K <> S) THEN PRINT \"DATA ERROR IN LINE \"; (140 + (L * 5)): END
+125 FOR I = 1 TO C: POKE A, D(I): LET A = (A + 1): NEXT I
+130 NEXT L
+135 CALL &4400: END
+140 DATA 4, 181, 18, 174, 120, 493
+145 DATA 2, 0, 154, 154
+"
+        );
+    }
+
+    #[test]
+    fn code_goes_to_free_user_ram() {
+        let bytes = [0x9A; 16];
+        let loader = |origin: u32, memory: MemoryConfig| {
+            let mut loader = Loader::new(origin, &bytes);
+            loader.set_memory(memory);
+            loader.generate()
+        };
+
+        loader(0x4400, MemoryConfig::Pc1500).unwrap();
+        assert_eq!(
+            loader(0x40C5, MemoryConfig::Pc1500).unwrap_err(),
+            "The 16 bytes at &40C5 overwrite the loader, load them from &41E7"
+        );
+        assert_eq!(
+            loader(0x47C0, MemoryConfig::Pc1500).unwrap_err(),
+            "The 16 bytes at &47C0 overwrite the loader's variables, which start at &47B1"
+        );
+        assert_eq!(
+            loader(0x47F8, MemoryConfig::Pc1500).unwrap_err(),
+            "The 16 bytes at &47F8 go to unmapped memory of the PC-1500, not user RAM"
+        );
+        loader(0x5000, MemoryConfig::Pc1500A).unwrap();
+        assert_eq!(
+            loader(0xC000, MemoryConfig::Pc1500A).unwrap_err(),
+            "The 16 bytes at &C000 go to ROM of the PC-1500A, not user RAM"
+        );
+    }
+
+    #[test]
+    fn line_numbers_must_fit() {
+        let bytes = [0; 100];
+        let mut loader = Loader::new(0x4400, &bytes);
+        loader.set_numbering(65000, 100);
+        assert!(loader
+            .generate()
+            .is_err_and(|error| error.contains("65279")));
+    }
+}
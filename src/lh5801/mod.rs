@@ -0,0 +1,16 @@
+// The LH5801 CPU of the PC-1500
+//
+// The opcode table describes every instruction once, for the tools that read
+// and write machine code and for the CPU core that runs it.
+
+mod assembler;
+mod cpu;
+mod disassembler;
+mod emulator;
+mod loader;
+mod opcode;
+
+pub use assembler::{read_symbols, Assembler, Assembly};
+pub use disassembler::{called, poked, Disassembler};
+pub use emulator::Emulator;
+pub use loader::Loader;
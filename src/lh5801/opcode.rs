@@ -0,0 +1,1319 @@
+// Instruction set of the LH5801
+//
+// One table describes every opcode for the assembler and disassembler.
+// Opcodes above &FF have the &FD prefix, which also selects the ME1 memory
+// bank for the #(R) and #(ab) forms.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Register {
+    A,
+    Xl,
+    Xh,
+    Yl,
+    Yh,
+    Ul,
+    Uh,
+    X,
+    Y,
+    U,
+    S,
+    P,
+}
+
+impl Register {
+    pub fn parse(name: &str) -> Option<Register> {
+        let register = match name.to_uppercase().as_str() {
+            "A" => Register::A,
+            "XL" => Register::Xl,
+            "XH" => Register::Xh,
+            "YL" => Register::Yl,
+            "YH" => Register::Yh,
+            "UL" => Register::Ul,
+            "UH" => Register::Uh,
+            "X" => Register::X,
+            "Y" => Register::Y,
+            "U" => Register::U,
+            "S" => Register::S,
+            "P" => Register::P,
+            _ => return None,
+        };
+        Some(register)
+    }
+}
+
+impl std::fmt::Display for Register {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Register::A => "A",
+            Register::Xl => "XL",
+            Register::Xh => "XH",
+            Register::Yl => "YL",
+            Register::Yh => "YH",
+            Register::Ul => "UL",
+            Register::Uh => "UH",
+            Register::X => "X",
+            Register::Y => "Y",
+            Register::U => "U",
+            Register::S => "S",
+            Register::P => "P",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+// Operand in the instruction, after the mnemonic
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Implied,
+    Register(Register),
+    // (X), memory at the register
+    Indirect(Register),
+    // #(X), the same in ME1
+    Me1Indirect(Register),
+    // (ab), memory at the address following the opcode
+    Absolute,
+    // #(ab)
+    Me1Absolute,
+    // VEJ (n), the vector is the opcode itself
+    Vector,
+}
+
+// Value following the opcode and any address
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Immediate {
+    None,
+    Byte,
+    Word,
+    // Branch offsets from the address after the instruction
+    Forward,
+    Backward,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Opcode {
+    pub code: u16,
+    pub mnemonic: &'static str,
+    pub mode: Mode,
+    pub immediate: Immediate,
+    // Cycles of the instruction, a taken branch or vector call adds to them
+    pub cycles: u8,
+}
+
+impl Opcode {
+    const fn new(
+        code: u16,
+        mnemonic: &'static str,
+        mode: Mode,
+        immediate: Immediate,
+        cycles: u8,
+    ) -> Opcode {
+        Opcode {
+            code,
+            mnemonic,
+            mode,
+            immediate,
+            cycles,
+        }
+    }
+
+    // The opcode of the code, which has the &FD prefix above &FF
+    pub fn find(code: u16) -> Option<&'static Opcode> {
+        OPCODES.iter().find(|opcode| opcode.code == code)
+    }
+
+    pub fn prefixed(&self) -> bool {
+        self.code > 0xFF
+    }
+
+    // Bytes of the instruction, with the prefix
+    pub fn length(&self) -> u32 {
+        let prefix = u32::from(self.prefixed());
+        let address = match self.mode {
+            Mode::Absolute | Mode::Me1Absolute => 2,
+            _ => 0,
+        };
+        let immediate = match self.immediate {
+            Immediate::None => 0,
+            Immediate::Word => 2,
+            Immediate::Byte | Immediate::Forward | Immediate::Backward => 1,
+        };
+        prefix + 1 + address + immediate
+    }
+}
+
+pub const OPCODES: &[Opcode] = &[
+    Opcode::new(
+        0x00,
+        "SBC",
+        Mode::Register(Register::Xl),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x01, "SBC", Mode::Indirect(Register::X), Immediate::None, 7),
+    Opcode::new(
+        0x02,
+        "ADC",
+        Mode::Register(Register::Xl),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x03, "ADC", Mode::Indirect(Register::X), Immediate::None, 7),
+    Opcode::new(
+        0x04,
+        "LDA",
+        Mode::Register(Register::Xl),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x05, "LDA", Mode::Indirect(Register::X), Immediate::None, 6),
+    Opcode::new(
+        0x06,
+        "CPA",
+        Mode::Register(Register::Xl),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x07, "CPA", Mode::Indirect(Register::X), Immediate::None, 7),
+    Opcode::new(
+        0x08,
+        "STA",
+        Mode::Register(Register::Xh),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x09, "AND", Mode::Indirect(Register::X), Immediate::None, 7),
+    Opcode::new(
+        0x0A,
+        "STA",
+        Mode::Register(Register::Xl),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x0B, "ORA", Mode::Indirect(Register::X), Immediate::None, 7),
+    Opcode::new(
+        0x0C,
+        "DCS",
+        Mode::Indirect(Register::X),
+        Immediate::None,
+        13,
+    ),
+    Opcode::new(0x0D, "EOR", Mode::Indirect(Register::X), Immediate::None, 7),
+    Opcode::new(0x0E, "STA", Mode::Indirect(Register::X), Immediate::None, 6),
+    Opcode::new(0x0F, "BIT", Mode::Indirect(Register::X), Immediate::None, 7),
+    Opcode::new(
+        0x10,
+        "SBC",
+        Mode::Register(Register::Yl),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x11, "SBC", Mode::Indirect(Register::Y), Immediate::None, 7),
+    Opcode::new(
+        0x12,
+        "ADC",
+        Mode::Register(Register::Yl),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x13, "ADC", Mode::Indirect(Register::Y), Immediate::None, 7),
+    Opcode::new(
+        0x14,
+        "LDA",
+        Mode::Register(Register::Yl),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x15, "LDA", Mode::Indirect(Register::Y), Immediate::None, 6),
+    Opcode::new(
+        0x16,
+        "CPA",
+        Mode::Register(Register::Yl),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x17, "CPA", Mode::Indirect(Register::Y), Immediate::None, 7),
+    Opcode::new(
+        0x18,
+        "STA",
+        Mode::Register(Register::Yh),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x19, "AND", Mode::Indirect(Register::Y), Immediate::None, 7),
+    Opcode::new(
+        0x1A,
+        "STA",
+        Mode::Register(Register::Yl),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x1B, "ORA", Mode::Indirect(Register::Y), Immediate::None, 7),
+    Opcode::new(
+        0x1C,
+        "DCS",
+        Mode::Indirect(Register::Y),
+        Immediate::None,
+        13,
+    ),
+    Opcode::new(0x1D, "EOR", Mode::Indirect(Register::Y), Immediate::None, 7),
+    Opcode::new(0x1E, "STA", Mode::Indirect(Register::Y), Immediate::None, 6),
+    Opcode::new(0x1F, "BIT", Mode::Indirect(Register::Y), Immediate::None, 7),
+    Opcode::new(
+        0x20,
+        "SBC",
+        Mode::Register(Register::Ul),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x21, "SBC", Mode::Indirect(Register::U), Immediate::None, 7),
+    Opcode::new(
+        0x22,
+        "ADC",
+        Mode::Register(Register::Ul),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x23, "ADC", Mode::Indirect(Register::U), Immediate::None, 7),
+    Opcode::new(
+        0x24,
+        "LDA",
+        Mode::Register(Register::Ul),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x25, "LDA", Mode::Indirect(Register::U), Immediate::None, 6),
+    Opcode::new(
+        0x26,
+        "CPA",
+        Mode::Register(Register::Ul),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x27, "CPA", Mode::Indirect(Register::U), Immediate::None, 7),
+    Opcode::new(
+        0x28,
+        "STA",
+        Mode::Register(Register::Uh),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x29, "AND", Mode::Indirect(Register::U), Immediate::None, 7),
+    Opcode::new(
+        0x2A,
+        "STA",
+        Mode::Register(Register::Ul),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x2B, "ORA", Mode::Indirect(Register::U), Immediate::None, 7),
+    Opcode::new(
+        0x2C,
+        "DCS",
+        Mode::Indirect(Register::U),
+        Immediate::None,
+        13,
+    ),
+    Opcode::new(0x2D, "EOR", Mode::Indirect(Register::U), Immediate::None, 7),
+    Opcode::new(0x2E, "STA", Mode::Indirect(Register::U), Immediate::None, 6),
+    Opcode::new(0x2F, "BIT", Mode::Indirect(Register::U), Immediate::None, 7),
+    Opcode::new(0x38, "NOP", Mode::Implied, Immediate::None, 5),
+    Opcode::new(
+        0x40,
+        "INC",
+        Mode::Register(Register::Xl),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x41, "SIN", Mode::Register(Register::X), Immediate::None, 6),
+    Opcode::new(
+        0x42,
+        "DEC",
+        Mode::Register(Register::Xl),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x43, "SDE", Mode::Register(Register::X), Immediate::None, 6),
+    Opcode::new(0x44, "INC", Mode::Register(Register::X), Immediate::None, 5),
+    Opcode::new(0x45, "LIN", Mode::Register(Register::X), Immediate::None, 6),
+    Opcode::new(0x46, "DEC", Mode::Register(Register::X), Immediate::None, 5),
+    Opcode::new(0x47, "LDE", Mode::Register(Register::X), Immediate::None, 6),
+    Opcode::new(
+        0x48,
+        "LDI",
+        Mode::Register(Register::Xh),
+        Immediate::Byte,
+        6,
+    ),
+    Opcode::new(
+        0x49,
+        "ANI",
+        Mode::Indirect(Register::X),
+        Immediate::Byte,
+        13,
+    ),
+    Opcode::new(
+        0x4A,
+        "LDI",
+        Mode::Register(Register::Xl),
+        Immediate::Byte,
+        6,
+    ),
+    Opcode::new(
+        0x4B,
+        "ORI",
+        Mode::Indirect(Register::X),
+        Immediate::Byte,
+        13,
+    ),
+    Opcode::new(
+        0x4C,
+        "CPI",
+        Mode::Register(Register::Xh),
+        Immediate::Byte,
+        7,
+    ),
+    Opcode::new(
+        0x4D,
+        "BII",
+        Mode::Indirect(Register::X),
+        Immediate::Byte,
+        10,
+    ),
+    Opcode::new(
+        0x4E,
+        "CPI",
+        Mode::Register(Register::Xl),
+        Immediate::Byte,
+        7,
+    ),
+    Opcode::new(
+        0x4F,
+        "ADI",
+        Mode::Indirect(Register::X),
+        Immediate::Byte,
+        13,
+    ),
+    Opcode::new(
+        0x50,
+        "INC",
+        Mode::Register(Register::Yl),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x51, "SIN", Mode::Register(Register::Y), Immediate::None, 6),
+    Opcode::new(
+        0x52,
+        "DEC",
+        Mode::Register(Register::Yl),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x53, "SDE", Mode::Register(Register::Y), Immediate::None, 6),
+    Opcode::new(0x54, "INC", Mode::Register(Register::Y), Immediate::None, 5),
+    Opcode::new(0x55, "LIN", Mode::Register(Register::Y), Immediate::None, 6),
+    Opcode::new(0x56, "DEC", Mode::Register(Register::Y), Immediate::None, 5),
+    Opcode::new(0x57, "LDE", Mode::Register(Register::Y), Immediate::None, 6),
+    Opcode::new(
+        0x58,
+        "LDI",
+        Mode::Register(Register::Yh),
+        Immediate::Byte,
+        6,
+    ),
+    Opcode::new(
+        0x59,
+        "ANI",
+        Mode::Indirect(Register::Y),
+        Immediate::Byte,
+        13,
+    ),
+    Opcode::new(
+        0x5A,
+        "LDI",
+        Mode::Register(Register::Yl),
+        Immediate::Byte,
+        6,
+    ),
+    Opcode::new(
+        0x5B,
+        "ORI",
+        Mode::Indirect(Register::Y),
+        Immediate::Byte,
+        13,
+    ),
+    Opcode::new(
+        0x5C,
+        "CPI",
+        Mode::Register(Register::Yh),
+        Immediate::Byte,
+        7,
+    ),
+    Opcode::new(
+        0x5D,
+        "BII",
+        Mode::Indirect(Register::Y),
+        Immediate::Byte,
+        10,
+    ),
+    Opcode::new(
+        0x5E,
+        "CPI",
+        Mode::Register(Register::Yl),
+        Immediate::Byte,
+        7,
+    ),
+    Opcode::new(
+        0x5F,
+        "ADI",
+        Mode::Indirect(Register::Y),
+        Immediate::Byte,
+        13,
+    ),
+    Opcode::new(
+        0x60,
+        "INC",
+        Mode::Register(Register::Ul),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x61, "SIN", Mode::Register(Register::U), Immediate::None, 6),
+    Opcode::new(
+        0x62,
+        "DEC",
+        Mode::Register(Register::Ul),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x63, "SDE", Mode::Register(Register::U), Immediate::None, 6),
+    Opcode::new(0x64, "INC", Mode::Register(Register::U), Immediate::None, 5),
+    Opcode::new(0x65, "LIN", Mode::Register(Register::U), Immediate::None, 6),
+    Opcode::new(0x66, "DEC", Mode::Register(Register::U), Immediate::None, 5),
+    Opcode::new(0x67, "LDE", Mode::Register(Register::U), Immediate::None, 6),
+    Opcode::new(
+        0x68,
+        "LDI",
+        Mode::Register(Register::Uh),
+        Immediate::Byte,
+        6,
+    ),
+    Opcode::new(
+        0x69,
+        "ANI",
+        Mode::Indirect(Register::U),
+        Immediate::Byte,
+        13,
+    ),
+    Opcode::new(
+        0x6A,
+        "LDI",
+        Mode::Register(Register::Ul),
+        Immediate::Byte,
+        6,
+    ),
+    Opcode::new(
+        0x6B,
+        "ORI",
+        Mode::Indirect(Register::U),
+        Immediate::Byte,
+        13,
+    ),
+    Opcode::new(
+        0x6C,
+        "CPI",
+        Mode::Register(Register::Uh),
+        Immediate::Byte,
+        7,
+    ),
+    Opcode::new(
+        0x6D,
+        "BII",
+        Mode::Indirect(Register::U),
+        Immediate::Byte,
+        10,
+    ),
+    Opcode::new(
+        0x6E,
+        "CPI",
+        Mode::Register(Register::Ul),
+        Immediate::Byte,
+        7,
+    ),
+    Opcode::new(
+        0x6F,
+        "ADI",
+        Mode::Indirect(Register::U),
+        Immediate::Byte,
+        13,
+    ),
+    Opcode::new(
+        0x80,
+        "SBC",
+        Mode::Register(Register::Xh),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x81, "BCR", Mode::Implied, Immediate::Forward, 8),
+    Opcode::new(
+        0x82,
+        "ADC",
+        Mode::Register(Register::Xh),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x83, "BCS", Mode::Implied, Immediate::Forward, 8),
+    Opcode::new(
+        0x84,
+        "LDA",
+        Mode::Register(Register::Xh),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x85, "BHR", Mode::Implied, Immediate::Forward, 8),
+    Opcode::new(
+        0x86,
+        "CPA",
+        Mode::Register(Register::Xh),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x87, "BHS", Mode::Implied, Immediate::Forward, 8),
+    Opcode::new(
+        0x88,
+        "LOP",
+        Mode::Register(Register::Ul),
+        Immediate::Backward,
+        8,
+    ),
+    Opcode::new(0x89, "BZR", Mode::Implied, Immediate::Forward, 8),
+    Opcode::new(0x8A, "RTI", Mode::Implied, Immediate::None, 14),
+    Opcode::new(0x8B, "BZS", Mode::Implied, Immediate::Forward, 8),
+    Opcode::new(
+        0x8C,
+        "DCA",
+        Mode::Indirect(Register::X),
+        Immediate::None,
+        15,
+    ),
+    Opcode::new(0x8D, "BVR", Mode::Implied, Immediate::Forward, 8),
+    Opcode::new(0x8E, "BCH", Mode::Implied, Immediate::Forward, 8),
+    Opcode::new(0x8F, "BVS", Mode::Implied, Immediate::Forward, 8),
+    Opcode::new(
+        0x90,
+        "SBC",
+        Mode::Register(Register::Yh),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x91, "BCR", Mode::Implied, Immediate::Backward, 8),
+    Opcode::new(
+        0x92,
+        "ADC",
+        Mode::Register(Register::Yh),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x93, "BCS", Mode::Implied, Immediate::Backward, 8),
+    Opcode::new(
+        0x94,
+        "LDA",
+        Mode::Register(Register::Yh),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0x95, "BHR", Mode::Implied, Immediate::Backward, 8),
+    Opcode::new(
+        0x96,
+        "CPA",
+        Mode::Register(Register::Yh),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0x97, "BHS", Mode::Implied, Immediate::Backward, 8),
+    Opcode::new(0x99, "BZR", Mode::Implied, Immediate::Backward, 8),
+    Opcode::new(0x9A, "RTN", Mode::Implied, Immediate::None, 11),
+    Opcode::new(0x9B, "BZS", Mode::Implied, Immediate::Backward, 8),
+    Opcode::new(
+        0x9C,
+        "DCA",
+        Mode::Indirect(Register::Y),
+        Immediate::None,
+        15,
+    ),
+    Opcode::new(0x9D, "BVR", Mode::Implied, Immediate::Backward, 8),
+    Opcode::new(0x9E, "BCH", Mode::Implied, Immediate::Backward, 8),
+    Opcode::new(0x9F, "BVS", Mode::Implied, Immediate::Backward, 8),
+    Opcode::new(
+        0xA0,
+        "SBC",
+        Mode::Register(Register::Uh),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0xA1, "SBC", Mode::Absolute, Immediate::None, 13),
+    Opcode::new(
+        0xA2,
+        "ADC",
+        Mode::Register(Register::Uh),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0xA3, "ADC", Mode::Absolute, Immediate::None, 13),
+    Opcode::new(
+        0xA4,
+        "LDA",
+        Mode::Register(Register::Uh),
+        Immediate::None,
+        5,
+    ),
+    Opcode::new(0xA5, "LDA", Mode::Absolute, Immediate::None, 12),
+    Opcode::new(
+        0xA6,
+        "CPA",
+        Mode::Register(Register::Uh),
+        Immediate::None,
+        6,
+    ),
+    Opcode::new(0xA7, "CPA", Mode::Absolute, Immediate::None, 13),
+    Opcode::new(0xA8, "SPV", Mode::Implied, Immediate::None, 4),
+    Opcode::new(0xA9, "AND", Mode::Absolute, Immediate::None, 13),
+    Opcode::new(
+        0xAA,
+        "LDI",
+        Mode::Register(Register::S),
+        Immediate::Word,
+        12,
+    ),
+    Opcode::new(0xAB, "ORA", Mode::Absolute, Immediate::None, 13),
+    Opcode::new(
+        0xAC,
+        "DCA",
+        Mode::Indirect(Register::U),
+        Immediate::None,
+        15,
+    ),
+    Opcode::new(0xAD, "EOR", Mode::Absolute, Immediate::None, 13),
+    Opcode::new(0xAE, "STA", Mode::Absolute, Immediate::None, 12),
+    Opcode::new(0xAF, "BIT", Mode::Absolute, Immediate::None, 13),
+    Opcode::new(0xB1, "SBI", Mode::Register(Register::A), Immediate::Byte, 7),
+    Opcode::new(0xB3, "ADI", Mode::Register(Register::A), Immediate::Byte, 7),
+    Opcode::new(0xB5, "LDI", Mode::Register(Register::A), Immediate::Byte, 6),
+    Opcode::new(0xB7, "CPI", Mode::Register(Register::A), Immediate::Byte, 7),
+    Opcode::new(0xB8, "RPV", Mode::Implied, Immediate::None, 4),
+    Opcode::new(0xB9, "ANI", Mode::Register(Register::A), Immediate::Byte, 7),
+    Opcode::new(0xBA, "JMP", Mode::Implied, Immediate::Word, 12),
+    Opcode::new(0xBB, "ORI", Mode::Register(Register::A), Immediate::Byte, 7),
+    Opcode::new(0xBD, "EAI", Mode::Implied, Immediate::Byte, 7),
+    Opcode::new(0xBE, "SJP", Mode::Implied, Immediate::Word, 19),
+    Opcode::new(0xBF, "BII", Mode::Register(Register::A), Immediate::Byte, 7),
+    Opcode::new(0xC0, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xC1, "VCR", Mode::Implied, Immediate::Byte, 8),
+    Opcode::new(0xC2, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xC3, "VCS", Mode::Implied, Immediate::Byte, 8),
+    Opcode::new(0xC4, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xC5, "VHR", Mode::Implied, Immediate::Byte, 8),
+    Opcode::new(0xC6, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xC7, "VHS", Mode::Implied, Immediate::Byte, 8),
+    Opcode::new(0xC8, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xC9, "VZR", Mode::Implied, Immediate::Byte, 8),
+    Opcode::new(0xCA, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xCB, "VZS", Mode::Implied, Immediate::Byte, 8),
+    Opcode::new(0xCC, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xCD, "VMJ", Mode::Implied, Immediate::Byte, 20),
+    Opcode::new(0xCE, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xCF, "VVS", Mode::Implied, Immediate::Byte, 8),
+    Opcode::new(0xD0, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xD1, "ROR", Mode::Implied, Immediate::None, 9),
+    Opcode::new(0xD2, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(
+        0xD3,
+        "DRR",
+        Mode::Indirect(Register::X),
+        Immediate::None,
+        12,
+    ),
+    Opcode::new(0xD4, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xD5, "SHR", Mode::Implied, Immediate::None, 9),
+    Opcode::new(0xD6, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(
+        0xD7,
+        "DRL",
+        Mode::Indirect(Register::X),
+        Immediate::None,
+        12,
+    ),
+    Opcode::new(0xD8, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xD9, "SHL", Mode::Implied, Immediate::None, 6),
+    Opcode::new(0xDA, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xDB, "ROL", Mode::Implied, Immediate::None, 8),
+    Opcode::new(0xDC, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xDD, "INC", Mode::Register(Register::A), Immediate::None, 5),
+    Opcode::new(0xDE, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xDF, "DEC", Mode::Register(Register::A), Immediate::None, 5),
+    Opcode::new(0xE0, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xE1, "SPU", Mode::Implied, Immediate::None, 4),
+    Opcode::new(0xE2, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xE3, "RPU", Mode::Implied, Immediate::None, 4),
+    Opcode::new(0xE4, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xE6, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xE8, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xE9, "ANI", Mode::Absolute, Immediate::Byte, 19),
+    Opcode::new(0xEA, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xEB, "ORI", Mode::Absolute, Immediate::Byte, 19),
+    Opcode::new(0xEC, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xED, "BII", Mode::Absolute, Immediate::Byte, 16),
+    Opcode::new(0xEE, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xEF, "ADI", Mode::Absolute, Immediate::Byte, 19),
+    Opcode::new(0xF0, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xF1, "AEX", Mode::Implied, Immediate::None, 6),
+    Opcode::new(0xF2, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xF4, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xF5, "TIN", Mode::Implied, Immediate::None, 7),
+    Opcode::new(0xF6, "VEJ", Mode::Vector, Immediate::None, 17),
+    Opcode::new(0xF7, "CIN", Mode::Implied, Immediate::None, 7),
+    Opcode::new(0xF9, "REC", Mode::Implied, Immediate::None, 4),
+    Opcode::new(0xFB, "SEC", Mode::Implied, Immediate::None, 4),
+    Opcode::new(
+        0xFD01,
+        "SBC",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD03,
+        "ADC",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD05,
+        "LDA",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        10,
+    ),
+    Opcode::new(
+        0xFD07,
+        "CPA",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD08,
+        "LDX",
+        Mode::Register(Register::X),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD09,
+        "AND",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD0A,
+        "POP",
+        Mode::Register(Register::X),
+        Immediate::None,
+        15,
+    ),
+    Opcode::new(
+        0xFD0B,
+        "ORA",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD0C,
+        "DCS",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        17,
+    ),
+    Opcode::new(
+        0xFD0D,
+        "EOR",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD0E,
+        "STA",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        10,
+    ),
+    Opcode::new(
+        0xFD0F,
+        "BIT",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD11,
+        "SBC",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD13,
+        "ADC",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD15,
+        "LDA",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::None,
+        10,
+    ),
+    Opcode::new(
+        0xFD17,
+        "CPA",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD18,
+        "LDX",
+        Mode::Register(Register::Y),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD19,
+        "AND",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD1A,
+        "POP",
+        Mode::Register(Register::Y),
+        Immediate::None,
+        15,
+    ),
+    Opcode::new(
+        0xFD1B,
+        "ORA",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD1C,
+        "DCS",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::None,
+        17,
+    ),
+    Opcode::new(
+        0xFD1D,
+        "EOR",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD1E,
+        "STA",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::None,
+        10,
+    ),
+    Opcode::new(
+        0xFD1F,
+        "BIT",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD21,
+        "SBC",
+        Mode::Me1Indirect(Register::U),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD23,
+        "ADC",
+        Mode::Me1Indirect(Register::U),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD25,
+        "LDA",
+        Mode::Me1Indirect(Register::U),
+        Immediate::None,
+        10,
+    ),
+    Opcode::new(
+        0xFD27,
+        "CPA",
+        Mode::Me1Indirect(Register::U),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD28,
+        "LDX",
+        Mode::Register(Register::U),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD29,
+        "AND",
+        Mode::Me1Indirect(Register::U),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD2A,
+        "POP",
+        Mode::Register(Register::U),
+        Immediate::None,
+        15,
+    ),
+    Opcode::new(
+        0xFD2B,
+        "ORA",
+        Mode::Me1Indirect(Register::U),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD2C,
+        "DCS",
+        Mode::Me1Indirect(Register::U),
+        Immediate::None,
+        17,
+    ),
+    Opcode::new(
+        0xFD2D,
+        "EOR",
+        Mode::Me1Indirect(Register::U),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD2E,
+        "STA",
+        Mode::Me1Indirect(Register::U),
+        Immediate::None,
+        10,
+    ),
+    Opcode::new(
+        0xFD2F,
+        "BIT",
+        Mode::Me1Indirect(Register::U),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD40,
+        "INC",
+        Mode::Register(Register::Xh),
+        Immediate::None,
+        9,
+    ),
+    Opcode::new(
+        0xFD42,
+        "DEC",
+        Mode::Register(Register::Xh),
+        Immediate::None,
+        9,
+    ),
+    Opcode::new(
+        0xFD48,
+        "LDX",
+        Mode::Register(Register::S),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD49,
+        "ANI",
+        Mode::Me1Indirect(Register::X),
+        Immediate::Byte,
+        17,
+    ),
+    Opcode::new(
+        0xFD4A,
+        "STX",
+        Mode::Register(Register::X),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD4B,
+        "ORI",
+        Mode::Me1Indirect(Register::X),
+        Immediate::Byte,
+        17,
+    ),
+    Opcode::new(0xFD4C, "OFF", Mode::Implied, Immediate::None, 8),
+    Opcode::new(
+        0xFD4D,
+        "BII",
+        Mode::Me1Indirect(Register::X),
+        Immediate::Byte,
+        14,
+    ),
+    Opcode::new(
+        0xFD4E,
+        "STX",
+        Mode::Register(Register::S),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD4F,
+        "ADI",
+        Mode::Me1Indirect(Register::X),
+        Immediate::Byte,
+        17,
+    ),
+    Opcode::new(
+        0xFD50,
+        "INC",
+        Mode::Register(Register::Yh),
+        Immediate::None,
+        9,
+    ),
+    Opcode::new(
+        0xFD52,
+        "DEC",
+        Mode::Register(Register::Yh),
+        Immediate::None,
+        9,
+    ),
+    Opcode::new(
+        0xFD58,
+        "LDX",
+        Mode::Register(Register::P),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD59,
+        "ANI",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::Byte,
+        17,
+    ),
+    Opcode::new(
+        0xFD5A,
+        "STX",
+        Mode::Register(Register::Y),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD5B,
+        "ORI",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::Byte,
+        17,
+    ),
+    Opcode::new(
+        0xFD5D,
+        "BII",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::Byte,
+        14,
+    ),
+    Opcode::new(
+        0xFD5E,
+        "STX",
+        Mode::Register(Register::P),
+        Immediate::None,
+        17,
+    ),
+    Opcode::new(
+        0xFD5F,
+        "ADI",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::Byte,
+        17,
+    ),
+    Opcode::new(
+        0xFD60,
+        "INC",
+        Mode::Register(Register::Uh),
+        Immediate::None,
+        9,
+    ),
+    Opcode::new(
+        0xFD62,
+        "DEC",
+        Mode::Register(Register::Uh),
+        Immediate::None,
+        9,
+    ),
+    Opcode::new(
+        0xFD69,
+        "ANI",
+        Mode::Me1Indirect(Register::U),
+        Immediate::Byte,
+        17,
+    ),
+    Opcode::new(
+        0xFD6A,
+        "STX",
+        Mode::Register(Register::U),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFD6B,
+        "ORI",
+        Mode::Me1Indirect(Register::U),
+        Immediate::Byte,
+        17,
+    ),
+    Opcode::new(
+        0xFD6D,
+        "BII",
+        Mode::Me1Indirect(Register::U),
+        Immediate::Byte,
+        14,
+    ),
+    Opcode::new(
+        0xFD6F,
+        "ADI",
+        Mode::Me1Indirect(Register::U),
+        Immediate::Byte,
+        17,
+    ),
+    Opcode::new(0xFD81, "SIE", Mode::Implied, Immediate::None, 8),
+    Opcode::new(
+        0xFD88,
+        "PSH",
+        Mode::Register(Register::X),
+        Immediate::None,
+        14,
+    ),
+    Opcode::new(
+        0xFD8A,
+        "POP",
+        Mode::Register(Register::A),
+        Immediate::None,
+        12,
+    ),
+    Opcode::new(
+        0xFD8C,
+        "DCA",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        19,
+    ),
+    Opcode::new(0xFD8E, "CDV", Mode::Implied, Immediate::None, 8),
+    Opcode::new(
+        0xFD98,
+        "PSH",
+        Mode::Register(Register::Y),
+        Immediate::None,
+        14,
+    ),
+    Opcode::new(
+        0xFD9C,
+        "DCA",
+        Mode::Me1Indirect(Register::Y),
+        Immediate::None,
+        19,
+    ),
+    Opcode::new(0xFDA1, "SBC", Mode::Me1Absolute, Immediate::None, 17),
+    Opcode::new(0xFDA3, "ADC", Mode::Me1Absolute, Immediate::None, 17),
+    Opcode::new(0xFDA5, "LDA", Mode::Me1Absolute, Immediate::None, 16),
+    Opcode::new(0xFDA7, "CPA", Mode::Me1Absolute, Immediate::None, 17),
+    Opcode::new(
+        0xFDA8,
+        "PSH",
+        Mode::Register(Register::U),
+        Immediate::None,
+        14,
+    ),
+    Opcode::new(0xFDA9, "AND", Mode::Me1Absolute, Immediate::None, 17),
+    Opcode::new(0xFDAA, "TTA", Mode::Implied, Immediate::None, 9),
+    Opcode::new(0xFDAB, "ORA", Mode::Me1Absolute, Immediate::None, 17),
+    Opcode::new(
+        0xFDAC,
+        "DCA",
+        Mode::Me1Indirect(Register::U),
+        Immediate::None,
+        19,
+    ),
+    Opcode::new(0xFDAD, "EOR", Mode::Me1Absolute, Immediate::None, 17),
+    Opcode::new(0xFDAE, "STA", Mode::Me1Absolute, Immediate::None, 16),
+    Opcode::new(0xFDAF, "BIT", Mode::Me1Absolute, Immediate::None, 17),
+    Opcode::new(0xFDB1, "HLT", Mode::Implied, Immediate::None, 9),
+    Opcode::new(0xFDBA, "ITA", Mode::Implied, Immediate::None, 9),
+    Opcode::new(0xFDBE, "RIE", Mode::Implied, Immediate::None, 8),
+    Opcode::new(0xFDC0, "RDP", Mode::Implied, Immediate::None, 8),
+    Opcode::new(0xFDC1, "SDP", Mode::Implied, Immediate::None, 8),
+    Opcode::new(
+        0xFDC8,
+        "PSH",
+        Mode::Register(Register::A),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(
+        0xFDCA,
+        "ADR",
+        Mode::Register(Register::X),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(0xFDCC, "ATP", Mode::Implied, Immediate::None, 9),
+    Opcode::new(0xFDCE, "AM0", Mode::Implied, Immediate::None, 9),
+    Opcode::new(
+        0xFDD3,
+        "DRR",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        16,
+    ),
+    Opcode::new(
+        0xFDD7,
+        "DRL",
+        Mode::Me1Indirect(Register::X),
+        Immediate::None,
+        16,
+    ),
+    Opcode::new(
+        0xFDDA,
+        "ADR",
+        Mode::Register(Register::Y),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(0xFDDE, "AM1", Mode::Implied, Immediate::None, 9),
+    Opcode::new(0xFDE9, "ANI", Mode::Me1Absolute, Immediate::Byte, 23),
+    Opcode::new(
+        0xFDEA,
+        "ADR",
+        Mode::Register(Register::U),
+        Immediate::None,
+        11,
+    ),
+    Opcode::new(0xFDEB, "ORI", Mode::Me1Absolute, Immediate::Byte, 23),
+    Opcode::new(0xFDEC, "ATT", Mode::Implied, Immediate::None, 9),
+    Opcode::new(0xFDED, "BII", Mode::Me1Absolute, Immediate::Byte, 20),
+    Opcode::new(0xFDEF, "ADI", Mode::Me1Absolute, Immediate::Byte, 23),
+];
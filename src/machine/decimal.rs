@@ -0,0 +1,527 @@
+// Numbers as the PC-1500 computes them: 10 significant decimal digits and an
+// exponent between -99 and 99. Results are rounded to 10 digits after every
+// operation, so repeated arithmetic drifts exactly like it does on the device.
+
+use std::cmp::Ordering;
+
+pub const DIGITS: u32 = 10;
+pub const MAX_EXPONENT: i32 = 99;
+
+// 10^DIGITS, one past the largest mantissa
+const MANTISSA_LIMIT: u128 = 10_000_000_000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DecimalError {
+    Overflow,
+    DivisionByZero,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Decimal {
+    negative: bool,
+    // Exactly DIGITS digits with a non-zero first digit, or 0
+    mantissa: u64,
+    // Power of ten of the first digit
+    exponent: i32,
+}
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal {
+        negative: false,
+        mantissa: 0,
+        exponent: 0,
+    };
+
+    pub const ONE: Decimal = Decimal {
+        negative: false,
+        mantissa: 1_000_000_000,
+        exponent: 0,
+    };
+
+    // Rounds digits * 10^scale to DIGITS significant digits. Values too small
+    // for the exponent range become zero, as on the device
+    fn normalize(negative: bool, digits: u128, scale: i32) -> Result<Decimal, DecimalError> {
+        if digits == 0 {
+            return Ok(Decimal::ZERO);
+        }
+
+        let count = count_digits(digits);
+        let mut mantissa = digits;
+        let mut exponent = scale + count as i32 - 1;
+        if count > DIGITS {
+            let divisor = 10_u128.pow(count - DIGITS);
+            let quotient = mantissa / divisor;
+            let remainder = mantissa - quotient * divisor;
+            mantissa = quotient;
+            if remainder * 2 >= divisor {
+                mantissa += 1;
+            }
+            if mantissa == MANTISSA_LIMIT {
+                mantissa /= 10;
+                exponent += 1;
+            }
+        } else {
+            mantissa *= 10_u128.pow(DIGITS - count);
+        }
+
+        if exponent > MAX_EXPONENT {
+            return Err(DecimalError::Overflow);
+        }
+        if exponent < -MAX_EXPONENT {
+            return Ok(Decimal::ZERO);
+        }
+
+        Ok(Decimal {
+            negative,
+            mantissa: mantissa as u64,
+            exponent,
+        })
+    }
+
+    // Scale of the last mantissa digit
+    fn scale(self) -> i32 {
+        self.exponent - (DIGITS as i32 - 1)
+    }
+
+    pub fn is_zero(self) -> bool {
+        self.mantissa == 0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.negative
+    }
+
+    pub fn neg(self) -> Decimal {
+        if self.is_zero() {
+            self
+        } else {
+            Decimal {
+                negative: !self.negative,
+                ..self
+            }
+        }
+    }
+
+    pub fn add(self, other: Decimal) -> Result<Decimal, DecimalError> {
+        if self.is_zero() {
+            return Ok(other);
+        }
+        if other.is_zero() {
+            return Ok(self);
+        }
+
+        let (high, low) = if self.exponent >= other.exponent {
+            (self, other)
+        } else {
+            (other, self)
+        };
+        // The smaller operand cannot change the rounded result any more
+        let shift = high.exponent - low.exponent;
+        if shift > DIGITS as i32 + 1 {
+            return Ok(high);
+        }
+
+        let high_digits = i128::from(high.mantissa) * 10_i128.pow(shift as u32);
+        let low_digits = i128::from(low.mantissa);
+        let sum = signed(high.negative, high_digits) + signed(low.negative, low_digits);
+
+        Decimal::normalize(sum < 0, sum.unsigned_abs(), low.scale())
+    }
+
+    pub fn sub(self, other: Decimal) -> Result<Decimal, DecimalError> {
+        self.add(other.neg())
+    }
+
+    pub fn mul(self, other: Decimal) -> Result<Decimal, DecimalError> {
+        let digits = u128::from(self.mantissa) * u128::from(other.mantissa);
+        Decimal::normalize(
+            self.negative != other.negative,
+            digits,
+            self.scale() + other.scale(),
+        )
+    }
+
+    pub fn div(self, other: Decimal) -> Result<Decimal, DecimalError> {
+        if other.is_zero() {
+            return Err(DecimalError::DivisionByZero);
+        }
+
+        // Enough extra digits for the quotient to round correctly
+        let extra = 2 * DIGITS;
+        let digits = u128::from(self.mantissa) * 10_u128.pow(extra) / u128::from(other.mantissa);
+        Decimal::normalize(
+            self.negative != other.negative,
+            digits,
+            self.scale() - other.scale() - extra as i32,
+        )
+    }
+
+    // Power of ten of the first significant digit
+    pub fn exponent(self) -> i32 {
+        self.exponent
+    }
+
+    // Rounds half away from zero to the given number of fractional digits
+    pub fn round(self, places: i32) -> Result<Decimal, DecimalError> {
+        let dropped = -places - self.scale();
+        if dropped <= 0 || self.is_zero() {
+            return Ok(self);
+        }
+        if dropped > DIGITS as i32 {
+            return Ok(Decimal::ZERO);
+        }
+
+        let divisor = 10_u128.pow(dropped as u32);
+        let digits = (u128::from(self.mantissa) + divisor / 2) / divisor;
+        Decimal::normalize(self.negative, digits, -places)
+    }
+
+    // Integer and fractional digits of the magnitude, the latter padded or
+    // rounded to exactly `places` digits
+    pub fn fixed(self, places: u32) -> Result<(String, String), DecimalError> {
+        let rounded = self.round(places as i32)?;
+        let digits: Vec<char> = rounded.mantissa.to_string().chars().collect();
+        let digit = |position: i32| {
+            usize::try_from(position)
+                .ok()
+                .filter(|_| !rounded.is_zero())
+                .and_then(|position| digits.get(position))
+                .copied()
+                .unwrap_or('0')
+        };
+
+        let integer: String = if rounded.exponent >= 0 && !rounded.is_zero() {
+            (0..=rounded.exponent).map(digit).collect()
+        } else {
+            "0".to_owned()
+        };
+        let fraction: String = (1..=places as i32)
+            .map(|k| digit(rounded.exponent + k))
+            .collect();
+        Ok((integer, fraction))
+    }
+
+    // Drops the fractional digits
+    pub fn trunc(self) -> Decimal {
+        if self.exponent < 0 {
+            return Decimal::ZERO;
+        }
+        if self.exponent >= DIGITS as i32 - 1 {
+            return self;
+        }
+
+        let divisor = 10_u64.pow((DIGITS as i32 - 1 - self.exponent) as u32);
+        Decimal {
+            mantissa: self.mantissa / divisor * divisor,
+            ..self
+        }
+    }
+
+    pub fn is_integer(self) -> bool {
+        self.trunc() == self
+    }
+
+    // Integral value, if the number has no fractional digits
+    pub fn to_integer(self) -> Option<i64> {
+        if !self.is_integer() {
+            return None;
+        }
+        if self.is_zero() {
+            return Some(0);
+        }
+        if self.exponent > 18 {
+            return None;
+        }
+
+        let magnitude =
+            i128::from(self.mantissa) * 10_i128.pow(self.exponent as u32) / 10_i128.pow(DIGITS - 1);
+        i64::try_from(signed(self.negative, magnitude)).ok()
+    }
+
+    // Memory format of the number in variables and the arithmetic registers:
+    // the exponent in two's complement, the sign as 0x80 when negative, the
+    // ten mantissa digits in BCD and a guard byte
+    pub fn to_bytes(self) -> [u8; 8] {
+        let mut bytes = [0; 8];
+        if self.is_zero() {
+            return bytes;
+        }
+        bytes[0] = self.exponent.to_le_bytes()[0];
+        bytes[1] = if self.negative { 0x80 } else { 0 };
+        let mut mantissa = self.mantissa;
+        for byte in bytes[2..7].iter_mut().rev() {
+            let (high, low) = (mantissa / 10 % 10, mantissa % 10);
+            *byte = u8::try_from(high << 4 | low).expect("two digits fit a byte");
+            mantissa /= 100;
+        }
+        bytes
+    }
+
+    // Parses the number syntax accepted by INPUT and VAL: an optional sign,
+    // digits with an optional decimal point and an optional E exponent
+    pub fn parse(text: &str) -> Option<Decimal> {
+        let mut chars = text.trim().chars().peekable();
+
+        let negative = match chars.peek() {
+            Some('-') => {
+                chars.next();
+                true
+            }
+            Some('+') => {
+                chars.next();
+                false
+            }
+            _ => false,
+        };
+
+        let mut digits: u128 = 0;
+        let mut scale: i32 = 0;
+        let mut any_digit = false;
+        let mut fraction = false;
+        while let Some(&c) = chars.peek() {
+            match c {
+                '0'..='9' => {
+                    any_digit = true;
+                    // Digits past the precision only move the exponent
+                    if digits < MANTISSA_LIMIT * 1000 {
+                        digits = digits * 10 + u128::from(c as u8 - b'0');
+                        if fraction {
+                            scale -= 1;
+                        }
+                    } else {
+                        scale += i32::from(!fraction);
+                    }
+                }
+                '.' if !fraction => fraction = true,
+                _ => break,
+            }
+            chars.next();
+        }
+        if !any_digit {
+            return None;
+        }
+
+        if matches!(chars.peek(), Some('E' | 'e')) {
+            chars.next();
+            let exponent_negative = match chars.peek() {
+                Some('-') => {
+                    chars.next();
+                    true
+                }
+                Some('+') => {
+                    chars.next();
+                    false
+                }
+                _ => false,
+            };
+            let mut exponent: i32 = 0;
+            let mut any_exponent_digit = false;
+            while let Some(digit) = chars.peek().and_then(|c| c.to_digit(10)) {
+                any_exponent_digit = true;
+                exponent = exponent.saturating_mul(10).saturating_add(digit as i32);
+                chars.next();
+            }
+            if !any_exponent_digit {
+                return None;
+            }
+            scale += if exponent_negative {
+                -exponent
+            } else {
+                exponent
+            };
+        }
+
+        if chars.next().is_some() {
+            return None;
+        }
+
+        Decimal::normalize(negative, digits, scale).ok()
+    }
+}
+
+fn count_digits(mut digits: u128) -> u32 {
+    let mut count = 0;
+    while digits > 0 {
+        digits /= 10;
+        count += 1;
+    }
+    count
+}
+
+fn signed(negative: bool, magnitude: i128) -> i128 {
+    if negative {
+        -magnitude
+    } else {
+        magnitude
+    }
+}
+
+impl From<i32> for Decimal {
+    fn from(value: i32) -> Self {
+        // Any i32 has at most 10 digits, so no rounding happens
+        Decimal::normalize(value < 0, u128::from(value.unsigned_abs()), 0)
+            .expect("i32 fits in the exponent range")
+    }
+}
+
+impl From<bool> for Decimal {
+    fn from(value: bool) -> Self {
+        if value {
+            Decimal::ONE
+        } else {
+            Decimal::ZERO
+        }
+    }
+}
+
+impl Ord for Decimal {
+    fn cmp(&self, other: &Self) -> Ordering {
+        let magnitude = |d: &Decimal| (!d.is_zero(), d.exponent, d.mantissa);
+        match (self.negative, other.negative) {
+            (false, true) => Ordering::Greater,
+            (true, false) => Ordering::Less,
+            (false, false) => magnitude(self).cmp(&magnitude(other)),
+            (true, true) => magnitude(other).cmp(&magnitude(self)),
+        }
+    }
+}
+
+impl PartialOrd for Decimal {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl std::fmt::Display for Decimal {
+    // Fixed notation for exponents the 10 digit display can show, scientific
+    // notation otherwise
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_zero() {
+            return write!(f, "0");
+        }
+        if self.negative {
+            write!(f, "-")?;
+        }
+
+        let digits = self.mantissa.to_string();
+        let significant: Vec<char> = digits.trim_end_matches('0').chars().collect();
+        let exponent = self.exponent;
+
+        if (0..DIGITS as i32).contains(&exponent) {
+            let units = exponent as usize + 1;
+            for i in 0..units {
+                write!(f, "{}", significant.get(i).unwrap_or(&'0'))?;
+            }
+            if significant.len() > units {
+                write!(f, ".")?;
+                for digit in &significant[units..] {
+                    write!(f, "{}", digit)?;
+                }
+            }
+            Ok(())
+        } else if (-(DIGITS as i32)..0).contains(&exponent) {
+            write!(f, "0.")?;
+            for _ in 0..-exponent - 1 {
+                write!(f, "0")?;
+            }
+            for digit in &significant {
+                write!(f, "{}", digit)?;
+            }
+            Ok(())
+        } else {
+            write!(f, "{}", significant[0])?;
+            if significant.len() > 1 {
+                write!(f, ".")?;
+                for digit in &significant[1..] {
+                    write!(f, "{}", digit)?;
+                }
+            }
+            // The sign of the exponent takes a column even when positive
+            let sign = if exponent < 0 { '-' } else { ' ' };
+            write!(f, "E{}{:02}", sign, exponent.unsigned_abs())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(text: &str) -> Decimal {
+        Decimal::parse(text).expect("valid number")
+    }
+
+    #[test]
+    fn integer_arithmetic_is_exact() {
+        let a = Decimal::from(123_456);
+        let b = Decimal::from(-789);
+
+        assert_eq!(a.add(b).unwrap().to_integer(), Some(122_667));
+        assert_eq!(a.mul(b).unwrap().to_integer(), Some(-97_406_784));
+        assert_eq!(a.sub(a).unwrap(), Decimal::ZERO);
+    }
+
+    #[test]
+    fn division_rounds_to_ten_digits() {
+        let third = Decimal::ONE.div(Decimal::from(3)).unwrap();
+
+        assert_eq!(third, parse("0.3333333333"));
+        assert_eq!(
+            parse("2").div(Decimal::from(3)).unwrap(),
+            parse(".6666666667")
+        );
+        assert_eq!(
+            Decimal::ONE.div(Decimal::ZERO),
+            Err(DecimalError::DivisionByZero)
+        );
+    }
+
+    #[test]
+    fn overflow_and_underflow() {
+        let big = parse("9.999999999E99");
+        let tiny = parse("1E-99");
+
+        assert_eq!(big.mul(Decimal::from(10)), Err(DecimalError::Overflow));
+        assert_eq!(tiny.div(Decimal::from(10)), Ok(Decimal::ZERO));
+    }
+
+    #[test]
+    fn trunc_and_compare() {
+        assert_eq!(parse("-2.5").trunc(), Decimal::from(-2));
+        assert_eq!(parse("2.5").trunc(), Decimal::from(2));
+        assert!(parse("-2.5") < parse("-2.4"));
+        assert!(parse("0.001") > Decimal::ZERO);
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Decimal::from(-42).to_string(), "-42");
+        assert_eq!(parse(".5").to_string(), "0.5");
+        assert_eq!(parse("0.05").to_string(), "0.05");
+        assert_eq!(parse("1234.5").to_string(), "1234.5");
+        assert_eq!(parse("1E12").to_string(), "1E 12");
+        assert_eq!(parse("1.5E-12").to_string(), "1.5E-12");
+    }
+
+    #[test]
+    fn fixed_places() {
+        let fixed = |text: &str, places: u32| parse(text).fixed(places).unwrap();
+
+        assert_eq!(fixed("2.675", 2), ("2".to_owned(), "68".to_owned()));
+        assert_eq!(fixed("99.96", 1), ("100".to_owned(), "0".to_owned()));
+        assert_eq!(fixed("0.004", 2), ("0".to_owned(), "00".to_owned()));
+    }
+
+    #[test]
+    fn memory_format() {
+        assert_eq!(Decimal::ZERO.to_bytes(), [0; 8]);
+        assert_eq!(
+            parse("-1234.5").to_bytes(),
+            [3, 0x80, 0x12, 0x34, 0x50, 0, 0, 0]
+        );
+        assert_eq!(
+            parse("0.03125").to_bytes(),
+            [0xFE, 0, 0x31, 0x25, 0, 0, 0, 0]
+        );
+    }
+}
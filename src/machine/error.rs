@@ -0,0 +1,43 @@
+// Error numbers of the PC-1500 ROM
+//
+// The device stops with "ERROR n IN line", or jumps to the ON ERROR GOTO line
+// with the number in ERN and the line in ERL. Several conditions share a
+// number, so the number alone does not tell NEXT without FOR from RETURN
+// without GOSUB. Syntax errors, number 1, never get past the parser.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ErrorCode {
+    // RETURN without GOSUB, NEXT without FOR and READ past the last DATA
+    Nesting,
+    // Subscript outside the array
+    Array,
+    UndefinedLine,
+    // A string where a number is expected, or the other way round
+    TypeMismatch,
+    // Argument of a statement or function outside its range
+    Argument,
+    UsingFormat,
+    Overflow,
+    DivisionByZero,
+}
+
+impl ErrorCode {
+    pub fn number(self) -> u8 {
+        match self {
+            ErrorCode::Nesting => 2,
+            ErrorCode::Array => 3,
+            ErrorCode::UndefinedLine => 4,
+            ErrorCode::TypeMismatch => 9,
+            ErrorCode::Argument => 19,
+            ErrorCode::UsingFormat => 36,
+            ErrorCode::Overflow => 37,
+            ErrorCode::DivisionByZero => 38,
+        }
+    }
+}
+
+impl std::fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "ERROR {}", self.number())
+    }
+}
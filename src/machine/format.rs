@@ -0,0 +1,254 @@
+// How PRINT and PAUSE lay values out on the 26 column display
+//
+// Without USING, a lone number is right-justified and strings are left-
+// justified. A comma splits the display into two 13 column halves, a semicolon
+// puts the next item right after the previous one.
+//
+// USING formats are made of `#` digit positions, an optional `.` followed by
+// more `#`, an optional `,` to group thousands and an optional trailing `^` for
+// scientific notation. `&` positions format strings instead. The sign takes a
+// digit position; numbers that do not fit fill the field with `*`.
+
+use super::decimal::{Decimal, DecimalError};
+
+pub const DISPLAY_COLUMNS: usize = 26;
+// Characters are 5 dots wide with a blank column in between, GPRINT and POINT
+// address the dot columns directly
+pub const GRAPHICS_COLUMNS: usize = DISPLAY_COLUMNS * 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Number {
+        // Positions before the decimal point, including the sign
+        integer: usize,
+        // Positions after the decimal point, None without a point
+        fraction: Option<usize>,
+        grouping: bool,
+        exponent: bool,
+    },
+    String {
+        width: usize,
+    },
+}
+
+impl Format {
+    pub fn parse(format: &str) -> Result<Format, String> {
+        if format.is_empty() {
+            return Err("empty format".to_owned());
+        }
+        if format.chars().all(|c| c == '&') {
+            return Ok(Format::String {
+                width: format.len(),
+            });
+        }
+
+        let mut integer = 0;
+        let mut fraction = None;
+        let mut grouping = false;
+        let mut exponent = false;
+        for c in format.chars() {
+            if exponent {
+                return Err("'^' must be the last character".to_owned());
+            }
+            match (c, &mut fraction) {
+                ('#', None) => integer += 1,
+                ('#', Some(digits)) => *digits += 1,
+                (',', None) => {
+                    grouping = true;
+                    integer += 1;
+                }
+                ('.', None) => fraction = Some(0),
+                ('.', Some(_)) => return Err("more than one '.'".to_owned()),
+                (',', Some(_)) => return Err("',' after the decimal point".to_owned()),
+                ('^', _) => exponent = true,
+                ('&', _) => return Err("'&' mixed with number positions".to_owned()),
+                (other, _) => return Err(format!("unexpected character '{}'", other)),
+            }
+        }
+
+        if !format.starts_with('#') {
+            return Err("no '#' before the decimal point".to_owned());
+        }
+        if exponent && grouping {
+            return Err("',' cannot be used with '^'".to_owned());
+        }
+
+        Ok(Format::Number {
+            integer,
+            fraction,
+            grouping,
+            exponent,
+        })
+    }
+
+    pub fn width(self) -> usize {
+        match self {
+            Format::Number {
+                integer,
+                fraction,
+                exponent,
+                ..
+            } => {
+                // "E-05" after the mantissa
+                integer + fraction.map_or(0, |digits| digits + 1) + if exponent { 4 } else { 0 }
+            }
+            Format::String { width } => width,
+        }
+    }
+
+    // None when the value is not a number
+    pub fn number(self, value: Decimal) -> Option<String> {
+        let Format::Number {
+            integer,
+            fraction,
+            grouping,
+            exponent,
+        } = self
+        else {
+            return None;
+        };
+        let places = fraction.unwrap_or(0) as u32;
+
+        let (mantissa, suffix) = if exponent && !value.is_zero() {
+            // One digit before the point, rounding may carry into the exponent
+            let mut power = value.exponent();
+            let Ok(mut scaled) = shift(value, -power) else {
+                return Some("*".repeat(self.width()));
+            };
+            if let Ok(rounded) = scaled.round(places as i32) {
+                if rounded.exponent() > 0 {
+                    power += 1;
+                    let Ok(carried) = shift(value, -power) else {
+                        return Some("*".repeat(self.width()));
+                    };
+                    scaled = carried;
+                }
+            }
+            let sign = if power < 0 { '-' } else { ' ' };
+            (scaled, format!("E{}{:02}", sign, power.unsigned_abs()))
+        } else if exponent {
+            (value, "E 00".to_owned())
+        } else {
+            (value, String::new())
+        };
+
+        let Ok((digits, decimals)) = mantissa.fixed(places) else {
+            return Some("*".repeat(self.width()));
+        };
+        let zero = !digits.chars().chain(decimals.chars()).any(|c| c != '0');
+        let mut text = if value.is_negative() && !zero {
+            "-".to_owned()
+        } else {
+            String::new()
+        };
+        text.push_str(&if grouping { group(&digits) } else { digits });
+
+        if text.len() > integer {
+            return Some("*".repeat(self.width()));
+        }
+        let mut field = " ".repeat(integer - text.len());
+        field.push_str(&text);
+        if fraction.is_some() {
+            field.push('.');
+            field.push_str(&decimals);
+        }
+        field.push_str(&suffix);
+        Some(field)
+    }
+
+    // None when the value is not a string
+    pub fn string(self, value: &str) -> Option<String> {
+        let Format::String { width } = self else {
+            return None;
+        };
+
+        let mut field: String = value.chars().take(width).collect();
+        while field.chars().count() < width {
+            field.push(' ');
+        }
+        Some(field)
+    }
+}
+
+// value * 10^power, exact since only the exponent changes, unless it leaves
+// the exponent range
+fn shift(value: Decimal, power: i32) -> Result<Decimal, DecimalError> {
+    let ten = Decimal::from(10);
+    (0..power.unsigned_abs()).try_fold(value, |result, _| {
+        if power > 0 {
+            result.mul(ten)
+        } else {
+            result.div(ten)
+        }
+    })
+}
+
+// Inserts a comma every three digits from the right
+fn group(digits: &str) -> String {
+    let count = digits.chars().count();
+    let mut grouped = String::new();
+    for (i, digit) in digits.chars().enumerate() {
+        if i > 0 && (count - i).is_multiple_of(3) {
+            grouped.push(',');
+        }
+        grouped.push(digit);
+    }
+    grouped
+}
+
+// Right-justifies numbers and left-justifies strings in a field
+pub fn justify(text: &str, width: usize, right: bool) -> String {
+    if right {
+        format!("{:>1$}", text, width)
+    } else {
+        format!("{:<1$}", text, width)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn number(format: &str, value: &str) -> String {
+        let value = Decimal::parse(value).expect("valid number");
+        Format::parse(format)
+            .expect("valid format")
+            .number(value)
+            .expect("number format")
+    }
+
+    #[test]
+    fn invalid_formats() {
+        Format::parse("").unwrap_err();
+        Format::parse("#.#.#").unwrap_err();
+        Format::parse(".##").unwrap_err();
+        Format::parse("##&").unwrap_err();
+        Format::parse("#^#").unwrap_err();
+        Format::parse("##X").unwrap_err();
+    }
+
+    #[test]
+    fn number_formats() {
+        assert_eq!(number("###.##", "3.14159"), "  3.14");
+        assert_eq!(number("###.##", "-2.5"), " -2.50");
+        assert_eq!(number("###", "1234"), "***");
+        assert_eq!(number("#,###,###", "1234567"), "1,234,567");
+        assert_eq!(number("#.##^", "12345"), "1.23E 04");
+        assert_eq!(number("#.##^", "0.09999"), "1.00E-01");
+    }
+
+    #[test]
+    fn shifts_out_of_range_are_errors() {
+        assert_eq!(shift(Decimal::from(5), 2), Ok(Decimal::from(500)));
+        assert_eq!(shift(Decimal::from(10), 99), Err(DecimalError::Overflow));
+    }
+
+    #[test]
+    fn string_format() {
+        let format = Format::parse("&&&&").unwrap();
+
+        assert_eq!(format.string("AB"), Some("AB  ".to_owned()));
+        assert_eq!(format.string("ABCDEF"), Some("ABCD".to_owned()));
+        assert_eq!(format.number(Decimal::ONE), None);
+    }
+}
@@ -0,0 +1,219 @@
+// Memory configurations of the SHARP PC-1500 family
+//
+// Addresses are 17 bits wide: 0x00000-0x0FFFF is the ME0 bank and
+// 0x10000-0x1FFFF the ME1 bank, where the I/O ports live.
+
+pub mod decimal;
+pub mod error;
+pub mod format;
+
+use std::ops::Range;
+
+pub const ME1: u32 = 0x10000;
+pub const MAX_ADDRESS: u32 = 0x1FFFF;
+// Longest string the device holds
+pub const STRING_LENGTH: usize = 80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemoryConfig {
+    // Stock PC-1500, 2KB of user RAM
+    Pc1500,
+    // PC-1500A, 6KB of user RAM
+    Pc1500A,
+    // PC-1500 with a CE-151 4KB module
+    Ce151,
+    // PC-1500 with a CE-155 8KB module
+    Ce155,
+}
+
+impl MemoryConfig {
+    // Reserve (DEF key) area, the first 0xC5 bytes of RAM, which move down
+    // with the CE-155's extra RAM below 0x4000
+    pub fn reserve_area(self) -> Range<u32> {
+        match self {
+            MemoryConfig::Pc1500 | MemoryConfig::Pc1500A | MemoryConfig::Ce151 => 0x4000..0x40C5,
+            MemoryConfig::Ce155 => 0x3800..0x38C5,
+        }
+    }
+
+    // Addresses available to the BASIC program and its variables, right
+    // after the reserve area
+    pub fn user_area(self) -> Range<u32> {
+        match self {
+            MemoryConfig::Pc1500 => 0x40C5..0x4800,
+            MemoryConfig::Pc1500A | MemoryConfig::Ce151 => 0x40C5..0x5800,
+            MemoryConfig::Ce155 => 0x38C5..0x6000,
+        }
+    }
+
+    pub fn user_bytes(self) -> usize {
+        let area = self.user_area();
+        (area.end - area.start) as usize
+    }
+
+    pub fn memory_map(self) -> MemoryMap {
+        let mut regions = vec![
+            (self.reserve_area(), Region::ReserveArea),
+            (self.user_area(), Region::UserRam),
+            (0x7600..0x7650, Region::DisplayBuffer),
+            (0x7650..0x7700, Region::SystemRam),
+            (0x7700..0x7750, Region::DisplayBuffer),
+            (0x7750..0x7C00, Region::SystemRam),
+            (0xC000..0x10000, Region::Rom),
+            (ME1 + 0xF000..ME1 + 0xF010, Region::Io),
+        ];
+        regions.sort_by_key(|(range, _)| range.start);
+
+        MemoryMap { regions }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    Unmapped,
+    Rom,
+    // Work area of the ROM interpreter
+    SystemRam,
+    // DEF key reservations at the start of user RAM
+    ReserveArea,
+    // BASIC program, variables and free space
+    UserRam,
+    // LCD dots, mapped at 0x7600 and 0x7700
+    DisplayBuffer,
+    Io,
+}
+
+impl std::fmt::Display for Region {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Region::Unmapped => write!(f, "unmapped memory"),
+            Region::Rom => write!(f, "ROM"),
+            Region::SystemRam => write!(f, "system RAM"),
+            Region::ReserveArea => write!(f, "reserve area"),
+            Region::UserRam => write!(f, "user RAM"),
+            Region::DisplayBuffer => write!(f, "display buffer"),
+            Region::Io => write!(f, "I/O ports"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct MemoryMap {
+    // Sorted by start address, never overlapping
+    regions: Vec<(Range<u32>, Region)>,
+}
+
+impl MemoryMap {
+    pub fn region(&self, address: u32) -> Region {
+        self.regions
+            .iter()
+            .find(|(range, _)| range.contains(&address))
+            .map_or(Region::Unmapped, |(_, region)| *region)
+    }
+
+    // Regions touched by the given address range, in address order
+    pub fn regions(&self, addresses: Range<u32>) -> Vec<Region> {
+        let mut regions: Vec<Region> = Vec::new();
+        for address in addresses {
+            let region = self.region(address);
+            if !regions.contains(&region) {
+                regions.push(region);
+            }
+        }
+        regions
+    }
+}
+
+impl std::fmt::Display for MemoryConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            MemoryConfig::Pc1500 => write!(f, "PC-1500"),
+            MemoryConfig::Pc1500A => write!(f, "PC-1500A"),
+            MemoryConfig::Ce151 => write!(f, "PC-1500 + CE-151"),
+            MemoryConfig::Ce155 => write!(f, "PC-1500 + CE-155"),
+        }
+    }
+}
+
+impl clap::ValueEnum for MemoryConfig {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[
+            MemoryConfig::Pc1500,
+            MemoryConfig::Pc1500A,
+            MemoryConfig::Ce151,
+            MemoryConfig::Ce155,
+        ]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        match self {
+            MemoryConfig::Pc1500 => Some(clap::builder::PossibleValue::new("pc1500")),
+            MemoryConfig::Pc1500A => Some(clap::builder::PossibleValue::new("pc1500a")),
+            MemoryConfig::Ce151 => Some(clap::builder::PossibleValue::new("ce151")),
+            MemoryConfig::Ce155 => Some(clap::builder::PossibleValue::new("ce155")),
+        }
+    }
+}
+
+// Devices plugged into the expansion connector, which add their own statements
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Peripheral {
+    // Four colour printer and plotter
+    Ce150,
+}
+
+impl std::fmt::Display for Peripheral {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Peripheral::Ce150 => write!(f, "CE-150"),
+        }
+    }
+}
+
+impl clap::ValueEnum for Peripheral {
+    fn value_variants<'a>() -> &'a [Self] {
+        &[Peripheral::Ce150]
+    }
+
+    fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
+        match self {
+            Peripheral::Ce150 => Some(clap::builder::PossibleValue::new("ce150")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::ValueEnum;
+
+    #[test]
+    fn stock_map() {
+        let map = MemoryConfig::Pc1500.memory_map();
+
+        assert_eq!(map.region(0x4000), Region::ReserveArea);
+        assert_eq!(map.region(0x40C5), Region::UserRam);
+        assert_eq!(map.region(0x4800), Region::Unmapped);
+        assert_eq!(map.region(0x7600), Region::DisplayBuffer);
+        assert_eq!(map.region(0xC000), Region::Rom);
+        assert_eq!(map.region(ME1 + 0xF000), Region::Io);
+    }
+
+    #[test]
+    fn module_extends_user_ram() {
+        let map = MemoryConfig::Ce155.memory_map();
+
+        assert_eq!(map.region(0x3800), Region::ReserveArea);
+        assert_eq!(map.region(0x38C5), Region::UserRam);
+        assert_eq!(map.region(0x3900), Region::UserRam);
+        assert_eq!(map.region(0x5FFF), Region::UserRam);
+        assert_eq!(map.region(0x6000), Region::Unmapped);
+    }
+
+    #[test]
+    fn user_area_follows_reserve_area() {
+        for memory in MemoryConfig::value_variants() {
+            assert_eq!(memory.reserve_area().end, memory.user_area().start);
+        }
+    }
+}
@@ -1,22 +1,44 @@
 #[forbid(unsafe_code)]
 mod ast;
+mod codegen;
+mod interpreter;
+mod ir;
+mod lh5801;
+mod machine;
 mod tokens;
 
+#[cfg(test)]
+mod test_support;
+
 use std::fs;
+use std::path::Path;
 
-use clap::{Arg, Command};
+use clap::{Arg, ArgAction, ArgMatches, Command};
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 enum Pass {
     Lex,
     Parse,
     Sem,
+    Mem,
+    Ir,
+    Asm,
+    Run,
     C,
 }
 
 impl clap::ValueEnum for Pass {
     fn value_variants<'a>() -> &'a [Self] {
-        &[Pass::Lex, Pass::Parse, Pass::Sem, Pass::C]
+        &[
+            Pass::Lex,
+            Pass::Parse,
+            Pass::Sem,
+            Pass::Mem,
+            Pass::Ir,
+            Pass::Asm,
+            Pass::Run,
+            Pass::C,
+        ]
     }
 
     fn to_possible_value(&self) -> Option<clap::builder::PossibleValue> {
@@ -24,6 +46,10 @@ impl clap::ValueEnum for Pass {
             Pass::Lex => Some(clap::builder::PossibleValue::new("lex")),
             Pass::Parse => Some(clap::builder::PossibleValue::new("parse")),
             Pass::Sem => Some(clap::builder::PossibleValue::new("sem")),
+            Pass::Mem => Some(clap::builder::PossibleValue::new("mem")),
+            Pass::Ir => Some(clap::builder::PossibleValue::new("ir")),
+            Pass::Asm => Some(clap::builder::PossibleValue::new("asm")),
+            Pass::Run => Some(clap::builder::PossibleValue::new("run")),
             Pass::C => Some(clap::builder::PossibleValue::new("c")),
         }
     }
@@ -32,6 +58,273 @@ impl clap::ValueEnum for Pass {
 // TODO: use clap for argument parsing
 fn main() {
     let args = Command::new("sbc")
+        .subcommand_negates_reqs(true)
+        .args_conflicts_with_subcommands(true)
+        .subcommand(
+            Command::new("assemble")
+                .about("Assemble LH5801 source, like the output of --pass asm")
+                .arg(
+                    Arg::new("input")
+                        .help("Assembly source file")
+                        .value_name("FILE")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help("Binary file for the machine code")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("listing")
+                        .long("listing")
+                        .value_name("FILE")
+                        .help("Listing file with the address and bytes of each line")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("symbols")
+                        .long("symbols")
+                        .value_name("FILE")
+                        .help("Symbol file with the value of each label and EQU")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("object")
+                        .long("object")
+                        .value_name("FILE")
+                        .help("Relocatable object file")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("rom-symbols")
+                        .long("rom-symbols")
+                        .value_name("FILE")
+                        .help("Symbol file giving the addresses of EXTERN symbols, the shipped ROM entry points by default")
+                        .required(false),
+                ),
+        )
+        .subcommand(
+            Command::new("disasm")
+                .about("Disassemble LH5801 machine code, or the machine code a BASIC program POKEs")
+                .arg(
+                    Arg::new("input")
+                        .help("Binary file, or BASIC source with --poke")
+                        .value_name("FILE")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("start")
+                        .short('s')
+                        .long("start")
+                        .value_name("ADDRESS")
+                        .help("Address the binary is loaded at, like &40C5")
+                        .value_parser(parse_address)
+                        .required_unless_present("poke"),
+                )
+                .arg(
+                    Arg::new("entry")
+                        .short('e')
+                        .long("entry")
+                        .value_name("ADDRESS")
+                        .help("Address code starts at, the start address by default; may be repeated")
+                        .value_parser(parse_address)
+                        .action(ArgAction::Append)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("symbols")
+                        .long("symbols")
+                        .value_name("FILE")
+                        .help("Symbol file naming addresses, like the ROM entry points")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("poke")
+                        .long("poke")
+                        .help("Disassemble the bytes the BASIC program POKEs, from the addresses it CALLs")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
+        .subcommand(
+            Command::new("loader")
+                .about("Write a BASIC program that POKEs machine code from DATA lines and CALLs it")
+                .arg(
+                    Arg::new("input")
+                        .help("Binary file, or assembly source with --asm")
+                        .value_name("FILE")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("output")
+                        .short('o')
+                        .long("output")
+                        .value_name("FILE")
+                        .help("BASIC file to write the loader to")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("asm")
+                        .long("asm")
+                        .help("Assemble the input first, loading it at its origin")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("start")
+                        .short('s')
+                        .long("start")
+                        .value_name("ADDRESS")
+                        .help("Address to load the binary at, in user RAM above the loader")
+                        .value_parser(parse_address)
+                        .required_unless_present("asm")
+                        .conflicts_with("asm"),
+                )
+                .arg(
+                    Arg::new("memory")
+                        .short('m')
+                        .long("memory")
+                        .value_name("CONFIG")
+                        .help("Memory configuration of the machine the loader runs on")
+                        .value_parser(clap::builder::EnumValueParser::<machine::MemoryConfig>::new())
+                        .default_value("pc1500"),
+                )
+                .arg(
+                    Arg::new("entry")
+                        .short('e')
+                        .long("entry")
+                        .value_name("ADDRESS")
+                        .help("Address to CALL, the start address by default")
+                        .value_parser(parse_address)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("rom-symbols")
+                        .long("rom-symbols")
+                        .value_name("FILE")
+                        .help("Symbol file giving the addresses of EXTERN symbols for --asm, the shipped ROM entry points by default")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("first-line")
+                        .long("first-line")
+                        .value_name("LINE")
+                        .help("Number of the first line")
+                        .value_parser(clap::value_parser!(u32))
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::new("step")
+                        .long("step")
+                        .value_name("STEP")
+                        .help("Step between line numbers")
+                        .value_parser(clap::value_parser!(u32))
+                        .default_value("10"),
+                )
+                .arg(
+                    Arg::new("bytes-per-line")
+                        .long("bytes-per-line")
+                        .value_name("COUNT")
+                        .help("Bytes on each DATA line")
+                        .value_parser(clap::value_parser!(usize))
+                        .default_value("8"),
+                ),
+        )
+        .subcommand(
+            Command::new("emulate")
+                .about("Run LH5801 machine code on the host, CALLed like BASIC does")
+                .arg(
+                    Arg::new("input")
+                        .help("Binary file, or assembly source with --asm")
+                        .value_name("FILE")
+                        .required(true)
+                        .index(1),
+                )
+                .arg(
+                    Arg::new("asm")
+                        .long("asm")
+                        .help("Assemble the input first, loading it at its origin")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("start")
+                        .short('s')
+                        .long("start")
+                        .value_name("ADDRESS")
+                        .help("Address to load the binary at, like &40C5")
+                        .value_parser(parse_address)
+                        .required_unless_present("asm")
+                        .conflicts_with("asm"),
+                )
+                .arg(
+                    Arg::new("entry")
+                        .short('e')
+                        .long("entry")
+                        .value_name("ADDRESS")
+                        .help("Address to CALL, the start address by default")
+                        .value_parser(parse_address)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("rom-symbols")
+                        .long("rom-symbols")
+                        .value_name("FILE")
+                        .help("Symbol file giving the addresses of EXTERN symbols for --asm, the shipped ROM entry points by default")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("rom")
+                        .long("rom")
+                        .value_name("FILE")
+                        .help("ROM image to load read-only")
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("rom-start")
+                        .long("rom-start")
+                        .value_name("ADDRESS")
+                        .help("Address of the ROM image")
+                        .value_parser(parse_address)
+                        .default_value("&C000"),
+                )
+                .arg(
+                    Arg::new("boot")
+                        .long("boot")
+                        .help("Start the ROM at its reset vector instead of CALLing the code")
+                        .requires("rom")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("trap")
+                        .long("trap")
+                        .value_name("ADDRESS")
+                        .help("ROM entry point that returns at once, reporting the registers it was called with; may be repeated")
+                        .value_parser(parse_address)
+                        .action(ArgAction::Append)
+                        .required(false),
+                )
+                .arg(
+                    Arg::new("cycles")
+                        .long("cycles")
+                        .value_name("COUNT")
+                        .help("Cycles to run before stopping the code")
+                        .value_parser(clap::value_parser!(u64))
+                        .default_value("10000000"),
+                )
+                .arg(
+                    Arg::new("dump")
+                        .long("dump")
+                        .value_name("RANGE")
+                        .help("Addresses to show after the run, like &7600-&764F; may be repeated")
+                        .value_parser(parse_range)
+                        .action(ArgAction::Append)
+                        .required(false),
+                ),
+        )
         .arg(
             Arg::new("input")
                 .help("BASIC source file to compile")
@@ -58,12 +351,149 @@ fn main() {
                 .default_value("parse")
                 .required(false),
         )
+        .arg(
+            Arg::new("verbose")
+                .short('v')
+                .long("verbose")
+                .help("Report what the optimisations changed")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("keep-line")
+                .long("keep-line")
+                .value_name("LINE")
+                .help("Line started from outside the program, like RUN n, kept by dead code elimination; may be repeated")
+                .value_parser(clap::value_parser!(u32))
+                .action(ArgAction::Append)
+                .required(false),
+        )
+        .arg(
+            Arg::new("keep-labels")
+                .long("keep-labels")
+                .help("Keep labelled lines, which DEF keys start, in dead code elimination")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("memory")
+                .short('m')
+                .long("memory")
+                .value_name("CONFIG")
+                .help("Memory configuration of the target machine")
+                .value_parser(clap::builder::EnumValueParser::<machine::MemoryConfig>::new())
+                .default_value("pc1500")
+                .required(false),
+        )
+        .arg(
+            Arg::new("stack-limit")
+                .long("stack-limit")
+                .value_name("FRAMES")
+                .help("GOSUB and FOR frames the ROM's stack holds, for the nesting check")
+                .value_parser(clap::value_parser!(usize))
+                .required(false),
+        )
+        .arg(
+            Arg::new("origin")
+                .long("origin")
+                .value_name("ADDRESS")
+                .help("Address the code of the asm pass starts at, right after the BASIC program by default")
+                .value_parser(parse_address)
+                .required(false),
+        )
+        .arg(
+            Arg::new("peripheral")
+                .long("peripheral")
+                .value_name("DEVICE")
+                .help("Peripheral connected to the target machine, may be repeated")
+                .value_parser(clap::builder::EnumValueParser::<machine::Peripheral>::new())
+                .action(ArgAction::Append)
+                .required(false),
+        )
+        .arg(
+            Arg::new("plot")
+                .long("plot")
+                .value_name("FILE")
+                .help("SVG file for the CE-150 plot of the run pass")
+                .required(false),
+        )
+        .arg(
+            Arg::new("keys")
+                .long("keys")
+                .value_name("FILE")
+                .help("Key script typed on the keyboard during the run pass")
+                .required(false),
+        )
+        .arg(
+            Arg::new("timing")
+                .long("timing")
+                .value_name("TIMING")
+                .help("Whether the run pass keeps the pace of the device or runs as fast as it can")
+                .value_parser(clap::builder::EnumValueParser::<interpreter::clock::Timing>::new())
+                .default_value("instant")
+                .required(false),
+        )
+        .arg(
+            Arg::new("transcript")
+                .long("transcript")
+                .value_name("FILE")
+                .help("File for the timestamped display output of the run pass")
+                .required(false),
+        )
+        .arg(
+            Arg::new("debug")
+                .long("debug")
+                .help("Run the program under the interactive debugger")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("trace")
+                .long("trace")
+                .help("Trace every statement of the run pass and the variables it changes, as if the program started with TRON")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("profile")
+                .long("profile")
+                .help("Report how often each line ran and its estimated device cycles after the run pass")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("snapshots")
+                .long("snapshots")
+                .value_name("DIR")
+                .help("Directory for the screens shown at each PAUSE and WAIT of the run pass")
+                .required(false),
+        )
+        .arg(
+            Arg::new("snapshot-format")
+                .long("snapshot-format")
+                .value_name("FORMAT")
+                .help("Image format of the screen snapshots")
+                .value_parser(clap::builder::EnumValueParser::<
+                    interpreter::display::SnapshotFormat,
+                >::new())
+                .default_value("png")
+                .required(false),
+        )
         .get_matches();
 
+    match args.subcommand() {
+        Some(("assemble", args)) => return assemble(args),
+        Some(("disasm", args)) => return disassemble(args),
+        Some(("loader", args)) => return loader(args),
+        Some(("emulate", args)) => return emulate(args),
+        _ => {}
+    }
+
     // Read file from first argument
     let input = fs::read_to_string(args.get_one::<String>("input").unwrap()).unwrap();
 
     let pass = *args.get_one::<Pass>("pass").unwrap();
+    let memory = *args.get_one::<machine::MemoryConfig>("memory").unwrap();
+    let peripherals: Vec<machine::Peripheral> = args
+        .get_many::<machine::Peripheral>("peripheral")
+        .unwrap_or_default()
+        .copied()
+        .collect();
 
     let tokens = tokens::Lexer::new(&input);
 
@@ -86,31 +516,450 @@ fn main() {
         }
     } else {
         if pass == Pass::Parse {
-            // let printer = ast::Printer::new();
-            // let output = printer.build(&program);
-            println!("{program:?}");
+            print!("{}", ast::Printer::new().build(&program));
+            return;
+        }
+
+        let stack_limit = args
+            .get_one::<usize>("stack-limit")
+            .copied()
+            .unwrap_or(ast::DEFAULT_STACK_LIMIT);
+        let mut sem_checker = ast::SemanticChecker::new(&program, memory, &peripherals);
+        sem_checker.set_stack_limit(stack_limit);
+        let diagnostics = sem_checker.check();
+
+        for warning in &diagnostics.warnings {
+            println!("Warning: {}", warning);
+        }
+
+        if !diagnostics.errors.is_empty() {
+            println!("Errors in semantic analysis:");
+            for error in diagnostics.errors {
+                println!("{}", error);
+            }
+            return;
+        }
+
+        if pass == Pass::Sem {
+            print!("{}", diagnostics.stack);
+            println!("No semantic errors found");
             return;
         }
 
-        let sem_checker = ast::SemanticChecker::new(&program);
-        let sem_errors = sem_checker.check();
+        if pass == Pass::Mem {
+            let mut estimator = ast::MemoryEstimator::new(&program);
+            estimator.set_stack_limit(stack_limit);
+            print!("{}", estimator.estimate(memory));
+            return;
+        }
 
-        match sem_errors {
-            Ok(_) => {
-                if pass == Pass::Sem {
-                    println!("No semantic errors found");
+        if pass == Pass::Ir || pass == Pass::Asm {
+            let mut code = ir::Lowering::new(&program).lower();
+            let folded = ir::ConstantFolder::new().fold(&mut code);
+            if !folded.errors.is_empty() {
+                println!("Errors folding constants:");
+                for error in folded.errors {
+                    println!("{}", error);
+                }
+                return;
+            }
+            let mut changes = folded.changes;
+            let mut roots: Vec<u32> = args
+                .get_many::<u32>("keep-line")
+                .unwrap_or_default()
+                .copied()
+                .collect();
+            if args.get_flag("keep-labels") {
+                roots.extend(code.labels.keys());
+            }
+            changes.extend(ir::DeadCodeEliminator::new(roots).eliminate(&mut code));
+            if args.get_flag("verbose") {
+                for change in changes {
+                    println!("; {}", change);
+                }
+            }
+            let ranges = ir::RangeAnalysis::new(&code).analyze();
+            if pass == Pass::Asm {
+                // Right after the BASIC program, below its variables at the
+                // end of user RAM, unless given
+                let origin = match args.get_one::<u32>("origin") {
+                    Some(origin) => Some(*origin),
+                    None => {
+                        let report = ast::MemoryEstimator::new(&program).estimate(memory);
+                        let origin = report.program_area().end;
+                        (origin < report.variable_area().start).then_some(origin)
+                    }
+                };
+                let Some(origin) = origin else {
+                    println!(
+                        "The program and its variables fill user RAM, give an address with --origin"
+                    );
                     return;
+                };
+                print!(
+                    "{}",
+                    codegen::CodeGenerator::new(&program, &code, &ranges, origin).generate()
+                );
+                return;
+            }
+            print!("{}", code);
+            for line in ranges.to_string().lines() {
+                println!("; {}", line);
+            }
+            return;
+        }
+
+        if pass == Pass::Run {
+            let console: Box<dyn interpreter::Console> = match args.get_one::<String>("snapshots") {
+                Some(directory) => Box::new(interpreter::Recorder::new(
+                    directory.into(),
+                    *args
+                        .get_one::<interpreter::display::SnapshotFormat>("snapshot-format")
+                        .unwrap(),
+                )),
+                None => Box::new(interpreter::Terminal),
+            };
+            let mut interpreter = interpreter::Interpreter::new(&program, memory, console);
+            interpreter.set_timing(
+                *args
+                    .get_one::<interpreter::clock::Timing>("timing")
+                    .unwrap(),
+            );
+            // TRON writes to stderr even without --trace
+            interpreter.set_trace(Box::new(std::io::stderr()), args.get_flag("trace"));
+            if args.get_flag("profile") {
+                interpreter.enable_profile();
+            }
+            if let Some(keys) = args.get_one::<String>("keys") {
+                let script = fs::read_to_string(keys).unwrap();
+                match interpreter::keyboard::parse_script(&script) {
+                    Ok(presses) => {
+                        interpreter.set_keyboard(interpreter::keyboard::Keyboard::new(presses))
+                    }
+                    Err(error) => {
+                        println!("Error in key script: {}", error);
+                        return;
+                    }
                 }
             }
+            let result = if args.get_flag("debug") {
+                let mut debugger = interpreter::debugger::Debugger::new(&program, interpreter);
+                let result = debugger
+                    .run(std::io::stdin().lock(), &mut std::io::stdout())
+                    .unwrap();
+                interpreter = debugger.into_interpreter();
+                result
+            } else {
+                interpreter.run()
+            };
+
+            for call in interpreter.calls() {
+                print!("CALL &{:04X} in line {}", call.address, call.line);
+                if let Some(argument) = call.argument {
+                    print!(" with argument {}", argument);
+                }
+                println!(" was not executed");
+            }
+            if let Err(error) = result {
+                println!("{}", error);
+            }
+            if let Some(transcript) = args.get_one::<String>("transcript") {
+                let lines: Vec<String> = interpreter
+                    .transcript()
+                    .iter()
+                    .map(|line| format!("{}\n", line))
+                    .collect();
+                fs::write(transcript, lines.concat()).unwrap();
+            }
+            if let Some(plot) = args.get_one::<String>("plot") {
+                fs::write(plot, interpreter.plotter().to_string()).unwrap();
+            }
+            if let Some(profile) = interpreter.profile() {
+                print!("{}", profile);
+            }
+            return;
+        }
+
+        println!("Generating C code is not supported yet");
+    }
+}
+
+// Assembles the input file with the ROM symbols of the arguments, printing
+// any errors
+fn assemble_file(args: &ArgMatches) -> Option<lh5801::Assembly> {
+    let input = args.get_one::<String>("input").unwrap();
+    let source = fs::read_to_string(input).unwrap();
+    // INCLUDE files are found next to the source
+    let directory = Path::new(input).parent().unwrap_or(Path::new(""));
+    let mut assembler = lh5801::Assembler::new(|name| fs::read_to_string(directory.join(name)));
+
+    let (path, symbols) = match args.get_one::<String>("rom-symbols") {
+        Some(path) => (path.as_str(), fs::read_to_string(path).unwrap()),
+        None => ("rom.sym", codegen::ROM_SYMBOLS.to_owned()),
+    };
+    match lh5801::read_symbols(path, &symbols) {
+        Ok(symbols) => assembler.define(symbols),
+        Err(errors) => {
+            println!("Errors in symbol file:");
+            for error in errors {
+                println!("{}", error);
+            }
+            return None;
+        }
+    }
+
+    match assembler.assemble(input, &source) {
+        Ok(assembly) => Some(assembly),
+        Err(errors) => {
+            println!("Errors assembling program:");
+            for error in errors {
+                println!("{}", error);
+            }
+            None
+        }
+    }
+}
+
+fn assemble(args: &ArgMatches) {
+    let input = args.get_one::<String>("input").unwrap();
+    let Some(assembly) = assemble_file(args) else {
+        return;
+    };
+
+    if let Some(output) = args.get_one::<String>("output") {
+        let externals = assembly.externals();
+        if externals.is_empty() {
+            fs::write(output, assembly.bytes()).unwrap();
+            println!(
+                "Assembled {} bytes for &{:04X}",
+                assembly.bytes().len(),
+                assembly.origin()
+            );
+        } else {
+            let names: Vec<&str> = externals.into_iter().collect();
+            println!(
+                "Cannot write a binary with unresolved external symbols, give them with --rom-symbols: {}",
+                names.join(", ")
+            );
+        }
+    }
+    if let Some(listing) = args.get_one::<String>("listing") {
+        fs::write(listing, assembly.listing()).unwrap();
+    }
+    if let Some(symbols) = args.get_one::<String>("symbols") {
+        fs::write(symbols, assembly.symbol_file()).unwrap();
+    }
+    if let Some(object) = args.get_one::<String>("object") {
+        let name = Path::new(input)
+            .file_stem()
+            .map_or(input.clone(), |stem| stem.to_string_lossy().into_owned());
+        fs::write(object, assembly.object(&name)).unwrap();
+    }
+
+    let written = ["output", "listing", "symbols", "object"];
+    if written.iter().all(|name| !args.contains_id(name)) {
+        print!("{}", assembly.listing());
+    }
+}
+
+// Decimal or &hex, like BASIC writes addresses
+fn parse_address(text: &str) -> Result<u32, String> {
+    let address = match text.strip_prefix('&') {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => text.parse(),
+    };
+    address
+        .ok()
+        .filter(|address| *address <= 0xFFFF)
+        .ok_or_else(|| format!("{} is not an address from 0 to &FFFF", text))
+}
+
+// Two addresses joined by a dash, both included
+fn parse_range(text: &str) -> Result<(u32, u32), String> {
+    let (start, end) = text
+        .split_once('-')
+        .ok_or_else(|| format!("{} is not a range like &7600-&764F", text))?;
+    let (start, end) = (parse_address(start)?, parse_address(end)?);
+    if start > end {
+        return Err(format!("{} ends before it starts", text));
+    }
+    Ok((start, end))
+}
+
+fn disassemble(args: &ArgMatches) {
+    let input = args.get_one::<String>("input").unwrap();
+    let mut symbols = std::collections::BTreeMap::new();
+    if let Some(path) = args.get_one::<String>("symbols") {
+        match lh5801::read_symbols(path, &fs::read_to_string(path).unwrap()) {
+            Ok(read) => symbols = read,
             Err(errors) => {
-                println!("Errors in semantic analysis:");
+                println!("Errors in symbol file:");
                 for error in errors {
                     println!("{}", error);
                 }
                 return;
             }
         }
+    }
+    let entries: Vec<u32> = args
+        .get_many::<u32>("entry")
+        .unwrap_or_default()
+        .copied()
+        .collect();
+
+    if !args.get_flag("poke") {
+        let start = *args.get_one::<u32>("start").unwrap();
+        let bytes = fs::read(input).unwrap();
+        let mut disassembler = lh5801::Disassembler::new(start, &bytes);
+        disassembler.define(&symbols);
+        let entries = if entries.is_empty() {
+            vec![start]
+        } else {
+            entries
+        };
+        print!("{}", disassembler.disassemble(&entries));
+        return;
+    }
+
+    let source = fs::read_to_string(input).unwrap();
+    let (program, errors) = ast::Parser::new(tokens::Lexer::new(&source)).parse();
+    if !errors.is_empty() {
+        println!("Errors parsing program:");
+        for error in errors {
+            println!("{}", error);
+        }
+        return;
+    }
+    let mut called = lh5801::called(&program);
+    called.extend(entries);
+    for (start, bytes) in lh5801::poked(&program) {
+        let end = start + u32::try_from(bytes.len()).unwrap_or(0);
+        let mut starts: Vec<u32> = called.range(start..end).copied().collect();
+        if starts.is_empty() {
+            starts.push(start);
+        }
+        let mut disassembler = lh5801::Disassembler::new(start, &bytes);
+        disassembler.define(&symbols);
+        print!("{}", disassembler.disassemble(&starts));
+    }
+}
+
+fn loader(args: &ArgMatches) {
+    let (origin, bytes) = if args.get_flag("asm") {
+        let Some(assembly) = assemble_file(args) else {
+            return;
+        };
+        let externals: Vec<&str> = assembly.externals().into_iter().collect();
+        if !externals.is_empty() {
+            println!(
+                "Cannot load code with unresolved external symbols, give them with --rom-symbols: {}",
+                externals.join(", ")
+            );
+            return;
+        }
+        (assembly.origin(), assembly.bytes().to_vec())
+    } else {
+        let input = args.get_one::<String>("input").unwrap();
+        (
+            *args.get_one::<u32>("start").unwrap(),
+            fs::read(input).unwrap(),
+        )
+    };
+
+    let mut loader = lh5801::Loader::new(origin, &bytes);
+    loader.set_memory(*args.get_one::<machine::MemoryConfig>("memory").unwrap());
+    if let Some(entry) = args.get_one::<u32>("entry") {
+        loader.set_entry(*entry);
+    }
+    loader.set_numbering(
+        *args.get_one::<u32>("first-line").unwrap(),
+        *args.get_one::<u32>("step").unwrap(),
+    );
+    loader.set_bytes_per_line(*args.get_one::<usize>("bytes-per-line").unwrap());
+    let program = match loader.generate() {
+        Ok(program) => program,
+        Err(error) => {
+            println!("Error generating loader: {}", error);
+            return;
+        }
+    };
 
-        todo!("Generate C code");
+    let output = ast::Printer::new().build(&program);
+    match args.get_one::<String>("output") {
+        Some(path) => fs::write(path, output).unwrap(),
+        None => print!("{}", output),
+    }
+}
+
+fn emulate(args: &ArgMatches) {
+    let (origin, bytes) = if args.get_flag("asm") {
+        let Some(assembly) = assemble_file(args) else {
+            return;
+        };
+        let externals: Vec<&str> = assembly.externals().into_iter().collect();
+        if !externals.is_empty() {
+            println!(
+                "Cannot run code with unresolved external symbols, give them with --rom-symbols: {}",
+                externals.join(", ")
+            );
+            return;
+        }
+        (assembly.origin(), assembly.bytes().to_vec())
+    } else {
+        let input = args.get_one::<String>("input").unwrap();
+        (
+            *args.get_one::<u32>("start").unwrap(),
+            fs::read(input).unwrap(),
+        )
+    };
+
+    let mut emulator = lh5801::Emulator::new();
+    emulator.set_cycle_limit(*args.get_one::<u64>("cycles").unwrap());
+    if let Err(error) = emulator.load(origin, &bytes) {
+        println!("Error loading code: {}", error);
+        return;
+    }
+    if let Some(rom) = args.get_one::<String>("rom") {
+        let start = *args.get_one::<u32>("rom-start").unwrap();
+        if let Err(error) = emulator.load_rom(start, &fs::read(rom).unwrap()) {
+            println!("Error loading ROM: {}", error);
+            return;
+        }
+    }
+    for address in args.get_many::<u32>("trap").unwrap_or_default() {
+        let address = *address;
+        emulator.hook(
+            u16::try_from(address).unwrap_or(u16::MAX),
+            move |registers, _| println!("Trap at &{:04X} with {}", address, registers),
+        );
+    }
+
+    let result = if args.get_flag("boot") {
+        emulator.boot()
+    } else {
+        let entry = args.get_one::<u32>("entry").copied().unwrap_or(origin);
+        emulator.call(u16::try_from(entry).unwrap_or(u16::MAX))
+    };
+    match result {
+        // The LH5801 of the PC-1500 runs at 1.3 MHz
+        Ok(cycles) => println!("Ran {} cycles, {} ms", cycles, cycles / 1300),
+        Err(error) => println!("{}", error),
+    }
+    println!("{}", emulator.cpu());
+    let written = emulator.memory().written();
+    if !written.is_empty() {
+        let ports: Vec<String> = written
+            .iter()
+            .map(|byte| format!("&{:02X}", byte))
+            .collect();
+        println!("ATP wrote {}", ports.join(" "));
+    }
+    for (start, end) in args.get_many::<(u32, u32)>("dump").unwrap_or_default() {
+        for line in (*start..=*end).step_by(8) {
+            let row: Vec<String> = (line..=(line + 7).min(*end))
+                .map(|address| format!("{:02X}", emulator.memory().peek(address)))
+                .collect();
+            println!("&{:04X}: {}", line, row.join(" "));
+        }
     }
 }
@@ -0,0 +1,13 @@
+// Helpers shared by the unit tests
+
+use crate::ast::{Parser, Program};
+use crate::tokens::Lexer;
+
+// Parses a program the test expects to be free of errors
+pub fn parse(source: &str) -> Program {
+    let (program, errors) = Parser::new(Lexer::new(source)).parse();
+    if let Some(error) = errors.first() {
+        panic!("{}", error);
+    }
+    program
+}
@@ -4,32 +4,51 @@ use std::{
     iter::{FusedIterator, Peekable},
     str::Chars,
 };
-pub use token::Token;
+pub use token::{Hex, LexError, Token};
 
 pub struct Lexer<'a> {
     input: Peekable<Chars<'a>>,
     current_line: usize,
+    // The token after the last one returned, with the line it is on
+    peeked: Option<(Option<Token>, usize)>,
 }
 
 impl<'a> Lexer<'a> {
     pub fn new(input: &'a str) -> Self {
         Self {
             input: input.chars().peekable(),
-            current_line: 0,
+            // Lines count from 1, like editors do
+            current_line: 1,
+            peeked: None,
         }
     }
 
+    // Line of the next token, the one peek gives
     pub fn current_line(&self) -> usize {
-        self.current_line
+        match self.peeked {
+            Some((_, line)) => line,
+            None => self.current_line,
+        }
+    }
+
+    pub fn peek(&mut self) -> Option<&Token> {
+        self.peek_mut().map(|token| &*token)
+    }
+
+    pub fn peek_mut(&mut self) -> Option<&mut Token> {
+        if self.peeked.is_none() {
+            self.skip_whitespace();
+            let line = self.current_line;
+            self.peeked = Some((self.next_token(), line));
+        }
+        self.peeked.as_mut().and_then(|(token, _)| token.as_mut())
     }
 
     fn next_token(&mut self) -> Option<Token> {
         self.skip_whitespace();
 
         let token = match self.input.next()? {
-            '"' => self
-                .string()
-                .unwrap_or_else(|_| panic!("Unterminated string at line {}", self.current_line)),
+            '"' => self.string().unwrap_or_else(Token::Invalid),
             '+' => Token::Plus,
             '-' => Token::Minus,
             '*' => Token::Star,
@@ -56,18 +75,14 @@ impl<'a> Lexer<'a> {
             ':' => Token::Colon,
             '(' => Token::LeftParen,
             ')' => Token::RightParen,
-            '\n' | '\r' => {
-                self.skip_newline();
+            c @ ('\n' | '\r') => {
+                self.skip_newline(c);
                 Token::Newline
             }
             c if c.is_ascii_alphabetic() => self.identifier(c),
-            c if c.is_ascii_digit() => self
-                .number(c)
-                .unwrap_or_else(|_| panic!("Invalid number at line {}", self.current_line)),
-            other => panic!(
-                "Unexpected character '{}' at line {}",
-                other, self.current_line
-            ),
+            c if c.is_ascii_digit() => self.number(c).unwrap_or_else(Token::Invalid),
+            '&' => self.hex_number().unwrap_or_else(Token::Invalid),
+            other => Token::Invalid(LexError::Character(other)),
         };
 
         Some(token)
@@ -77,10 +92,16 @@ impl<'a> Lexer<'a> {
         while self.input.next_if(|&c| matches!(c, ' ' | '\t')).is_some() {}
     }
 
-    // We already know the first character is a whitespace before entering this function
-    fn skip_newline(&mut self) {
-        while self.input.next_if(|&c| matches!(c, '\n' | '\r')).is_some() {
-            self.current_line += 1;
+    // We already know the first character is a line break before entering this function.
+    // Counts every \n, \r\n or lone \r in the run
+    fn skip_newline(&mut self, first: char) {
+        self.current_line += 1;
+        let mut previous = first;
+        while let Some(c) = self.input.next_if(|&c| matches!(c, '\n' | '\r')) {
+            if !(previous == '\r' && c == '\n') {
+                self.current_line += 1;
+            }
+            previous = c;
         }
     }
 
@@ -95,30 +116,54 @@ impl<'a> Lexer<'a> {
             // Greedily match a keyword
             let tok = match ident.as_str() {
                 "AND" => Some(Token::And),
+                "BEEP" => Some(Token::Beep),
                 "CALL" => Some(Token::Call),
+                "CLS" => Some(Token::Cls),
+                "COLOR" => Some(Token::Color),
+                "CSIZE" => Some(Token::CSize),
+                "CURSOR" => Some(Token::Cursor),
                 "DATA" => Some(Token::Data),
                 "DIM" => Some(Token::Dim),
                 "ELSE" => Some(Token::Else),
                 "END" => Some(Token::End),
+                "ERL" => Some(Token::Erl),
+                "ERN" => Some(Token::Ern),
+                "ERROR" => Some(Token::Error),
                 "FOR" => Some(Token::For),
+                "GCURSOR" => Some(Token::GCursor),
+                "GLCURSOR" => Some(Token::GlCursor),
                 "GOSUB" => Some(Token::Gosub),
                 "GOTO" => Some(Token::Goto),
+                "GPRINT" => Some(Token::GPrint),
+                "GRAPH" => Some(Token::Graph),
                 "IF" => Some(Token::If),
                 "INPUT" => Some(Token::Input),
                 "LET" => Some(Token::Let),
+                "LF" => Some(Token::Lf),
+                "LINE" => Some(Token::Line),
+                "LPRINT" => Some(Token::LPrint),
                 "NEXT" => Some(Token::Next),
                 "NOT" => Some(Token::Not),
+                "ON" => Some(Token::On),
                 "OR" => Some(Token::Or),
                 "PAUSE" => Some(Token::Pause),
+                "POINT" => Some(Token::Point),
                 "POKE" => Some(Token::Poke),
                 "PRINT" => Some(Token::Print),
                 "READ" => Some(Token::Read),
                 "REM" => Some(self.comment()),
                 "RESTORE" => Some(Token::Restore),
                 "RETURN" => Some(Token::Return),
+                "RLINE" => Some(Token::RLine),
+                "ROTATE" => Some(Token::Rotate),
+                "SORGN" => Some(Token::Sorgn),
                 "STEP" => Some(Token::Step),
+                "TEXT" => Some(Token::Text),
                 "THEN" => Some(Token::Then),
                 "TO" => Some(Token::To),
+                "TROFF" => Some(Token::Troff),
+                "TRON" => Some(Token::Tron),
+                "USING" => Some(Token::Using),
                 "WAIT" => Some(Token::Wait),
                 _ => None,
             };
@@ -133,23 +178,48 @@ impl<'a> Lexer<'a> {
             ident.push('$');
             self.input.next();
         }
+        // The only keyword with a '$', it cannot be told apart from INKEY
+        // until the '$' is read
+        if ident == "INKEY$" {
+            return Token::Inkey;
+        }
 
         Token::Identifier(ident.to_owned())
     }
 
     // We already know the first character is a digit before entering this function
-    fn number(&mut self, first: char) -> Result<Token, ()> {
+    fn number(&mut self, first: char) -> Result<Token, LexError> {
         let mut chars = String::new();
         chars.push(first);
         while let Some(c) = self.input.next_if(|&c| c.is_ascii_digit()) {
             chars.push(c);
         }
 
-        Ok(Token::Number(chars.parse().map_err(|_e| ())?))
+        chars
+            .parse()
+            .map(Token::Number)
+            .map_err(|_e| LexError::Number)
+    }
+
+    // We already know the first character is an ampersand before entering this function
+    fn hex_number(&mut self) -> Result<Token, LexError> {
+        // Both &7600 and &H7600 are accepted
+        self.input.next_if_eq(&'H');
+
+        let mut chars = String::new();
+        while let Some(c) = self.input.next_if(char::is_ascii_hexdigit) {
+            chars.push(c);
+        }
+
+        u32::from_str_radix(&chars, 16)
+            .ok()
+            .and_then(|value| i32::try_from(value).ok())
+            .map(Token::HexNumber)
+            .ok_or(LexError::HexNumber)
     }
 
     // We already know the first character is a double quote before entering this function
-    fn string(&mut self) -> Result<Token, ()> {
+    fn string(&mut self) -> Result<Token, LexError> {
         // 20 is just a heuristic
         let mut chars = String::with_capacity(20);
 
@@ -157,18 +227,20 @@ impl<'a> Lexer<'a> {
             chars.push(c);
         }
 
-        self.input.next(); // Consume the closing double quote, or newline
-
-        Ok(Token::String(chars.to_owned()))
+        // The line break stays for the Newline token
+        self.input
+            .next_if_eq(&'"')
+            .map(|_| Token::String(chars))
+            .ok_or(LexError::UnterminatedString)
     }
 
     fn comment(&mut self) -> Token {
-        let s: String = self
-            .input
-            .by_ref()
-            .take_while(|&c| c != '\n' && c != '\r')
-            .collect();
+        let mut s = String::new();
+        while let Some(c) = self.input.next_if(|&c| c != '\n' && c != '\r') {
+            s.push(c);
+        }
 
+        // The line break stays for the Newline token
         Token::Rem(s.trim().to_owned())
     }
 }
@@ -177,11 +249,16 @@ impl Iterator for Lexer<'_> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        self.next_token()
+        match self.peeked.take() {
+            Some((token, _)) => token,
+            None => self.next_token(),
+        }
     }
 
+    // Every token but a peeked one takes at least a character
     fn size_hint(&self) -> (usize, Option<usize>) {
-        self.input.size_hint()
+        let (_, upper) = self.input.size_hint();
+        (0, upper.and_then(|upper| upper.checked_add(1)))
     }
 }
 
@@ -230,6 +307,36 @@ mod tests {
         assert_eq!(lexer.next(), Some(super::Token::Number(456)));
     }
 
+    #[test]
+    fn hex_number() {
+        let input = "&7600";
+        let mut lexer = super::Lexer::new(input);
+        assert_eq!(lexer.next(), Some(super::Token::HexNumber(0x7600)));
+    }
+
+    #[test]
+    fn hex_number_with_prefix() {
+        let input = "&HFF,&0a";
+        let mut lexer = super::Lexer::new(input);
+        assert_eq!(lexer.next(), Some(super::Token::HexNumber(0xFF)));
+        assert_eq!(lexer.next(), Some(super::Token::Comma));
+        assert_eq!(lexer.next(), Some(super::Token::HexNumber(0x0A)));
+    }
+
+    #[test]
+    fn invalid_hex_numbers() {
+        let input = "& &FFFFFFFF,&7600";
+        let mut lexer = super::Lexer::new(input);
+        let invalid = super::Token::Invalid(super::LexError::HexNumber);
+        assert_eq!(lexer.next(), Some(invalid));
+        assert_eq!(
+            lexer.next(),
+            Some(super::Token::Invalid(super::LexError::HexNumber))
+        );
+        assert_eq!(lexer.next(), Some(super::Token::Comma));
+        assert_eq!(lexer.next(), Some(super::Token::HexNumber(0x7600)));
+    }
+
     #[test]
     fn parentheses() {
         let input = "(123)";
@@ -281,4 +388,14 @@ mod tests {
         assert_eq!(lexer.next(), Some(super::Token::Newline));
         assert_eq!(lexer.next(), Some(super::Token::Rem("world".to_owned())));
     }
+
+    #[test]
+    fn comment_keeps_line_break() {
+        let input = "10 REM hello\n20 END";
+        let mut lexer = super::Lexer::new(input);
+        assert_eq!(lexer.next(), Some(super::Token::Number(10)));
+        assert_eq!(lexer.next(), Some(super::Token::Rem("hello".to_owned())));
+        assert_eq!(lexer.next(), Some(super::Token::Newline));
+        assert_eq!(lexer.next(), Some(super::Token::Number(20)));
+    }
 }
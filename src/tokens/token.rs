@@ -2,6 +2,8 @@
 pub enum Token {
     Identifier(String),
     Number(i32),
+    // Written as &7600 or &H7600
+    HexNumber(i32),
     String(String),
 
     // --- Keywords ---
@@ -9,6 +11,8 @@ pub enum Token {
     Goto,
     Gosub,
     Return,
+    On,
+    Error,
     If,
     Else,
     Then,
@@ -24,6 +28,7 @@ pub enum Token {
     Not,
     // IO Intrinsics, might as well be keywords
     Print,
+    Using,
     Input,
     Pause,
     Wait,
@@ -34,6 +39,32 @@ pub enum Token {
     // Inline assembly
     Poke,
     Call,
+    // Display and sound
+    Cls,
+    Cursor,
+    GCursor,
+    GPrint,
+    Point,
+    Inkey,
+    // Error trapping
+    Ern,
+    Erl,
+    Beep,
+    // CE-150 printer and plotter
+    LPrint,
+    Line,
+    RLine,
+    GlCursor,
+    Color,
+    CSize,
+    Rotate,
+    Graph,
+    Text,
+    Sorgn,
+    Lf,
+    // Tracing
+    Tron,
+    Troff,
 
     // Comments, kind of a keyword
     Rem(String),
@@ -55,6 +86,41 @@ pub enum Token {
     Semicolon,
     Slash,
     Star,
+
+    // Text the lexer could not make a token of, left for the parser to report
+    Invalid(LexError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LexError {
+    // & without digits, or digits past the largest number
+    HexNumber,
+    // Digits past the largest number
+    Number,
+    // No closing quote before the end of the line
+    UnterminatedString,
+    // A character no token starts with
+    Character(char),
+}
+
+impl std::fmt::Display for LexError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            LexError::HexNumber => write!(f, "Invalid hexadecimal number"),
+            LexError::Number => write!(f, "Invalid number"),
+            LexError::UnterminatedString => write!(f, "Unterminated string"),
+            LexError::Character(c) => write!(f, "Unexpected character '{}'", c),
+        }
+    }
+}
+
+// A hexadecimal literal the way it is written, &7600
+pub struct Hex<T>(pub T);
+
+impl<T: std::fmt::UpperHex> std::fmt::Display for Hex<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "&{:X}", self.0)
+    }
 }
 
 impl std::fmt::Display for Token {
@@ -74,6 +140,8 @@ impl std::fmt::Display for Token {
             Token::Not => write!(f, "NOT"),
             Token::Or => write!(f, "OR"),
             Token::Return => write!(f, "RETURN"),
+            Token::On => write!(f, "ON"),
+            Token::Error => write!(f, "ERROR"),
             Token::Step => write!(f, "STEP"),
             Token::Then => write!(f, "THEN"),
             Token::To => write!(f, "TO"),
@@ -82,11 +150,34 @@ impl std::fmt::Display for Token {
             Token::Input => write!(f, "INPUT"),
             Token::Pause => write!(f, "PAUSE"),
             Token::Print => write!(f, "PRINT"),
+            Token::Using => write!(f, "USING"),
             Token::Read => write!(f, "READ"),
             Token::Restore => write!(f, "RESTORE"),
             Token::Wait => write!(f, "WAIT"),
             Token::Poke => write!(f, "POKE"),
             Token::Call => write!(f, "CALL"),
+            Token::Cls => write!(f, "CLS"),
+            Token::Cursor => write!(f, "CURSOR"),
+            Token::GCursor => write!(f, "GCURSOR"),
+            Token::GPrint => write!(f, "GPRINT"),
+            Token::Point => write!(f, "POINT"),
+            Token::Inkey => write!(f, "INKEY$"),
+            Token::Ern => write!(f, "ERN"),
+            Token::Erl => write!(f, "ERL"),
+            Token::Beep => write!(f, "BEEP"),
+            Token::LPrint => write!(f, "LPRINT"),
+            Token::Line => write!(f, "LINE"),
+            Token::RLine => write!(f, "RLINE"),
+            Token::GlCursor => write!(f, "GLCURSOR"),
+            Token::Color => write!(f, "COLOR"),
+            Token::CSize => write!(f, "CSIZE"),
+            Token::Rotate => write!(f, "ROTATE"),
+            Token::Graph => write!(f, "GRAPH"),
+            Token::Text => write!(f, "TEXT"),
+            Token::Sorgn => write!(f, "SORGN"),
+            Token::Tron => write!(f, "TRON"),
+            Token::Troff => write!(f, "TROFF"),
+            Token::Lf => write!(f, "LF"),
             // Comments
             Token::Rem(content) => write!(f, "REM({})", content),
             // Operators
@@ -109,7 +200,9 @@ impl std::fmt::Display for Token {
             // Other
             Token::Identifier(ident) => write!(f, "{}", ident),
             Token::Number(num) => write!(f, "{}", num),
+            Token::HexNumber(num) => write!(f, "{}", Hex(*num)),
             Token::String(string) => write!(f, "\"{}\"", string),
+            Token::Invalid(error) => write!(f, "INVALID({})", error),
         }
     }
 }
@@ -0,0 +1,9 @@
+10 CSIZE 2: COLOR 0
+20 LPRINT "CE-150"
+30 GRAPH
+40 LINE (0, 0)-(200, -100), 0, 1, B
+50 GLCURSOR (20, -60): COLOR 3
+60 LPRINT "PLOT"
+70 RLINE -(50, 50)-(50, -50), 2, 2
+80 TEXT
+90 LPRINT "A<B", 10